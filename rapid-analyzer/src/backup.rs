@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::program::{read_source, Signal, System, Task};
+
+/// File extensions of the modules loaded from a backup, compared case-insensitively.
+const MODULE_EXTENSIONS: &[&str] = &["mod", "modx", "sys", "sysx"];
+
+/// The directories of a task that hold its modules, in the order they are loaded.
+const MODULE_DIRECTORIES: &[&str] = &["SYSMOD", "PROGMOD"];
+
+/// A backup of a robot controller, as written by the controller or RobotStudio. The backup holds
+/// the modules of each task below `RAPID/TASK<n>`, split into system modules in `SYSMOD` and
+/// program modules in `PROGMOD`, next to the `SYSPAR` configuration and the `BACKINFO`
+/// description of the system. The I/O signals of `SYSPAR/EIO.cfg` are declared in every task.
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// The name of the backed up system from `BACKINFO`, if the backup has one.
+    pub system_id: Option<String>,
+    /// The tasks of the backup, in the order of their directories.
+    pub system: System,
+}
+
+impl Backup {
+    /// Loads every task of the backup in `path` with its system modules and program modules.
+    ///
+    /// The tasks are named after the task list in `BACKINFO/backinfo.txt`, or the `CAB_TASKS`
+    /// section of `SYSPAR/SYS.cfg` without it, and otherwise after their directory. Every task
+    /// sees the signals of `SYSPAR/EIO.cfg`.
+    pub fn load(path: &Path) -> io::Result<Backup> {
+        let rapid = path.join("RAPID");
+        if !rapid.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{}: not a controller backup, expected a RAPID directory",
+                    path.display()
+                ),
+            ));
+        }
+        let info = optional_source(&path.join("BACKINFO").join("backinfo.txt"))?;
+        let system_id = info.as_deref().and_then(system_id);
+        let mut names = info.as_deref().map(task_names).unwrap_or_default();
+        if names.is_empty() {
+            names = optional_source(&path.join("SYSPAR").join("SYS.cfg"))?
+                .as_deref()
+                .map(cabinet_tasks)
+                .unwrap_or_default();
+        }
+        let signals = optional_source(&path.join("SYSPAR").join("EIO.cfg"))?
+            .as_deref()
+            .map(io_signals)
+            .unwrap_or_default();
+
+        let mut system = System::new();
+        for (number, dir) in task_directories(&rapid)? {
+            let name = names
+                .iter()
+                .find(|(task, _)| *task == number)
+                .map(|(_, name)| name.clone())
+                .unwrap_or_else(|| dir.file_name().unwrap().to_string_lossy().into_owned());
+            let mut task = Task::new(name);
+            task.add_signals(signals.iter().cloned());
+            for module_dir in MODULE_DIRECTORIES {
+                for file in module_files(&dir.join(module_dir))? {
+                    let source = read_source(&file)?;
+                    task.add_file(file, source);
+                }
+            }
+            system.add_task(task);
+        }
+        Ok(Backup {
+            path: path.to_owned(),
+            system_id,
+            system,
+        })
+    }
+}
+
+/// Reads a file of the backup that may be missing.
+fn optional_source(path: &Path) -> io::Result<Option<String>> {
+    if path.is_file() {
+        read_source(path).map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the `TASK<n>` directories below `rapid` with their number, ordered by number.
+fn task_directories(rapid: &Path) -> io::Result<Vec<(usize, PathBuf)>> {
+    let mut tasks = Vec::new();
+    for entry in fs::read_dir(rapid)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_ascii_uppercase();
+        let number = name.strip_prefix("TASK").and_then(|n| n.parse().ok());
+        if let (Some(number), true) = (number, entry.file_type()?.is_dir()) {
+            tasks.push((number, entry.path()));
+        }
+    }
+    tasks.sort();
+    Ok(tasks)
+}
+
+/// Returns the module files in `dir` ordered by name, or nothing if there is no such directory.
+fn module_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_module = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                MODULE_EXTENSIONS
+                    .iter()
+                    .any(|known| extension.eq_ignore_ascii_case(known))
+            });
+        if is_module && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the first line of the `>>SYSTEM_ID:` section of `backinfo.txt`.
+fn system_id(info: &str) -> Option<String> {
+    let mut lines = info.lines().map(str::trim);
+    lines.find(|line| line.eq_ignore_ascii_case(">>SYSTEM_ID:"))?;
+    lines
+        .next()
+        .filter(|line| !line.is_empty() && !line.starts_with(">>"))
+        .map(str::to_owned)
+}
+
+/// Returns the task numbers and names of the `>>TASK1: (T_ROB1,,)` lines of `backinfo.txt`.
+fn task_names(info: &str) -> Vec<(usize, String)> {
+    info.lines()
+        .filter_map(|line| {
+            let (task, rest) = line.trim().strip_prefix(">>TASK")?.split_once(':')?;
+            let number = task.parse().ok()?;
+            let name = rest.trim().strip_prefix('(')?.split([',', ')']).next()?;
+            let name = name.trim();
+            (!name.is_empty()).then(|| (number, name.to_owned()))
+        })
+        .collect()
+}
+
+/// Returns the names of the `CAB_TASKS` instances of `SYS.cfg`, numbered from 1 in the order
+/// they are listed, which is the order of the task directories.
+fn cabinet_tasks(config: &str) -> Vec<(usize, String)> {
+    config_instances(config, "CAB_TASKS")
+        .into_iter()
+        .filter_map(|mut instance| instance.remove("Name"))
+        .enumerate()
+        .map(|(index, name)| (index + 1, name))
+        .collect()
+}
+
+/// Returns the signals of the `EIO_SIGNAL` instances of `EIO.cfg` with the data type of their
+/// `-SignalType`. Signals of other types, such as cross connections, are left out.
+fn io_signals(config: &str) -> Vec<Signal> {
+    config_instances(config, "EIO_SIGNAL")
+        .into_iter()
+        .filter_map(|mut instance| {
+            let data_type = match instance.get("SignalType")?.to_ascii_uppercase().as_str() {
+                "DI" => "signaldi",
+                "DO" => "signaldo",
+                "AI" => "signalai",
+                "AO" => "signalao",
+                "GI" => "signalgi",
+                "GO" => "signalgo",
+                _ => return None,
+            };
+            Some(Signal {
+                name: instance.remove("Name")?,
+                data_type: data_type.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Returns the attributes of each instance in a section of a configuration file such as
+/// `SYS.cfg`. An instance is a line of `-Attribute "value"` pairs, continued on the next line if
+/// it ends with `\`.
+fn config_instances(config: &str, section: &str) -> Vec<HashMap<String, String>> {
+    let mut instances: Vec<HashMap<String, String>> = Vec::new();
+    let mut in_section = false;
+    let mut continued = false;
+    for line in config.lines().map(str::trim) {
+        if line == "#" {
+            in_section = false;
+        } else if !continued && line.ends_with(':') && !line.starts_with('-') {
+            in_section = line.strip_suffix(':') == Some(section);
+        } else if in_section && !line.is_empty() {
+            let (line, continues) = match line.strip_suffix('\\') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if !continued {
+                instances.push(HashMap::new());
+            }
+            instances.last_mut().unwrap().extend(attributes(line));
+            continued = continues;
+            continue;
+        }
+        continued = false;
+    }
+    instances
+}
+
+/// Splits a line of a configuration instance into its `-Attribute "value"` pairs. Values without
+/// quotes end at the next space.
+fn attributes(mut line: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    while let Some(rest) = line.trim_start().strip_prefix('-') {
+        let (name, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let rest = rest.trim_start();
+        let (value, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => rest.split_once(' ').unwrap_or((rest, "")),
+        };
+        attributes.push((name.to_owned(), value.to_owned()));
+        line = rest;
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Diagnostic, LintConfig};
+
+    #[test]
+    fn read_task_names() {
+        let info =
+            ">>SYSTEM_ID:\nCell 4\n\n>>TASK1: (T_ROB1,,)\n>>TASK2: (T_ROB2,T_ROB1,)\n>>TASK3: ()\n";
+        assert_eq!(system_id(info), Some("Cell 4".to_owned()));
+        assert_eq!(
+            task_names(info),
+            vec![(1, "T_ROB1".to_owned()), (2, "T_ROB2".to_owned())]
+        );
+        assert_eq!(system_id(">>SYSTEM_ID:\n>>TASK1: (T_ROB1,,)"), None);
+
+        let config = "SYS:CFG_1.0:6:0::\n#\nCAB_TASKS:\n\n      -Name \"T_ROB1\" -Type \"NORMAL\"\n\n      -Name \"T_PLC\" \\\n      -Type \"STATIC\"\n#\nCAB_TASK_MODULES:\n\n      -File \"HOME:/Base.sys\" -Name \"Base\"\n";
+        assert_eq!(
+            cabinet_tasks(config),
+            vec![(1, "T_ROB1".to_owned()), (2, "T_PLC".to_owned())]
+        );
+    }
+
+    #[test]
+    fn read_io_signals() {
+        let config = "EIO:CFG_1.0:6:1::\n#\nEIO_DEVICE:\n\n      -Name \"d652\" -Network \"DeviceNet\"\n#\nEIO_SIGNAL:\n\n      -Name \"diStart\" -SignalType \"DI\" -Device \"d652\" \\\n      -DeviceMap \"0\"\n\n      -Name \"aoSpeed\" -SignalType \"AO\" -Label \"Conveyor speed\"\n\n      -Name \"goCode\" \\\n      -SignalType \"GO\" -DeviceMap \"8-15\"\n\n      -Name \"diMissing\"\n#\nEIO_CROSS:\n\n      -Name \"cross\" -Res \"doStart\" -Act1 \"diStart\"\n";
+        let signal = |name: &str, data_type: &str| Signal {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+        };
+        assert_eq!(
+            io_signals(config),
+            vec![
+                signal("diStart", "signaldi"),
+                signal("aoSpeed", "signalao"),
+                signal("goCode", "signalgo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn load_backup_directory() {
+        let backup = Backup::load(Path::new("../rapid-parser/data/BACKUP")).unwrap();
+        assert_eq!(backup.system_id.as_deref(), Some("SyntheticCell"));
+        let tasks: Vec<(&str, Vec<String>)> = backup
+            .system
+            .tasks
+            .iter()
+            .map(|task| {
+                let files = task
+                    .files
+                    .iter()
+                    .map(|file| {
+                        file.path
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .into_owned()
+                    })
+                    .collect();
+                (task.name.as_str(), files)
+            })
+            .collect();
+        assert_eq!(
+            tasks,
+            vec![
+                (
+                    "T_ROB1",
+                    vec![
+                        "user.sys".to_owned(),
+                        "Gripper.mod".to_owned(),
+                        "MainModule.mod".to_owned()
+                    ]
+                ),
+                (
+                    "T_LOGGER",
+                    vec!["user.sys".to_owned(), "Logger.mod".to_owned()]
+                ),
+            ]
+        );
+
+        let analysis = backup.system.analyze();
+        let shared: Vec<_> = analysis
+            .shared
+            .iter()
+            .map(|shared| {
+                let access = shared
+                    .tasks
+                    .iter()
+                    .map(|access| (access.task, access.reads, access.writes))
+                    .collect::<Vec<_>>();
+                (shared.name.as_str(), shared.data_type.as_str(), access)
+            })
+            .collect();
+        assert_eq!(
+            shared,
+            vec![
+                (
+                    "currentTool",
+                    "tooldata",
+                    vec![(0, true, false), (1, true, false)]
+                ),
+                (
+                    "cycleCount",
+                    "num",
+                    vec![(0, true, true), (1, false, false)]
+                ),
+                (
+                    "loggerRunning",
+                    "bool",
+                    vec![(0, true, false), (1, false, true)]
+                ),
+            ]
+        );
+
+        assert_eq!(
+            backup.system.tasks[0].signals,
+            vec![Signal {
+                name: "doGripper".to_owned(),
+                data_type: "signaldo".to_owned(),
+            }]
+        );
+        let diagnostics = backup.system.diagnose(&LintConfig::default());
+        let found: Vec<&Diagnostic> = diagnostics.iter().flatten().flatten().collect();
+        assert_eq!(found, Vec::<&Diagnostic>::new());
+    }
+
+    #[test]
+    fn reject_other_directories() {
+        let error = Backup::load(Path::new("../rapid-parser/data")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "../rapid-parser/data: not a controller backup, expected a RAPID directory"
+        );
+    }
+}
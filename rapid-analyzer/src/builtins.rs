@@ -0,0 +1,135 @@
+use std::sync::OnceLock;
+
+use rapid_parser::ast::{Module, ModuleInfo};
+use rapid_parser::parse_module;
+
+/// The RAPID source of the catalogue of data types, data and routines that the controller system
+/// provides. It is written in RAPID itself so that record components and routine signatures,
+/// including optional `\switch` parameters and access modes, use the same AST shapes as user code.
+pub const SOURCE: &str = include_str!("../data/builtins.sys");
+
+/// The placeholder type of built-in parameters that accept a value of any data type.
+pub const ANYTYPE: &str = "anytype";
+
+/// Returns the parsed built-in catalogue. It is parsed once on first use.
+pub fn module() -> &'static ModuleInfo {
+    static MODULE: OnceLock<ModuleInfo> = OnceLock::new();
+    MODULE.get_or_init(|| match parse_module(SOURCE).into_result() {
+        Ok(Module::Module(module)) => module,
+        _ => panic!("the built-in catalogue failed to parse"),
+    })
+}
+
+pub fn is_anytype(name: &str) -> bool {
+    name.eq_ignore_ascii_case(ANYTYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::Resolver;
+    use crate::test_util::messages;
+    use crate::{SymbolKind, SymbolTable, Type, TypeChecker};
+
+    #[test]
+    fn catalogue_resolves_cleanly() {
+        let mut table = SymbolTable::new();
+        let mut diagnostics = Vec::new();
+        let mut resolver = Resolver::new(&mut table, &mut diagnostics);
+        let scope = resolver.declare_builtins();
+        resolver.resolve_module(module(), scope);
+        assert_eq!(diagnostics, vec![]);
+
+        let move_l = table.lookup(scope, "movel").unwrap();
+        assert!(matches!(
+            table.symbol(move_l).kind,
+            SymbolKind::Procedure(_)
+        ));
+        assert!(table.is_builtin(move_l));
+        let mut diagnostics = Vec::new();
+        let checker = TypeChecker::new(&table, scope, &mut diagnostics);
+        let tool0 = table.lookup(scope, "tool0").unwrap();
+        let tooldata = table.lookup(scope, "tooldata").unwrap();
+        assert_eq!(checker.symbol_type(tool0), Some(Type::Record(tooldata)));
+        assert_eq!(checker.resolve_type_name("errnum", scope), Type::NUM);
+    }
+
+    #[test]
+    fn resolve_and_check_against_builtins() {
+        let input = r#"
+            MODULE mymodule
+                VAR robtarget target;
+                PERS tooldata gripper := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+                VAR socketdev socket;
+                PROC main()
+                    VAR pos offset;
+                    VAR num x;
+                    offset := target.trans + offset * 2;
+                    target.trans := -offset;
+                    target.rot := target.rot * target.rot;
+                    x := target.trans * target.rot;
+                    x := NumToStr(target.extax.eax_a, 2);
+                    target.robconf.cf1 := ERRNO;
+                    MoveL target, v100, fine, gripper \WObj:=wobj0;
+                    IF SocketGetStatus(socket) = SOCKET_CONNECTED THEN
+                        SocketClose socket;
+                    ENDIF
+                ENDPROC
+                PROC Offs()
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "shadowed-declaration: procedure 'Offs' shadows the built-in function",
+                "invalid-operands: operator '*' cannot be applied to pos and orient",
+                "type-mismatch: cannot assign string to num",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_program_using_system_routines() {
+        let input = r#"
+            MODULE Cell
+                VAR signaldi diPartFound;
+                VAR signaldo doClamp;
+                VAR signalao aoSpeed;
+                VAR signalgo goProgram;
+                VAR signalai aiPressure;
+                VAR intnum pressureInt;
+                VAR intnum errorInt;
+                PERS tooldata gripper := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+                PERS tooldata activeTool := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+                VAR robtarget searchStart;
+                VAR robtarget found;
+                PROC main()
+                    VAR num answer;
+                    VAR num count;
+                    VAR iodev logFile;
+                    CONNECT pressureInt WITH onPressure;
+                    ISignalAI \Single, aiPressure, AIO_ABOVE_HIGH, 6, 0, 0, pressureInt;
+                    CONNECT errorInt WITH onError;
+                    IError COMMON_ERR, TYPE_ERR, errorInt;
+                    TPReadFK answer, "Start the cycle?", "Yes", stEmpty, stEmpty, stEmpty, "No";
+                    IF answer = 5 ExitCycle;
+                    TPReadNum count, "Number of parts";
+                    GetSysData activeTool;
+                    SetAO aoSpeed, 2.5;
+                    SetGO goProgram, 3;
+                    SetDO doClamp, 1;
+                    WaitDO doClamp, 1 \MaxTime:=2;
+                    SearchL \Stop, diPartFound, found, searchStart, v100, gripper;
+                    Open diskhome \File:="cell.log", logFile \Append;
+                    Close logFile;
+                ENDPROC
+                TRAP onPressure
+                    StopMove;
+                ENDTRAP
+                TRAP onError
+                    Stop;
+                ENDTRAP
+            ENDMODULE"#;
+        assert_eq!(messages(input), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,387 @@
+use rapid_parser::ast::{
+    Argument, ArgumentKind, OptionalParameterDeclarationTypeKind, ParameterDeclaration,
+    ParameterDeclarationType, ParameterDeclarationTypeKind,
+};
+use rapid_parser::Span;
+
+use crate::diagnostics::{Diagnostic, Edit};
+
+/// One optional parameter. Switches have no declaration because they carry no value.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Alternative<'a> {
+    pub name: &'a str,
+    pub parameter: Option<&'a ParameterDeclaration>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Slot<'a> {
+    Required(&'a ParameterDeclaration),
+    /// A group of mutually exclusive optional parameters (`\a | b`). A single optional parameter
+    /// is a group with one alternative.
+    Optional(Vec<Alternative<'a>>),
+}
+
+/// The parameter list of a procedure or function, in declaration order.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Signature<'a> {
+    pub slots: Vec<Slot<'a>>,
+}
+
+impl<'a> Signature<'a> {
+    /// Builds the signature of a routine. Returns `None` if the parameter list contains
+    /// placeholders or syntax errors, since arguments cannot be checked against it then.
+    pub fn new(parameters: &'a [ParameterDeclarationType]) -> Option<Self> {
+        let mut slots = Vec::new();
+        for parameter in parameters {
+            match &parameter.kind {
+                ParameterDeclarationTypeKind::ParameterDeclaration(p) => {
+                    slots.push(Slot::Required(p))
+                }
+                ParameterDeclarationTypeKind::OptionalParameterDeclaration(alternatives) => {
+                    let mut group = Vec::new();
+                    for alternative in alternatives {
+                        group.push(match &alternative.kind {
+                            OptionalParameterDeclarationTypeKind::OptionalParameterDeclaration(
+                                p,
+                            ) => Alternative {
+                                name: &p.name,
+                                parameter: Some(p),
+                            },
+                            OptionalParameterDeclarationTypeKind::Switch(name) => Alternative {
+                                name,
+                                parameter: None,
+                            },
+                            OptionalParameterDeclarationTypeKind::ALT => return None,
+                        });
+                    }
+                    slots.push(Slot::Optional(group));
+                }
+                ParameterDeclarationTypeKind::PAR | ParameterDeclarationTypeKind::Error => {
+                    return None
+                }
+            }
+        }
+        Some(Signature { slots })
+    }
+
+    fn required(&self, name: &str) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| matches!(slot, Slot::Required(p) if p.name.eq_ignore_ascii_case(name)))
+    }
+
+    fn optional(&self, name: &str) -> Option<(usize, Alternative<'a>)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .find_map(|(index, slot)| match slot {
+                Slot::Optional(group) => group
+                    .iter()
+                    .find(|alternative| alternative.name.eq_ignore_ascii_case(name))
+                    .map(|alternative| (index, *alternative)),
+                Slot::Required(_) => None,
+            })
+    }
+
+    /// Matches the arguments of a call to the parameters of this signature, reporting missing,
+    /// unknown, repeated, conflicting and out of order arguments.
+    ///
+    /// # Arguments
+    ///
+    /// * `callee` - The name of the called routine, used in messages.
+    /// * `arguments` - The arguments of the call.
+    /// * `span` - The span of the whole call, used to report missing arguments.
+    /// * `diagnostics` - Receives the problems found.
+    ///
+    /// # Returns
+    ///
+    /// For each argument, the parameter declaration it is bound to, or `None` for switches and
+    /// arguments that could not be bound.
+    pub fn bind(
+        &self,
+        callee: &str,
+        arguments: &[Argument],
+        span: Span,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Vec<Option<&'a ParameterDeclaration>> {
+        let mut bound: Vec<Option<Span>> = vec![None; self.slots.len()];
+        let mut position = 0;
+        let mut bindings = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            let (index, parameter) = match &argument.kind {
+                ArgumentKind::Required(None, _) => {
+                    let next = self.slots.iter().enumerate().position(|(index, slot)| {
+                        matches!(slot, Slot::Required(_)) && bound[index].is_none()
+                    });
+                    let Some(index) = next else {
+                        diagnostics.push(Diagnostic::error(
+                            "too-many-arguments",
+                            format!("too many arguments in call to '{}'", callee),
+                            argument.span,
+                        ));
+                        bindings.push(None);
+                        continue;
+                    };
+                    let Slot::Required(parameter) = &self.slots[index] else {
+                        unreachable!()
+                    };
+                    (index, Some(*parameter))
+                }
+                ArgumentKind::Required(Some(name), _) => {
+                    if let Some(index) = self.required(name) {
+                        let Slot::Required(parameter) = &self.slots[index] else {
+                            unreachable!()
+                        };
+                        (index, Some(*parameter))
+                    } else if let Some((index, alternative)) = self.optional(name) {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                "invalid-argument",
+                                format!(
+                                    "optional parameter '{}' must be passed as '\\{}'",
+                                    alternative.name, alternative.name
+                                ),
+                                argument.span,
+                            )
+                            .with_fix(
+                                format!("pass as '\\{}'", name),
+                                vec![Edit {
+                                    span: Span::new(argument.span.start, argument.span.start),
+                                    replacement: "\\".to_owned(),
+                                }],
+                            ),
+                        );
+                        (index, alternative.parameter)
+                    } else {
+                        diagnostics.push(unknown_argument(callee, name, argument.span));
+                        bindings.push(None);
+                        continue;
+                    }
+                }
+                ArgumentKind::Optional(name, _) | ArgumentKind::Conditional(name, _) => {
+                    if let Some((index, alternative)) = self.optional(name) {
+                        // A conditional argument passes a switch on unchanged
+                        let has_value = matches!(argument.kind, ArgumentKind::Optional(_, Some(_)));
+                        let is_conditional = matches!(argument.kind, ArgumentKind::Conditional(..));
+                        match alternative.parameter {
+                            None if has_value => diagnostics.push(
+                                Diagnostic::error(
+                                    "invalid-argument",
+                                    format!("switch '{}' does not take a value", alternative.name),
+                                    argument.span,
+                                )
+                                .with_fix(
+                                    "remove the value",
+                                    vec![Edit {
+                                        span: argument.span,
+                                        replacement: format!("\\{}", name),
+                                    }],
+                                ),
+                            ),
+                            Some(_) if !has_value && !is_conditional => {
+                                diagnostics.push(Diagnostic::error(
+                                    "invalid-argument",
+                                    format!(
+                                        "optional argument '{}' requires a value",
+                                        alternative.name
+                                    ),
+                                    argument.span,
+                                ))
+                            }
+                            _ => {}
+                        }
+                        (index, alternative.parameter)
+                    } else if let Some(index) = self.required(name) {
+                        let Slot::Required(parameter) = &self.slots[index] else {
+                            unreachable!()
+                        };
+                        diagnostics.push(Diagnostic::error(
+                            "invalid-argument",
+                            format!(
+                                "parameter '{}' is required and cannot be passed with '\\'",
+                                parameter.name
+                            ),
+                            argument.span,
+                        ));
+                        (index, Some(*parameter))
+                    } else {
+                        diagnostics.push(unknown_argument(callee, name, argument.span));
+                        bindings.push(None);
+                        continue;
+                    }
+                }
+            };
+
+            if let Some(previous) = bound[index] {
+                let message = match &self.slots[index] {
+                    Slot::Optional(group) if group.len() > 1 => format!(
+                        "only one of {} can be passed",
+                        group
+                            .iter()
+                            .map(|alternative| format!("'\\{}'", alternative.name))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    _ => "argument is passed more than once".to_owned(),
+                };
+                diagnostics.push(
+                    Diagnostic::error("conflicting-arguments", message, argument.span)
+                        .with_related("previous argument", previous),
+                );
+            } else if index < position {
+                diagnostics.push(Diagnostic::error(
+                    "argument-order",
+                    "argument is out of order with respect to the parameter list",
+                    argument.span,
+                ));
+            }
+            bound[index] = Some(argument.span);
+            position = position.max(index + 1);
+            bindings.push(parameter);
+        }
+
+        let missing: Vec<&str> = self
+            .slots
+            .iter()
+            .zip(&bound)
+            .filter_map(|(slot, bound)| match slot {
+                Slot::Required(parameter) if bound.is_none() => Some(parameter.name.as_str()),
+                _ => None,
+            })
+            .collect();
+        if !missing.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                "missing-argument",
+                format!(
+                    "missing argument{} {} in call to '{}'",
+                    if missing.len() == 1 { "" } else { "s" },
+                    missing
+                        .iter()
+                        .map(|name| format!("'{}'", name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    callee
+                ),
+                span,
+            ));
+        }
+        bindings
+    }
+}
+
+fn unknown_argument(callee: &str, name: &str, span: Span) -> Diagnostic {
+    Diagnostic::error(
+        "unknown-argument",
+        format!("'{}' has no parameter named '{}'", callee, name),
+        span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::messages;
+
+    #[test]
+    fn check_argument_count_and_names() {
+        let input = r#"
+            MODULE mymodule
+                PROC move(num x, num y, \num speed, \switch fast | switch slow)
+                ENDPROC
+                FUNC num twice(num value)
+                    RETURN value * 2;
+                ENDFUNC
+                PROC main()
+                    move 1, 2;
+                    move y:=2, x:=1;
+                    move 1, 2, 3;
+                    move 1;
+                    move 1, 2 \distance:=3;
+                    move 1, 2 \speed:=twice(1) \fast;
+                    move 1, 2 \fast \slow;
+                    move 1, 2 \fast:=1;
+                    move 1, 2 \speed;
+                    move 1, 2, speed:=3;
+                    move \x:=1, 2;
+                    move \fast, 1, 2;
+                    move 1, "2";
+                    main;
+                    twice 1;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "argument-order: argument is out of order with respect to the parameter list",
+                "too-many-arguments: too many arguments in call to 'move'",
+                "missing-argument: missing argument 'y' in call to 'move'",
+                "unknown-argument: 'move' has no parameter named 'distance'",
+                "conflicting-arguments: only one of '\\fast', '\\slow' can be passed",
+                "invalid-argument: switch 'fast' does not take a value",
+                "invalid-argument: optional argument 'speed' requires a value",
+                "invalid-argument: optional parameter 'speed' must be passed as '\\speed'",
+                "invalid-argument: parameter 'x' is required and cannot be passed with '\\'",
+                "argument-order: argument is out of order with respect to the parameter list",
+                "argument-order: argument is out of order with respect to the parameter list",
+                "type-mismatch: argument 'y' must be num, found string",
+                "not-callable: function 'twice' is not a procedure",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_conditional_arguments() {
+        let input = r#"
+            MODULE mymodule
+                PROC inner(\num speed, \switch fast)
+                ENDPROC
+                PROC outer(\num speed, \switch fast)
+                    inner \speed?speed \fast?fast;
+                    inner \slow?fast;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec!["unknown-argument: 'inner' has no parameter named 'slow'"]
+        );
+    }
+
+    #[test]
+    fn check_access_modes() {
+        let input = r#"
+            MODULE mymodule
+                VAR num counter;
+                PERS num total := 0;
+                CONST num limit := 10;
+                VAR socketdev socket;
+                PROC update(VAR num v, PERS num p, INOUT num io)
+                ENDPROC
+                PROC forward(INOUT num io, PERS num p)
+                    update io, p, io;
+                    update p, io, limit;
+                ENDPROC
+                PROC main()
+                    VAR bool ok;
+                    update counter, total, counter;
+                    update total, counter, total;
+                    update counter + 1, total, 1;
+                    ok := StrToVal("1", counter);
+                    SocketCreate socket;
+                    FOR i FROM 1 TO 2 DO
+                        update i, total, counter;
+                    ENDFOR
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "not-assignable: argument for VAR parameter 'v' must be a variable",
+                "not-assignable: argument for INOUT parameter 'io' must be a variable or persistent",
+                "not-assignable: argument for VAR parameter 'v' must be a variable",
+                "not-assignable: argument for PERS parameter 'p' must be a persistent",
+                "not-assignable: argument for VAR parameter 'v' must be a variable",
+                "not-assignable: argument for INOUT parameter 'io' must be a variable or persistent",
+                "not-assignable: argument for VAR parameter 'v' must be a variable",
+            ]
+        );
+    }
+}
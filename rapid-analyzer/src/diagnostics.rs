@@ -0,0 +1,217 @@
+use std::fmt::Display;
+
+use rapid_parser::{ParseError, Span, UserError};
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+/// A secondary location that helps to explain a diagnostic, such as the earlier declaration of a
+/// duplicated name.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RelatedInformation {
+    pub message: String,
+    pub span: Span,
+}
+
+/// A replacement of the source text in `span`. An empty span inserts the text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Edit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// A rewrite of the source that resolves a diagnostic. Fixes are only attached where the rewrite
+/// keeps the intent of the code, so they can be applied without review.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Fix {
+    pub message: String,
+    pub edits: Vec<Edit>,
+}
+
+/// A problem found during semantic analysis.
+///
+/// `code` is a stable, kebab-case identifier (e.g. `undeclared-identifier`) that tools can use to
+/// filter or configure diagnostics independently of the message text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+    pub related: Vec<RelatedInformation>,
+    pub fixes: Vec<Fix>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            related: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, span)
+        }
+    }
+
+    /// Converts a syntax error from `parse_module` or an incremental parse. Every syntax error has
+    /// the code `syntax-error`.
+    pub fn syntax_error<T: Display>(error: &ParseError<usize, T, UserError>) -> Self {
+        let (message, span) = match error {
+            ParseError::UnrecognizedEof { location, expected } => (
+                format!("unexpected end of file{}", expected_tokens(expected)),
+                Span::new(*location, *location),
+            ),
+            ParseError::UnrecognizedToken { token, expected } => (
+                format!(
+                    "unexpected token '{}'{}",
+                    token.1,
+                    expected_tokens(expected)
+                ),
+                Span::new(token.0, token.2),
+            ),
+            ParseError::InvalidToken { location } => {
+                ("invalid token".to_owned(), Span::new(*location, *location))
+            }
+            ParseError::ExtraToken { token } => (
+                format!("unexpected token '{}' after the module", token.1),
+                Span::new(token.0, token.2),
+            ),
+            ParseError::User { error } => (error.message.to_owned(), error.span),
+        };
+        Diagnostic::error("syntax-error", message, span)
+    }
+
+    pub fn with_related(mut self, message: impl Into<String>, span: Span) -> Self {
+        self.related.push(RelatedInformation {
+            message: message.into(),
+            span,
+        });
+        self
+    }
+
+    pub fn with_fix(mut self, message: impl Into<String>, edits: Vec<Edit>) -> Self {
+        self.fixes.push(Fix {
+            message: message.into(),
+            edits,
+        });
+        self
+    }
+
+    /// Moves every span of the diagnostic, its related information and its fixes with `shift`,
+    /// after an edit of the source it was reported for.
+    pub(crate) fn shift(&mut self, shift: &impl Fn(Span) -> Span) {
+        self.span = shift(self.span);
+        for related in &mut self.related {
+            related.span = shift(related.span);
+        }
+        for edit in self.fixes.iter_mut().flat_map(|fix| &mut fix.edits) {
+            edit.span = shift(edit.span);
+        }
+    }
+}
+
+/// Lists the tokens a parser expected after a syntax error, or nothing if it did not say.
+fn expected_tokens(expected: &[String]) -> String {
+    if expected.is_empty() {
+        String::new()
+    } else {
+        format!(", expected one of: {}", expected.join(", "))
+    }
+}
+
+/// Applies the first fix of each diagnostic to `source`. A fix whose edits overlap those of a fix
+/// applied before is skipped; running the analysis again on the result reports it again.
+///
+/// # Returns
+///
+/// The fixed source and the number of fixes applied.
+pub fn apply_fixes(source: &str, diagnostics: &[Diagnostic]) -> (String, usize) {
+    let mut edits: Vec<&Edit> = Vec::new();
+    let mut applied = 0;
+    for fix in diagnostics.iter().filter_map(|d| d.fixes.first()) {
+        let overlaps = fix.edits.iter().any(|edit| {
+            edits.iter().any(|other| {
+                edit.span.start < other.span.end && other.span.start < edit.span.end
+                    || edit.span == other.span
+            })
+        });
+        if !overlaps {
+            edits.extend(&fix.edits);
+            applied += 1;
+        }
+    }
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.span.start));
+    let mut fixed = source.to_owned();
+    for edit in edits {
+        fixed.replace_range(edit.span.start..edit.span.end, &edit.replacement);
+    }
+    (fixed, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_syntax_errors() {
+        let error = |expected: &[&str]| -> ParseError<usize, String, UserError> {
+            ParseError::UnrecognizedToken {
+                token: (4, "<".to_owned(), 5),
+                expected: expected.iter().map(|token| token.to_string()).collect(),
+            }
+        };
+        assert_eq!(
+            Diagnostic::syntax_error(&error(&["\";\"", "\",\""])).message,
+            "unexpected token '<', expected one of: \";\", \",\""
+        );
+        assert_eq!(
+            Diagnostic::syntax_error(&error(&[])).message,
+            "unexpected token '<'"
+        );
+        let eof = ParseError::<usize, String, UserError>::UnrecognizedEof {
+            location: 9,
+            expected: Vec::new(),
+        };
+        assert_eq!(
+            Diagnostic::syntax_error(&eof).message,
+            "unexpected end of file"
+        );
+    }
+
+    #[test]
+    fn apply_non_overlapping_fixes() {
+        let source = "LOCAL VAR num x := 1;";
+        let edit = |start, end, replacement: &str| Edit {
+            span: Span::new(start, end),
+            replacement: replacement.to_owned(),
+        };
+        let diagnostics = vec![
+            Diagnostic::error("a", "a", Span::default())
+                .with_fix("drop LOCAL", vec![edit(0, 6, "")]),
+            Diagnostic::error("b", "b", Span::default())
+                .with_fix("rename", vec![edit(4, 9, "PERS")]),
+            Diagnostic::error("c", "c", Span::default()).with_fix(
+                "rename and retype",
+                vec![edit(14, 15, "y"), edit(19, 20, "2")],
+            ),
+            Diagnostic::error("d", "d", Span::default()),
+        ];
+        assert_eq!(
+            apply_fixes(source, &diagnostics),
+            ("VAR num y := 2;".to_owned(), 2)
+        );
+    }
+}
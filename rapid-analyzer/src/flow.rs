@@ -0,0 +1,773 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+
+use rapid_parser::ast::{
+    ErrorHandler, ExprKind, RoutineDeclaration, Statement, StatementKind, Term, TestCaseKind,
+};
+use rapid_parser::Span;
+
+pub type BlockId = usize;
+
+/// The part of a routine a block belongs to.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Region {
+    Body,
+    /// The `BACKWARD` handler, run when a procedure is stepped backwards.
+    Backward,
+    /// The `ERROR` handler, entered when an instruction of the body raises an error.
+    Error,
+    /// The `UNDO` handler, run when the routine is abandoned, such as when the program pointer is
+    /// moved out of it.
+    Undo,
+}
+
+impl Region {
+    /// The keyword of the handler, or `body`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Body => "body",
+            Region::Backward => "BACKWARD",
+            Region::Error => "ERROR",
+            Region::Undo => "UNDO",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BlockKind {
+    /// Statements that run one after the other.
+    Code(Region),
+    /// The routine returns to its caller.
+    Return,
+    /// The routine propagates an error to its caller.
+    Raise,
+    /// `EXIT` stops the program.
+    Stop,
+}
+
+/// A basic block: straight-line statements followed by a jump to one or more successors.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Block<'a> {
+    pub kind: BlockKind,
+    /// The statements of the block, without labels, comments and declarations. Compound statements
+    /// are split into blocks and do not appear here.
+    pub statements: Vec<&'a Statement>,
+    /// The code that selects the successor, such as `WHILE x > 0`, the condition of an `ELSEIF`
+    /// or `TEST state`.
+    pub branch: Option<Span>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum EdgeKind {
+    /// Execution continues with the next block.
+    Next,
+    /// The condition of an `IF`, `ELSEIF` or `WHILE` holds, or a `FOR` loop runs once more.
+    True,
+    /// The condition does not hold, or a `FOR` loop is done. `WHILE TRUE` has no such edge.
+    False,
+    /// A `CASE` of a `TEST` matches.
+    Case,
+    /// No `CASE` of a `TEST` matches.
+    Default,
+    Goto,
+    Return,
+    /// Execution reaches the end of the routine or one of its handlers.
+    End,
+    Exit,
+    Raise,
+    /// An instruction of the block raises an error, which the error handler catches.
+    Error,
+    /// `RETRY` runs the instruction that raised the error again.
+    Retry,
+    /// `TRYNEXT` continues after the instruction that raised the error.
+    TryNext,
+}
+
+impl EdgeKind {
+    /// The lowercase name of the kind, as used in graph exports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::True => "true",
+            EdgeKind::False => "false",
+            EdgeKind::Case => "case",
+            EdgeKind::Default => "default",
+            EdgeKind::Goto => "goto",
+            EdgeKind::Return => "return",
+            EdgeKind::End => "end",
+            EdgeKind::Exit => "exit",
+            EdgeKind::Raise => "raise",
+            EdgeKind::Error => "error",
+            EdgeKind::Retry => "retry",
+            EdgeKind::TryNext => "trynext",
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Edge {
+    pub from: BlockId,
+    pub to: BlockId,
+    pub kind: EdgeKind,
+}
+
+/// The control-flow graph of a routine, with its handlers.
+///
+/// Every instruction of the body may raise an error, so each block of the body holding one has an
+/// `Error` edge to the error handler. Since the graph does not know which instruction failed,
+/// `RETRY` and `TRYNEXT` lead back to every one of these blocks.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ControlFlowGraph<'a> {
+    pub blocks: Vec<Block<'a>>,
+    pub edges: Vec<Edge>,
+    /// The first block of the body.
+    pub entry: BlockId,
+    pub return_block: BlockId,
+    pub raise_block: BlockId,
+    pub stop_block: BlockId,
+    /// The first blocks of the handlers the routine has.
+    pub backward: Option<BlockId>,
+    pub error: Option<BlockId>,
+    pub undo: Option<BlockId>,
+}
+
+impl<'a> ControlFlowGraph<'a> {
+    /// Builds the graph of a procedure, function or trap. Returns `None` for placeholders and
+    /// routines that could not be parsed.
+    pub fn new(routine: &'a RoutineDeclaration) -> Option<Self> {
+        let (statements, backward, error, undo) = match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => (
+                &proc.statements,
+                proc.backward_handler.as_deref(),
+                proc.error_handler.as_ref(),
+                proc.undo_handler.as_deref(),
+            ),
+            RoutineDeclaration::FuncDeclaration(func) => (
+                &func.statements,
+                None,
+                func.error_handler.as_ref(),
+                func.undo_handler.as_deref(),
+            ),
+            RoutineDeclaration::TrapDeclaration(trap) => (
+                &trap.statements,
+                None,
+                trap.error_handler.as_ref(),
+                trap.undo_handler.as_deref(),
+            ),
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => return None,
+        };
+        Some(Self::build(statements, backward, error, undo))
+    }
+
+    /// Builds the graph of a routine body with the given handlers.
+    pub fn build(
+        statements: &'a [Statement],
+        backward: Option<&'a [Statement]>,
+        error: Option<&'a ErrorHandler>,
+        undo: Option<&'a [Statement]>,
+    ) -> Self {
+        let mut builder = Builder::new();
+        builder.region(Region::Body, builder.graph.entry, statements);
+        if let Some(handler) = error {
+            let entry = builder.block(BlockKind::Code(Region::Error));
+            builder.graph.error = Some(entry);
+            builder.region(Region::Error, entry, &handler.statements);
+        }
+        if let Some(statements) = backward {
+            let entry = builder.block(BlockKind::Code(Region::Backward));
+            builder.graph.backward = Some(entry);
+            builder.region(Region::Backward, entry, statements);
+        }
+        if let Some(statements) = undo {
+            let entry = builder.block(BlockKind::Code(Region::Undo));
+            builder.graph.undo = Some(entry);
+            builder.region(Region::Undo, entry, statements);
+        }
+        builder.finish()
+    }
+
+    /// Returns the edges leaving `block`.
+    pub fn successors(&self, block: BlockId) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.from == block)
+    }
+
+    /// Returns the edges entering `block`.
+    pub fn predecessors(&self, block: BlockId) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(move |edge| edge.to == block)
+    }
+
+    /// Returns for each block whether execution can reach it from the start of the body or of the
+    /// `BACKWARD` or `UNDO` handler.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut queue: VecDeque<BlockId> = [Some(self.entry), self.backward, self.undo]
+            .into_iter()
+            .flatten()
+            .collect();
+        while let Some(block) = queue.pop_front() {
+            if std::mem::replace(&mut reachable[block], true) {
+                continue;
+            }
+            queue.extend(self.successors(block).map(|edge| edge.to));
+        }
+        reachable
+    }
+
+    /// Exports the graph in the Graphviz DOT format. Each block is labelled with the first line of
+    /// its statements, taken from `source`, the source the routine was parsed from.
+    pub fn to_dot(&self, name: &str, source: &str) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph {} {{", quote(name)).unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for (id, block) in self.blocks.iter().enumerate() {
+            let (label, shape) = match block.kind {
+                BlockKind::Code(region) => {
+                    let mut label = format!("B{}", id);
+                    if [Some(self.entry), self.backward, self.error, self.undo].contains(&Some(id))
+                    {
+                        write!(label, " ({})", region.name()).unwrap();
+                    }
+                    let lines = block
+                        .statements
+                        .iter()
+                        .map(|statement| statement.span)
+                        .chain(block.branch);
+                    for span in lines {
+                        let text = source.get(span.start..span.end).unwrap_or_default();
+                        label.push('\n');
+                        label.push_str(text.lines().next().unwrap_or_default().trim());
+                    }
+                    (label, "box")
+                }
+                BlockKind::Return => ("return".to_owned(), "oval"),
+                BlockKind::Raise => ("raise".to_owned(), "oval"),
+                BlockKind::Stop => ("stop".to_owned(), "oval"),
+            };
+            writeln!(
+                dot,
+                "    b{} [label={}, shape={}];",
+                id,
+                quote(&label),
+                shape
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            write!(dot, "    b{} -> b{}", edge.from, edge.to).unwrap();
+            if edge.kind != EdgeKind::Next {
+                write!(dot, " [label={}]", quote(edge.kind.name())).unwrap();
+            }
+            writeln!(dot, ";").unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Quotes a DOT identifier, with left-justified lines.
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\n' => quoted.push_str("\\l"),
+            _ => quoted.push(c),
+        }
+    }
+    if text.contains('\n') {
+        quoted.push_str("\\l");
+    }
+    quoted.push('"');
+    quoted
+}
+
+struct Builder<'a> {
+    graph: ControlFlowGraph<'a>,
+    region: Region,
+    /// The block that receives the next statement, or `None` after a jump, when the next statement
+    /// starts a block nothing leads to.
+    current: Option<BlockId>,
+    labels: Vec<(&'a str, BlockId)>,
+    gotos: Vec<(BlockId, &'a str)>,
+    /// The blocks ending in `RAISE` in the body.
+    raises: Vec<BlockId>,
+    /// The blocks ending in `RETRY` or `TRYNEXT` in the error handler.
+    resumes: Vec<(BlockId, EdgeKind)>,
+}
+
+impl<'a> Builder<'a> {
+    fn new() -> Self {
+        let mut builder = Builder {
+            graph: ControlFlowGraph {
+                blocks: Vec::new(),
+                edges: Vec::new(),
+                entry: 0,
+                return_block: 0,
+                raise_block: 0,
+                stop_block: 0,
+                backward: None,
+                error: None,
+                undo: None,
+            },
+            region: Region::Body,
+            current: None,
+            labels: Vec::new(),
+            gotos: Vec::new(),
+            raises: Vec::new(),
+            resumes: Vec::new(),
+        };
+        builder.graph.entry = builder.block(BlockKind::Code(Region::Body));
+        builder.graph.return_block = builder.block(BlockKind::Return);
+        builder.graph.raise_block = builder.block(BlockKind::Raise);
+        builder.graph.stop_block = builder.block(BlockKind::Stop);
+        builder
+    }
+
+    fn block(&mut self, kind: BlockKind) -> BlockId {
+        self.graph.blocks.push(Block {
+            kind,
+            statements: Vec::new(),
+            branch: None,
+        });
+        self.graph.blocks.len() - 1
+    }
+
+    fn code_block(&mut self) -> BlockId {
+        self.block(BlockKind::Code(self.region))
+    }
+
+    fn edge(&mut self, from: BlockId, to: BlockId, kind: EdgeKind) {
+        self.graph.edges.push(Edge { from, to, kind });
+    }
+
+    /// Returns the block that receives the next statement, starting one if there is none.
+    fn current(&mut self) -> BlockId {
+        match self.current {
+            Some(block) => block,
+            None => {
+                let block = self.code_block();
+                self.current = Some(block);
+                block
+            }
+        }
+    }
+
+    /// Starts a new block that the current one, if any, continues into.
+    fn follow(&mut self) -> BlockId {
+        let block = self.code_block();
+        if let Some(current) = self.current {
+            self.edge(current, block, EdgeKind::Next);
+        }
+        self.current = Some(block);
+        block
+    }
+
+    /// Adds the statements of a region starting at `entry`, whose end returns from the routine.
+    fn region(&mut self, region: Region, entry: BlockId, statements: &'a [Statement]) {
+        self.region = region;
+        self.current = Some(entry);
+        self.statements(statements);
+        if let Some(current) = self.current.take() {
+            self.edge(current, self.graph.return_block, EdgeKind::End);
+        }
+    }
+
+    fn statements(&mut self, statements: &'a [Statement]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Ends the current block with `statement`, which jumps to `to` if it is known.
+    fn jump(&mut self, statement: &'a Statement, to: Option<BlockId>, kind: EdgeKind) -> BlockId {
+        let block = self.current();
+        self.graph.blocks[block].statements.push(statement);
+        if let Some(to) = to {
+            self.edge(block, to, kind);
+        }
+        self.current = None;
+        block
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        match &statement.kind {
+            StatementKind::TypeDefinition(_)
+            | StatementKind::DataDeclaration(_)
+            | StatementKind::RoutineDeclaration(_)
+            | StatementKind::Comment(_)
+            | StatementKind::SMT => {}
+            StatementKind::Label(name) => {
+                // A label on an empty block, such as the one after an IF, marks that block
+                let block = match self.current {
+                    Some(block)
+                        if self.graph.blocks[block].statements.is_empty()
+                            && self.graph.blocks[block].branch.is_none() =>
+                    {
+                        block
+                    }
+                    _ => self.follow(),
+                };
+                self.labels.push((name, block));
+            }
+            StatementKind::Assignment(..)
+            | StatementKind::ProcCall(..)
+            | StatementKind::Connect(..)
+            | StatementKind::Error => {
+                let block = self.current();
+                self.graph.blocks[block].statements.push(statement);
+            }
+            StatementKind::Goto(label) => {
+                let block = self.jump(statement, None, EdgeKind::Goto);
+                self.gotos.push((block, &label.name));
+            }
+            StatementKind::Return(_) => {
+                self.jump(statement, Some(self.graph.return_block), EdgeKind::Return);
+            }
+            StatementKind::Exit => {
+                self.jump(statement, Some(self.graph.stop_block), EdgeKind::Exit);
+            }
+            StatementKind::Raise(_) => {
+                // The error handler catches the errors raised in the body, once it exists
+                if self.region == Region::Body {
+                    let block = self.jump(statement, None, EdgeKind::Raise);
+                    self.raises.push(block);
+                } else {
+                    self.jump(statement, Some(self.graph.raise_block), EdgeKind::Raise);
+                }
+            }
+            StatementKind::Retry | StatementKind::TryNext => {
+                let kind = match statement.kind {
+                    StatementKind::Retry => EdgeKind::Retry,
+                    _ => EdgeKind::TryNext,
+                };
+                let block = self.jump(statement, None, kind);
+                if self.region == Region::Error {
+                    self.resumes.push((block, kind));
+                }
+            }
+            StatementKind::If(condition, statements, else_ifs, else_statements) => {
+                let mut header = self.current();
+                self.graph.blocks[header].branch =
+                    Some(Span::new(statement.span.start, condition.span.end));
+                let mut ends = Vec::new();
+                self.branch(header, EdgeKind::True, statements, &mut ends);
+                for (condition, statements) in else_ifs {
+                    let next = self.code_block();
+                    self.graph.blocks[next].branch = Some(condition.span);
+                    self.edge(header, next, EdgeKind::False);
+                    header = next;
+                    self.branch(header, EdgeKind::True, statements, &mut ends);
+                }
+                if else_statements.is_empty() {
+                    ends.push((header, EdgeKind::False));
+                } else {
+                    self.branch(header, EdgeKind::False, else_statements, &mut ends);
+                }
+                self.join(ends);
+            }
+            StatementKind::While(condition, statements) => {
+                let header = Span::new(statement.span.start, condition.span.end);
+                let forever = matches!(condition.kind, ExprKind::Term(Term::Bool(true)));
+                self.repeat(header, statements, forever);
+            }
+            StatementKind::For(_, _, to, step, statements) => {
+                let end = step.as_ref().unwrap_or(to).span.end;
+                self.repeat(Span::new(statement.span.start, end), statements, false);
+            }
+            StatementKind::Test(expr, cases, default) => {
+                let header = self.current();
+                self.graph.blocks[header].branch =
+                    Some(Span::new(statement.span.start, expr.span.end));
+                let mut ends = Vec::new();
+                for case in cases {
+                    if let TestCaseKind::Case(_, statements) = &case.kind {
+                        self.branch(header, EdgeKind::Case, statements, &mut ends);
+                    }
+                }
+                match default {
+                    Some(statements) => {
+                        self.branch(header, EdgeKind::Default, statements, &mut ends)
+                    }
+                    None => ends.push((header, EdgeKind::Default)),
+                }
+                self.join(ends);
+            }
+        }
+    }
+
+    /// Adds the statements of one branch of `header`, recording the block it ends in unless it
+    /// jumps away.
+    fn branch(
+        &mut self,
+        header: BlockId,
+        kind: EdgeKind,
+        statements: &'a [Statement],
+        ends: &mut Vec<(BlockId, EdgeKind)>,
+    ) {
+        let entry = self.code_block();
+        self.edge(header, entry, kind);
+        self.current = Some(entry);
+        self.statements(statements);
+        ends.extend(self.current.take().map(|end| (end, EdgeKind::Next)));
+    }
+
+    /// Continues after a compound statement in a block that the `ends` of its branches lead to.
+    fn join(&mut self, ends: Vec<(BlockId, EdgeKind)>) {
+        if ends.is_empty() {
+            self.current = None;
+            return;
+        }
+        let join = self.code_block();
+        for (end, kind) in ends {
+            self.edge(end, join, kind);
+        }
+        self.current = Some(join);
+    }
+
+    /// Adds a `WHILE` or `FOR` loop whose test is `header`. A loop that runs `forever`, such as
+    /// `WHILE TRUE`, is only left by jumping out of it.
+    fn repeat(&mut self, header: Span, statements: &'a [Statement], forever: bool) {
+        let test = self.follow();
+        self.graph.blocks[test].branch = Some(header);
+        let body = self.code_block();
+        self.edge(test, body, EdgeKind::True);
+        self.current = Some(body);
+        self.statements(statements);
+        if let Some(end) = self.current {
+            self.edge(end, test, EdgeKind::Next);
+        }
+        let after = self.code_block();
+        if !forever {
+            self.edge(test, after, EdgeKind::False);
+        }
+        self.current = Some(after);
+    }
+
+    /// Resolves the `GOTO` statements and connects the body to the error handler.
+    fn finish(mut self) -> ControlFlowGraph<'a> {
+        for (block, name) in std::mem::take(&mut self.gotos) {
+            let target = self
+                .labels
+                .iter()
+                .find(|(label, _)| label.eq_ignore_ascii_case(name));
+            if let Some((_, target)) = target {
+                self.edge(block, *target, EdgeKind::Goto);
+            }
+        }
+        let Some(error) = self.graph.error else {
+            for block in std::mem::take(&mut self.raises) {
+                self.edge(block, self.graph.raise_block, EdgeKind::Raise);
+            }
+            return self.graph;
+        };
+        for block in std::mem::take(&mut self.raises) {
+            self.edge(block, error, EdgeKind::Raise);
+        }
+        // Any instruction of the body may fail, including the conditions of compound statements
+        for id in 0..self.graph.blocks.len() {
+            let block = &self.graph.blocks[id];
+            let fails = block.kind == BlockKind::Code(Region::Body)
+                && (block.statements.iter().any(|statement| may_fail(statement))
+                    || block.branch.is_some());
+            if fails {
+                self.edge(id, error, EdgeKind::Error);
+            }
+        }
+        let failing: Vec<BlockId> = self
+            .graph
+            .predecessors(error)
+            .map(|edge| edge.from)
+            .collect();
+        for (block, kind) in std::mem::take(&mut self.resumes) {
+            for &target in &failing {
+                self.edge(block, target, kind);
+            }
+        }
+        self.graph
+    }
+}
+
+/// Returns whether a statement may raise an error other than with `RAISE`.
+fn may_fail(statement: &Statement) -> bool {
+    !matches!(
+        statement.kind,
+        StatementKind::Goto(_)
+            | StatementKind::Return(None)
+            | StatementKind::Raise(_)
+            | StatementKind::Exit
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapid_parser::ast::Module;
+    use rapid_parser::parse_module;
+
+    fn routine(module: &Module) -> &RoutineDeclaration {
+        let Module::Module(module) = module else {
+            panic!("expected a module");
+        };
+        let StatementKind::RoutineDeclaration(routine) = &module.statements[0].kind else {
+            panic!("expected a routine");
+        };
+        routine
+    }
+
+    /// Describes the edges of a graph, naming each block after its first statement or branch.
+    fn edges(graph: &ControlFlowGraph, input: &str) -> Vec<String> {
+        let name = |id: BlockId| {
+            let block = &graph.blocks[id];
+            let span = block
+                .statements
+                .first()
+                .map(|statement| statement.span)
+                .or(block.branch);
+            match (span, block.kind) {
+                (Some(span), _) => input[span.start..span.end]
+                    .lines()
+                    .next()
+                    .unwrap()
+                    .to_owned(),
+                (None, BlockKind::Code(region)) => format!("B{} {}", id, region.name()),
+                (None, BlockKind::Return) => "return".to_owned(),
+                (None, BlockKind::Raise) => "raise".to_owned(),
+                (None, BlockKind::Stop) => "stop".to_owned(),
+            }
+        };
+        graph
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{} -{}-> {}",
+                    name(edge.from),
+                    edge.kind.name(),
+                    name(edge.to)
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_routine_graph() {
+        let input = r#"MODULE m
+    PROC run(num n)
+        VAR num i;
+        IF n > 0 THEN
+            i := 1;
+        ELSEIF n < 0 THEN
+            RETURN;
+        ENDIF
+        WHILE i < 10 DO
+            Incr i;
+        ENDWHILE
+        again:
+        TEST i
+        CASE 1:
+            GOTO again;
+        DEFAULT:
+            RAISE;
+        ENDTEST
+        EXIT;
+    ERROR
+        IF ERRNO = ERR_DIVZERO THEN
+            RETRY;
+        ENDIF
+        TRYNEXT;
+    UNDO
+        i := 0;
+    ENDPROC
+ENDMODULE"#;
+        let module = parse_module(input).into_result().unwrap();
+        let graph = ControlFlowGraph::new(routine(&module)).unwrap();
+        assert_eq!(
+            edges(&graph, input),
+            vec![
+                "IF n > 0 -true-> i := 1;",
+                "IF n > 0 -false-> n < 0",
+                "n < 0 -true-> RETURN;",
+                "RETURN; -return-> return",
+                "i := 1; -next-> B7 body",
+                "n < 0 -false-> B7 body",
+                "B7 body -next-> WHILE i < 10",
+                "WHILE i < 10 -true-> Incr i;",
+                "Incr i; -next-> WHILE i < 10",
+                "WHILE i < 10 -false-> TEST i",
+                "TEST i -case-> GOTO again;",
+                "TEST i -default-> RAISE;",
+                "EXIT; -exit-> stop",
+                "IF ERRNO = ERR_DIVZERO -true-> RETRY;",
+                "IF ERRNO = ERR_DIVZERO -false-> TRYNEXT;",
+                "i := 0; -end-> return",
+                "GOTO again; -goto-> TEST i",
+                "RAISE; -raise-> IF ERRNO = ERR_DIVZERO",
+                "IF n > 0 -error-> IF ERRNO = ERR_DIVZERO",
+                "i := 1; -error-> IF ERRNO = ERR_DIVZERO",
+                "n < 0 -error-> IF ERRNO = ERR_DIVZERO",
+                "WHILE i < 10 -error-> IF ERRNO = ERR_DIVZERO",
+                "Incr i; -error-> IF ERRNO = ERR_DIVZERO",
+                "TEST i -error-> IF ERRNO = ERR_DIVZERO",
+                "RETRY; -retry-> RAISE;",
+                "RETRY; -retry-> IF n > 0",
+                "RETRY; -retry-> i := 1;",
+                "RETRY; -retry-> n < 0",
+                "RETRY; -retry-> WHILE i < 10",
+                "RETRY; -retry-> Incr i;",
+                "RETRY; -retry-> TEST i",
+                "TRYNEXT; -trynext-> RAISE;",
+                "TRYNEXT; -trynext-> IF n > 0",
+                "TRYNEXT; -trynext-> i := 1;",
+                "TRYNEXT; -trynext-> n < 0",
+                "TRYNEXT; -trynext-> WHILE i < 10",
+                "TRYNEXT; -trynext-> Incr i;",
+                "TRYNEXT; -trynext-> TEST i",
+            ]
+        );
+
+        // Every branch of the TEST jumps away, so nothing leads to the EXIT
+        let reachable = graph.reachable();
+        let unreachable: Vec<&str> = graph
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(id, _)| !reachable[*id])
+            .flat_map(|(_, block)| &block.statements)
+            .map(|statement| &input[statement.span.start..statement.span.end])
+            .collect();
+        assert_eq!(unreachable, vec!["EXIT;"]);
+        assert!(reachable[graph.undo.unwrap()] && !reachable[graph.stop_block]);
+    }
+
+    #[test]
+    fn export_graph_to_dot() {
+        let input = r#"MODULE m
+    FUNC num sign(num x)
+        IF x < 0 RETURN -1;
+        RETURN 1;
+    ENDFUNC
+ENDMODULE"#;
+        let module = parse_module(input).into_result().unwrap();
+        let graph = ControlFlowGraph::new(routine(&module)).unwrap();
+        assert_eq!(
+            graph.to_dot("sign", input),
+            r#"digraph "sign" {
+    node [shape=box, fontname="monospace"];
+    b0 [label="B0 (body)\lIF x < 0\l", shape=box];
+    b1 [label="return", shape=oval];
+    b2 [label="raise", shape=oval];
+    b3 [label="stop", shape=oval];
+    b4 [label="B4\lRETURN -1;\l", shape=box];
+    b5 [label="B5\lRETURN 1;\l", shape=box];
+    b0 -> b4 [label="true"];
+    b4 -> b1 [label="return"];
+    b0 -> b5 [label="false"];
+    b5 -> b1 [label="return"];
+}
+"#
+        );
+    }
+}
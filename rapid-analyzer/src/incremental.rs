@@ -0,0 +1,664 @@
+// Incremental analysis for editors, on top of `rapid_parser::incremental`. An `AnalysisCache` keeps
+// the diagnostics of each routine of a module apart. When an update of the `ParseCache` reparsed
+// only comments and routines whose heads (scope, name and parameters) are unchanged and in place,
+// the module declarations and every other routine mean the same as before: only the reparsed
+// routines are analysed again, and the module-level checks (declarations, unused data and
+// routines, interrupts, lint configuration) are redone from what each routine uses. Any other
+// change analyses the whole module again. The result is always the same as that of
+// `diagnose_parsed`.
+
+use std::collections::{HashMap, HashSet};
+
+use rapid_parser::ast::{Module, ModuleInfo, RoutineDeclaration, Statement, StatementKind};
+use rapid_parser::incremental::{ParseCache, Reparse};
+use rapid_parser::Span;
+
+use crate::diagnostics::Diagnostic;
+use crate::interrupts::{self, RoutineInterrupts};
+use crate::lint::{self, LintConfig};
+use crate::resolve::Resolver;
+use crate::symbols::{ReferenceKind, ScopeKind, SymbolTable};
+use crate::types::TypeChecker;
+use crate::Analysis;
+
+/// How an [`AnalysisCache::update`] brought the diagnostics up to date.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Reanalysis {
+    /// The whole module was analysed again.
+    Full,
+    /// Only `analysed` routines were analysed again, and the results of `reused` routines were
+    /// kept.
+    Incremental { analysed: usize, reused: usize },
+}
+
+/// The diagnostics of the module held by a [`ParseCache`], updated along with it.
+#[derive(Debug)]
+pub struct AnalysisCache {
+    config: LintConfig,
+    /// The length of the analysed source, to find how far an update moves the items after it.
+    source_len: usize,
+    /// What is kept of each statement of the module.
+    items: Vec<Item>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug)]
+enum Item {
+    Routine(Box<RoutineAnalysis>),
+    Comment,
+    /// A declaration, which is analysed again on every update.
+    Other,
+}
+
+/// The results of analysing one routine on its own.
+#[derive(Debug)]
+struct RoutineAnalysis {
+    /// The text from the start of the routine to the end of its parameter list, and where it is.
+    head: String,
+    head_span: Span,
+    phases: Phases,
+    unused: Vec<Diagnostic>,
+    labels: Vec<Diagnostic>,
+    unreachable: Vec<Diagnostic>,
+    retries: Vec<Diagnostic>,
+    /// The names the routine uses from the module, and how.
+    uses: Vec<(String, ReferenceKind)>,
+    interrupts: Option<RoutineInterrupts>,
+}
+
+/// The diagnostics of the parameters, data and statements of a routine, or of a declaration, in the
+/// phases of the analysis.
+#[derive(Debug, Default)]
+struct Phases {
+    declared: Vec<Diagnostic>,
+    resolved: Vec<Diagnostic>,
+    checked: Vec<Diagnostic>,
+}
+
+impl RoutineAnalysis {
+    fn shift(&mut self, shift: &impl Fn(Span) -> Span) {
+        self.head_span = shift(self.head_span);
+        for diagnostic in [
+            &mut self.phases.declared,
+            &mut self.phases.resolved,
+            &mut self.phases.checked,
+            &mut self.unused,
+            &mut self.labels,
+            &mut self.unreachable,
+            &mut self.retries,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            diagnostic.shift(shift);
+        }
+        if let Some(interrupts) = &mut self.interrupts {
+            interrupts.shift(shift);
+        }
+    }
+}
+
+impl AnalysisCache {
+    /// Analyses the module held by `parse`, with the lint rules configured by `config`.
+    pub fn new(parse: &ParseCache, config: LintConfig) -> Self {
+        let mut cache = AnalysisCache {
+            config,
+            source_len: 0,
+            items: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        cache.analyse(parse, Vec::new());
+        cache
+    }
+
+    /// The diagnostics of the module, the same as `diagnose_parsed` returns for it.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Brings the diagnostics up to date after `parse` was updated. Must be called after every
+    /// successful [`ParseCache::update`], with the [`Reparse`] it returned.
+    pub fn update(&mut self, parse: &ParseCache, reparse: Reparse) -> Reanalysis {
+        let kept = match reparse {
+            Reparse::Full => None,
+            Reparse::Incremental { span, reused } => self.reuse(parse, span, reused),
+        };
+        let Some(kept) = kept else {
+            self.analyse(parse, Vec::new());
+            return Reanalysis::Full;
+        };
+        let reused = kept.iter().filter(|routine| routine.is_some()).count();
+        let analysed = self.analyse(parse, kept);
+        Reanalysis::Incremental { analysed, reused }
+    }
+
+    /// Takes the analysis of the routines that were not parsed again, moved to their new place,
+    /// in the order of the statements of the module. Returns `None` if the module has to be
+    /// analysed as a whole: if anything but routines with unchanged heads and comments was parsed
+    /// again.
+    fn reuse(
+        &mut self,
+        parse: &ParseCache,
+        span: Span,
+        reused: usize,
+    ) -> Option<Vec<Option<Box<RoutineAnalysis>>>> {
+        let Module::Module(module) = parse.module() else {
+            return None;
+        };
+        let statements = &module.statements;
+        let first = statements
+            .iter()
+            .position(|statement| statement.span.start >= span.start)
+            .unwrap_or(statements.len());
+        let old = first..self.items.len().checked_sub(reused)? + first;
+        let new = first..statements.len() - reused + first;
+
+        let mut old_heads = Vec::new();
+        for item in &self.items[old.clone()] {
+            match item {
+                Item::Routine(routine) => old_heads.push((routine.head_span, &routine.head[..])),
+                Item::Comment => {}
+                Item::Other => return None,
+            }
+        }
+        let mut new_heads = Vec::new();
+        for statement in &statements[new.clone()] {
+            match &statement.kind {
+                StatementKind::RoutineDeclaration(routine) => {
+                    new_heads.push(routine_head(routine, statement, parse.source())?)
+                }
+                StatementKind::Comment(_) => {}
+                _ => return None,
+            }
+        }
+        if old_heads != new_heads {
+            return None;
+        }
+
+        let delta = parse.source().len() as isize - self.source_len as isize;
+        let old_end = (span.end as isize - delta) as usize;
+        let shift = |span: Span| {
+            if span.start < old_end {
+                return span;
+            }
+            let moved = |offset: usize| (offset as isize + delta) as usize;
+            Span::new(moved(span.start), moved(span.end))
+        };
+        let mut items = std::mem::take(&mut self.items);
+        let after = items.split_off(old.end);
+        items.truncate(first);
+        let routine = |item| match item {
+            Item::Routine(routine) => Some(routine),
+            Item::Comment | Item::Other => None,
+        };
+        let mut kept: Vec<_> = items.into_iter().map(routine).collect();
+        kept.extend(new.map(|_| None));
+        kept.extend(after.into_iter().map(|item| {
+            let mut routine = routine(item)?;
+            routine.shift(&shift);
+            Some(routine)
+        }));
+        Some(kept)
+    }
+
+    /// Analyses the module held by `parse`, reusing the `kept` analysis of routines, and returns
+    /// how many routines were analysed. `kept` is empty to analyse every routine.
+    fn analyse(
+        &mut self,
+        parse: &ParseCache,
+        mut kept: Vec<Option<Box<RoutineAnalysis>>>,
+    ) -> usize {
+        self.source_len = parse.source().len();
+        let syntax_errors = parse
+            .errors()
+            .iter()
+            .map(Diagnostic::syntax_error)
+            .collect();
+        let Module::Module(module) = parse.module() else {
+            self.items.clear();
+            self.diagnostics = syntax_errors;
+            return 0;
+        };
+        kept.resize_with(module.statements.len(), || None);
+        let (items, diagnostics, analysed) = analyse_module(module, parse.source(), kept);
+        self.items = items;
+        self.diagnostics = syntax_errors;
+        self.diagnostics.extend(diagnostics);
+        lint::apply(&mut self.diagnostics, module, parse.source(), &self.config);
+        analysed
+    }
+}
+
+/// Analyses the statements of a module that have no `kept` routine analysis, like
+/// `analyze_module` does, and assembles the diagnostics of the module in the order of
+/// `diagnose_parsed`, without the lint configuration applied.
+///
+/// # Returns
+///
+/// The items of the module, its diagnostics and the number of routines analysed.
+fn analyse_module(
+    module: &ModuleInfo,
+    source: &str,
+    kept: Vec<Option<Box<RoutineAnalysis>>>,
+) -> (Vec<Item>, Vec<Diagnostic>, usize) {
+    let statements = &module.statements;
+    let mut symbols = SymbolTable::new();
+    let mut diagnostics = Vec::new();
+    // The diagnostics of the module-level symbol of each statement, which depend on the statements
+    // before it
+    let mut declared = Vec::new();
+    let mut phases: Vec<Phases> = statements.iter().map(|_| Phases::default()).collect();
+    // The references made by each statement analysed, as ranges of the references of the table
+    let mut references = vec![Vec::new(); statements.len()];
+    let analyse: Vec<bool> = kept.iter().map(Option::is_none).collect();
+
+    let mut resolver = Resolver::new(&mut symbols, &mut diagnostics);
+    let builtins = resolver.declare_builtins();
+    let module_scope = resolver
+        .table
+        .add_scope(ScopeKind::Module, Some(builtins), module.span);
+    for (index, statement) in statements.iter().enumerate() {
+        resolver.declare_module_statement(statement, module_scope);
+        declared.push(std::mem::take(resolver.diagnostics));
+        if !analyse[index] {
+            continue;
+        }
+        let start = resolver.table.references().len();
+        if let StatementKind::RoutineDeclaration(routine) = &statement.kind {
+            resolver.declare_routine(routine, module_scope);
+        }
+        phases[index].declared = std::mem::take(resolver.diagnostics);
+        references[index].push(start..resolver.table.references().len());
+    }
+    for (index, statement) in statements.iter().enumerate() {
+        if analyse[index] {
+            let start = resolver.table.references().len();
+            resolver.resolve_module_statement(statement, module_scope);
+            phases[index].resolved = std::mem::take(resolver.diagnostics);
+            references[index].push(start..resolver.table.references().len());
+        }
+    }
+    let mut checker = TypeChecker::new(&symbols, module_scope, &mut diagnostics);
+    for (index, statement) in statements.iter().enumerate() {
+        if analyse[index] {
+            checker.check_module_statement(statement);
+            phases[index].checked = std::mem::take(checker.diagnostics);
+        }
+    }
+    let analysis = Analysis {
+        by_reference: checker.by_reference,
+        symbols,
+        module_scope,
+        diagnostics: Vec::new(),
+    };
+    let table = &analysis.symbols;
+
+    let mut analysed = 0;
+    let mut items = Vec::new();
+    for (index, (statement, kept)) in statements.iter().zip(kept).enumerate() {
+        items.push(match &statement.kind {
+            StatementKind::RoutineDeclaration(routine) => {
+                match (kept, routine_head(routine, statement, source)) {
+                    (Some(kept), _) => Item::Routine(kept),
+                    (None, Some(head)) => {
+                        analysed += 1;
+                        let phases = std::mem::take(&mut phases[index]);
+                        let routine = analyse_routine(
+                            routine,
+                            statement,
+                            head,
+                            phases,
+                            &references[index],
+                            &analysis,
+                        );
+                        Item::Routine(Box::new(routine))
+                    }
+                    (None, None) => Item::Other,
+                }
+            }
+            StatementKind::Comment(_) => Item::Comment,
+            _ => Item::Other,
+        });
+    }
+    let routines = || {
+        items.iter().filter_map(|item| match item {
+            Item::Routine(routine) => Some(&**routine),
+            Item::Comment | Item::Other => None,
+        })
+    };
+    let phases_of = |index: usize| match &items[index] {
+        Item::Routine(routine) => &routine.phases,
+        Item::Comment | Item::Other => &phases[index],
+    };
+
+    let mut diagnostics = Vec::new();
+    for (index, declared) in declared.into_iter().enumerate() {
+        diagnostics.extend(declared);
+        diagnostics.extend(phases_of(index).declared.iter().cloned());
+    }
+    for index in 0..items.len() {
+        diagnostics.extend(phases_of(index).resolved.iter().cloned());
+    }
+    for index in 0..items.len() {
+        diagnostics.extend(phases_of(index).checked.iter().cloned());
+    }
+
+    lint::check_module_attributes(module, &mut diagnostics);
+    lint::check_declaration_order(module, &mut diagnostics);
+    // The uses of module symbols by the routines kept, which are not in the symbol table
+    let mut uses: HashMap<_, Vec<ReferenceKind>> = HashMap::new();
+    let mut traps = HashSet::new();
+    for (index, item) in items.iter().enumerate() {
+        let Item::Routine(routine) = item else {
+            continue;
+        };
+        if analyse[index] {
+            continue;
+        }
+        for (name, kind) in &routine.uses {
+            if let Some(id) = table.lookup_local(module_scope, name) {
+                uses.entry(id).or_default().push(*kind);
+                traps.extend(interrupts::connected_trap(table.symbol(id), *kind));
+            }
+        }
+    }
+    traps.extend(table.references().iter().filter_map(|reference| {
+        interrupts::connected_trap(table.symbol(reference.symbol?), reference.kind)
+    }));
+    let in_routine = |span: Span| {
+        statements.iter().zip(&items).any(|(statement, item)| {
+            matches!(item, Item::Routine(_)) && statement.span.contains(span.start)
+        })
+    };
+    let mut unused: Vec<_> = table
+        .symbols()
+        .filter(|(id, symbol)| {
+            !table.is_builtin(*id)
+                && (symbol.scope == module_scope || !in_routine(symbol.name_span))
+        })
+        .filter_map(|(id, symbol)| {
+            let mut references: Vec<_> = table.references_to(id).map(|r| r.kind).collect();
+            references.extend(uses.get(&id).into_iter().flatten());
+            lint::unused_symbol(table, symbol, &references)
+        })
+        .collect();
+    unused.extend(routines().flat_map(|routine| routine.unused.iter().cloned()));
+    unused.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics.extend(unused);
+    diagnostics.extend(routines().flat_map(|routine| routine.labels.iter().cloned()));
+    lint::check_unreachable_sequence(statements, &mut diagnostics);
+    for (statement, item) in statements.iter().zip(&items) {
+        match item {
+            Item::Routine(routine) => diagnostics.extend(routine.unreachable.iter().cloned()),
+            Item::Comment | Item::Other => {
+                lint::check_unreachable_blocks(statement, &mut diagnostics)
+            }
+        }
+    }
+    diagnostics.extend(routines().flat_map(|routine| routine.retries.iter().cloned()));
+    let interrupts: Vec<_> = routines()
+        .filter_map(|routine| routine.interrupts.as_ref())
+        .collect();
+    diagnostics.extend(
+        interrupts::report(&[module], &interrupts, &traps)
+            .into_iter()
+            .flatten(),
+    );
+    (items, diagnostics, analysed)
+}
+
+/// Collects what the lint rules and interrupt checks find in a routine that was analysed with the
+/// rest of the module in `analysis`. `references` are the ranges of the references it made.
+fn analyse_routine(
+    routine: &RoutineDeclaration,
+    statement: &Statement,
+    (head_span, head): (Span, &str),
+    phases: Phases,
+    references: &[std::ops::Range<usize>],
+    analysis: &Analysis,
+) -> RoutineAnalysis {
+    let table = &analysis.symbols;
+    let references: Vec<_> = references
+        .iter()
+        .map(|range| &table.references()[range.clone()])
+        .collect();
+    let uses = references
+        .iter()
+        .copied()
+        .flatten()
+        .filter_map(|reference| {
+            let symbol = table.symbol(reference.symbol?);
+            (symbol.scope == analysis.module_scope)
+                .then(|| (symbol.name.to_owned(), reference.kind))
+        })
+        .collect();
+    let mut unused = Vec::new();
+    for (id, symbol) in table.symbols() {
+        let local = symbol.scope != analysis.module_scope && !table.is_builtin(id);
+        if local && statement.span.contains(symbol.name_span.start) {
+            let references: Vec<_> = table.references_to(id).map(|r| r.kind).collect();
+            unused.extend(lint::unused_symbol(table, symbol, &references));
+        }
+    }
+    let mut labels = Vec::new();
+    for references in references {
+        lint::check_labels(table, references, &mut labels);
+    }
+    let mut unreachable = Vec::new();
+    lint::check_unreachable_blocks(statement, &mut unreachable);
+    let mut retries = Vec::new();
+    lint::check_endless_retry(routine, &mut retries);
+    RoutineAnalysis {
+        head: head.to_owned(),
+        head_span,
+        phases,
+        unused,
+        labels,
+        unreachable,
+        retries,
+        uses,
+        interrupts: RoutineInterrupts::new(0, routine, analysis),
+    }
+}
+
+/// Returns the head of a routine, from its start to the end of its parameter list, with its text.
+/// Returns `None` for a routine that could not be parsed.
+fn routine_head<'s>(
+    routine: &RoutineDeclaration,
+    statement: &Statement,
+    source: &'s str,
+) -> Option<(Span, &'s str)> {
+    let end = match routine {
+        RoutineDeclaration::ProcDeclaration(proc) => {
+            parameters_end(source, proc.name_span, &proc.parameters)?
+        }
+        RoutineDeclaration::FuncDeclaration(func) => {
+            parameters_end(source, func.name_span, &func.parameters)?
+        }
+        RoutineDeclaration::TrapDeclaration(trap) => trap.name_span.end,
+        RoutineDeclaration::RDN | RoutineDeclaration::Error => return None,
+    };
+    let span = Span::new(statement.span.start, end);
+    Some((span, source.get(span.start..span.end)?))
+}
+
+fn parameters_end(
+    source: &str,
+    name: Span,
+    parameters: &[rapid_parser::ast::ParameterDeclarationType],
+) -> Option<usize> {
+    let start = parameters
+        .last()
+        .map_or(name.end, |parameter| parameter.span.end);
+    Some(start + source.get(start..)?.find(')')? + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use rapid_parser::incremental::TextEdit;
+
+    use super::*;
+    use crate::diagnose_with;
+
+    const SOURCE: &str = r#"MODULE demo
+    VAR intnum timerInt;
+    LOCAL VAR num count := 0;
+
+    PROC main()
+        VAR num unused;
+        count := count + 1;
+        ITimer 1, timerInt;
+        RETURN;
+        TPWrite "never";
+    ENDPROC
+
+    PROC helper()
+        WaitTime count;
+        GOTO missing;
+    ENDPROC
+
+    LOCAL PROC setup()
+        count := 1;
+    ENDPROC
+
+    TRAP onTimer
+        TPWrite "tick";
+    ENDTRAP
+ENDMODULE
+"#;
+
+    /// Applies the replacement of `find` to both caches and checks that the diagnostics are the
+    /// same as those of a full analysis.
+    fn update(
+        parse: &mut ParseCache,
+        analysis: &mut AnalysisCache,
+        find: &str,
+        replacement: &str,
+    ) -> Reanalysis {
+        let start = parse.source().find(find).unwrap();
+        let edit = TextEdit::new(Span::new(start, start + find.len()), replacement);
+        let reparse = parse.update(parse.version() + 1, &[edit]).unwrap();
+        let reanalysis = analysis.update(parse, reparse);
+        let expected = diagnose_with(parse.source(), &LintConfig::default());
+        assert_eq!(analysis.diagnostics(), expected.as_slice());
+        reanalysis
+    }
+
+    #[test]
+    fn analyse_only_the_edited_routine() {
+        let mut parse = ParseCache::new(1, SOURCE);
+        let mut analysis = AnalysisCache::new(&parse, LintConfig::default());
+        assert_eq!(
+            analysis.diagnostics(),
+            diagnose_with(SOURCE, &LintConfig::default()).as_slice()
+        );
+        let codes: Vec<_> = analysis.diagnostics().iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "unused-var",
+                "unused-routine",
+                "missing-label",
+                "unreachable-code",
+                "unconnected-interrupt",
+                "unconnected-trap",
+            ]
+        );
+
+        // The diagnostics of the routines after the edit move with the text
+        assert_eq!(
+            update(
+                &mut parse,
+                &mut analysis,
+                "count := count + 1",
+                "count := 100"
+            ),
+            Reanalysis::Incremental {
+                analysed: 1,
+                reused: 3
+            }
+        );
+        // The last read of `count` is gone, and a call uses `setup`
+        assert_eq!(
+            update(&mut parse, &mut analysis, "WaitTime count;", "setup;"),
+            Reanalysis::Incremental {
+                analysed: 1,
+                reused: 3
+            }
+        );
+        // Connecting the interrupt in one routine connects the trap and handles the interrupt
+        // ordered in another
+        assert_eq!(
+            update(
+                &mut parse,
+                &mut analysis,
+                "TPWrite \"tick\";",
+                "TPWrite \"tick\";\n        CONNECT timerInt WITH onTimer;",
+            ),
+            Reanalysis::Incremental {
+                analysed: 1,
+                reused: 3
+            }
+        );
+        // Comments between routines are parsed again with the routines they touch
+        assert_eq!(
+            update(
+                &mut parse,
+                &mut analysis,
+                "count := 1;\n    ENDPROC\n",
+                "count := 1;\n    ENDPROC\n\n    ! The timer\n",
+            ),
+            Reanalysis::Incremental {
+                analysed: 1,
+                reused: 3
+            }
+        );
+        let codes: Vec<_> = analysis.diagnostics().iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                "unused-var",
+                "unused-var",
+                "missing-label",
+                "unreachable-code"
+            ]
+        );
+    }
+
+    #[test]
+    fn fall_back_to_a_full_analysis() {
+        let mut parse = ParseCache::new(1, SOURCE);
+        let mut analysis = AnalysisCache::new(&parse, LintConfig::default());
+        // The parameters of a routine change how it is called
+        assert_eq!(
+            update(&mut parse, &mut analysis, "helper()", "helper(num value)"),
+            Reanalysis::Full
+        );
+        // Module data is not kept
+        assert_eq!(
+            update(&mut parse, &mut analysis, "count := 0", "count := 1"),
+            Reanalysis::Full
+        );
+        // Neither are routines with syntax errors
+        assert_eq!(
+            update(&mut parse, &mut analysis, "ENDTRAP", ""),
+            Reanalysis::Full
+        );
+        assert_eq!(
+            update(&mut parse, &mut analysis, "ENDMODULE", "ENDTRAP\nENDMODULE"),
+            Reanalysis::Full
+        );
+        assert_eq!(
+            update(
+                &mut parse,
+                &mut analysis,
+                "GOTO missing;",
+                "GOTO found;\n        found:"
+            ),
+            Reanalysis::Incremental {
+                analysed: 1,
+                reused: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,584 @@
+use std::collections::{HashMap, HashSet};
+
+use rapid_parser::ast::{
+    Argument, ArgumentKind, Expr, ExprKind, ModuleInfo, RoutineDeclaration, Scope, Statement,
+    StatementKind, Term, VariableKind,
+};
+use rapid_parser::Span;
+
+use crate::diagnostics::Diagnostic;
+use crate::flow::{ControlFlowGraph, EdgeKind};
+use crate::symbols::{ReferenceKind, ScopeKind, Symbol, SymbolKind};
+use crate::Analysis;
+
+/// The instructions that order an interrupt, which they pass to the `intnum` given as their last
+/// required argument.
+const SUBSCRIPTIONS: &[&str] = &[
+    "IError",
+    "IPers",
+    "ISignalAI",
+    "ISignalAO",
+    "ISignalDI",
+    "ISignalDO",
+    "ISignalGI",
+    "ISignalGO",
+    "ITimer",
+    "IVarValue",
+];
+
+/// An `intnum` variable. It is known by its name rather than its declaration, so that the modules
+/// of a task agree on global data and the interrupts of a routine can be kept while others change.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+enum Interrupt {
+    Global(String),
+    /// `LOCAL` data of the module with the index.
+    Local(usize, String),
+    /// Data of a routine, with the index of its module and the name of the routine.
+    Routine(usize, String, String),
+}
+
+/// A `CONNECT` statement of a task.
+#[derive(Debug, Clone)]
+struct Connect {
+    /// The index of the module in the task.
+    module: usize,
+    routine: String,
+    interrupt: Interrupt,
+    name: String,
+    span: Span,
+}
+
+/// An instruction such as `ITimer` that orders an interrupt.
+#[derive(Debug, Clone)]
+struct Subscription {
+    procedure: &'static str,
+    name: String,
+    interrupt: Interrupt,
+    span: Span,
+}
+
+/// What a routine does with interrupts, collected from its flow on its own so that [`report`] can
+/// check the routines of a task together.
+#[derive(Debug, Clone)]
+pub struct RoutineInterrupts {
+    /// The index of the module of the routine in the task.
+    module: usize,
+    connects: Vec<Connect>,
+    deleted: Vec<Interrupt>,
+    subscriptions: Vec<Subscription>,
+    /// The connections repeated by the flow of the routine, such as a `CONNECT` in a loop.
+    reconnected: Vec<Diagnostic>,
+}
+
+impl RoutineInterrupts {
+    /// Collects what a routine of the module with the index `module` does with interrupts. Returns
+    /// `None` for a routine that could not be parsed.
+    pub fn new(module: usize, routine: &RoutineDeclaration, analysis: &Analysis) -> Option<Self> {
+        let graph = ControlFlowGraph::new(routine)?;
+        let routine = routine_name(routine)?;
+        let mut found = RoutineInterrupts {
+            module,
+            connects: Vec::new(),
+            deleted: Vec::new(),
+            subscriptions: Vec::new(),
+            reconnected: Vec::new(),
+        };
+        let context = Context {
+            analysis,
+            module,
+            routine,
+        };
+        // The statement of each connection, to follow the flow from it
+        let mut statements = Vec::new();
+        for statement in graph.blocks.iter().flat_map(|block| &block.statements) {
+            match &statement.kind {
+                StatementKind::Connect(interrupt, _) => {
+                    if let Some(key) = context.interrupt_at(interrupt.span) {
+                        statements.push(*statement);
+                        found.connects.push(Connect {
+                            module,
+                            routine: routine.to_owned(),
+                            interrupt: key,
+                            name: interrupt.name.clone(),
+                            span: interrupt.span,
+                        });
+                    }
+                }
+                StatementKind::ProcCall(callee, arguments) => {
+                    let ExprKind::Term(Term::String(procedure)) = &callee.kind else {
+                        continue;
+                    };
+                    let Some((argument, span)) = interrupt_argument(arguments) else {
+                        continue;
+                    };
+                    let Some(key) = context.interrupt_at(span) else {
+                        continue;
+                    };
+                    if procedure.eq_ignore_ascii_case("IDelete") {
+                        found.deleted.push(key);
+                    } else if let Some(procedure) = SUBSCRIPTIONS
+                        .iter()
+                        .find(|known| known.eq_ignore_ascii_case(procedure))
+                    {
+                        found.subscriptions.push(Subscription {
+                            procedure,
+                            name: argument.to_owned(),
+                            interrupt: key,
+                            span,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut reported = HashSet::new();
+        for (index, connect) in found.connects.iter().enumerate() {
+            let Some(again) =
+                connected_again(&graph, statements[index], &connect.interrupt, &context)
+            else {
+                continue;
+            };
+            let Some(again) = statements.iter().position(|s| std::ptr::eq(*s, again)) else {
+                continue;
+            };
+            if reported.insert(again) {
+                let mut diagnostic = Diagnostic::warning(
+                    "reconnected-interrupt",
+                    format!(
+                        "interrupt '{}' may still be connected here, delete it with IDelete before connecting it again",
+                        found.connects[again].name
+                    ),
+                    found.connects[again].span,
+                );
+                if again != index {
+                    diagnostic = diagnostic.with_related("connected here before", connect.span);
+                }
+                found.reconnected.push(diagnostic);
+            }
+        }
+        Some(found)
+    }
+
+    /// Moves every span of the interrupts by `shift`, like the text they were collected from.
+    pub(crate) fn shift(&mut self, shift: &impl Fn(Span) -> Span) {
+        for connect in &mut self.connects {
+            connect.span = shift(connect.span);
+        }
+        for subscription in &mut self.subscriptions {
+            subscription.span = shift(subscription.span);
+        }
+        for diagnostic in &mut self.reconnected {
+            diagnostic.shift(shift);
+        }
+    }
+}
+
+/// Checks how the modules of a task use interrupts: traps that no `CONNECT` connects, interrupts
+/// that are connected again before `IDelete` deletes them, and interrupts ordered with `ITimer`,
+/// `ISignalDI` and the like that are never connected to a trap.
+///
+/// # Arguments
+///
+/// * `modules` - The modules of the task, or the one module analysed on its own.
+/// * `analyses` - The analysis of each module, in the same order.
+///
+/// # Returns
+///
+/// The diagnostics of each module, in the order of `modules`.
+pub fn check_task(modules: &[&ModuleInfo], analyses: &[Analysis]) -> Vec<Vec<Diagnostic>> {
+    let mut routines = Vec::new();
+    for (index, (module, analysis)) in modules.iter().zip(analyses).enumerate() {
+        for statement in &module.statements {
+            if let StatementKind::RoutineDeclaration(routine) = &statement.kind {
+                routines.extend(RoutineInterrupts::new(index, routine, analysis));
+            }
+        }
+    }
+    let traps = analyses
+        .iter()
+        .flat_map(|analysis| {
+            let table = &analysis.symbols;
+            table.references().iter().filter_map(|reference| {
+                connected_trap(table.symbol(reference.symbol?), reference.kind)
+            })
+        })
+        .collect();
+    report(modules, &routines.iter().collect::<Vec<_>>(), &traps)
+}
+
+/// Returns the name of a global trap in lower case if a reference of the kind connects it.
+pub(crate) fn connected_trap(symbol: &Symbol, kind: ReferenceKind) -> Option<String> {
+    let trap = matches!(symbol.kind, SymbolKind::Trap(_)) && symbol.visibility != Scope::LOCAL;
+    (trap && kind == ReferenceKind::Call).then(|| symbol.name.to_ascii_lowercase())
+}
+
+/// Checks the interrupts of a task from what each of its routines does with them, and reports the
+/// global traps of `modules` whose names are not among the `connected` traps.
+///
+/// # Returns
+///
+/// The diagnostics of each module, in the order of `modules`.
+pub(crate) fn report(
+    modules: &[&ModuleInfo],
+    routines: &[&RoutineInterrupts],
+    connected: &HashSet<String>,
+) -> Vec<Vec<Diagnostic>> {
+    let mut diagnostics: Vec<Vec<Diagnostic>> = vec![Vec::new(); modules.len()];
+    check_traps(modules, connected, &mut diagnostics);
+
+    let connects: Vec<&Connect> = routines.iter().flat_map(|r| &r.connects).collect();
+    let interrupts: HashSet<&Interrupt> = connects.iter().map(|c| &c.interrupt).collect();
+    for routine in routines {
+        for subscription in &routine.subscriptions {
+            if !interrupts.contains(&subscription.interrupt) {
+                diagnostics[routine.module].push(Diagnostic::warning(
+                    "unconnected-interrupt",
+                    format!(
+                        "interrupt '{}' is never connected to a trap, so the interrupt {} orders is not handled",
+                        subscription.name, subscription.procedure
+                    ),
+                    subscription.span,
+                ));
+            }
+        }
+    }
+
+    let mut reported = HashSet::new();
+    for routine in routines {
+        for diagnostic in &routine.reconnected {
+            reported.insert((routine.module, diagnostic.span));
+            diagnostics[routine.module].push(diagnostic.clone());
+        }
+    }
+
+    // Connections in several routines of an interrupt that is never deleted
+    let deleted: HashSet<&Interrupt> = routines.iter().flat_map(|r| &r.deleted).collect();
+    let mut first: HashMap<&Interrupt, &Connect> = HashMap::new();
+    for connect in connects {
+        if deleted.contains(&connect.interrupt) {
+            continue;
+        }
+        let Some(previous) = first.get(&connect.interrupt) else {
+            first.insert(&connect.interrupt, connect);
+            continue;
+        };
+        let same_routine = previous.module == connect.module
+            && previous.routine.eq_ignore_ascii_case(&connect.routine);
+        if same_routine || !reported.insert((connect.module, connect.span)) {
+            continue;
+        }
+        let message = if previous.module == connect.module {
+            format!(
+                "interrupt '{}' is already connected in '{}' and never deleted with IDelete",
+                connect.name, previous.routine
+            )
+        } else {
+            format!(
+                "interrupt '{}' is already connected in '{}' of module '{}' and never deleted with IDelete",
+                connect.name, previous.routine, modules[previous.module].name
+            )
+        };
+        let mut diagnostic = Diagnostic::warning("reconnected-interrupt", message, connect.span);
+        if previous.module == connect.module {
+            diagnostic = diagnostic.with_related("connected here before", previous.span);
+        }
+        diagnostics[connect.module].push(diagnostic);
+    }
+
+    for found in &mut diagnostics {
+        found.sort_by_key(|diagnostic| diagnostic.span.start);
+    }
+    diagnostics
+}
+
+/// Reports the global traps of each module that are not `connected`. `LOCAL` traps are reported as
+/// unused routines.
+fn check_traps(
+    modules: &[&ModuleInfo],
+    connected: &HashSet<String>,
+    diagnostics: &mut [Vec<Diagnostic>],
+) {
+    for (index, module) in modules.iter().enumerate() {
+        for statement in &module.statements {
+            let StatementKind::RoutineDeclaration(RoutineDeclaration::TrapDeclaration(trap)) =
+                &statement.kind
+            else {
+                continue;
+            };
+            if trap.scope != Scope::LOCAL && !connected.contains(&trap.name.to_ascii_lowercase()) {
+                diagnostics[index].push(Diagnostic::warning(
+                    "unconnected-trap",
+                    format!("trap '{}' is never connected to an interrupt", trap.name),
+                    trap.name_span,
+                ));
+            }
+        }
+    }
+}
+
+/// Follows the flow of a routine from `connect` and returns the first `CONNECT` of the same
+/// interrupt it reaches, which may be `connect` itself in a loop. A path ends at `IDelete` of the
+/// interrupt and at calls of the task's own procedures, which may delete it.
+fn connected_again<'a>(
+    graph: &ControlFlowGraph<'a>,
+    connect: &Statement,
+    interrupt: &Interrupt,
+    context: &Context,
+) -> Option<&'a Statement> {
+    let (mut block, position) = graph.blocks.iter().enumerate().find_map(|(id, block)| {
+        let position = block
+            .statements
+            .iter()
+            .position(|statement| std::ptr::eq(*statement, connect))?;
+        Some((id, position))
+    })?;
+    // The block of the CONNECT is first followed from after it, and only seen as a whole when a
+    // loop leads back to it
+    let mut from = position + 1;
+    let mut seen = vec![false; graph.blocks.len()];
+    let mut stack = Vec::new();
+    loop {
+        let mut deletes = false;
+        for statement in &graph.blocks[block].statements[from..] {
+            match &statement.kind {
+                StatementKind::Connect(other, _)
+                    if context.interrupt_at(other.span).as_ref() == Some(interrupt) =>
+                {
+                    return Some(statement);
+                }
+                StatementKind::ProcCall(callee, arguments)
+                    if may_delete(callee, arguments, interrupt, context) =>
+                {
+                    deletes = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if !deletes {
+            // Errors leave the normal flow, the error handler is not followed
+            stack.extend(
+                graph
+                    .successors(block)
+                    .filter(|edge| {
+                        !matches!(
+                            edge.kind,
+                            EdgeKind::Error | EdgeKind::Raise | EdgeKind::Retry | EdgeKind::TryNext
+                        )
+                    })
+                    .map(|edge| edge.to),
+            );
+        }
+        block = loop {
+            let next = stack.pop()?;
+            if !std::mem::replace(&mut seen[next], true) {
+                break next;
+            }
+        };
+        from = 0;
+    }
+}
+
+/// Returns whether a procedure call may delete `interrupt`: `IDelete` of it, a procedure of the
+/// task, or a late bound call.
+fn may_delete(
+    callee: &Expr,
+    arguments: &[Argument],
+    interrupt: &Interrupt,
+    context: &Context,
+) -> bool {
+    let ExprKind::Term(Term::String(procedure)) = &callee.kind else {
+        return true;
+    };
+    if procedure.eq_ignore_ascii_case("IDelete") {
+        return interrupt_argument(arguments)
+            .and_then(|(_, span)| context.interrupt_at(span))
+            .is_none_or(|key| key == *interrupt);
+    }
+    let table = &context.analysis.symbols;
+    table
+        .references()
+        .iter()
+        .find(|reference| reference.kind == ReferenceKind::Call && reference.span == callee.span)
+        .and_then(|reference| reference.symbol)
+        .is_some_and(|symbol| !table.is_builtin(symbol))
+}
+
+/// Returns the last required argument of a call if it is a plain variable, with its name and
+/// span.
+fn interrupt_argument(arguments: &[Argument]) -> Option<(&str, Span)> {
+    let expr = arguments
+        .iter()
+        .rev()
+        .find_map(|argument| match &argument.kind {
+            ArgumentKind::Required(_, expr) => Some(expr),
+            _ => None,
+        })?;
+    match &expr.kind {
+        ExprKind::Term(Term::Var(variable)) => match &variable.kind {
+            VariableKind::Variable(name) => Some((name, variable.span)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The routine whose flow is followed, to find the interrupts its names refer to.
+struct Context<'a, 'b> {
+    analysis: &'b Analysis<'a>,
+    module: usize,
+    routine: &'b str,
+}
+
+impl Context<'_, '_> {
+    /// Returns the `intnum` variable declared in the task that the name at `span` refers to.
+    fn interrupt_at(&self, span: Span) -> Option<Interrupt> {
+        let table = &self.analysis.symbols;
+        let id = table
+            .references()
+            .iter()
+            .find(|reference| reference.span == span)?
+            .symbol?;
+        let symbol = table.symbol(id);
+        if !matches!(symbol.kind, SymbolKind::Data(_)) || table.is_builtin(id) {
+            return None;
+        }
+        let name = symbol.name.to_ascii_lowercase();
+        Some(match table.scope(symbol.scope).kind {
+            ScopeKind::Routine | ScopeKind::Loop => {
+                Interrupt::Routine(self.module, self.routine.to_ascii_lowercase(), name)
+            }
+            _ if symbol.visibility == Scope::LOCAL => Interrupt::Local(self.module, name),
+            _ => Interrupt::Global(name),
+        })
+    }
+}
+
+fn routine_name(routine: &RoutineDeclaration) -> Option<&str> {
+    match routine {
+        RoutineDeclaration::ProcDeclaration(proc) => Some(&proc.name),
+        RoutineDeclaration::FuncDeclaration(func) => Some(&func.name),
+        RoutineDeclaration::TrapDeclaration(trap) => Some(&trap.name),
+        RoutineDeclaration::RDN | RoutineDeclaration::Error => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{diagnose, LintConfig, Task};
+
+    fn messages(diagnostics: &[crate::Diagnostic]) -> Vec<String> {
+        diagnostics
+            .iter()
+            .map(|d| format!("{}: {}", d.code, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn check_interrupts_of_module() {
+        let input = r#"
+            MODULE mymodule
+                VAR intnum timerInt;
+                VAR intnum loopInt;
+                VAR intnum lostInt;
+                VAR intnum resetInt;
+                VAR intnum sharedInt;
+                VAR intnum branchInt;
+                PROC main()
+                    CONNECT timerInt WITH onTimer;
+                    ITimer 1, timerInt;
+                    ITimer \Single, 2, lostInt;
+                    WHILE TRUE DO
+                        CONNECT loopInt WITH onTimer;
+                        ITimer \Single, 1, loopInt;
+                        WaitTime 1;
+                    ENDWHILE
+                ENDPROC
+                PROC restart()
+                    FOR i FROM 1 TO 3 DO
+                        IDelete resetInt;
+                        CONNECT resetInt WITH onTimer;
+                    ENDFOR
+                    CONNECT sharedInt WITH onTimer;
+                    IF TRUE THEN
+                        CONNECT branchInt WITH onTimer;
+                    ELSE
+                        CONNECT branchInt WITH onTimer;
+                    ENDIF
+                ENDPROC
+                PROC again()
+                    CONNECT sharedInt WITH onTimer;
+                ENDPROC
+                TRAP onTimer
+                ENDTRAP
+                TRAP forgotten
+                ENDTRAP
+                LOCAL TRAP unused
+                ENDTRAP
+            ENDMODULE"#;
+        let diagnostics = diagnose(input);
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "unused-routine: trap 'unused' is never used",
+                "unconnected-interrupt: interrupt 'lostInt' is never connected to a trap, so the interrupt ITimer orders is not handled",
+                "reconnected-interrupt: interrupt 'loopInt' may still be connected here, delete it with IDelete before connecting it again",
+                "reconnected-interrupt: interrupt 'sharedInt' is already connected in 'restart' and never deleted with IDelete",
+                "unconnected-trap: trap 'forgotten' is never connected to an interrupt",
+            ]
+        );
+        let related: Vec<_> = diagnostics
+            .iter()
+            .filter(|d| d.code == "reconnected-interrupt")
+            .map(|d| {
+                d.related
+                    .iter()
+                    .map(|r| &input[r.span.start..r.span.end])
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(related, vec![vec![], vec!["sharedInt"]]);
+    }
+
+    #[test]
+    fn check_interrupts_of_task() {
+        let mut task = Task::new("T_ROB1");
+        task.add_file(
+            "MainModule.mod",
+            r#"MODULE MainModule
+    PROC main()
+        CONNECT sensorInt WITH onSensor;
+        ITimer 1, sensorInt;
+        ITimer 1, otherInt;
+    ENDPROC
+ENDMODULE"#
+                .to_owned(),
+        );
+        task.add_file(
+            "Sensor.mod",
+            r#"MODULE Sensor
+    VAR intnum sensorInt;
+    VAR intnum otherInt;
+    PROC init()
+        CONNECT sensorInt WITH onSensor;
+    ENDPROC
+    TRAP onSensor
+    ENDTRAP
+ENDMODULE"#
+                .to_owned(),
+        );
+        let diagnostics: Vec<Vec<String>> = task
+            .diagnose(&LintConfig::default())
+            .iter()
+            .map(|diagnostics| messages(diagnostics))
+            .collect();
+        assert_eq!(
+            diagnostics,
+            vec![
+                vec!["unconnected-interrupt: interrupt 'otherInt' is never connected to a trap, so the interrupt ITimer orders is not handled".to_owned()],
+                vec!["reconnected-interrupt: interrupt 'sensorInt' is already connected in 'main' of module 'MainModule' and never deleted with IDelete".to_owned()],
+            ]
+        );
+    }
+}
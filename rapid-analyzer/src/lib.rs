@@ -0,0 +1,329 @@
+pub mod backup;
+pub mod builtins;
+pub mod calls;
+pub mod diagnostics;
+pub mod flow;
+pub mod incremental;
+pub mod interrupts;
+pub mod lint;
+pub mod outline;
+pub mod program;
+pub mod resolve;
+pub mod symbols;
+pub mod types;
+
+use rapid_parser::ast::{Module, ModuleInfo};
+use rapid_parser::{parse_module, Span};
+
+pub use backup::Backup;
+pub use diagnostics::{apply_fixes, Diagnostic, Edit, Fix, RelatedInformation, Severity};
+pub use flow::{Block, BlockId, BlockKind, ControlFlowGraph, Edge, EdgeKind, Region};
+pub use lint::{LintConfig, Rule};
+pub use outline::{outline, OutlineItem, OutlineKind};
+pub use program::{
+    PersistentAccess, SharedPersistent, Signal, System, SystemAnalysis, Task, TaskFile,
+};
+pub use symbols::{
+    Reference, ReferenceKind, ScopeData, ScopeId, ScopeKind, Symbol, SymbolId, SymbolKind,
+    SymbolTable,
+};
+pub use types::{AtomicType, Type, TypeChecker};
+
+/// The result of analysing a module: its symbol table and every semantic problem found.
+#[derive(Debug)]
+pub struct Analysis<'a> {
+    pub symbols: SymbolTable<'a>,
+    pub module_scope: ScopeId,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The spans of the variables passed to `VAR`, `INOUT` and `PERS` parameters, which the called
+    /// routine may change, except the `PERS` parameters that built-in routines only read. The
+    /// references to them are recorded as reads.
+    pub by_reference: Vec<Span>,
+}
+
+/// Builds the scoped symbol tables for a module and resolves every name used in it against its own
+/// declarations and the built-in catalogue.
+///
+/// # Arguments
+///
+/// * `module` - The module to analyse, as returned by `rapid_parser::parse_module`.
+///
+/// # Returns
+///
+/// An `Analysis` with the symbol table and diagnostics for undeclared identifiers, duplicate
+/// declarations, shadowed names and type errors.
+pub fn analyze_module(module: &ModuleInfo) -> Analysis<'_> {
+    analyze_task_module(module, &[])
+}
+
+/// Like [`analyze_module`] for a module of a task, which also sees the global declarations of the
+/// `others` modules of the task. See [`program::Task`] to analyse a whole task.
+pub fn analyze_task_module<'a>(module: &'a ModuleInfo, others: &[&'a ModuleInfo]) -> Analysis<'a> {
+    let mut symbols = SymbolTable::new();
+    let mut diagnostics = Vec::new();
+    let mut resolver = resolve::Resolver::new(&mut symbols, &mut diagnostics);
+    let mut parent = resolver.declare_builtins();
+    if !others.is_empty() {
+        parent = resolver.declare_task(others, Some(parent));
+    }
+    let module_scope = resolver.declare_module(module, Some(parent));
+    resolver.resolve_module(module, module_scope);
+    let mut checker = TypeChecker::new(&symbols, module_scope, &mut diagnostics);
+    checker.check_module(module);
+    let by_reference = checker.by_reference;
+    Analysis {
+        symbols,
+        module_scope,
+        diagnostics,
+        by_reference,
+    }
+}
+
+/// Parses and analyses a module and returns its syntax errors followed by its semantic
+/// diagnostics and the findings of the lint rules. A module with syntax errors is analysed as far
+/// as it could be parsed.
+pub fn diagnose(source: &str) -> Vec<Diagnostic> {
+    diagnose_with(source, &LintConfig::default())
+}
+
+/// Like [`diagnose`], with the lint rules configured by `config`.
+pub fn diagnose_with(source: &str, config: &LintConfig) -> Vec<Diagnostic> {
+    let parsed = parse_module(source);
+    let syntax_errors = parsed.errors.iter().map(Diagnostic::syntax_error).collect();
+    diagnose_parsed(source, &parsed.module, syntax_errors, config)
+}
+
+/// Like [`diagnose_with`] for a module that is already parsed, such as one kept up to date by
+/// `rapid_parser::incremental::ParseCache`. `syntax_errors` come first in the result.
+pub fn diagnose_parsed(
+    source: &str,
+    module: &Module,
+    syntax_errors: Vec<Diagnostic>,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = syntax_errors;
+    if let Module::Module(module) = module {
+        let analysis = analyze_module(module);
+        let mut lints = lint::check_module(module, &analysis);
+        let interrupts = interrupts::check_task(&[module], std::slice::from_ref(&analysis));
+        lints.extend(interrupts.into_iter().flatten());
+        diagnostics.extend(analysis.diagnostics);
+        diagnostics.extend(lints);
+        lint::apply(&mut diagnostics, module, source, config);
+    }
+    diagnostics
+}
+
+/// Helpers shared by the tests of several modules.
+#[cfg(test)]
+mod test_util {
+    use rapid_parser::ast::Module;
+    use rapid_parser::parse_module;
+
+    use crate::analyze_module;
+
+    /// Analyses a module and returns its semantic diagnostics as `code: message`.
+    pub fn messages(input: &str) -> Vec<String> {
+        let Module::Module(module) = parse_module(input).into_result().unwrap() else {
+            panic!("expected a module");
+        };
+        analyze_module(&module)
+            .diagnostics
+            .into_iter()
+            .map(|d| format!("{}: {}", d.code, d.message))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapid_parser::ast::Module;
+    use rapid_parser::parse_module;
+
+    fn diagnostics(input: &str) -> Vec<Diagnostic> {
+        let Module::Module(module) = parse_module(input).into_result().unwrap() else {
+            panic!("expected a module");
+        };
+        analyze_module(&module).diagnostics
+    }
+
+    fn codes(input: &str) -> Vec<&'static str> {
+        diagnostics(input).iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn resolve_module_and_routine_names() {
+        let input = r#"
+            MODULE mymodule
+                RECORD point
+                    num x;
+                    num y;
+                ENDRECORD
+                VAR point origin;
+                PERS num counter := 0;
+                PROC main(num offset)
+                    VAR num increment := 1;
+                    counter := counter + increment + offset;
+                    origin.x := helper(counter);
+                    next:
+                    GOTO next;
+                ENDPROC
+                FUNC num helper(num value)
+                    RETURN value * 2;
+                ENDFUNC
+            ENDMODULE"#;
+        assert_eq!(diagnostics(input), vec![]);
+    }
+
+    #[test]
+    fn resolve_names_case_insensitively() {
+        let input = r#"
+            MODULE mymodule
+                VAR NUM Counter;
+                PROC main()
+                    COUNTER := counter + 1;
+                    Main;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(diagnostics(input), vec![]);
+    }
+
+    #[test]
+    fn report_undeclared_identifiers_and_types() {
+        let input = r#"
+            MODULE mymodule
+                VAR point origin;
+                PROC main()
+                    missing := 1;
+                    undefinedProc;
+                ENDPROC
+            ENDMODULE"#;
+        let diagnostics = diagnostics(input);
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unknown data type 'point'",
+                "undeclared identifier 'missing'",
+                "undeclared identifier 'undefinedProc'"
+            ]
+        );
+        let span = diagnostics[1].span;
+        assert_eq!(span.len(), "missing".len());
+    }
+
+    #[test]
+    fn report_duplicate_declarations() {
+        let input = r#"
+            MODULE mymodule
+                VAR num counter;
+                PERS num COUNTER;
+                PROC main(num value)
+                    VAR num value;
+                ENDPROC
+                PROC Main()
+                ENDPROC
+            ENDMODULE"#;
+        let diagnostics = diagnostics(input);
+        assert_eq!(
+            diagnostics.iter().map(|d| d.code).collect::<Vec<_>>(),
+            vec!["duplicate-declaration"; 3]
+        );
+        assert_eq!(diagnostics[0].related.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn report_shadowed_declarations() {
+        let input = r#"
+            MODULE mymodule
+                VAR num counter;
+                PROC main(num counter)
+                    FOR counter FROM 1 TO 10 DO
+                    ENDFOR
+                ENDPROC
+            ENDMODULE"#;
+        let diagnostics = diagnostics(input);
+        assert_eq!(
+            diagnostics.iter().map(|d| d.code).collect::<Vec<_>>(),
+            vec!["shadowed-parameter", "shadowed-declaration"]
+        );
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn report_invalid_scopes() {
+        let input = r#"
+            MODULE mymodule
+                TASK CONST num limit := 1;
+                TASK PERS num shared := 1;
+                PROC main()
+                    LOCAL VAR num temp;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(codes(input), vec!["invalid-scope", "invalid-scope"]);
+    }
+
+    #[test]
+    fn apply_safe_fixes() {
+        let input = r#"MODULE mymodule
+    PROC move(num x, \num speed, \switch fast)
+    ENDPROC
+    PROC main()
+        LOCAL VAR num temp;
+        move temp, speed:=2 \fast:=TRUE;
+    ENDPROC
+ENDMODULE"#;
+        let diagnostics = diagnose(input);
+        assert_eq!(
+            diagnostics.iter().filter(|d| !d.fixes.is_empty()).count(),
+            3
+        );
+        let (fixed, applied) = apply_fixes(input, &diagnostics);
+        assert_eq!(applied, 3);
+        assert_eq!(
+            fixed,
+            r#"MODULE mymodule
+    PROC move(num x, \num speed, \switch fast)
+    ENDPROC
+    PROC main()
+        VAR num temp;
+        move temp, \speed:=2 \fast;
+    ENDPROC
+ENDMODULE"#
+        );
+        assert_eq!(diagnose(&fixed), vec![]);
+    }
+
+    #[test]
+    fn record_scopes_and_references() {
+        let input = r#"
+            MODULE mymodule
+                LOCAL VAR num counter;
+                PROC main()
+                    FOR i FROM 1 TO 10 DO
+                        counter := counter + i;
+                    ENDFOR
+                ENDPROC
+            ENDMODULE"#;
+        let Module::Module(module) = parse_module(input).into_result().unwrap() else {
+            panic!("expected a module");
+        };
+        let analysis = analyze_module(&module);
+        let table = &analysis.symbols;
+        let counter = table.lookup(analysis.module_scope, "counter").unwrap();
+        assert_eq!(
+            table.symbol(counter).visibility,
+            rapid_parser::ast::Scope::LOCAL
+        );
+        let kinds: Vec<_> = table.references_to(counter).map(|r| r.kind).collect();
+        assert_eq!(kinds, vec![ReferenceKind::Write, ReferenceKind::Read]);
+
+        let offset = input.find("counter + i").unwrap();
+        let scope = table.scope_at(analysis.module_scope, offset);
+        assert_eq!(table.scope(scope).kind, ScopeKind::Loop);
+        assert!(table.lookup(scope, "i").is_some());
+        assert!(table.lookup(analysis.module_scope, "i").is_none());
+    }
+}
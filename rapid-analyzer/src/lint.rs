@@ -0,0 +1,966 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rapid_parser::ast::{
+    ModuleAttribute, ModuleInfo, RoutineDeclaration, Statement, StatementKind, TestCaseKind,
+    VarDeclarationType,
+};
+use rapid_parser::{LineIndex, Span};
+
+use crate::diagnostics::{Diagnostic, Edit, Severity};
+use crate::flow::{BlockKind, ControlFlowGraph, Region};
+use crate::symbols::{Reference, ReferenceKind, ScopeKind, Symbol, SymbolKind, SymbolTable};
+use crate::Analysis;
+
+/// The name of the project configuration file, looked up in the directory of a module and its
+/// ancestors.
+pub const CONFIG_FILE: &str = "rapid.toml";
+
+/// A named check whose diagnostics can be reconfigured or suppressed. The name is also the code of
+/// the diagnostics the rule reports.
+#[derive(PartialEq, Eq, Debug)]
+pub struct Rule {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub description: &'static str,
+}
+
+/// Every lint rule with its default severity.
+pub const RULES: &[Rule] = &[
+    Rule {
+        name: "unused-var",
+        severity: Severity::Warning,
+        description: "A VAR in a routine or a LOCAL VAR in a module is never read.",
+    },
+    Rule {
+        name: "unused-routine",
+        severity: Severity::Warning,
+        description: "A LOCAL routine is never called.",
+    },
+    Rule {
+        name: "unreachable-code",
+        severity: Severity::Warning,
+        description: "Statements follow a RETURN, EXIT, RAISE, RETRY, TRYNEXT or GOTO without a label in between.",
+    },
+    Rule {
+        name: "missing-label",
+        severity: Severity::Error,
+        description: "A GOTO jumps to a label that is not declared in its routine.",
+    },
+    Rule {
+        name: "shadowed-parameter",
+        severity: Severity::Warning,
+        description: "A parameter hides module data, a routine or a built-in of the same name.",
+    },
+    Rule {
+        name: "shadowed-declaration",
+        severity: Severity::Warning,
+        description: "Routine data or a loop variable hides a declaration of an outer scope.",
+    },
+    Rule {
+        name: "module-attributes",
+        severity: Severity::Error,
+        description: "Module attributes are out of order or mutually exclusive.",
+    },
+    Rule {
+        name: "declaration-order",
+        severity: Severity::Warning,
+        description: "A module does not declare its types, data and routines in that order.",
+    },
+    Rule {
+        name: "endless-retry",
+        severity: Severity::Warning,
+        description: "An error handler can only RETRY and does nothing before, so the failing instruction fails again.",
+    },
+    Rule {
+        name: "unconnected-trap",
+        severity: Severity::Warning,
+        description: "A global TRAP is never connected to an interrupt with CONNECT.",
+    },
+    Rule {
+        name: "reconnected-interrupt",
+        severity: Severity::Warning,
+        description: "An interrupt is connected again before IDelete deletes it.",
+    },
+    Rule {
+        name: "unconnected-interrupt",
+        severity: Severity::Warning,
+        description: "ITimer, ISignalDI or a similar instruction orders an interrupt that is never connected to a trap.",
+    },
+];
+
+/// Looks up a rule by name.
+pub fn rule(name: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.name == name)
+}
+
+/// An invalid or unreadable configuration file.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}: {}", path.display(), self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<String> for ConfigError {
+    fn from(message: String) -> Self {
+        ConfigError {
+            path: None,
+            message,
+        }
+    }
+}
+
+/// The severity of each rule, as set in the `[lint]` table of a configuration file:
+///
+/// ```toml
+/// [lint]
+/// unused-var = "allow"
+/// declaration-order = "error"
+/// ```
+///
+/// A rule is set to one of `allow`, `hint`, `info`, `warning` or `error`; `allow` turns it off.
+/// Rules that are not mentioned keep their default severity.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct LintConfig {
+    levels: HashMap<&'static str, Option<Severity>>,
+}
+
+impl LintConfig {
+    /// Parses the contents of a configuration file. Tables other than `[lint]` are ignored.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let table: toml::Table = text
+            .parse()
+            .map_err(|error: toml::de::Error| error.message().to_owned())?;
+        let mut config = LintConfig::default();
+        let Some(lint) = table.get("lint") else {
+            return Ok(config);
+        };
+        let lint = lint
+            .as_table()
+            .ok_or_else(|| "'lint' must be a table".to_owned())?;
+        for (name, value) in lint {
+            let rule = rule(name).ok_or_else(|| format!("unknown lint rule '{}'", name))?;
+            let level = value.as_str().and_then(parse_level).ok_or_else(|| {
+                format!(
+                    "invalid level for '{}', expected one of: allow, hint, info, warning, error",
+                    name
+                )
+            })?;
+            config.levels.insert(rule.name, level);
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let with_path = |message: String| ConfigError {
+            path: Some(path.to_owned()),
+            message,
+        };
+        let text = fs::read_to_string(path).map_err(|error| with_path(error.to_string()))?;
+        Self::parse(&text).map_err(|error| with_path(error.message))
+    }
+
+    /// Loads the nearest configuration file in `dir` or one of its ancestors.
+    pub fn discover(dir: &Path) -> Result<Option<(PathBuf, Self)>, ConfigError> {
+        for dir in dir.ancestors() {
+            let path = dir.join(CONFIG_FILE);
+            if path.is_file() {
+                let config = Self::load(&path)?;
+                return Ok(Some((path, config)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sets the severity of a rule; `None` turns the rule off.
+    pub fn set(&mut self, rule: &'static Rule, level: Option<Severity>) {
+        self.levels.insert(rule.name, level);
+    }
+
+    /// Returns the configured severity of a rule, or `None` if it is turned off.
+    pub fn level(&self, rule: &Rule) -> Option<Severity> {
+        self.levels
+            .get(rule.name)
+            .copied()
+            .unwrap_or(Some(rule.severity))
+    }
+}
+
+fn parse_level(level: &str) -> Option<Option<Severity>> {
+    match level {
+        "allow" => Some(None),
+        "hint" => Some(Some(Severity::Hint)),
+        "info" => Some(Some(Severity::Info)),
+        "warning" => Some(Some(Severity::Warning)),
+        "error" => Some(Some(Severity::Error)),
+        _ => None,
+    }
+}
+
+/// Runs every lint rule on an analysed module. The diagnostics have the default severity of their
+/// rule; [`apply`] applies the configuration and suppressions.
+pub fn check_module(module: &ModuleInfo, analysis: &Analysis) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check_module_attributes(module, &mut diagnostics);
+    check_declaration_order(module, &mut diagnostics);
+    check_unused(analysis, &mut diagnostics);
+    check_labels(
+        &analysis.symbols,
+        analysis.symbols.references(),
+        &mut diagnostics,
+    );
+    check_unreachable(&module.statements, &mut diagnostics);
+    for statement in &module.statements {
+        if let StatementKind::RoutineDeclaration(routine) = &statement.kind {
+            check_endless_retry(routine, &mut diagnostics);
+        }
+    }
+    diagnostics
+}
+
+/// Applies the configured severities to the diagnostics of lint rules and drops those that are
+/// turned off or suppressed in the source. Diagnostics without a rule are kept as they are.
+///
+/// A `! rapid-lint: allow(<rule>, ...)` comment suppresses the rules for the statement it trails, or
+/// for the next statement if it is on a line of its own. Trailing the `MODULE` line it applies to
+/// the whole module, trailing a routine head to the whole routine.
+pub fn apply(
+    diagnostics: &mut Vec<Diagnostic>,
+    module: &ModuleInfo,
+    source: &str,
+    config: &LintConfig,
+) {
+    let mut suppressions = Suppressions {
+        index: LineIndex::new(source),
+        allowed: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    suppressions.collect(&module.statements, module.span);
+    diagnostics.retain_mut(|diagnostic| {
+        let Some(rule) = rule(diagnostic.code) else {
+            return true;
+        };
+        let Some(severity) = config.level(rule) else {
+            return false;
+        };
+        diagnostic.severity = severity;
+        !suppressions.allows(rule.name, diagnostic.span.start)
+    });
+    diagnostics.extend(suppressions.diagnostics);
+}
+
+/// The `allow` comments of a module and the regions they apply to.
+struct Suppressions {
+    index: LineIndex,
+    allowed: Vec<(&'static str, Span)>,
+    /// Problems with the comments themselves, such as unknown rules.
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Suppressions {
+    fn line(&self, offset: usize) -> usize {
+        self.index.line_col(offset).line
+    }
+
+    /// Collects the `allow` comments in `statements`, which are nested in `parent`. Comments on the
+    /// first line of `parent` apply to all of it.
+    fn collect(&mut self, statements: &[Statement], parent: Span) {
+        for (i, statement) in statements.iter().enumerate() {
+            if let StatementKind::Comment(text) = &statement.kind {
+                let Some(directive) = parse_directive(text) else {
+                    continue;
+                };
+                let comment = Span::new(
+                    statement.span.start,
+                    statement.span.start + text.trim_end().len(),
+                );
+                let Ok(rules) = directive else {
+                    self.diagnostics.push(Diagnostic::warning(
+                        "lint-directive",
+                        "expected 'rapid-lint: allow(<rule>, ...)'",
+                        comment,
+                    ));
+                    continue;
+                };
+                let line = self.line(statement.span.start);
+                let target = match i.checked_sub(1).map(|previous| &statements[previous]) {
+                    Some(previous)
+                        if !is_comment(previous) && self.line(previous.span.end) == line =>
+                    {
+                        Some(previous.span)
+                    }
+                    None if self.line(parent.start) == line => Some(parent),
+                    _ => statements[i + 1..]
+                        .iter()
+                        .find(|next| !is_comment(next))
+                        .map(|next| next.span),
+                };
+                for name in rules {
+                    match rule(name) {
+                        Some(rule) => {
+                            if let Some(target) = target {
+                                self.allowed.push((rule.name, target));
+                            }
+                        }
+                        None => self.diagnostics.push(Diagnostic::warning(
+                            "lint-directive",
+                            format!("unknown lint rule '{}'", name),
+                            comment,
+                        )),
+                    }
+                }
+            }
+            for block in blocks(statement) {
+                self.collect(block, statement.span);
+            }
+        }
+    }
+
+    fn allows(&self, rule: &str, offset: usize) -> bool {
+        self.allowed
+            .iter()
+            .any(|(allowed, span)| *allowed == rule && span.start <= offset && offset < span.end)
+    }
+}
+
+fn is_comment(statement: &Statement) -> bool {
+    matches!(statement.kind, StatementKind::Comment(_))
+}
+
+/// Parses a `! rapid-lint: allow(a, b)` comment. Returns `None` for ordinary comments and an error
+/// for comments that start like a directive but are malformed.
+fn parse_directive(text: &str) -> Option<Result<Vec<&str>, ()>> {
+    let rest = text.strip_prefix('!')?.trim().strip_prefix("rapid-lint:")?;
+    let rules = rest
+        .trim()
+        .strip_prefix("allow")
+        .map(str::trim_start)
+        .and_then(|rest| rest.strip_prefix('('))
+        .and_then(|rest| rest.strip_suffix(')'));
+    Some(match rules {
+        Some(rules) => Ok(rules
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .collect()),
+        None => Err(()),
+    })
+}
+
+/// Returns the statement lists nested directly in a statement, including the bodies and handlers of
+/// routines.
+fn blocks(statement: &Statement) -> Vec<&[Statement]> {
+    match &statement.kind {
+        StatementKind::RoutineDeclaration(routine) => match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => {
+                let mut blocks = vec![&proc.statements[..]];
+                blocks.extend(proc.backward_handler.as_deref());
+                blocks.extend(proc.error_handler.as_ref().map(|h| &h.statements[..]));
+                blocks.extend(proc.undo_handler.as_deref());
+                blocks
+            }
+            RoutineDeclaration::FuncDeclaration(func) => {
+                let mut blocks = vec![&func.statements[..]];
+                blocks.extend(func.error_handler.as_ref().map(|h| &h.statements[..]));
+                blocks.extend(func.undo_handler.as_deref());
+                blocks
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => {
+                let mut blocks = vec![&trap.statements[..]];
+                blocks.extend(trap.error_handler.as_ref().map(|h| &h.statements[..]));
+                blocks.extend(trap.undo_handler.as_deref());
+                blocks
+            }
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => Vec::new(),
+        },
+        StatementKind::If(_, statements, else_ifs, else_statements) => {
+            let mut blocks = vec![&statements[..]];
+            blocks.extend(else_ifs.iter().map(|(_, statements)| &statements[..]));
+            blocks.push(else_statements);
+            blocks
+        }
+        StatementKind::For(.., statements) | StatementKind::While(_, statements) => {
+            vec![statements]
+        }
+        StatementKind::Test(_, cases, default) => {
+            let mut blocks: Vec<&[Statement]> = cases
+                .iter()
+                .filter_map(|case| match &case.kind {
+                    TestCaseKind::Case(_, statements) => Some(&statements[..]),
+                    TestCaseKind::CSE => None,
+                })
+                .collect();
+            blocks.extend(default.as_deref());
+            blocks
+        }
+        _ => Vec::new(),
+    }
+}
+
+pub(crate) fn check_module_attributes(module: &ModuleInfo, diagnostics: &mut Vec<Diagnostic>) {
+    let attributes = &module.attributes;
+    let span = module.attributes_span;
+    if !attributes.windows(2).all(|pair| pair[0] <= pair[1]) {
+        let mut sorted: Vec<_> = attributes.iter().collect();
+        sorted.sort();
+        let names: Vec<String> = sorted.iter().map(|a| format!("{:?}", a)).collect();
+        diagnostics.push(
+            Diagnostic::error(
+                "module-attributes",
+                "module attributes must be given in the order SYSMODULE, NOSTEPIN, VIEWONLY, READONLY, NOVIEW",
+                span,
+            )
+            .with_fix(
+                "reorder the attributes",
+                vec![Edit {
+                    span,
+                    replacement: format!("({})", names.join(", ")),
+                }],
+            ),
+        );
+    }
+    let conflicts = [
+        (ModuleAttribute::NOVIEW, ModuleAttribute::NOSTEPIN),
+        (ModuleAttribute::NOVIEW, ModuleAttribute::VIEWONLY),
+        (ModuleAttribute::NOVIEW, ModuleAttribute::READONLY),
+        (ModuleAttribute::VIEWONLY, ModuleAttribute::READONLY),
+    ];
+    for (a, b) in conflicts {
+        if attributes.contains(&a) && attributes.contains(&b) {
+            diagnostics.push(Diagnostic::error(
+                "module-attributes",
+                format!("{:?} cannot be combined with {:?}", a, b),
+                span,
+            ));
+        }
+    }
+}
+
+pub(crate) fn check_declaration_order(module: &ModuleInfo, diagnostics: &mut Vec<Diagnostic>) {
+    let mut first_data = None;
+    let mut first_routine = None;
+    for statement in &module.statements {
+        match &statement.kind {
+            StatementKind::TypeDefinition(_) => {
+                if let Some(earlier) = first_data.or(first_routine) {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            "declaration-order",
+                            "type definitions must come before data and routine declarations",
+                            statement.span,
+                        )
+                        .with_related("first declared here", earlier),
+                    );
+                }
+            }
+            StatementKind::DataDeclaration(_) => {
+                if let Some(routine) = first_routine {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            "declaration-order",
+                            "data declarations must come before routine declarations",
+                            statement.span,
+                        )
+                        .with_related("first routine declared here", routine),
+                    );
+                }
+                first_data.get_or_insert(statement.span);
+            }
+            StatementKind::RoutineDeclaration(_) => {
+                first_routine.get_or_insert(statement.span);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Reports VARs that are never read and LOCAL routines that are never called. Global data and
+/// routines may be used by other modules of the task, so they are not reported.
+fn check_unused(analysis: &Analysis, diagnostics: &mut Vec<Diagnostic>) {
+    let table = &analysis.symbols;
+    let mut unused: Vec<_> = table
+        .symbols()
+        .filter(|(id, _)| !table.is_builtin(*id))
+        .filter_map(|(id, symbol)| {
+            let references: Vec<_> = table.references_to(id).map(|r| r.kind).collect();
+            unused_symbol(table, symbol, &references)
+        })
+        .collect();
+    unused.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics.extend(unused);
+}
+
+/// Reports a symbol if it is a local VAR that is never read or a LOCAL routine that is never
+/// called, given how it is referenced.
+pub(crate) fn unused_symbol(
+    table: &SymbolTable,
+    symbol: &Symbol,
+    references: &[ReferenceKind],
+) -> Option<Diagnostic> {
+    let scope = table.scope(symbol.scope).kind;
+    let local = scope == ScopeKind::Routine || symbol.visibility == rapid_parser::ast::Scope::LOCAL;
+    if !local {
+        return None;
+    }
+    match symbol.kind {
+        SymbolKind::Data(decl) if decl.declaration_type == VarDeclarationType::VarDeclaration => {
+            if references.iter().any(|kind| *kind != ReferenceKind::Write) {
+                return None;
+            }
+            let message = if !references.is_empty() {
+                format!("variable '{}' is assigned but never read", symbol.name)
+            } else {
+                format!("variable '{}' is never used", symbol.name)
+            };
+            Some(Diagnostic::warning("unused-var", message, symbol.name_span))
+        }
+        kind if kind.is_routine() && scope == ScopeKind::Module && references.is_empty() => {
+            Some(Diagnostic::warning(
+                "unused-routine",
+                format!("{} '{}' is never used", kind.description(), symbol.name),
+                symbol.name_span,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Reports the `GOTO`s among `references` whose label is not declared in their routine.
+pub(crate) fn check_labels(
+    table: &SymbolTable,
+    references: &[Reference],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for reference in references {
+        if reference.kind != ReferenceKind::Label {
+            continue;
+        }
+        let is_label = reference
+            .symbol
+            .is_some_and(|symbol| table.symbol(symbol).kind == SymbolKind::Label);
+        if !is_label {
+            diagnostics.push(Diagnostic::error(
+                "missing-label",
+                format!("no label '{}' in this routine", reference.name),
+                reference.span,
+            ));
+        }
+    }
+}
+
+/// Returns the keyword of a statement after which execution never continues with the next one.
+fn exit_keyword(statement: &Statement) -> Option<&'static str> {
+    match statement.kind {
+        StatementKind::Return(_) => Some("RETURN"),
+        StatementKind::Exit => Some("EXIT"),
+        StatementKind::Raise(_) => Some("RAISE"),
+        StatementKind::Retry => Some("RETRY"),
+        StatementKind::TryNext => Some("TRYNEXT"),
+        StatementKind::Goto(_) => Some("GOTO"),
+        _ => None,
+    }
+}
+
+/// Reports the statements between an exit such as `RETURN` and the next label, which a `GOTO` could
+/// jump to.
+fn check_unreachable(statements: &[Statement], diagnostics: &mut Vec<Diagnostic>) {
+    check_unreachable_sequence(statements, diagnostics);
+    for statement in statements {
+        check_unreachable_blocks(statement, diagnostics);
+    }
+}
+
+/// Reports unreachable code in the blocks nested in a statement, such as the body of a routine.
+pub(crate) fn check_unreachable_blocks(statement: &Statement, diagnostics: &mut Vec<Diagnostic>) {
+    for block in blocks(statement) {
+        check_unreachable(block, diagnostics);
+    }
+}
+
+/// Reports unreachable code in a sequence of statements, without the blocks nested in them.
+pub(crate) fn check_unreachable_sequence(
+    statements: &[Statement],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut exit: Option<(&Statement, &'static str)> = None;
+    let mut unreachable: Option<Span> = None;
+    let mut report = |exit: Option<(&Statement, &str)>, unreachable: Option<Span>| {
+        if let (Some((statement, keyword)), Some(span)) = (exit, unreachable) {
+            diagnostics.push(
+                Diagnostic::warning("unreachable-code", "unreachable code", span).with_related(
+                    format!("any code following this {} is unreachable", keyword),
+                    statement.span,
+                ),
+            );
+        }
+    };
+    for statement in statements {
+        match statement.kind {
+            StatementKind::Comment(_) => continue,
+            StatementKind::Label(_) => {
+                report(exit.take(), unreachable.take());
+                continue;
+            }
+            _ => {}
+        }
+        if exit.is_some() {
+            unreachable = Some(match unreachable {
+                Some(span) => Span::new(span.start, statement.span.end),
+                None => statement.span,
+            });
+        } else if let Some(keyword) = exit_keyword(statement) {
+            exit = Some((statement, keyword));
+        }
+    }
+    report(exit, unreachable);
+}
+
+/// Reports an error handler that cannot end other than with `RETRY` and does nothing before it, so
+/// the instruction that failed runs again under the same conditions and fails again.
+pub(crate) fn check_endless_retry(routine: &RoutineDeclaration, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(graph) = ControlFlowGraph::new(routine) else {
+        return;
+    };
+    let Some(error) = graph.error else {
+        return;
+    };
+    // Walk the handler up to where it leaves, noting anything other than RETRY it does
+    let mut seen = vec![false; graph.blocks.len()];
+    let mut stack = vec![error];
+    let mut retries = Vec::new();
+    let mut acts = false;
+    while let Some(block) = stack.pop() {
+        if std::mem::replace(&mut seen[block], true) {
+            continue;
+        }
+        // RETRY ends its block, leading back into the body
+        match graph.blocks[block].statements.as_slice() {
+            [] => {}
+            [retry] if retry.kind == StatementKind::Retry => {
+                retries.push(retry.span);
+                continue;
+            }
+            _ => acts = true,
+        }
+        let mut successors = graph.successors(block).peekable();
+        if successors.peek().is_none() {
+            acts = true;
+        }
+        for edge in successors {
+            if graph.blocks[edge.to].kind == BlockKind::Code(Region::Error) {
+                stack.push(edge.to);
+            } else {
+                acts = true;
+            }
+        }
+    }
+    if acts {
+        return;
+    }
+    if let Some(span) = retries.into_iter().min_by_key(|span| span.start) {
+        diagnostics.push(Diagnostic::warning(
+            "endless-retry",
+            "the error handler always retries without changing anything, so the failing instruction fails again",
+            span,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnose_with;
+
+    fn lint(input: &str, config: &LintConfig) -> Vec<(&'static str, Severity, String)> {
+        diagnose_with(input, config)
+            .into_iter()
+            .map(|d| (d.code, d.severity, d.message))
+            .collect()
+    }
+
+    fn codes(input: &str) -> Vec<&'static str> {
+        lint(input, &LintConfig::default())
+            .into_iter()
+            .map(|(code, ..)| code)
+            .collect()
+    }
+
+    #[test]
+    fn report_unused_data_and_routines() {
+        let input = r#"MODULE mymodule
+    VAR num shared;
+    LOCAL VAR num hidden;
+    LOCAL PROC helper()
+    ENDPROC
+    LOCAL PROC used()
+    ENDPROC
+    LOCAL FUNC num twice(num x)
+        RETURN 2 * x;
+    ENDFUNC
+    LOCAL TRAP handler
+    ENDTRAP
+    PROC main()
+        VAR num written;
+        VAR num unused;
+        VAR num read;
+        written := read;
+        used;
+    ENDPROC
+ENDMODULE"#;
+        assert_eq!(
+            lint(input, &LintConfig::default()),
+            vec![
+                (
+                    "unused-var",
+                    Severity::Warning,
+                    "variable 'hidden' is never used".to_owned()
+                ),
+                (
+                    "unused-routine",
+                    Severity::Warning,
+                    "procedure 'helper' is never used".to_owned()
+                ),
+                (
+                    "unused-routine",
+                    Severity::Warning,
+                    "function 'twice' is never used".to_owned()
+                ),
+                (
+                    "unused-routine",
+                    Severity::Warning,
+                    "trap 'handler' is never used".to_owned()
+                ),
+                (
+                    "unused-var",
+                    Severity::Warning,
+                    "variable 'written' is assigned but never read".to_owned()
+                ),
+                (
+                    "unused-var",
+                    Severity::Warning,
+                    "variable 'unused' is never used".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_unreachable_code_and_missing_labels() {
+        let input = r#"MODULE mymodule
+    PROC main()
+        VAR num i;
+        i := 0;
+        again:
+        i := i + 1;
+        IF i < 10 THEN
+            GOTO again;
+            ! the comment is not code
+            i := 0;
+            i := 1;
+        ENDIF
+        GOTO done;
+        RETURN;
+        ! reachable again
+        finish:
+        RETURN;
+    ENDPROC
+ENDMODULE"#;
+        let diagnostics = diagnose_with(input, &LintConfig::default());
+        let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec!["missing-label", "unreachable-code", "unreachable-code"]
+        );
+        assert_eq!(diagnostics[0].message, "no label 'done' in this routine");
+        let unreachable = &diagnostics[2];
+        assert_eq!(
+            &input[unreachable.span.start..unreachable.span.end],
+            "i := 0;\n            i := 1;"
+        );
+        assert_eq!(
+            unreachable.related[0].message,
+            "any code following this GOTO is unreachable"
+        );
+        let after_goto = &diagnostics[1];
+        assert_eq!(
+            &input[after_goto.span.start..after_goto.span.end],
+            "RETURN;"
+        );
+    }
+
+    #[test]
+    fn report_endless_retry() {
+        let input = r#"MODULE mymodule
+    VAR num attempts;
+    PROC stubborn()
+        attempts := 1 / attempts;
+    ERROR
+        IF ERRNO = ERR_DIVZERO THEN
+            RETRY;
+        ENDIF
+        ! nothing to fix
+        RETRY;
+    ENDPROC
+    PROC patient()
+        attempts := 1 / attempts;
+    ERROR
+        IF attempts < 3 THEN
+            WaitTime 1;
+            RETRY;
+        ENDIF
+        RAISE;
+    ENDPROC
+    PROC once()
+        attempts := 1 / attempts;
+    ERROR
+        IF ERRNO = ERR_DIVZERO RETRY;
+    ENDPROC
+ENDMODULE"#;
+        let diagnostics = diagnose_with(input, &LintConfig::default());
+        let codes: Vec<_> = diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec!["endless-retry"]);
+        let retry = diagnostics[0].span;
+        assert_eq!(retry.start, input.find("RETRY").unwrap());
+    }
+
+    #[test]
+    fn report_shadowed_parameters() {
+        let input = r#"MODULE mymodule
+    VAR num speed;
+    PROC main(num speed)
+        VAR num total;
+        FOR speed FROM 1 TO 2 DO
+            total := total + speed;
+        ENDFOR
+    ENDPROC
+ENDMODULE"#;
+        assert_eq!(
+            codes(input),
+            vec!["shadowed-parameter", "shadowed-declaration"]
+        );
+    }
+
+    #[test]
+    fn check_module_attributes_and_declaration_order() {
+        let input = r#"MODULE mymodule (NOVIEW, SYSMODULE, READONLY)
+    PROC main()
+    ENDPROC
+    VAR num late;
+    RECORD point
+        num x;
+    ENDRECORD
+ENDMODULE"#;
+        let diagnostics = diagnose_with(input, &LintConfig::default());
+        let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "module attributes must be given in the order SYSMODULE, NOSTEPIN, VIEWONLY, READONLY, NOVIEW",
+                "NOVIEW cannot be combined with READONLY",
+                "data declarations must come before routine declarations",
+                "type definitions must come before data and routine declarations",
+            ]
+        );
+        let (fixed, applied) = crate::apply_fixes(input, &diagnostics);
+        assert_eq!(applied, 1);
+        assert!(fixed.starts_with("MODULE mymodule (SYSMODULE, READONLY, NOVIEW)\n"));
+    }
+
+    #[test]
+    fn suppress_rules_with_comments() {
+        let input = r#"MODULE mymodule ! rapid-lint: allow(unused-routine)
+    LOCAL PROC helper()
+    ENDPROC
+    PROC main()
+        ! rapid-lint: allow(unused-var)
+        VAR num unused;
+        VAR num trailing; ! rapid-lint: allow(unused-var, unreachable-code)
+        VAR num reported;
+        RETURN;
+        ! rapid-lint: allow(unreachable-code)
+        main;
+    ENDPROC
+    PROC other() ! rapid-lint: allow(unused-var)
+        VAR num ignored;
+        ! rapid-lint: allow(no-such-rule)
+        ! rapid-lint: deny(unused-var)
+    ENDPROC
+ENDMODULE"#;
+        assert_eq!(
+            lint(input, &LintConfig::default()),
+            vec![
+                (
+                    "unused-var",
+                    Severity::Warning,
+                    "variable 'reported' is never used".to_owned()
+                ),
+                (
+                    "lint-directive",
+                    Severity::Warning,
+                    "unknown lint rule 'no-such-rule'".to_owned()
+                ),
+                (
+                    "lint-directive",
+                    Severity::Warning,
+                    "expected 'rapid-lint: allow(<rule>, ...)'".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn configure_rule_severities() {
+        let config = LintConfig::parse(
+            r#"
+[format]
+indent = 2
+
+[lint]
+unused-var = "allow"
+declaration-order = "error"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.level(rule("unused-var").unwrap()), None);
+        assert_eq!(
+            config.level(rule("unused-routine").unwrap()),
+            Some(Severity::Warning)
+        );
+        let input = r#"MODULE mymodule
+    PROC main()
+        VAR num unused;
+    ENDPROC
+    VAR num late;
+ENDMODULE"#;
+        assert_eq!(
+            lint(input, &config),
+            vec![(
+                "declaration-order",
+                Severity::Error,
+                "data declarations must come before routine declarations".to_owned()
+            )]
+        );
+
+        let error = LintConfig::parse("[lint]\nunused = \"warning\"").unwrap_err();
+        assert_eq!(error.message, "unknown lint rule 'unused'");
+        let error = LintConfig::parse("[lint]\nunused-var = \"deny\"").unwrap_err();
+        assert_eq!(
+            error.message,
+            "invalid level for 'unused-var', expected one of: allow, hint, info, warning, error"
+        );
+        assert!(LintConfig::parse("[lint").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use rapid_parser::ast::{
+    DataDeclaration, ModuleInfo, RoutineDeclaration, Statement, StatementKind, TypeDefinition,
+    VarDeclaration, VarDeclarationType,
+};
+use rapid_parser::Span;
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum OutlineKind {
+    Module,
+    Record,
+    Component,
+    Alias,
+    Constant,
+    Variable,
+    Persistent,
+    Procedure,
+    Function,
+    Trap,
+}
+
+impl OutlineKind {
+    /// The lowercase name of the kind, as used in JSON output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutlineKind::Module => "module",
+            OutlineKind::Record => "record",
+            OutlineKind::Component => "component",
+            OutlineKind::Alias => "alias",
+            OutlineKind::Constant => "constant",
+            OutlineKind::Variable => "variable",
+            OutlineKind::Persistent => "persistent",
+            OutlineKind::Procedure => "procedure",
+            OutlineKind::Function => "function",
+            OutlineKind::Trap => "trap",
+        }
+    }
+}
+
+/// An entry of the document outline, such as a routine with its local data as children.
+#[derive(PartialEq, Debug, Clone)]
+pub struct OutlineItem {
+    pub name: String,
+    /// The data type of data and components, or the routine kind and return type.
+    pub detail: Option<String>,
+    pub kind: OutlineKind,
+    /// The whole declaration.
+    pub span: Span,
+    pub name_span: Span,
+    pub children: Vec<OutlineItem>,
+}
+
+/// Returns the outline of a module: the module with its records, aliases, data and routines.
+/// Routines contain their local data declarations.
+pub fn outline(module: &ModuleInfo) -> OutlineItem {
+    OutlineItem {
+        name: module.name.clone(),
+        detail: None,
+        kind: OutlineKind::Module,
+        span: module.span,
+        name_span: module.name_span,
+        children: statement_items(&module.statements),
+    }
+}
+
+fn statement_items(statements: &[Statement]) -> Vec<OutlineItem> {
+    statements
+        .iter()
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::TypeDefinition(TypeDefinition::RecordDefinition(_, record)) => {
+                let components = record
+                    .components
+                    .iter()
+                    .map(|component| OutlineItem {
+                        name: component.name.clone(),
+                        detail: Some(component.data_type.clone()),
+                        kind: OutlineKind::Component,
+                        span: component.span,
+                        name_span: component.name_span,
+                        children: Vec::new(),
+                    })
+                    .collect();
+                Some(OutlineItem {
+                    name: record.name.clone(),
+                    detail: None,
+                    kind: OutlineKind::Record,
+                    span: record.span,
+                    name_span: record.name_span,
+                    children: components,
+                })
+            }
+            StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(_, alias)) => {
+                Some(OutlineItem {
+                    name: alias.name.clone(),
+                    detail: Some(alias.data_type.clone()),
+                    kind: OutlineKind::Alias,
+                    span: alias.span,
+                    name_span: alias.name_span,
+                    children: Vec::new(),
+                })
+            }
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                Some(data_item(decl))
+            }
+            StatementKind::RoutineDeclaration(routine) => {
+                let (kind, detail, name, name_span, statements) = match routine {
+                    RoutineDeclaration::ProcDeclaration(proc) => (
+                        OutlineKind::Procedure,
+                        "PROC".to_owned(),
+                        &proc.name,
+                        proc.name_span,
+                        &proc.statements,
+                    ),
+                    RoutineDeclaration::FuncDeclaration(func) => (
+                        OutlineKind::Function,
+                        format!("FUNC {}", func.data_type),
+                        &func.name,
+                        func.name_span,
+                        &func.statements,
+                    ),
+                    RoutineDeclaration::TrapDeclaration(trap) => (
+                        OutlineKind::Trap,
+                        "TRAP".to_owned(),
+                        &trap.name,
+                        trap.name_span,
+                        &trap.statements,
+                    ),
+                    RoutineDeclaration::RDN | RoutineDeclaration::Error => return None,
+                };
+                let locals = statements
+                    .iter()
+                    .filter_map(|statement| match &statement.kind {
+                        StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(
+                            _,
+                            decl,
+                        )) => Some(data_item(decl)),
+                        _ => None,
+                    })
+                    .collect();
+                Some(OutlineItem {
+                    name: name.clone(),
+                    detail: Some(detail),
+                    kind,
+                    span: statement.span,
+                    name_span,
+                    children: locals,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn data_item(decl: &VarDeclaration) -> OutlineItem {
+    let kind = match decl.declaration_type {
+        VarDeclarationType::ConstDeclaration => OutlineKind::Constant,
+        VarDeclarationType::VarDeclaration => OutlineKind::Variable,
+        VarDeclarationType::PersDeclaration => OutlineKind::Persistent,
+    };
+    OutlineItem {
+        name: decl.definition.identifier.clone(),
+        detail: Some(decl.data_type.clone()),
+        kind,
+        span: decl.span,
+        name_span: decl.definition.identifier_span,
+        children: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapid_parser::ast::Module;
+    use rapid_parser::parse_module;
+
+    #[test]
+    fn outline_module_with_routine_locals() {
+        let source = "MODULE demo\n    RECORD point\n        num x;\n    ENDRECORD\n    PERS num count := 0;\n    FUNC num twice(num a)\n        CONST num factor := 2;\n        RETURN a * factor;\n    ENDFUNC\nENDMODULE";
+        let Module::Module(module) = parse_module(source).module else {
+            panic!("module should parse");
+        };
+        let outline = outline(&module);
+        assert_eq!(outline.kind, OutlineKind::Module);
+        let items: Vec<_> = outline
+            .children
+            .iter()
+            .map(|item| (item.name.as_str(), item.kind, item.detail.as_deref()))
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                ("point", OutlineKind::Record, None),
+                ("count", OutlineKind::Persistent, Some("num")),
+                ("twice", OutlineKind::Function, Some("FUNC num")),
+            ]
+        );
+        assert_eq!(outline.children[0].children[0].kind, OutlineKind::Component);
+        let local = &outline.children[2].children[0];
+        assert_eq!(
+            (local.name.as_str(), local.kind),
+            ("factor", OutlineKind::Constant)
+        );
+        assert_eq!(
+            &source[local.name_span.start..local.name_span.end],
+            "factor"
+        );
+    }
+}
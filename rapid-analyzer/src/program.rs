@@ -0,0 +1,982 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rapid_parser::ast::{
+    self, DimensionKind, ExprKind, Module, ModuleInfo, Number, Scope, Term, VarDeclarationType,
+};
+use rapid_parser::lexer::{Lexer, SyntaxKind};
+use rapid_parser::parse_module;
+
+use crate::diagnostics::Diagnostic;
+use crate::symbols::{ReferenceKind, Symbol, SymbolId, SymbolKind};
+use crate::{analyze_task_module, interrupts, lint, Analysis, LintConfig};
+
+/// A source file of a task. Module files hold one module, legacy `.prg` program files may hold
+/// several.
+#[derive(Debug)]
+pub struct TaskFile {
+    pub path: PathBuf,
+    pub source: String,
+    /// The modules of the file, in the order they appear. Their spans refer to `source`.
+    pub modules: Vec<Module>,
+    pub syntax_errors: Vec<Diagnostic>,
+}
+
+impl TaskFile {
+    fn new(path: PathBuf, source: String) -> Self {
+        let (modules, syntax_errors) = parse_file(&source);
+        TaskFile {
+            path,
+            source,
+            modules,
+            syntax_errors,
+        }
+    }
+}
+
+/// The program modules and system modules loaded into one task of the controller.
+///
+/// Every module sees the global declarations of the other modules of the task, including `TASK
+/// PERS` persistents, while `LOCAL` declarations stay private to their module.
+#[derive(Debug, Default)]
+pub struct Task {
+    pub name: String,
+    pub files: Vec<TaskFile>,
+    /// The I/O signals of the controller, which every module of the task can use.
+    pub signals: Vec<Signal>,
+    /// A system module declaring `signals`, whose declarations the modules of the task see next to
+    /// those of the other modules.
+    signal_module: Option<Module>,
+}
+
+/// An I/O signal configured on the controller, such as in the `EIO.cfg` of a backup.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Signal {
+    pub name: String,
+    /// The data type of the signal: `signaldi`, `signaldo`, `signalai`, `signalao`, `signalgi` or
+    /// `signalgo`.
+    pub data_type: String,
+}
+
+impl Task {
+    pub fn new(name: impl Into<String>) -> Self {
+        Task {
+            name: name.into(),
+            ..Task::default()
+        }
+    }
+
+    /// Loads a task from a `.pgf` program file, which lists the module files of the program, or
+    /// from a single module or `.prg` file. The task is named after the file.
+    pub fn load(path: &Path) -> io::Result<Task> {
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut task = Task::new(name);
+        let source = read_source(path)?;
+        let is_pgf = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pgf"));
+        if !is_pgf {
+            task.add_file(path, source);
+            return Ok(task);
+        }
+        let modules = program_modules(&source)
+            .map_err(|error| io::Error::other(format!("{}: {}", path.display(), error)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for module in modules {
+            let path = dir.join(module);
+            let source = read_source(&path)?;
+            task.add_file(path, source);
+        }
+        Ok(task)
+    }
+
+    /// Parses a module or program file and adds its modules to the task.
+    pub fn add_file(&mut self, path: impl Into<PathBuf>, source: String) {
+        self.files.push(TaskFile::new(path.into(), source));
+    }
+
+    /// Adds I/O signals of the controller, which every module of the task can then use. Signals
+    /// whose name is not a RAPID identifier are ignored.
+    pub fn add_signals(&mut self, signals: impl IntoIterator<Item = Signal>) {
+        self.signals.extend(signals.into_iter().filter(|signal| {
+            let mut chars = signal.name.chars();
+            chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }));
+        let mut source = String::from("MODULE EIO_SIGNALS (SYSMODULE, NOVIEW)\n");
+        for signal in &self.signals {
+            source.push_str(&format!("    VAR {} {};\n", signal.data_type, signal.name));
+        }
+        source.push_str("ENDMODULE\n");
+        self.signal_module = Some(parse_module(&source).module);
+    }
+
+    /// Replaces the source of the file at `index` and parses it again, such as after applying
+    /// fixes to it.
+    pub fn set_source(&mut self, index: usize, source: String) {
+        let path = std::mem::take(&mut self.files[index].path);
+        self.files[index] = TaskFile::new(path, source);
+    }
+
+    /// Returns every module of the task that could be parsed, with the index of its file.
+    pub fn modules(&self) -> impl Iterator<Item = (usize, &ModuleInfo)> {
+        self.files.iter().enumerate().flat_map(|(index, file)| {
+            file.modules.iter().filter_map(move |module| match module {
+                Module::Module(module) => Some((index, module)),
+                Module::Error => None,
+            })
+        })
+    }
+
+    /// Analyses every module against the global declarations of the others and the signals of the
+    /// task, and reports global names that several modules declare.
+    ///
+    /// # Returns
+    ///
+    /// One `Analysis` per module, in the order of [`Task::modules`].
+    pub fn analyze(&self) -> Vec<Analysis<'_>> {
+        let modules: Vec<&ModuleInfo> = self.modules().map(|(_, module)| module).collect();
+        let signals = match &self.signal_module {
+            Some(Module::Module(module)) => Some(module),
+            _ => None,
+        };
+        let mut analyses: Vec<Analysis> = modules
+            .iter()
+            .enumerate()
+            .map(|(index, module)| {
+                let others: Vec<&ModuleInfo> = modules
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != index)
+                    .map(|(_, other)| *other)
+                    .chain(signals)
+                    .collect();
+                analyze_task_module(module, &others)
+            })
+            .collect();
+        check_globals(&modules, &mut analyses);
+        analyses
+    }
+
+    /// Like [`crate::diagnose_with`] for every file of the task, analysing the modules together.
+    ///
+    /// # Returns
+    ///
+    /// The diagnostics of each file, in the order of [`Task::files`].
+    pub fn diagnose(&self, config: &LintConfig) -> Vec<Vec<Diagnostic>> {
+        self.diagnose_analyses(self.analyze(), config)
+    }
+
+    /// Collects the syntax errors of each file, the diagnostics of `analyses` and the findings of
+    /// the lint rules.
+    fn diagnose_analyses(
+        &self,
+        analyses: Vec<Analysis>,
+        config: &LintConfig,
+    ) -> Vec<Vec<Diagnostic>> {
+        let mut diagnostics: Vec<Vec<Diagnostic>> = self
+            .files
+            .iter()
+            .map(|file| file.syntax_errors.clone())
+            .collect();
+        let modules: Vec<&ModuleInfo> = self.modules().map(|(_, module)| module).collect();
+        let interrupts = interrupts::check_task(&modules, &analyses);
+        for (((index, module), analysis), interrupts) in
+            self.modules().zip(analyses).zip(interrupts)
+        {
+            let lints = lint::check_module(module, &analysis);
+            let mut found = analysis.diagnostics;
+            found.extend(lints);
+            found.extend(interrupts);
+            lint::apply(&mut found, module, &self.files[index].source, config);
+            diagnostics[index].extend(found);
+        }
+        diagnostics
+    }
+}
+
+/// The tasks of a multitasking controller. A global persistent is shared by every task that
+/// declares it, while a `TASK PERS` persistent belongs to its task.
+#[derive(Debug, Default)]
+pub struct System {
+    pub tasks: Vec<Task>,
+}
+
+/// How one task uses a shared persistent.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PersistentAccess {
+    /// The index of the task in [`System::tasks`].
+    pub task: usize,
+    pub reads: bool,
+    /// Whether the task assigns the persistent or passes it to a `VAR`, `INOUT` or `PERS` parameter
+    /// that may change it. Built-in routines only change the `PERS` parameters they write, such as
+    /// the `DestObject` of `GetSysData`.
+    pub writes: bool,
+}
+
+/// A global persistent declared by several tasks, which share its value.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SharedPersistent {
+    pub name: String,
+    /// The data type in the first task that declares it.
+    pub data_type: String,
+    /// The tasks that declare it, in the order of [`System::tasks`].
+    pub tasks: Vec<PersistentAccess>,
+}
+
+/// The analysis of every task of a system.
+#[derive(Debug)]
+pub struct SystemAnalysis<'a> {
+    /// The analyses of the modules of each task, as returned by [`Task::analyze`].
+    pub tasks: Vec<Vec<Analysis<'a>>>,
+    pub shared: Vec<SharedPersistent>,
+}
+
+impl System {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_task(&mut self, task: Task) {
+        self.tasks.push(task);
+    }
+
+    /// Analyses every task and cross-checks the persistents they share, which must agree on their
+    /// type and dimensions.
+    pub fn analyze(&self) -> SystemAnalysis<'_> {
+        let mut tasks: Vec<Vec<Analysis>> = self.tasks.iter().map(Task::analyze).collect();
+        let shared = check_shared_persistents(&self.tasks, &mut tasks);
+        SystemAnalysis { tasks, shared }
+    }
+
+    /// Like [`Task::diagnose`] for every task of the system.
+    ///
+    /// # Returns
+    ///
+    /// The diagnostics of each file of each task, in the order of [`System::tasks`].
+    pub fn diagnose(&self, config: &LintConfig) -> Vec<Vec<Vec<Diagnostic>>> {
+        self.tasks
+            .iter()
+            .zip(self.analyze().tasks)
+            .map(|(task, analyses)| task.diagnose_analyses(analyses, config))
+            .collect()
+    }
+}
+
+fn is_shared_persistent(symbol: &Symbol) -> bool {
+    matches!(symbol.kind, SymbolKind::Data(decl)
+        if decl.declaration_type == VarDeclarationType::PersDeclaration
+            && symbol.visibility == Scope::GLOBAL)
+}
+
+/// A declaration in a system, as the index of the task, the index of the module in the task and
+/// the declared symbol.
+type Declaration = (usize, usize, SymbolId);
+
+/// Reports global persistents that several tasks declare with different types or dimensions, or
+/// with different initial values, and collects how each task uses them.
+fn check_shared_persistents(
+    tasks: &[Task],
+    analyses: &mut [Vec<Analysis>],
+) -> Vec<SharedPersistent> {
+    // The first declaration of each global persistent in each task, in the order the persistents
+    // are first declared
+    let mut declarations: Vec<(String, Vec<Declaration>)> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (task, modules) in analyses.iter().enumerate() {
+        for (module, analysis) in modules.iter().enumerate() {
+            let table = &analysis.symbols;
+            let mut symbols: Vec<SymbolId> = table
+                .scope(analysis.module_scope)
+                .symbols()
+                .filter(|id| is_shared_persistent(table.symbol(*id)))
+                .collect();
+            symbols.sort_by_key(|id| table.symbol(*id).name_span.start);
+            for id in symbols {
+                let key = table.symbol(id).name.to_ascii_lowercase();
+                let position = *index.entry(key.clone()).or_insert_with(|| {
+                    declarations.push((key, Vec::new()));
+                    declarations.len() - 1
+                });
+                let found = &mut declarations[position].1;
+                if found.last().is_none_or(|(other, ..)| *other != task) {
+                    found.push((task, module, id));
+                }
+            }
+        }
+    }
+
+    let files: Vec<Vec<usize>> = tasks
+        .iter()
+        .map(|task| task.modules().map(|(file, _)| file).collect())
+        .collect();
+    let mut shared = Vec::new();
+    for (_, found) in declarations
+        .into_iter()
+        .filter(|(_, found)| found.len() > 1)
+    {
+        let declaration = |(task, module, id): Declaration| {
+            let symbol = analyses[task][module].symbols.symbol(id);
+            let SymbolKind::Data(decl) = symbol.kind else {
+                unreachable!()
+            };
+            let source = tasks[task].files[files[task][module]].source.as_str();
+            (symbol.name, symbol.name_span, decl, source)
+        };
+        let (name, _, first, first_source) = declaration(found[0]);
+        let first_task = &tasks[found[0].0].name;
+        let first_type = PersistentType::new(first);
+        let mut mismatches = Vec::new();
+        for &(task, module, id) in &found[1..] {
+            let (_, name_span, decl, source) = declaration((task, module, id));
+            if !PersistentType::new(decl).agrees(&first_type) {
+                mismatches.push((
+                    task,
+                    module,
+                    Diagnostic::error(
+                        "shared-pers-mismatch",
+                        format!(
+                            "persistent '{}' is shared with task '{}', which declares it as {}",
+                            name, first_task, first_type
+                        ),
+                        name_span,
+                    ),
+                ));
+            } else if let (Some(value), Some(first_value)) =
+                (&decl.definition.expression, &first.definition.expression)
+            {
+                if tokens(&source[value.span.start..value.span.end])
+                    != tokens(&first_source[first_value.span.start..first_value.span.end])
+                {
+                    mismatches.push((
+                        task,
+                        module,
+                        Diagnostic::warning(
+                            "shared-pers-initial-value",
+                            format!(
+                                "persistent '{}' is shared with task '{}', which initializes it differently; only the value of the task loaded first is used",
+                                name, first_task
+                            ),
+                            value.span,
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let name = name.to_owned();
+        let tasks = found
+            .iter()
+            .map(|&(task, ..)| {
+                let (reads, writes) = persistent_access(&analyses[task], &name);
+                PersistentAccess {
+                    task,
+                    reads,
+                    writes,
+                }
+            })
+            .collect();
+        shared.push(SharedPersistent {
+            name,
+            data_type: first_type.to_string(),
+            tasks,
+        });
+        for (task, module, diagnostic) in mismatches {
+            analyses[task][module].diagnostics.push(diagnostic);
+        }
+    }
+    shared
+}
+
+/// The data type of a persistent and the sizes of its dimensions, where they are integer literals.
+struct PersistentType<'a> {
+    data_type: &'a str,
+    sizes: Option<Vec<Option<u64>>>,
+}
+
+impl<'a> PersistentType<'a> {
+    fn new(decl: &'a ast::VarDeclaration) -> Self {
+        let sizes = match decl.definition.dim.as_ref().map(|dim| &dim.kind) {
+            Some(DimensionKind::Dimension(sizes)) => Some(
+                sizes
+                    .iter()
+                    .map(|size| match &size.kind {
+                        ExprKind::Term(Term::Num(Number::Integer(size))) => Some(*size),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        PersistentType {
+            data_type: &decl.data_type,
+            sizes,
+        }
+    }
+
+    /// Returns `false` if the types differ or the dimensions are known to differ.
+    fn agrees(&self, other: &PersistentType) -> bool {
+        let sizes = match (&self.sizes, &other.sizes) {
+            (None, None) => true,
+            (Some(sizes), Some(other)) => {
+                sizes.len() == other.len()
+                    && sizes
+                        .iter()
+                        .zip(other)
+                        .all(|(a, b)| a.is_none() || b.is_none() || a == b)
+            }
+            _ => false,
+        };
+        sizes && self.data_type.eq_ignore_ascii_case(other.data_type)
+    }
+}
+
+impl fmt::Display for PersistentType<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.data_type)?;
+        if let Some(sizes) = &self.sizes {
+            let sizes: Vec<String> = sizes
+                .iter()
+                .map(|size| size.map_or("*".to_owned(), |size| size.to_string()))
+                .collect();
+            write!(f, "{{{}}}", sizes.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the text of the significant tokens of `source`, to compare expressions regardless of
+/// layout.
+fn tokens(source: &str) -> Vec<&str> {
+    Lexer::new(source)
+        .filter(|token| !token.kind.is_trivia())
+        .map(|token| &source[token.span.start..token.span.end])
+        .collect()
+}
+
+/// Returns whether the modules of a task read and write the global persistent `name`.
+fn persistent_access(analyses: &[Analysis], name: &str) -> (bool, bool) {
+    let (mut reads, mut writes) = (false, false);
+    for analysis in analyses {
+        let table = &analysis.symbols;
+        for reference in table.references() {
+            let Some(id) = reference.symbol else {
+                continue;
+            };
+            let symbol = table.symbol(id);
+            if !is_shared_persistent(symbol) || !symbol.name.eq_ignore_ascii_case(name) {
+                continue;
+            }
+            let by_reference = analysis
+                .by_reference
+                .iter()
+                .any(|span| span.start == reference.span.start);
+            match reference.kind {
+                ReferenceKind::Write => writes = true,
+                ReferenceKind::Read if by_reference => writes = true,
+                ReferenceKind::Read => reads = true,
+                _ => {}
+            }
+        }
+    }
+    (reads, writes)
+}
+
+/// Reports modules of a task that declare the same global name, and modules that are loaded
+/// twice. A persistent may be declared in several modules of a task if the declarations agree on
+/// its scope and type, as they then refer to the same value.
+fn check_globals(modules: &[&ModuleInfo], analyses: &mut [Analysis]) {
+    let mut module_names: HashMap<String, usize> = HashMap::new();
+    let mut globals: HashMap<String, (usize, SymbolId)> = HashMap::new();
+    for (index, module) in modules.iter().enumerate() {
+        let mut found = Vec::new();
+        if let Some(&first) = module_names.get(&module.name.to_ascii_lowercase()) {
+            found.push(Diagnostic::error(
+                "duplicate-global",
+                format!(
+                    "module '{}' is already loaded into the task",
+                    modules[first].name
+                ),
+                module.name_span,
+            ));
+        } else {
+            module_names.insert(module.name.to_ascii_lowercase(), index);
+        }
+
+        let analysis = &analyses[index];
+        let table = &analysis.symbols;
+        let mut symbols: Vec<SymbolId> = table
+            .scope(analysis.module_scope)
+            .symbols()
+            .filter(|id| table.symbol(*id).visibility != Scope::LOCAL)
+            .collect();
+        symbols.sort_by_key(|id| table.symbol(*id).name_span.start);
+        for id in symbols {
+            let symbol = table.symbol(id);
+            let key = symbol.name.to_ascii_lowercase();
+            let Some(&(other, other_id)) = globals.get(&key) else {
+                globals.insert(key, (index, id));
+                continue;
+            };
+            let previous = analyses[other].symbols.symbol(other_id);
+            if let Some(message) = clash(symbol, previous, &modules[other].name) {
+                found.push(Diagnostic::error(
+                    "duplicate-global",
+                    message,
+                    symbol.name_span,
+                ));
+            }
+        }
+        analyses[index].diagnostics.extend(found);
+    }
+}
+
+/// Describes why `symbol` may not be declared next to the global `previous` of `module`.
+fn clash(symbol: &Symbol, previous: &Symbol, module: &str) -> Option<String> {
+    if let (SymbolKind::Data(decl), SymbolKind::Data(previous_decl)) = (symbol.kind, previous.kind)
+    {
+        let persistent = decl.declaration_type == VarDeclarationType::PersDeclaration
+            && previous_decl.declaration_type == VarDeclarationType::PersDeclaration;
+        if persistent && symbol.visibility == previous.visibility {
+            if decl
+                .data_type
+                .eq_ignore_ascii_case(&previous_decl.data_type)
+            {
+                return None;
+            }
+            return Some(format!(
+                "persistent '{}' is declared as {} in module '{}'",
+                symbol.name, previous_decl.data_type, module
+            ));
+        }
+    }
+    Some(format!(
+        "'{}' is already declared as a global {} in module '{}'",
+        symbol.name,
+        previous.kind.description(),
+        module
+    ))
+}
+
+/// Parses every module of a file. Each module is parsed from a copy of the source with the other
+/// modules blanked out, so that the spans of all of them refer to the file.
+fn parse_file(source: &str) -> (Vec<Module>, Vec<Diagnostic>) {
+    let source = mask_header(source);
+    let mut starts: Vec<usize> = Lexer::new(&source)
+        .filter(|token| token.kind == SyntaxKind::ModuleKw)
+        .map(|token| token.span.start)
+        .collect();
+    // Anything in front of the first module belongs to it, so that it is reported
+    if starts.is_empty() {
+        starts.push(0);
+    } else {
+        starts[0] = 0;
+    }
+    let mut modules = Vec::new();
+    let mut errors = Vec::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(source.len());
+        let mut text = blank(&source[..start]);
+        text.push_str(&source[start..end]);
+        text.push_str(&blank(&source[end..]));
+        let parsed = parse_module(&text);
+        errors.extend(parsed.errors.iter().map(Diagnostic::syntax_error));
+        modules.push(parsed.module);
+    }
+    (modules, errors)
+}
+
+/// Replaces every character but line breaks with spaces, keeping the length in bytes.
+fn blank(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\n' | '\r' => c.to_string(),
+            _ => " ".repeat(c.len_utf8()),
+        })
+        .collect()
+}
+
+/// Blanks out the `%%% VERSION:1 LANGUAGE:ENGLISH %%%` header that controllers write in front of
+/// older modules, keeping line breaks so that offsets still match the file.
+pub fn mask_header(source: &str) -> String {
+    let start = source.len() - source.trim_start().len();
+    if !source[start..].starts_with("%%%") {
+        return source.to_owned();
+    }
+    let Some(end) = source[start + 3..]
+        .find("%%%")
+        .map(|offset| start + 3 + offset + 3)
+    else {
+        return source.to_owned();
+    };
+    let mut masked = blank(&source[..end]);
+    masked.push_str(&source[end..]);
+    masked
+}
+
+/// Returns the module files listed by the `<Module>` elements of a `.pgf` program file, relative
+/// to the directory of the program file.
+pub fn program_modules(source: &str) -> Result<Vec<String>, String> {
+    if !source.contains("<Program") {
+        return Err("not a program file, expected a <Program> element".to_owned());
+    }
+    let mut modules = Vec::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("<Module>") {
+        rest = &rest[start + "<Module>".len()..];
+        let end = rest
+            .find("</Module>")
+            .ok_or_else(|| "unterminated <Module> element".to_owned())?;
+        modules.push(rest[..end].trim().to_owned());
+        rest = &rest[end..];
+    }
+    Ok(modules)
+}
+
+/// Reads a source file as UTF-8, falling back to Latin-1 which older controllers use.
+pub fn read_source(path: &Path) -> io::Result<String> {
+    let bytes = fs::read(path)
+        .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))?;
+    match String::from_utf8(bytes) {
+        Ok(source) => Ok(source),
+        Err(error) => Ok(error.as_bytes().iter().map(|b| *b as char).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(task: &Task) -> Vec<Vec<String>> {
+        task.diagnose(&LintConfig::default())
+            .iter()
+            .map(|diagnostics| {
+                diagnostics
+                    .iter()
+                    .map(|d| format!("{}: {}", d.code, d.message))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn resolve_globals_across_modules() {
+        let mut task = Task::new("T_ROB1");
+        task.add_file(
+            "MainModule.mod",
+            r#"MODULE MainModule
+    PROC main()
+        counter := counter + limit;
+        helper;
+        hidden;
+        limit := 1;
+    ENDPROC
+ENDMODULE"#
+                .to_owned(),
+        );
+        task.add_file(
+            "Shared.sys",
+            r#"MODULE Shared(SYSMODULE)
+    TASK PERS num counter := 0;
+    CONST num limit := 10;
+    PROC helper()
+        hidden;
+    ENDPROC
+    LOCAL PROC hidden()
+    ENDPROC
+ENDMODULE"#
+                .to_owned(),
+        );
+        assert_eq!(
+            messages(&task),
+            vec![
+                vec![
+                    "undeclared-identifier: undeclared identifier 'hidden'".to_owned(),
+                    "const-assignment: cannot assign to constant 'limit'".to_owned(),
+                ],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn report_duplicate_globals() {
+        let mut task = Task::new("T_ROB1");
+        task.add_file(
+            "A.mod",
+            r#"MODULE A
+    VAR num shared;
+    PERS num count := 0;
+    TASK PERS string state := "";
+    LOCAL VAR num temp;
+    PROC run()
+    ENDPROC
+ENDMODULE"#
+                .to_owned(),
+        );
+        task.add_file(
+            "B.mod",
+            r#"MODULE B
+    VAR num shared;
+    PERS num count := 0;
+    TASK PERS num state := 0;
+    LOCAL VAR num temp;
+    LOCAL PROC run()
+    ENDPROC
+ENDMODULE"#
+                .to_owned(),
+        );
+        task.add_file("a.mod", "MODULE a\nENDMODULE".to_owned());
+        assert_eq!(
+            messages(&task)[1..],
+            vec![
+                vec![
+                    "duplicate-global: 'shared' is already declared as a global variable in module 'A'",
+                    "duplicate-global: persistent 'state' is declared as string in module 'A'",
+                    "unused-var: variable 'temp' is never used",
+                    "unused-routine: procedure 'run' is never used",
+                ],
+                vec!["duplicate-global: module 'A' is already loaded into the task"],
+            ]
+        );
+    }
+
+    #[test]
+    fn cross_check_shared_persistents() {
+        let task = |name: &str, source: &str| {
+            let mut task = Task::new(name);
+            task.add_file(format!("{}.mod", name), source.to_owned());
+            task
+        };
+        let mut system = System::new();
+        system.add_task(task(
+            "T_ROB1",
+            r#"MODULE Main
+    PERS num count := 0;
+    PERS num grid{3} := [0, 0, 0];
+    PERS bool ready := TRUE;
+    TASK PERS num own := 0;
+    PROC main()
+        count := count + 1;
+        Clear grid{1};
+    ENDPROC
+ENDMODULE"#,
+        ));
+        system.add_task(task(
+            "T_ROB2",
+            r#"MODULE Main
+    PERS string count := "";
+    PERS num grid{4} := [0, 0, 0, 0];
+    PERS bool ready := FALSE;
+    TASK PERS string own := "";
+    PROC main()
+        WaitUntil ready;
+    ENDPROC
+ENDMODULE"#,
+        ));
+        system.add_task(task(
+            "T_ROB3",
+            r#"MODULE Main
+    CONST num n := 3;
+    PERS num grid{n} := [0,0,0];
+    PERS bool ready:=TRUE;
+    PROC main()
+        ready := grid{1} > 0;
+    ENDPROC
+ENDMODULE"#,
+        ));
+
+        let analysis = system.analyze();
+        let access = |shared: &SharedPersistent| {
+            shared
+                .tasks
+                .iter()
+                .map(|access| (access.task, access.reads, access.writes))
+                .collect::<Vec<_>>()
+        };
+        let shared: Vec<_> = analysis
+            .shared
+            .iter()
+            .map(|shared| {
+                (
+                    shared.name.as_str(),
+                    shared.data_type.as_str(),
+                    access(shared),
+                )
+            })
+            .collect();
+        assert_eq!(
+            shared,
+            vec![
+                ("count", "num", vec![(0, true, true), (1, false, false)]),
+                (
+                    "grid",
+                    "num{3}",
+                    vec![(0, false, true), (1, false, false), (2, true, false)]
+                ),
+                (
+                    "ready",
+                    "bool",
+                    vec![(0, false, false), (1, true, false), (2, false, true)]
+                ),
+            ]
+        );
+
+        let messages: Vec<Vec<String>> = system
+            .diagnose(&LintConfig::default())
+            .iter()
+            .map(|files| {
+                files[0]
+                    .iter()
+                    .map(|d| format!("{}: {}", d.code, d.message))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                vec![],
+                vec![
+                    "shared-pers-mismatch: persistent 'count' is shared with task 'T_ROB1', which declares it as num",
+                    "shared-pers-mismatch: persistent 'grid' is shared with task 'T_ROB1', which declares it as num{3}",
+                    "shared-pers-initial-value: persistent 'ready' is shared with task 'T_ROB1', which initializes it differently; only the value of the task loaded first is used",
+                ],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn count_persistent_arguments_as_writes() {
+        let mut system = System::new();
+        for (name, source) in [
+            (
+                "T_ROB1",
+                r#"MODULE Main
+    PERS tooldata currentTool := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+    PERS bool loggerRunning := FALSE;
+    PROC main()
+        MoveJ CRobT(), v100, fine, currentTool;
+        WaitUntil loggerRunning;
+    ENDPROC
+ENDMODULE"#,
+            ),
+            (
+                "T_LOGGER",
+                r#"MODULE Logger
+    PERS tooldata currentTool := [TRUE, [[0, 0, 100], [1, 0, 0, 0]], [1, [0, 0, 1], [1, 0, 0, 0], 0, 0, 0]];
+    PERS bool loggerRunning := FALSE;
+    PROC main()
+        setFlag loggerRunning;
+        GetSysData currentTool;
+    ENDPROC
+    PROC setFlag(PERS bool flag)
+        flag := TRUE;
+    ENDPROC
+ENDMODULE"#,
+            ),
+        ] {
+            let mut task = Task::new(name);
+            task.add_file(format!("{}.mod", name), source.to_owned());
+            system.add_task(task);
+        }
+        let analysis = system.analyze();
+        let shared: Vec<_> = analysis
+            .shared
+            .iter()
+            .map(|shared| {
+                let access = shared
+                    .tasks
+                    .iter()
+                    .map(|access| (access.task, access.reads, access.writes))
+                    .collect::<Vec<_>>();
+                (shared.name.as_str(), access)
+            })
+            .collect();
+        assert_eq!(
+            shared,
+            vec![
+                ("currentTool", vec![(0, true, false), (1, false, true)]),
+                ("loggerRunning", vec![(0, true, false), (1, false, true)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_program_files() {
+        let source = "%%%\n  VERSION:1\n  LANGUAGE:ENGLISH\n%%%\n\nMODULE A\n    PROC main()\n        go;\n    ENDPROC\nENDMODULE\n\nMODULE B\n    ! Gr\u{f6}\u{df}e\n    PROC go()\n    ENDPROC\nENDMODULE\n";
+        let mut task = Task::new("legacy");
+        task.add_file("legacy.prg", source.to_owned());
+        let names: Vec<_> = task
+            .modules()
+            .map(|(file, module)| {
+                let name = &source[module.name_span.start..module.name_span.end];
+                (file, name, module.span.end)
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (0, "A", source.find("ENDMODULE").unwrap() + 9),
+                (0, "B", source.len() - 1),
+            ]
+        );
+        assert_eq!(messages(&task), vec![Vec::<String>::new()]);
+
+        let mut task = Task::new("broken");
+        task.add_file("broken.prg", "garbage\nMODULE A\nENDMODULE".to_owned());
+        assert_eq!(task.files[0].syntax_errors.len(), 1);
+        assert_eq!(task.files[0].syntax_errors[0].span.start, 0);
+    }
+
+    #[test]
+    fn load_program_files() {
+        let dir = std::env::temp_dir().join(format!("rapid-program-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Robot.pgf"),
+            "<?xml version=\"1.0\" encoding=\"ISO-8859-1\" ?>\n<Program>\n    <Module>Main.mod</Module>\n    <Module> Tools.mod </Module>\n</Program>\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.mod"),
+            "MODULE Main\n    PROC main()\n        grip;\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Tools.mod"),
+            b"MODULE Tools\n    ! Gr\xf6\xdfe\n    PROC grip()\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+        fs::write(dir.join("Other.pgf"), "<Program>\n    <Module>Missing.mod").unwrap();
+
+        let task = Task::load(&dir.join("Robot.pgf")).unwrap();
+        assert_eq!(task.name, "Robot");
+        assert_eq!(task.files.len(), 2);
+        assert!(task.files[1].path.ends_with("Tools.mod"));
+        assert!(task.files[1].source.contains("Gr\u{f6}\u{df}e"));
+        assert_eq!(messages(&task), vec![Vec::<String>::new(); 2]);
+
+        let error = Task::load(&dir.join("Other.pgf")).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("Other.pgf: unterminated <Module> element"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mask_version_header() {
+        let source = "%%%\n  VERSION:1\n  LANGUAGE:ENGLISH\n%%%\n\nMODULE m\nENDMODULE";
+        let masked = mask_header(source);
+        assert_eq!(masked.len(), source.len());
+        assert_eq!(masked.find("MODULE"), source.find("MODULE"));
+        assert!(masked.trim_start().starts_with("MODULE"));
+        assert_eq!(mask_header("MODULE m\nENDMODULE"), "MODULE m\nENDMODULE");
+    }
+}
@@ -0,0 +1,730 @@
+use rapid_parser::ast::{
+    self, ArgumentKind, DataDeclaration, Expr, ExprKind, ModuleInfo,
+    OptionalParameterDeclarationTypeKind, ParameterDeclarationTypeKind, ParameterKind,
+    RoutineDeclaration, Scope, Statement, StatementKind, Term, TestCaseKind, TypeDefinition,
+    Variable, VariableKind,
+};
+use rapid_parser::Span;
+
+use crate::builtins;
+use crate::diagnostics::{Diagnostic, Edit};
+use crate::symbols::{
+    Reference, ReferenceKind, ScopeId, ScopeKind, Symbol, SymbolKind, SymbolTable,
+};
+
+/// The atomic data types that are built into the language itself. All other system types are
+/// declared by the built-in catalogue.
+pub const ATOMIC_TYPES: &[&str] = &["num", "dnum", "bool", "string"];
+
+fn is_atomic_type(name: &str) -> bool {
+    ATOMIC_TYPES.iter().any(|t| t.eq_ignore_ascii_case(name))
+}
+
+/// Returns the span of an identifier that starts at `start`.
+fn ident_span(start: usize, name: &str) -> Span {
+    Span::new(start, start + name.len())
+}
+
+/// Returns the symbol that a module-level statement declares in `scope`, if any.
+fn module_symbol(statement: &Statement, scope: ScopeId) -> Option<Symbol<'_>> {
+    let (name, kind, visibility, name_span, span) = match &statement.kind {
+        StatementKind::TypeDefinition(TypeDefinition::RecordDefinition(visibility, record)) => (
+            &record.name,
+            SymbolKind::Record(record),
+            *visibility,
+            record.name_span,
+            record.span,
+        ),
+        StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(visibility, alias)) => (
+            &alias.name,
+            SymbolKind::Alias(alias),
+            *visibility,
+            alias.name_span,
+            alias.span,
+        ),
+        StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(visibility, decl)) => (
+            &decl.definition.identifier,
+            SymbolKind::Data(decl),
+            *visibility,
+            decl.definition.identifier_span,
+            decl.span,
+        ),
+        StatementKind::RoutineDeclaration(RoutineDeclaration::ProcDeclaration(proc)) => (
+            &proc.name,
+            SymbolKind::Procedure(proc),
+            proc.scope,
+            proc.name_span,
+            proc.span,
+        ),
+        StatementKind::RoutineDeclaration(RoutineDeclaration::FuncDeclaration(func)) => (
+            &func.name,
+            SymbolKind::Function(func),
+            func.scope,
+            func.name_span,
+            func.span,
+        ),
+        StatementKind::RoutineDeclaration(RoutineDeclaration::TrapDeclaration(trap)) => (
+            &trap.name,
+            SymbolKind::Trap(trap),
+            trap.scope,
+            trap.name_span,
+            trap.span,
+        ),
+        _ => return None,
+    };
+    Some(Symbol {
+        name,
+        kind,
+        visibility,
+        scope,
+        name_span,
+        span,
+    })
+}
+
+/// Declares the symbols of modules and resolves the names used in them.
+///
+/// Declaration and resolution are separate passes so that every module-level name is visible
+/// regardless of where it is declared in the module.
+pub struct Resolver<'a, 't> {
+    pub table: &'t mut SymbolTable<'a>,
+    pub diagnostics: &'t mut Vec<Diagnostic>,
+}
+
+impl<'a, 't> Resolver<'a, 't> {
+    pub fn new(table: &'t mut SymbolTable<'a>, diagnostics: &'t mut Vec<Diagnostic>) -> Self {
+        Resolver { table, diagnostics }
+    }
+
+    fn declare(&mut self, symbol: Symbol<'a>) {
+        let scope = symbol.scope;
+        let name = symbol.name;
+        let name_span = symbol.name_span;
+        let description = symbol.kind.description();
+        let code = match symbol.kind {
+            SymbolKind::Parameter(_) | SymbolKind::Switch => "shadowed-parameter",
+            _ => "shadowed-declaration",
+        };
+        let enclosing = self
+            .table
+            .scope(scope)
+            .parent
+            .and_then(|parent| self.table.lookup(parent, name));
+        let builtin = self.table.is_builtin_scope(scope);
+        match self.table.declare(symbol) {
+            // Parameters of built-in routines may reuse the names of other built-ins
+            Ok(_) if builtin => {}
+            Ok(_) => {
+                if let Some(outer) = enclosing.filter(|outer| self.table.is_builtin(*outer)) {
+                    let outer = self.table.symbol(outer);
+                    self.diagnostics.push(Diagnostic::warning(
+                        code,
+                        format!(
+                            "{} '{}' shadows the built-in {}",
+                            description,
+                            name,
+                            outer.kind.description()
+                        ),
+                        name_span,
+                    ));
+                } else if let Some(outer) = enclosing.filter(|outer| self.table.is_imported(*outer))
+                {
+                    // A module may hide the global declarations of other modules with its own,
+                    // clashes between global declarations are reported by the task
+                    if self.table.scope(scope).kind != ScopeKind::Module {
+                        let outer = self.table.symbol(outer);
+                        self.diagnostics.push(Diagnostic::warning(
+                            code,
+                            format!(
+                                "{} '{}' shadows the global {} of another module",
+                                description,
+                                name,
+                                outer.kind.description()
+                            ),
+                            name_span,
+                        ));
+                    }
+                } else if let Some(outer) = enclosing {
+                    let outer = self.table.symbol(outer);
+                    self.diagnostics.push(
+                        Diagnostic::warning(
+                            code,
+                            format!(
+                                "{} '{}' shadows the {} declared in an outer scope",
+                                description,
+                                name,
+                                outer.kind.description()
+                            ),
+                            name_span,
+                        )
+                        .with_related(
+                            format!("'{}' is declared here", outer.name),
+                            outer.name_span,
+                        ),
+                    );
+                }
+            }
+            Err(existing) => {
+                let existing = self.table.symbol(existing);
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "duplicate-declaration",
+                        format!("'{}' is already declared in this scope", name),
+                        name_span,
+                    )
+                    .with_related(
+                        format!("previous declaration of '{}'", existing.name),
+                        existing.name_span,
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Declares the built-in catalogue in a new root scope, which should be passed as the parent of
+    /// every module scope.
+    pub fn declare_builtins(&mut self) -> ScopeId {
+        let module = builtins::module();
+        self.declare_module_in(module, ScopeKind::Builtin, None)
+    }
+
+    /// Declares every module-level symbol and the parameters, data and labels of each routine.
+    pub fn declare_module(&mut self, module: &'a ModuleInfo, parent: Option<ScopeId>) -> ScopeId {
+        self.declare_module_in(module, ScopeKind::Module, parent)
+    }
+
+    /// Declares the global symbols of `modules`, the other modules of a task, in a new task scope
+    /// that should be passed as the parent of the module scope. Only the first of several global
+    /// declarations of a name is kept; such clashes are reported by the task, not here.
+    pub fn declare_task(&mut self, modules: &[&'a ModuleInfo], parent: Option<ScopeId>) -> ScopeId {
+        let scope = self
+            .table
+            .add_scope(ScopeKind::Task, parent, Span::default());
+        for module in modules {
+            for statement in &module.statements {
+                if let Some(symbol) = module_symbol(statement, scope) {
+                    if symbol.visibility != Scope::LOCAL {
+                        let _ = self.table.declare(symbol);
+                    }
+                }
+            }
+        }
+        scope
+    }
+
+    fn declare_module_in(
+        &mut self,
+        module: &'a ModuleInfo,
+        kind: ScopeKind,
+        parent: Option<ScopeId>,
+    ) -> ScopeId {
+        let scope = self.table.add_scope(kind, parent, module.span);
+        for statement in &module.statements {
+            self.declare_module_statement(statement, scope);
+            if let StatementKind::RoutineDeclaration(routine) = &statement.kind {
+                self.declare_routine(routine, scope);
+            }
+        }
+        scope
+    }
+
+    /// Declares the module-level symbol of a statement of a module, without the parameters, data
+    /// and labels of a routine.
+    pub(crate) fn declare_module_statement(&mut self, statement: &'a Statement, scope: ScopeId) {
+        if let StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(Scope::TASK, decl)) =
+            &statement.kind
+        {
+            if decl.declaration_type == ast::VarDeclarationType::ConstDeclaration {
+                self.diagnostics.push(Diagnostic::error(
+                    "invalid-scope",
+                    "constants cannot be declared TASK",
+                    statement.span,
+                ));
+            }
+        }
+        if let Some(symbol) = module_symbol(statement, scope) {
+            self.declare(symbol);
+        }
+    }
+
+    fn declare_data(&mut self, decl: &'a ast::VarDeclaration, visibility: Scope, scope: ScopeId) {
+        self.declare(Symbol {
+            name: &decl.definition.identifier,
+            kind: SymbolKind::Data(decl),
+            visibility,
+            scope,
+            name_span: decl.definition.identifier_span,
+            span: decl.span,
+        });
+    }
+
+    /// Declares the parameters, data and labels of a routine.
+    pub(crate) fn declare_routine(
+        &mut self,
+        routine: &'a RoutineDeclaration,
+        module_scope: ScopeId,
+    ) {
+        let (span, parameters, statements) = match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => {
+                (proc.span, &proc.parameters[..], &proc.statements)
+            }
+            RoutineDeclaration::FuncDeclaration(func) => {
+                (func.span, &func.parameters[..], &func.statements)
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => (trap.span, &[][..], &trap.statements),
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => return,
+        };
+        let scope = self
+            .table
+            .add_scope(ScopeKind::Routine, Some(module_scope), span);
+        for parameter in parameters {
+            match &parameter.kind {
+                ParameterDeclarationTypeKind::ParameterDeclaration(p) => {
+                    self.declare_parameter(p, scope)
+                }
+                ParameterDeclarationTypeKind::OptionalParameterDeclaration(alternatives) => {
+                    for alternative in alternatives {
+                        match &alternative.kind {
+                            OptionalParameterDeclarationTypeKind::OptionalParameterDeclaration(
+                                p,
+                            ) => self.declare_parameter(p, scope),
+                            OptionalParameterDeclarationTypeKind::Switch(name) => {
+                                let start = alternative.span.end - name.len();
+                                self.declare(Symbol {
+                                    name,
+                                    kind: SymbolKind::Switch,
+                                    visibility: Scope::LOCAL,
+                                    scope,
+                                    name_span: Span::new(start, alternative.span.end),
+                                    span: alternative.span,
+                                });
+                            }
+                            OptionalParameterDeclarationTypeKind::ALT => {}
+                        }
+                    }
+                }
+                ParameterDeclarationTypeKind::PAR | ParameterDeclarationTypeKind::Error => {}
+            }
+        }
+        for statement in statements {
+            if let StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(
+                visibility,
+                decl,
+            )) = &statement.kind
+            {
+                if *visibility != Scope::GLOBAL {
+                    let keyword = Span::new(statement.span.start, decl.span.start);
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            "invalid-scope",
+                            "routine data cannot be declared LOCAL or TASK",
+                            statement.span,
+                        )
+                        .with_fix(
+                            "remove the scope, routine data is always local",
+                            vec![Edit {
+                                span: keyword,
+                                replacement: String::new(),
+                            }],
+                        ),
+                    );
+                }
+                self.declare_data(decl, Scope::LOCAL, scope);
+            }
+        }
+        let mut labels = Vec::new();
+        collect_labels(statements, &mut labels);
+        let (backward, error, undo) = match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => (
+                proc.backward_handler.as_deref(),
+                &proc.error_handler,
+                &proc.undo_handler,
+            ),
+            RoutineDeclaration::FuncDeclaration(func) => {
+                (None, &func.error_handler, &func.undo_handler)
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => {
+                (None, &trap.error_handler, &trap.undo_handler)
+            }
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => (None, &None, &None),
+        };
+        let error = error.as_ref().map(|handler| &handler.statements[..]);
+        for handler in [backward, error, undo.as_deref()].into_iter().flatten() {
+            collect_labels(handler, &mut labels);
+        }
+        for (name, span) in labels {
+            self.declare(Symbol {
+                name,
+                kind: SymbolKind::Label,
+                visibility: Scope::LOCAL,
+                scope,
+                name_span: ident_span(span.start, name),
+                span,
+            });
+        }
+    }
+
+    fn declare_parameter(&mut self, parameter: &'a ast::ParameterDeclaration, scope: ScopeId) {
+        self.declare(Symbol {
+            name: &parameter.name,
+            kind: SymbolKind::Parameter(parameter),
+            visibility: Scope::LOCAL,
+            scope,
+            name_span: parameter.name_span,
+            span: parameter.span,
+        });
+    }
+
+    /// Resolves every name used in a module that was declared with [`Resolver::declare_module`].
+    pub fn resolve_module(&mut self, module: &'a ModuleInfo, scope: ScopeId) {
+        for statement in &module.statements {
+            self.resolve_module_statement(statement, scope);
+        }
+    }
+
+    /// Resolves the names used in one statement of a module, such as a routine.
+    pub(crate) fn resolve_module_statement(&mut self, statement: &'a Statement, scope: ScopeId) {
+        match &statement.kind {
+            StatementKind::TypeDefinition(TypeDefinition::RecordDefinition(_, record)) => {
+                for component in &record.components {
+                    self.resolve_type(&component.data_type, component.data_type_span, scope);
+                }
+            }
+            StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(_, alias)) => {
+                self.resolve_type(&alias.data_type, alias.data_type_span, scope);
+            }
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                self.resolve_data(decl, scope)
+            }
+            StatementKind::RoutineDeclaration(routine) => self.resolve_routine(routine, scope),
+            _ => {}
+        }
+    }
+
+    fn resolve_type(&mut self, name: &str, span: Span, scope: ScopeId) {
+        let symbol = self.table.lookup_type(scope, name);
+        let builtin = self.table.is_builtin_scope(scope) && builtins::is_anytype(name);
+        if symbol.is_none() && !is_atomic_type(name) && !builtin {
+            self.diagnostics.push(Diagnostic::error(
+                "undeclared-type",
+                format!("unknown data type '{}'", name),
+                span,
+            ));
+        }
+        self.table.add_reference(Reference {
+            name: name.to_owned(),
+            span,
+            scope,
+            kind: ReferenceKind::Type,
+            symbol,
+        });
+    }
+
+    fn resolve_name(&mut self, name: &str, span: Span, kind: ReferenceKind, scope: ScopeId) {
+        let symbol = self.table.lookup(scope, name);
+        if symbol.is_none() && kind != ReferenceKind::Label {
+            self.diagnostics.push(Diagnostic::error(
+                "undeclared-identifier",
+                format!("undeclared identifier '{}'", name),
+                span,
+            ));
+        }
+        self.table.add_reference(Reference {
+            name: name.to_owned(),
+            span,
+            scope,
+            kind,
+            symbol,
+        });
+    }
+
+    fn resolve_data(&mut self, decl: &'a ast::VarDeclaration, scope: ScopeId) {
+        self.resolve_type(&decl.data_type, decl.data_type_span, scope);
+        if let Some(dim) = &decl.definition.dim {
+            self.resolve_dimension(dim, scope);
+        }
+        if let Some(expr) = &decl.definition.expression {
+            self.resolve_expr(expr, scope);
+        }
+    }
+
+    fn resolve_routine(&mut self, routine: &'a RoutineDeclaration, module_scope: ScopeId) {
+        let span = match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => proc.span,
+            RoutineDeclaration::FuncDeclaration(func) => func.span,
+            RoutineDeclaration::TrapDeclaration(trap) => trap.span,
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => return,
+        };
+        let scope = self.table.scope_at(module_scope, span.start);
+        match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => {
+                self.resolve_parameters(&proc.parameters, scope);
+                self.resolve_statements(&proc.statements, scope);
+                for handler in [&proc.backward_handler, &proc.undo_handler]
+                    .into_iter()
+                    .flatten()
+                {
+                    self.resolve_statements(handler, scope);
+                }
+                if let Some(handler) = &proc.error_handler {
+                    self.resolve_error_handler(handler, scope);
+                }
+            }
+            RoutineDeclaration::FuncDeclaration(func) => {
+                self.resolve_type(&func.data_type, func.data_type_span, module_scope);
+                self.resolve_parameters(&func.parameters, scope);
+                self.resolve_statements(&func.statements, scope);
+                if let Some(handler) = &func.undo_handler {
+                    self.resolve_statements(handler, scope);
+                }
+                if let Some(handler) = &func.error_handler {
+                    self.resolve_error_handler(handler, scope);
+                }
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => {
+                self.resolve_statements(&trap.statements, scope);
+                if let Some(handler) = &trap.undo_handler {
+                    self.resolve_statements(handler, scope);
+                }
+                if let Some(handler) = &trap.error_handler {
+                    self.resolve_error_handler(handler, scope);
+                }
+            }
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => {}
+        }
+    }
+
+    /// Resolves the error numbers an error handler catches, which name `errnum` data such as the
+    /// built-in `ERR_*` constants, and its statements.
+    fn resolve_error_handler(&mut self, handler: &'a ast::ErrorHandler, scope: ScopeId) {
+        for number in &handler.numbers {
+            if let ExprKind::Term(Term::String(name)) = &number.kind {
+                self.resolve_name(name, number.span, ReferenceKind::Read, scope);
+            }
+        }
+        self.resolve_statements(&handler.statements, scope);
+    }
+
+    fn resolve_parameters(
+        &mut self,
+        parameters: &'a [ast::ParameterDeclarationType],
+        scope: ScopeId,
+    ) {
+        for parameter in parameters {
+            match &parameter.kind {
+                ParameterDeclarationTypeKind::ParameterDeclaration(p) => {
+                    self.resolve_parameter(p, scope)
+                }
+                ParameterDeclarationTypeKind::OptionalParameterDeclaration(alternatives) => {
+                    for alternative in alternatives {
+                        if let OptionalParameterDeclarationTypeKind::OptionalParameterDeclaration(
+                            p,
+                        ) = &alternative.kind
+                        {
+                            self.resolve_parameter(p, scope);
+                        }
+                    }
+                }
+                ParameterDeclarationTypeKind::PAR | ParameterDeclarationTypeKind::Error => {}
+            }
+        }
+    }
+
+    fn resolve_parameter(&mut self, parameter: &'a ast::ParameterDeclaration, scope: ScopeId) {
+        self.resolve_type(&parameter.data_type, parameter.data_type_span, scope);
+        if let Some(dim) = &parameter.dim {
+            self.resolve_dimension(dim, scope);
+        }
+    }
+
+    fn resolve_statements(&mut self, statements: &'a [Statement], scope: ScopeId) {
+        for statement in statements {
+            self.resolve_statement(statement, scope);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &'a Statement, scope: ScopeId) {
+        match &statement.kind {
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                self.resolve_data(decl, scope)
+            }
+            StatementKind::Assignment(target, expr) => {
+                if let ast::AssignmentTarget::Variable(variable) = target {
+                    self.resolve_variable(variable, ReferenceKind::Write, scope);
+                }
+                self.resolve_expr(expr, scope);
+            }
+            StatementKind::ProcCall(callee, arguments) => {
+                match &callee.kind {
+                    ExprKind::Term(Term::String(name)) => {
+                        self.resolve_name(name, callee.span, ReferenceKind::Call, scope)
+                    }
+                    // Late binding: the procedure name is computed at runtime
+                    _ => self.resolve_expr(callee, scope),
+                }
+                self.resolve_arguments(arguments, scope);
+            }
+            StatementKind::Goto(label) => {
+                self.resolve_name(&label.name, label.span, ReferenceKind::Label, scope)
+            }
+            StatementKind::Return(Some(expr)) | StatementKind::Raise(Some(expr)) => {
+                self.resolve_expr(expr, scope)
+            }
+            StatementKind::Connect(interrupt, trap) => {
+                self.resolve_name(&interrupt.name, interrupt.span, ReferenceKind::Write, scope);
+                self.resolve_name(&trap.name, trap.span, ReferenceKind::Call, scope);
+            }
+            StatementKind::If(condition, statements, else_ifs, else_statements) => {
+                self.resolve_expr(condition, scope);
+                self.resolve_statements(statements, scope);
+                for (condition, statements) in else_ifs {
+                    self.resolve_expr(condition, scope);
+                    self.resolve_statements(statements, scope);
+                }
+                self.resolve_statements(else_statements, scope);
+            }
+            StatementKind::For(variable, from, to, step, statements) => {
+                self.resolve_expr(from, scope);
+                self.resolve_expr(to, scope);
+                if let Some(step) = step {
+                    self.resolve_expr(step, scope);
+                }
+                let loop_scope = self
+                    .table
+                    .add_scope(ScopeKind::Loop, Some(scope), statement.span);
+                self.declare(Symbol {
+                    name: &variable.name,
+                    kind: SymbolKind::LoopVariable,
+                    visibility: Scope::LOCAL,
+                    scope: loop_scope,
+                    name_span: variable.span,
+                    span: statement.span,
+                });
+                self.resolve_statements(statements, loop_scope);
+            }
+            StatementKind::While(condition, statements) => {
+                self.resolve_expr(condition, scope);
+                self.resolve_statements(statements, scope);
+            }
+            StatementKind::Test(expr, cases, default) => {
+                self.resolve_expr(expr, scope);
+                for case in cases {
+                    if let TestCaseKind::Case(values, statements) = &case.kind {
+                        for value in values {
+                            self.resolve_expr(value, scope);
+                        }
+                        self.resolve_statements(statements, scope);
+                    }
+                }
+                if let Some(default) = default {
+                    self.resolve_statements(default, scope);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_arguments(&mut self, arguments: &'a [ast::Argument], scope: ScopeId) {
+        for argument in arguments {
+            match &argument.kind {
+                ArgumentKind::Required(_, expr) | ArgumentKind::Optional(_, Some(expr)) => {
+                    self.resolve_expr(expr, scope)
+                }
+                ArgumentKind::Optional(_, None) => {}
+                ArgumentKind::Conditional(_, parameter) => {
+                    self.resolve_parameter_reference(parameter, scope)
+                }
+            }
+        }
+    }
+
+    fn resolve_parameter_reference(&mut self, parameter: &'a ast::Parameter, scope: ScopeId) {
+        match &parameter.kind {
+            ParameterKind::Parameter(name) => {
+                self.resolve_name(name, parameter.span, ReferenceKind::Read, scope)
+            }
+            ParameterKind::ParameterElement(name, dim) => {
+                let span = ident_span(parameter.span.start, name);
+                self.resolve_name(name, span, ReferenceKind::Read, scope);
+                self.resolve_dimension(dim, scope);
+            }
+            ParameterKind::ParameterComponent(inner, _) => {
+                self.resolve_parameter_reference(inner, scope)
+            }
+        }
+    }
+
+    fn resolve_variable(&mut self, variable: &'a Variable, kind: ReferenceKind, scope: ScopeId) {
+        match &variable.kind {
+            VariableKind::Variable(name) => self.resolve_name(name, variable.span, kind, scope),
+            VariableKind::VariableElement(name, dim) => {
+                let span = ident_span(variable.span.start, name);
+                self.resolve_name(name, span, kind, scope);
+                self.resolve_dimension(dim, scope);
+            }
+            // Components are checked against the record type during type checking
+            VariableKind::VariableComponent(inner, _) => self.resolve_variable(inner, kind, scope),
+        }
+    }
+
+    fn resolve_dimension(&mut self, dim: &'a ast::Dimension, scope: ScopeId) {
+        if let ast::DimensionKind::Dimension(exprs) = &dim.kind {
+            for expr in exprs {
+                self.resolve_expr(expr, scope);
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &'a Expr, scope: ScopeId) {
+        match &expr.kind {
+            ExprKind::Term(Term::Var(variable)) => {
+                self.resolve_variable(variable, ReferenceKind::Read, scope)
+            }
+            ExprKind::Term(Term::Array(elements)) => {
+                for element in elements {
+                    self.resolve_expr(element, scope);
+                }
+            }
+            ExprKind::Term(_) | ExprKind::EXP | ExprKind::Error => {}
+            ExprKind::Op(left, _, right) => {
+                self.resolve_expr(left, scope);
+                self.resolve_expr(right, scope);
+            }
+            ExprKind::UnaryOp(_, operand) => self.resolve_expr(operand, scope),
+            ExprKind::FuncCall(name, arguments) => {
+                let span = ident_span(expr.span.start, name);
+                self.resolve_name(name, span, ReferenceKind::Call, scope);
+                self.resolve_arguments(arguments, scope);
+            }
+        }
+    }
+}
+
+fn collect_labels<'a>(statements: &'a [Statement], labels: &mut Vec<(&'a str, Span)>) {
+    for statement in statements {
+        match &statement.kind {
+            StatementKind::Label(name) => labels.push((name, statement.span)),
+            StatementKind::If(_, statements, else_ifs, else_statements) => {
+                collect_labels(statements, labels);
+                for (_, statements) in else_ifs {
+                    collect_labels(statements, labels);
+                }
+                collect_labels(else_statements, labels);
+            }
+            StatementKind::For(.., statements) | StatementKind::While(_, statements) => {
+                collect_labels(statements, labels)
+            }
+            StatementKind::Test(_, cases, default) => {
+                for case in cases {
+                    if let TestCaseKind::Case(_, statements) = &case.kind {
+                        collect_labels(statements, labels);
+                    }
+                }
+                if let Some(default) = default {
+                    collect_labels(default, labels);
+                }
+            }
+            _ => {}
+        }
+    }
+}
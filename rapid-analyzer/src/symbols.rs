@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+
+use rapid_parser::ast::{self, Scope};
+use rapid_parser::Span;
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub struct ScopeId(usize);
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub struct SymbolId(usize);
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ScopeKind {
+    /// The scope holding the built-in catalogue. It encloses every module scope.
+    Builtin,
+    /// The global declarations of the other modules of a task. It lies between the built-in scope
+    /// and the module scope.
+    Task,
+    Module,
+    Routine,
+    /// The body of a `FOR` loop, which implicitly declares the loop variable.
+    Loop,
+}
+
+#[derive(Debug)]
+pub struct ScopeData {
+    pub kind: ScopeKind,
+    pub parent: Option<ScopeId>,
+    pub span: Span,
+    pub children: Vec<ScopeId>,
+    names: HashMap<String, SymbolId>,
+}
+
+impl ScopeData {
+    /// Returns the symbols declared directly in this scope.
+    pub fn symbols(&self) -> impl Iterator<Item = SymbolId> + '_ {
+        self.names.values().copied()
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SymbolKind<'a> {
+    Record(&'a ast::RecordDefinition),
+    Alias(&'a ast::AliasDefinition),
+    Data(&'a ast::VarDeclaration),
+    Procedure(&'a ast::ProcDeclaration),
+    Function(&'a ast::FuncDeclaration),
+    Trap(&'a ast::TrapDeclaration),
+    Parameter(&'a ast::ParameterDeclaration),
+    /// A `switch` parameter, which only carries a name.
+    Switch,
+    Label,
+    LoopVariable,
+}
+
+impl<'a> SymbolKind<'a> {
+    /// A short human readable description used in diagnostics.
+    pub fn description(&self) -> &'static str {
+        match self {
+            SymbolKind::Record(_) => "record",
+            SymbolKind::Alias(_) => "alias",
+            SymbolKind::Data(decl) => match decl.declaration_type {
+                ast::VarDeclarationType::VarDeclaration => "variable",
+                ast::VarDeclarationType::PersDeclaration => "persistent",
+                ast::VarDeclarationType::ConstDeclaration => "constant",
+            },
+            SymbolKind::Procedure(_) => "procedure",
+            SymbolKind::Function(_) => "function",
+            SymbolKind::Trap(_) => "trap",
+            SymbolKind::Parameter(_) | SymbolKind::Switch => "parameter",
+            SymbolKind::Label => "label",
+            SymbolKind::LoopVariable => "loop variable",
+        }
+    }
+
+    pub fn is_type(&self) -> bool {
+        matches!(self, SymbolKind::Record(_) | SymbolKind::Alias(_))
+    }
+
+    pub fn is_routine(&self) -> bool {
+        matches!(
+            self,
+            SymbolKind::Procedure(_) | SymbolKind::Function(_) | SymbolKind::Trap(_)
+        )
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Symbol<'a> {
+    pub name: &'a str,
+    pub kind: SymbolKind<'a>,
+    pub visibility: Scope,
+    /// The scope the symbol is declared in.
+    pub scope: ScopeId,
+    pub name_span: Span,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum ReferenceKind {
+    Read,
+    Write,
+    Call,
+    Type,
+    Label,
+}
+
+/// A use of a name in the source, together with the symbol it resolved to (if any).
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Reference {
+    pub name: String,
+    pub span: Span,
+    /// The innermost scope the reference occurs in.
+    pub scope: ScopeId,
+    pub kind: ReferenceKind,
+    pub symbol: Option<SymbolId>,
+}
+
+/// Scoped symbol tables for a module, plus every name reference found in it.
+///
+/// Names are compared case-insensitively, like RAPID itself does.
+#[derive(Debug, Default)]
+pub struct SymbolTable<'a> {
+    scopes: Vec<ScopeData>,
+    symbols: Vec<Symbol<'a>>,
+    references: Vec<Reference>,
+}
+
+fn key(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_scope(&mut self, kind: ScopeKind, parent: Option<ScopeId>, span: Span) -> ScopeId {
+        let id = ScopeId(self.scopes.len());
+        self.scopes.push(ScopeData {
+            kind,
+            parent,
+            span,
+            children: Vec::new(),
+            names: HashMap::new(),
+        });
+        if let Some(parent) = parent {
+            self.scopes[parent.0].children.push(id);
+        }
+        id
+    }
+
+    /// Declares a symbol in its scope. If the name is already taken in that scope the symbol is not
+    /// added and the existing symbol is returned as the error.
+    pub fn declare(&mut self, symbol: Symbol<'a>) -> Result<SymbolId, SymbolId> {
+        let name = key(symbol.name);
+        let scope = symbol.scope;
+        if let Some(existing) = self.scopes[scope.0].names.get(&name) {
+            return Err(*existing);
+        }
+        let id = SymbolId(self.symbols.len());
+        self.symbols.push(symbol);
+        self.scopes[scope.0].names.insert(name, id);
+        Ok(id)
+    }
+
+    /// Looks up a name in `scope` only.
+    pub fn lookup_local(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        self.scopes[scope.0].names.get(&key(name)).copied()
+    }
+
+    /// Looks up a name in `scope` and then in each of its enclosing scopes.
+    pub fn lookup(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        let name = key(name);
+        let mut current = Some(scope);
+        while let Some(id) = current {
+            let data = &self.scopes[id.0];
+            if let Some(symbol) = data.names.get(&name) {
+                return Some(*symbol);
+            }
+            current = data.parent;
+        }
+        None
+    }
+
+    /// Looks up a data type name like [`SymbolTable::lookup`], skipping symbols that are not types
+    /// so that a parameter named like its type (e.g. `pos Pos`) does not hide the type.
+    pub fn lookup_type(&self, scope: ScopeId, name: &str) -> Option<SymbolId> {
+        let name = key(name);
+        let mut current = Some(scope);
+        while let Some(id) = current {
+            let data = &self.scopes[id.0];
+            match data.names.get(&name) {
+                Some(symbol) if self.symbols[symbol.0].kind.is_type() => return Some(*symbol),
+                _ => current = data.parent,
+            }
+        }
+        None
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &ScopeData {
+        &self.scopes[id.0]
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> &Symbol<'a> {
+        &self.symbols[id.0]
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = (SymbolId, &Symbol<'a>)> {
+        self.symbols
+            .iter()
+            .enumerate()
+            .map(|(i, symbol)| (SymbolId(i), symbol))
+    }
+
+    /// Returns `true` if the symbol comes from the built-in catalogue, in which case its spans refer
+    /// to [`crate::builtins::SOURCE`] rather than to the analysed module.
+    pub fn is_builtin(&self, id: SymbolId) -> bool {
+        self.is_builtin_scope(self.symbol(id).scope)
+    }
+
+    pub fn is_builtin_scope(&self, scope: ScopeId) -> bool {
+        let mut current = Some(scope);
+        while let Some(id) = current {
+            let data = &self.scopes[id.0];
+            match data.kind {
+                ScopeKind::Builtin => return true,
+                ScopeKind::Task | ScopeKind::Module => return false,
+                _ => current = data.parent,
+            }
+        }
+        false
+    }
+
+    /// Returns `true` if the symbol is declared by another module of the task, in which case its
+    /// spans refer to the source of that module.
+    pub fn is_imported(&self, id: SymbolId) -> bool {
+        self.scope(self.symbol(id).scope).kind == ScopeKind::Task
+    }
+
+    pub fn add_reference(&mut self, reference: Reference) {
+        self.references.push(reference);
+    }
+
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    pub fn references_to(&self, symbol: SymbolId) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |r| r.symbol == Some(symbol))
+    }
+
+    /// Returns the symbol declared or referenced at `offset` in the analysed module.
+    pub fn symbol_at(&self, offset: usize) -> Option<SymbolId> {
+        let reference = self
+            .references
+            .iter()
+            .find(|r| r.symbol.is_some() && r.span.contains(offset));
+        if let Some(reference) = reference {
+            return reference.symbol;
+        }
+        self.symbols()
+            .find(|(id, symbol)| {
+                symbol.name_span.contains(offset) && !self.is_builtin(*id) && !self.is_imported(*id)
+            })
+            .map(|(id, _)| id)
+    }
+
+    /// Returns every symbol visible in `scope`, innermost first. Symbols hidden by a declaration of
+    /// the same name in an inner scope are left out.
+    pub fn visible(&self, scope: ScopeId) -> Vec<SymbolId> {
+        let mut seen = std::collections::HashSet::new();
+        let mut visible = Vec::new();
+        let mut current = Some(scope);
+        while let Some(id) = current {
+            let data = &self.scopes[id.0];
+            let mut symbols: Vec<SymbolId> = data
+                .names
+                .iter()
+                .filter(|(name, _)| seen.insert(name.as_str()))
+                .map(|(_, symbol)| *symbol)
+                .collect();
+            symbols.sort();
+            visible.extend(symbols);
+            current = data.parent;
+        }
+        visible
+    }
+
+    /// Returns the innermost scope below `root` whose span contains `offset`.
+    pub fn scope_at(&self, root: ScopeId, offset: usize) -> ScopeId {
+        let mut current = root;
+        'descend: loop {
+            for child in &self.scopes[current.0].children {
+                if self.scopes[child.0].span.contains(offset) {
+                    current = *child;
+                    continue 'descend;
+                }
+            }
+            return current;
+        }
+    }
+}
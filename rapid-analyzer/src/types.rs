@@ -0,0 +1,1674 @@
+use rapid_parser::ast::{
+    self, DataDeclaration, Expr, ExprKind, ModuleInfo, Number, OpCode, RoutineDeclaration,
+    Statement, StatementKind, Term, TestCaseKind, TypeDefinition, Variable, VariableKind,
+};
+use rapid_parser::Span;
+
+use crate::calls::Signature;
+use crate::diagnostics::Diagnostic;
+use crate::flow::{BlockKind, ControlFlowGraph, EdgeKind, Region};
+use crate::symbols::{ScopeId, ScopeKind, SymbolId, SymbolKind, SymbolTable};
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum AtomicType {
+    Num,
+    Dnum,
+    Bool,
+    String,
+}
+
+impl AtomicType {
+    pub fn from_name(name: &str) -> Option<AtomicType> {
+        match name.to_ascii_lowercase().as_str() {
+            "num" => Some(AtomicType::Num),
+            "dnum" => Some(AtomicType::Dnum),
+            "bool" => Some(AtomicType::Bool),
+            "string" => Some(AtomicType::String),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AtomicType::Num => "num",
+            AtomicType::Dnum => "dnum",
+            AtomicType::Bool => "bool",
+            AtomicType::String => "string",
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, AtomicType::Num | AtomicType::Dnum)
+    }
+
+    /// Returns the range of integers a numeric type holds exactly; larger values are rounded.
+    pub fn exact_integers(&self) -> Option<(i64, u64)> {
+        match self {
+            AtomicType::Num => Some((-8_388_607, 8_388_608)),
+            AtomicType::Dnum => Some((-(DNUM_MAX_INTEGER as i64), DNUM_MAX_INTEGER)),
+            _ => None,
+        }
+    }
+}
+
+/// The largest integer a `dnum` holds exactly.
+const DNUM_MAX_INTEGER: u64 = 4_503_599_627_370_496;
+
+/// The `PERS` parameters that built-in routines write, by routine and parameter name. The other
+/// built-in routines only read their `PERS` parameters, such as the tool of a move instruction.
+const WRITTEN_PERS_PARAMETERS: &[(&str, &str)] = &[("GetSysData", "DestObject")];
+
+/// The type of a RAPID value, with aliases already resolved to the type they name.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum Type {
+    Atomic(AtomicType),
+    Record(SymbolId),
+    /// An array of the element type with the given number of dimensions.
+    Array(Box<Type>, usize),
+    /// A type that could not be determined. It is compatible with every other type so that one
+    /// error does not cascade into many.
+    Unknown,
+}
+
+impl Type {
+    pub const NUM: Type = Type::Atomic(AtomicType::Num);
+    pub const BOOL: Type = Type::Atomic(AtomicType::Bool);
+    pub const STRING: Type = Type::Atomic(AtomicType::String);
+
+    pub fn is_unknown(&self) -> bool {
+        *self == Type::Unknown
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Atomic(atomic) if atomic.is_numeric())
+    }
+
+    /// Returns a RAPID-like name for the type, e.g. `num` or `pos{*,*}`.
+    pub fn display(&self, table: &SymbolTable) -> String {
+        match self {
+            Type::Atomic(atomic) => atomic.name().to_owned(),
+            Type::Record(id) => table.symbol(*id).name.to_owned(),
+            Type::Array(element, dims) => {
+                format!(
+                    "{}{{{}}}",
+                    element.display(table),
+                    vec!["*"; *dims].join(",")
+                )
+            }
+            Type::Unknown => "unknown".to_owned(),
+        }
+    }
+
+    /// Returns `true` if a value of type `value` can be stored in a location of type `self`.
+    pub fn accepts(&self, value: &Type) -> bool {
+        match (self, value) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Atomic(a), Type::Atomic(b)) if a.is_numeric() && b.is_numeric() => true,
+            (Type::Array(a, n), Type::Array(b, m)) => n == m && a.accepts(b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+/// The type names an alias chain passes through, starting with the name itself, with the symbol
+/// each name refers to. The chain ends at a name that is not an alias and before an alias that was
+/// already followed, which is kept in `cycle`.
+struct Aliases<'s, 'a> {
+    table: &'s SymbolTable<'a>,
+    scope: ScopeId,
+    next: Option<&'s str>,
+    followed: Vec<SymbolId>,
+    cycle: Option<SymbolId>,
+}
+
+impl<'s, 'a> Aliases<'s, 'a> {
+    fn new(table: &'s SymbolTable<'a>, scope: ScopeId, name: &'s str) -> Self {
+        Aliases {
+            table,
+            scope,
+            next: Some(name),
+            followed: Vec::new(),
+            cycle: None,
+        }
+    }
+}
+
+impl<'s> Iterator for Aliases<'s, '_> {
+    type Item = (&'s str, Option<SymbolId>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let name = self.next.take()?;
+        // Atomic types are part of the language and cannot be redeclared
+        if AtomicType::from_name(name).is_some() {
+            return Some((name, None));
+        }
+        let symbol = self.table.lookup_type(self.scope, name);
+        if let Some(id) = symbol {
+            if let SymbolKind::Alias(alias) = self.table.symbol(id).kind {
+                if self.followed.contains(&id) {
+                    self.cycle = Some(id);
+                    return None;
+                }
+                self.followed.push(id);
+                self.next = Some(&alias.data_type);
+            }
+        }
+        Some((name, symbol))
+    }
+}
+
+/// Infers the types of expressions and checks that operators, conditions and assignments are used
+/// with compatible types.
+pub struct TypeChecker<'a, 't> {
+    table: &'t SymbolTable<'a>,
+    module_scope: ScopeId,
+    pub diagnostics: &'t mut Vec<Diagnostic>,
+    /// The spans of the variables passed to `VAR`, `INOUT` and `PERS` parameters, which the called
+    /// routine may change. Arguments to the `PERS` parameters that built-in routines only read are
+    /// left out.
+    pub by_reference: Vec<Span>,
+    /// What a `RETURN` in the statements being checked returns, if that is checked.
+    returns: Option<Returns>,
+    /// Whether the statements being checked belong to an error handler, where `RETRY`, `TRYNEXT`
+    /// and `RAISE` without an error number are allowed.
+    in_error_handler: bool,
+}
+
+/// What the routine being checked returns.
+#[derive(Debug, Clone)]
+enum Returns {
+    /// Nothing: a procedure or trap, with the kind and name of the routine.
+    Nothing(&'static str, String),
+    /// A value of the type: a function, with its name.
+    Value(Type, String),
+}
+
+impl<'a, 't> TypeChecker<'a, 't> {
+    pub fn new(
+        table: &'t SymbolTable<'a>,
+        module_scope: ScopeId,
+        diagnostics: &'t mut Vec<Diagnostic>,
+    ) -> Self {
+        TypeChecker {
+            table,
+            module_scope,
+            diagnostics,
+            by_reference: Vec::new(),
+            returns: None,
+            in_error_handler: false,
+        }
+    }
+
+    fn scope_at(&self, offset: usize) -> ScopeId {
+        self.table.scope_at(self.module_scope, offset)
+    }
+
+    fn display(&self, ty: &Type) -> String {
+        ty.display(self.table)
+    }
+
+    /// Resolves a data type name, following alias chains.
+    pub fn resolve_type_name(&self, name: &str, scope: ScopeId) -> Type {
+        for (name, symbol) in Aliases::new(self.table, scope, name) {
+            if let Some(atomic) = AtomicType::from_name(name) {
+                return Type::Atomic(atomic);
+            }
+            match symbol.map(|id| (id, self.table.symbol(id).kind)) {
+                Some((id, SymbolKind::Record(_))) => return Type::Record(id),
+                Some((_, SymbolKind::Alias(_))) => {}
+                _ => return Type::Unknown,
+            }
+        }
+        // An alias cycle
+        Type::Unknown
+    }
+
+    fn declared_type(&self, data_type: &str, dim: Option<&ast::Dimension>, scope: ScopeId) -> Type {
+        let element = self.resolve_type_name(data_type, scope);
+        match dim.map(|dim| &dim.kind) {
+            Some(ast::DimensionKind::Dimension(dims)) => Type::Array(Box::new(element), dims.len()),
+            Some(ast::DimensionKind::DIM) => Type::Unknown,
+            None => element,
+        }
+    }
+
+    /// Returns the declared type of a data object, parameter or loop variable.
+    pub fn symbol_type(&self, id: SymbolId) -> Option<Type> {
+        let symbol = self.table.symbol(id);
+        match symbol.kind {
+            SymbolKind::Data(decl) => Some(self.declared_type(
+                &decl.data_type,
+                decl.definition.dim.as_ref(),
+                symbol.scope,
+            )),
+            SymbolKind::Parameter(parameter) => {
+                Some(self.declared_type(&parameter.data_type, parameter.dim.as_ref(), symbol.scope))
+            }
+            SymbolKind::LoopVariable => Some(Type::NUM),
+            SymbolKind::Switch => Some(Type::Unknown),
+            _ => None,
+        }
+    }
+
+    /// Returns the type of a record component, or `None` if the record has no such component.
+    pub fn component_type(&self, record: SymbolId, component: &str) -> Option<Type> {
+        let symbol = self.table.symbol(record);
+        let SymbolKind::Record(definition) = symbol.kind else {
+            return None;
+        };
+        definition
+            .components
+            .iter()
+            .find(|c| c.name.eq_ignore_ascii_case(component))
+            .map(|c| self.resolve_type_name(&c.data_type, symbol.scope))
+    }
+
+    /// Returns a record type declared by the built-in catalogue, ignoring user declarations that
+    /// shadow it.
+    fn builtin_type(&self, name: &str) -> Option<Type> {
+        let mut scope = self.module_scope;
+        while let Some(parent) = self.table.scope(scope).parent {
+            scope = parent;
+        }
+        if self.table.scope(scope).kind != ScopeKind::Builtin {
+            return None;
+        }
+        self.table.lookup_local(scope, name).map(Type::Record)
+    }
+
+    /// Returns the result type of the arithmetic RAPID defines on `pos` and `orient` values: vector
+    /// addition, subtraction and cross product, scaling by a number and quaternion multiplication.
+    fn geometry_result(&self, op: &OpCode, left: &Type, right: &Type) -> Option<Type> {
+        let pos = self.builtin_type("pos")?;
+        let orient = self.builtin_type("orient")?;
+        let is = |ty: &Type, expected: &Type| ty.is_unknown() || ty == expected;
+        let numeric = |ty: &Type| ty.is_unknown() || ty.is_numeric();
+        match op {
+            OpCode::Add | OpCode::Sub | OpCode::Mul if is(left, &pos) && is(right, &pos) => {
+                Some(pos)
+            }
+            OpCode::Mul
+                if (is(left, &pos) && numeric(right)) || (numeric(left) && is(right, &pos)) =>
+            {
+                Some(pos)
+            }
+            OpCode::Mul if is(left, &orient) && is(right, &orient) => Some(orient),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the chain of an alias comes back to the alias itself. An alias that only
+    /// leads into a cycle of others is not part of it.
+    fn is_alias_cycle(&self, alias: &ast::AliasDefinition) -> bool {
+        let mut aliases = Aliases::new(self.table, self.module_scope, &alias.name);
+        aliases.by_ref().for_each(drop);
+        aliases.cycle.is_some() && aliases.cycle == aliases.followed.first().copied()
+    }
+
+    pub fn check_module(&mut self, module: &ModuleInfo) {
+        for statement in &module.statements {
+            self.check_module_statement(statement);
+        }
+    }
+
+    /// Checks one statement of a module, such as a routine.
+    pub(crate) fn check_module_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(_, alias))
+                if self.is_alias_cycle(alias) =>
+            {
+                self.diagnostics.push(Diagnostic::error(
+                    "alias-cycle",
+                    format!("alias '{}' refers to itself", alias.name),
+                    alias.name_span,
+                ));
+            }
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                self.check_declaration(decl)
+            }
+            StatementKind::RoutineDeclaration(routine) => self.check_routine(routine),
+            _ => {}
+        }
+    }
+
+    fn check_routine(&mut self, routine: &RoutineDeclaration) {
+        let (statements, error, handlers, returns): (_, _, Vec<&Vec<Statement>>, _) = match routine
+        {
+            RoutineDeclaration::ProcDeclaration(proc) => (
+                &proc.statements,
+                &proc.error_handler,
+                [&proc.backward_handler, &proc.undo_handler]
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+                Returns::Nothing("procedure", proc.name.clone()),
+            ),
+            RoutineDeclaration::FuncDeclaration(func) => (
+                &func.statements,
+                &func.error_handler,
+                func.undo_handler.iter().collect(),
+                Returns::Value(
+                    self.resolve_type_name(&func.data_type, self.module_scope),
+                    func.name.clone(),
+                ),
+            ),
+            RoutineDeclaration::TrapDeclaration(trap) => (
+                &trap.statements,
+                &trap.error_handler,
+                trap.undo_handler.iter().collect(),
+                Returns::Nothing("trap", trap.name.clone()),
+            ),
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => return,
+        };
+        self.returns = Some(returns);
+        self.check_statements(statements);
+        if let Some(handler) = error {
+            self.check_error_numbers(&handler.numbers);
+            self.in_error_handler = true;
+            self.check_statements(&handler.statements);
+            self.in_error_handler = false;
+        }
+        // The UNDO handler of a function runs when its result is no longer wanted
+        if let Some(Returns::Value(..)) = self.returns {
+            self.returns = None;
+        }
+        for handler in handlers {
+            self.check_statements(handler);
+        }
+        self.returns = None;
+        if let RoutineDeclaration::FuncDeclaration(func) = routine {
+            self.check_missing_return(routine, func);
+        }
+    }
+
+    /// Reports a function whose body or error handler can reach `ENDFUNC` without a `RETURN`.
+    fn check_missing_return(&mut self, routine: &RoutineDeclaration, func: &ast::FuncDeclaration) {
+        let Some(graph) = ControlFlowGraph::new(routine) else {
+            return;
+        };
+        let reachable = graph.reachable();
+        let falls_through = graph.predecessors(graph.return_block).any(|edge| {
+            edge.kind == EdgeKind::End
+                && reachable[edge.from]
+                && matches!(
+                    graph.blocks[edge.from].kind,
+                    BlockKind::Code(Region::Body | Region::Error)
+                )
+        });
+        if falls_through {
+            let end = Span::new(func.span.end - "ENDFUNC".len(), func.span.end);
+            self.diagnostics.push(
+                Diagnostic::error(
+                    "missing-return",
+                    format!(
+                        "function '{}' can reach its end without returning a value",
+                        func.name
+                    ),
+                    func.name_span,
+                )
+                .with_related("execution can reach this ENDFUNC", end),
+            );
+        }
+    }
+
+    fn check_declaration(&mut self, decl: &ast::VarDeclaration) {
+        if let Some(dim) = &decl.definition.dim {
+            self.check_dimension_count(dim, &decl.definition.identifier);
+        }
+        let Some(expr) = &decl.definition.expression else {
+            return;
+        };
+        let scope = self.scope_at(decl.span.start);
+        let target = self.declared_type(&decl.data_type, decl.definition.dim.as_ref(), scope);
+        let sizes = self.array_sizes(decl.definition.dim.as_ref(), scope);
+        self.check_value(&target, &sizes, expr);
+    }
+
+    /// Reports an array declaration or element access with more than three dimensions.
+    fn check_dimension_count(&mut self, dim: &ast::Dimension, name: &str) -> bool {
+        let ast::DimensionKind::Dimension(dims) = &dim.kind else {
+            return true;
+        };
+        if dims.len() <= 3 {
+            return true;
+        }
+        self.diagnostics.push(Diagnostic::error(
+            "invalid-index",
+            format!(
+                "'{}' uses {} dimensions but arrays have at most 3",
+                name,
+                dims.len()
+            ),
+            dim.span,
+        ));
+        false
+    }
+
+    /// Returns the size of each dimension of an array declaration, where it is an integer or a
+    /// constant initialized with one.
+    fn array_sizes(&self, dim: Option<&ast::Dimension>, scope: ScopeId) -> Vec<Option<u64>> {
+        let Some(ast::DimensionKind::Dimension(dims)) = dim.map(|dim| &dim.kind) else {
+            return Vec::new();
+        };
+        dims.iter()
+            .map(|size| self.constant_integer(size, scope))
+            .collect()
+    }
+
+    /// Returns the array sizes of a variable that names a whole array declared in this module.
+    fn variable_sizes(&self, variable: &Variable) -> Vec<Option<u64>> {
+        let VariableKind::Variable(name) = &variable.kind else {
+            return Vec::new();
+        };
+        let scope = self.scope_at(variable.span.start);
+        let Some(id) = self.table.lookup(scope, name) else {
+            return Vec::new();
+        };
+        let symbol = self.table.symbol(id);
+        match symbol.kind {
+            SymbolKind::Data(decl) => self.array_sizes(decl.definition.dim.as_ref(), symbol.scope),
+            _ => Vec::new(),
+        }
+    }
+
+    fn constant_integer(&self, expr: &Expr, scope: ScopeId) -> Option<u64> {
+        match &expr.kind {
+            ExprKind::Term(Term::Num(Number::Integer(value))) => Some(*value),
+            ExprKind::Term(Term::Var(Variable {
+                kind: VariableKind::Variable(name),
+                ..
+            })) => {
+                let symbol = self.table.symbol(self.table.lookup(scope, name)?);
+                match symbol.kind {
+                    SymbolKind::Data(decl)
+                        if decl.declaration_type == ast::VarDeclarationType::ConstDeclaration =>
+                    {
+                        self.constant_integer(decl.definition.expression.as_ref()?, symbol.scope)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks a value stored in a location of type `target`. Aggregates are checked element by
+    /// element, against the `sizes` of the array dimensions where they are known and against the
+    /// components of records.
+    fn check_value(&mut self, target: &Type, sizes: &[Option<u64>], expr: &Expr) {
+        let ExprKind::Term(Term::Array(elements)) = &expr.kind else {
+            let value = self.infer(expr);
+            self.check_assignable(target, &value, expr.span);
+            self.check_literals(target, expr);
+            return;
+        };
+        match target {
+            Type::Array(element, dims) => {
+                if let Some(Some(size)) = sizes.first() {
+                    if elements.len() as u64 != *size {
+                        self.diagnostics.push(Diagnostic::error(
+                            "aggregate-shape",
+                            format!(
+                                "expected {} elements for {}, found {}",
+                                size,
+                                self.display(target),
+                                elements.len()
+                            ),
+                            expr.span,
+                        ));
+                    }
+                }
+                let inner = match dims {
+                    1 => (**element).clone(),
+                    _ => Type::Array(element.clone(), dims - 1),
+                };
+                let sizes = sizes.get(1..).unwrap_or_default();
+                for element in elements {
+                    self.check_value(&inner, sizes, element);
+                }
+            }
+            Type::Record(id) => {
+                let symbol = self.table.symbol(*id);
+                let SymbolKind::Record(record) = symbol.kind else {
+                    return;
+                };
+                if elements.len() != record.components.len() {
+                    self.diagnostics.push(Diagnostic::error(
+                        "aggregate-shape",
+                        format!(
+                            "expected {} components for {}, found {}",
+                            record.components.len(),
+                            symbol.name,
+                            elements.len()
+                        ),
+                        expr.span,
+                    ));
+                }
+                for (component, element) in record.components.iter().zip(elements) {
+                    let ty = self.resolve_type_name(&component.data_type, symbol.scope);
+                    self.check_value(&ty, &[], element);
+                }
+                for element in elements.iter().skip(record.components.len()) {
+                    self.infer(element);
+                }
+            }
+            Type::Atomic(_) => {
+                self.diagnostics.push(Diagnostic::error(
+                    "type-mismatch",
+                    format!("cannot assign an aggregate to {}", self.display(target)),
+                    expr.span,
+                ));
+                for element in elements {
+                    self.infer(element);
+                }
+            }
+            Type::Unknown => {
+                for element in elements {
+                    self.infer(element);
+                }
+            }
+        }
+    }
+
+    fn check_assignable(&mut self, target: &Type, value: &Type, span: Span) {
+        if !target.accepts(value) {
+            self.diagnostics.push(Diagnostic::error(
+                "type-mismatch",
+                format!(
+                    "cannot assign {} to {}",
+                    self.display(value),
+                    self.display(target)
+                ),
+                span,
+            ));
+        }
+    }
+
+    /// Reports integer literals in a value for `target` that a `num` cannot hold exactly, instead of
+    /// letting the controller round them. Literals too large even for a `dnum` are reported by
+    /// `infer`.
+    fn check_literals(&mut self, target: &Type, expr: &Expr) {
+        let element = match target {
+            Type::Array(element, _) => element,
+            ty => ty,
+        };
+        if *element == Type::NUM {
+            self.check_literal_range(AtomicType::Num, expr, false);
+        }
+    }
+
+    fn check_literal_range(&mut self, ty: AtomicType, expr: &Expr, negated: bool) {
+        match &expr.kind {
+            ExprKind::Term(Term::Num(Number::Integer(value))) => {
+                let (min, max) = ty.exact_integers().unwrap();
+                let limit = if negated { min.unsigned_abs() } else { max };
+                if *value > limit && *value <= DNUM_MAX_INTEGER {
+                    self.diagnostics.push(Diagnostic::error(
+                        "literal-out-of-range",
+                        format!(
+                            "integer {}{} is outside the exact range of {} ({} to {}) and would be rounded, use dnum",
+                            if negated { "-" } else { "" },
+                            value,
+                            ty.name(),
+                            min,
+                            max
+                        ),
+                        expr.span,
+                    ));
+                }
+            }
+            ExprKind::Term(Term::Array(elements)) => {
+                for element in elements {
+                    self.check_literal_range(ty, element, false);
+                }
+            }
+            ExprKind::UnaryOp(OpCode::Sub, operand) => {
+                self.check_literal_range(ty, operand, !negated)
+            }
+            ExprKind::UnaryOp(OpCode::Add, operand) => {
+                self.check_literal_range(ty, operand, negated)
+            }
+            ExprKind::Op(
+                left,
+                OpCode::Add
+                | OpCode::Sub
+                | OpCode::Mul
+                | OpCode::Div
+                | OpCode::DivInt
+                | OpCode::Mod,
+                right,
+            ) => {
+                self.check_literal_range(ty, left, false);
+                self.check_literal_range(ty, right, false);
+            }
+            _ => {}
+        }
+    }
+
+    fn expect(&mut self, expected: &Type, expr: &Expr, what: &str) {
+        let actual = self.infer(expr);
+        if !expected.accepts(&actual) {
+            self.diagnostics.push(Diagnostic::error(
+                "type-mismatch",
+                format!(
+                    "{} must be {}, found {}",
+                    what,
+                    self.display(expected),
+                    self.display(&actual)
+                ),
+                expr.span,
+            ));
+        }
+    }
+
+    fn check_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            self.check_statement(statement);
+        }
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                self.check_declaration(decl)
+            }
+            StatementKind::Assignment(ast::AssignmentTarget::Variable(variable), expr) => {
+                self.check_constant_assignment(variable);
+                let target = self.variable_type(variable);
+                let sizes = self.variable_sizes(variable);
+                self.check_value(&target, &sizes, expr);
+            }
+            StatementKind::If(condition, statements, else_ifs, else_statements) => {
+                self.expect(&Type::BOOL, condition, "condition");
+                self.check_statements(statements);
+                for (condition, statements) in else_ifs {
+                    self.expect(&Type::BOOL, condition, "condition");
+                    self.check_statements(statements);
+                }
+                self.check_statements(else_statements);
+            }
+            StatementKind::For(_, from, to, step, statements) => {
+                self.expect(&Type::NUM, from, "loop start");
+                self.expect(&Type::NUM, to, "loop end");
+                if let Some(step) = step {
+                    self.expect(&Type::NUM, step, "loop step");
+                }
+                self.check_statements(statements);
+            }
+            StatementKind::While(condition, statements) => {
+                self.expect(&Type::BOOL, condition, "condition");
+                self.check_statements(statements);
+            }
+            StatementKind::Test(expr, cases, default) => {
+                let tested = self.infer(expr);
+                for case in cases {
+                    if let TestCaseKind::Case(values, statements) = &case.kind {
+                        for value in values {
+                            self.expect(&tested, value, "test case value");
+                        }
+                        self.check_statements(statements);
+                    }
+                }
+                if let Some(default) = default {
+                    self.check_statements(default);
+                }
+            }
+            StatementKind::ProcCall(callee, arguments) => match &callee.kind {
+                ExprKind::Term(Term::String(name)) => {
+                    self.check_call(name, arguments, callee.span, statement.span, false);
+                }
+                _ => {
+                    self.expect(&Type::STRING, callee, "late bound procedure name");
+                    self.infer_arguments(arguments);
+                }
+            },
+            StatementKind::Connect(interrupt, trap) => self.check_connect(interrupt, trap),
+            StatementKind::Return(value) => self.check_return(value.as_ref(), statement.span),
+            StatementKind::Raise(Some(expr)) => {
+                self.expect(&Type::NUM, expr, "error number");
+            }
+            StatementKind::Raise(None) | StatementKind::Retry | StatementKind::TryNext
+                if !self.in_error_handler =>
+            {
+                let message = match statement.kind {
+                    StatementKind::Retry => "RETRY is only allowed in an error handler",
+                    StatementKind::TryNext => "TRYNEXT is only allowed in an error handler",
+                    _ => "RAISE without an error number is only allowed in an error handler",
+                };
+                self.diagnostics.push(Diagnostic::error(
+                    "misplaced-statement",
+                    message,
+                    statement.span,
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks that `CONNECT` connects an `intnum` variable with a trap. Unknown names are reported
+    /// by the resolver.
+    fn check_connect(&mut self, interrupt: &ast::Identifier, trap: &ast::Identifier) {
+        let table = self.table;
+        if let Some(id) = table.lookup(self.scope_at(interrupt.span.start), &interrupt.name) {
+            let symbol = table.symbol(id);
+            let (data_type, dim) = match symbol.kind {
+                SymbolKind::Data(decl)
+                    if decl.declaration_type == ast::VarDeclarationType::VarDeclaration =>
+                {
+                    (Some(&decl.data_type), decl.definition.dim.as_ref())
+                }
+                SymbolKind::Parameter(parameter)
+                    if matches!(
+                        parameter.access_mode,
+                        ast::AccessMode::VAR | ast::AccessMode::INOUT
+                    ) =>
+                {
+                    (Some(&parameter.data_type), parameter.dim.as_ref())
+                }
+                _ => (None, None),
+            };
+            let message = match data_type {
+                None => Some(format!(
+                    "interrupt number must be an intnum variable, found {} '{}'",
+                    symbol.kind.description(),
+                    interrupt.name
+                )),
+                Some(_) if dim.is_some() => Some(format!(
+                    "interrupt number must be an intnum variable, '{}' is an array",
+                    interrupt.name
+                )),
+                Some(data_type) if !self.is_type_named(data_type, symbol.scope, "intnum") => {
+                    Some(format!(
+                        "interrupt number must be an intnum variable, '{}' is {}",
+                        interrupt.name, data_type
+                    ))
+                }
+                Some(_) => None,
+            };
+            if let Some(message) = message {
+                self.diagnostics.push(Diagnostic::error(
+                    "invalid-interrupt",
+                    message,
+                    interrupt.span,
+                ));
+            }
+        }
+        if let Some(id) = table.lookup(self.scope_at(trap.span.start), &trap.name) {
+            let kind = table.symbol(id).kind;
+            if !matches!(kind, SymbolKind::Trap(_)) {
+                self.diagnostics.push(Diagnostic::error(
+                    "not-callable",
+                    format!("{} '{}' is not a trap", kind.description(), trap.name),
+                    trap.span,
+                ));
+            }
+        }
+    }
+
+    /// Returns whether `data_type` is the type `name` or an alias of it.
+    fn is_type_named(&self, data_type: &str, scope: ScopeId, name: &str) -> bool {
+        Aliases::new(self.table, scope, data_type)
+            .any(|(other, _)| other.eq_ignore_ascii_case(name))
+    }
+
+    /// Checks that the error numbers an error handler catches name `errnum` data, such as the
+    /// built-in `ERR_*` constants. Unknown names are reported by the resolver.
+    fn check_error_numbers(&mut self, numbers: &[Expr]) {
+        for number in numbers {
+            let ExprKind::Term(Term::String(name)) = &number.kind else {
+                continue;
+            };
+            let Some(id) = self.table.lookup(self.scope_at(number.span.start), name) else {
+                continue;
+            };
+            let symbol = self.table.symbol(id);
+            let data_type = match symbol.kind {
+                SymbolKind::Data(decl) => Some(decl.data_type.as_str()),
+                SymbolKind::Parameter(parameter) => Some(parameter.data_type.as_str()),
+                SymbolKind::LoopVariable => Some("num"),
+                _ => None,
+            };
+            match data_type {
+                Some(data_type) if !self.is_type_named(data_type, symbol.scope, "errnum") => {
+                    self.diagnostics.push(Diagnostic::error(
+                        "type-mismatch",
+                        format!("error number must be errnum, found {}", data_type),
+                        number.span,
+                    ));
+                }
+                Some(_) => {}
+                None => self.diagnostics.push(Diagnostic::error(
+                    "invalid-error-number",
+                    format!("'{}' is not an error number", name),
+                    number.span,
+                )),
+            }
+        }
+    }
+
+    /// Checks that a `RETURN` in a function returns a value of its type, and that a `RETURN` in a
+    /// procedure or trap returns nothing.
+    fn check_return(&mut self, value: Option<&Expr>, span: Span) {
+        match (self.returns.clone(), value) {
+            (Some(Returns::Nothing(kind, name)), Some(expr)) => {
+                self.infer(expr);
+                self.diagnostics.push(Diagnostic::error(
+                    "invalid-return",
+                    format!("{} '{}' cannot return a value", kind, name),
+                    expr.span,
+                ));
+            }
+            (Some(Returns::Value(ty, name)), None) => {
+                self.diagnostics.push(Diagnostic::error(
+                    "invalid-return",
+                    format!(
+                        "function '{}' must return a value of type {}",
+                        name,
+                        self.display(&ty)
+                    ),
+                    span,
+                ));
+            }
+            (Some(Returns::Value(ty, _)), Some(expr)) => {
+                if let ExprKind::Term(Term::Array(_)) = expr.kind {
+                    self.check_value(&ty, &[], expr);
+                } else {
+                    self.expect(&ty, expr, "return value");
+                    self.check_literals(&ty, expr);
+                }
+            }
+            (None, Some(expr)) => {
+                self.infer(expr);
+            }
+            (_, None) => {}
+        }
+    }
+
+    fn check_constant_assignment(&mut self, variable: &Variable) {
+        let root = root_name(variable);
+        let scope = self.scope_at(variable.span.start);
+        let Some(id) = self.table.lookup(scope, root) else {
+            return;
+        };
+        if let SymbolKind::Data(decl) = self.table.symbol(id).kind {
+            if decl.declaration_type == ast::VarDeclarationType::ConstDeclaration {
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        "const-assignment",
+                        format!("cannot assign to constant '{}'", root),
+                        variable.span,
+                    )
+                    .with_related("constant declared here", decl.definition.identifier_span),
+                );
+            }
+        }
+    }
+
+    fn infer_arguments(&mut self, arguments: &[ast::Argument]) {
+        for argument in arguments {
+            match &argument.kind {
+                ast::ArgumentKind::Required(_, expr)
+                | ast::ArgumentKind::Optional(_, Some(expr)) => {
+                    self.infer(expr);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Checks the arguments of a call against the signature of the called routine and returns the
+    /// type of the value the call produces.
+    fn check_call(
+        &mut self,
+        name: &str,
+        arguments: &[ast::Argument],
+        name_span: Span,
+        span: Span,
+        function: bool,
+    ) -> Type {
+        let table = self.table;
+        let Some(symbol) = table
+            .lookup(self.scope_at(span.start), name)
+            .map(|id| table.symbol(id))
+        else {
+            self.infer_arguments(arguments);
+            return Type::Unknown;
+        };
+        let (parameters, result) = match symbol.kind {
+            SymbolKind::Procedure(proc) if !function => (&proc.parameters, Type::Unknown),
+            SymbolKind::Function(func) if function => (
+                &func.parameters,
+                self.resolve_type_name(&func.data_type, symbol.scope),
+            ),
+            kind => {
+                self.diagnostics.push(Diagnostic::error(
+                    "not-callable",
+                    format!(
+                        "{} '{}' is not a {}",
+                        kind.description(),
+                        name,
+                        if function { "function" } else { "procedure" }
+                    ),
+                    name_span,
+                ));
+                self.infer_arguments(arguments);
+                return Type::Unknown;
+            }
+        };
+        let Some(signature) = Signature::new(parameters) else {
+            self.infer_arguments(arguments);
+            return result;
+        };
+        let bindings = signature.bind(name, arguments, span, self.diagnostics);
+        for (argument, parameter) in arguments.iter().zip(bindings) {
+            match (&argument.kind, parameter) {
+                (
+                    ast::ArgumentKind::Required(_, expr)
+                    | ast::ArgumentKind::Optional(_, Some(expr)),
+                    Some(parameter),
+                ) => self.check_argument(name, parameter, symbol.scope, expr),
+                (
+                    ast::ArgumentKind::Required(_, expr)
+                    | ast::ArgumentKind::Optional(_, Some(expr)),
+                    None,
+                ) => {
+                    self.infer(expr);
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// Checks an argument against the type and access mode of the parameter it is passed to.
+    /// `scope` is the scope the called routine is declared in.
+    fn check_argument(
+        &mut self,
+        routine: &str,
+        parameter: &ast::ParameterDeclaration,
+        scope: ScopeId,
+        expr: &Expr,
+    ) {
+        let expected = self.declared_type(&parameter.data_type, parameter.dim.as_ref(), scope);
+        if matches!(expr.kind, ExprKind::Term(Term::Array(_))) {
+            self.check_value(&expected, &[], expr);
+        } else {
+            self.check_argument_type(parameter, &expected, expr);
+        }
+
+        let (required, mode) = match parameter.access_mode {
+            ast::AccessMode::IN => return,
+            ast::AccessMode::VAR => ("a variable", "VAR"),
+            ast::AccessMode::PERS => ("a persistent", "PERS"),
+            ast::AccessMode::INOUT | ast::AccessMode::REF => ("a variable or persistent", "INOUT"),
+        };
+        // Whether the argument refers to a variable and whether it refers to a persistent
+        let storage = match &expr.kind {
+            ExprKind::Term(Term::Var(variable)) => {
+                let read_only = parameter.access_mode == ast::AccessMode::PERS
+                    && self.table.scope(scope).kind == ScopeKind::Builtin
+                    && !WRITTEN_PERS_PARAMETERS.iter().any(|(name, written)| {
+                        name.eq_ignore_ascii_case(routine)
+                            && written.eq_ignore_ascii_case(&parameter.name)
+                    });
+                if !read_only {
+                    self.by_reference.push(expr.span);
+                }
+                let scope = self.scope_at(expr.span.start);
+                let Some(id) = self.table.lookup(scope, root_name(variable)) else {
+                    return;
+                };
+                match self.table.symbol(id).kind {
+                    SymbolKind::Data(decl) => match decl.declaration_type {
+                        ast::VarDeclarationType::VarDeclaration => (true, false),
+                        ast::VarDeclarationType::PersDeclaration => (false, true),
+                        ast::VarDeclarationType::ConstDeclaration => (false, false),
+                    },
+                    SymbolKind::Parameter(p) => match p.access_mode {
+                        ast::AccessMode::PERS => (false, true),
+                        ast::AccessMode::INOUT | ast::AccessMode::REF => (true, true),
+                        ast::AccessMode::IN | ast::AccessMode::VAR => (true, false),
+                    },
+                    _ => (false, false),
+                }
+            }
+            _ => (false, false),
+        };
+        let accepted = match parameter.access_mode {
+            ast::AccessMode::VAR => storage.0,
+            ast::AccessMode::PERS => storage.1,
+            _ => storage.0 || storage.1,
+        };
+        if !accepted {
+            self.diagnostics.push(Diagnostic::error(
+                "not-assignable",
+                format!(
+                    "argument for {} parameter '{}' must be {}",
+                    mode, parameter.name, required
+                ),
+                expr.span,
+            ));
+        }
+    }
+
+    fn check_argument_type(
+        &mut self,
+        parameter: &ast::ParameterDeclaration,
+        expected: &Type,
+        expr: &Expr,
+    ) {
+        let actual = self.infer(expr);
+        if !expected.accepts(&actual) {
+            self.diagnostics.push(Diagnostic::error(
+                "type-mismatch",
+                format!(
+                    "argument '{}' must be {}, found {}",
+                    parameter.name,
+                    self.display(expected),
+                    self.display(&actual)
+                ),
+                expr.span,
+            ));
+        }
+        self.check_literals(expected, expr);
+    }
+
+    /// Returns the type of a variable reference, reporting bad indexing and unknown components.
+    pub fn variable_type(&mut self, variable: &Variable) -> Type {
+        match &variable.kind {
+            VariableKind::Variable(name) => self.name_type(name, variable.span),
+            VariableKind::VariableElement(name, dim) => {
+                let base = self.name_type(name, variable.span);
+                let indices = match &dim.kind {
+                    ast::DimensionKind::Dimension(indices) => indices,
+                    ast::DimensionKind::DIM => return Type::Unknown,
+                };
+                for index in indices {
+                    self.expect(&Type::NUM, index, "array index");
+                }
+                if !self.check_dimension_count(dim, name) {
+                    return Type::Unknown;
+                }
+                match base {
+                    Type::Array(element, dims) if dims == indices.len() => *element,
+                    Type::Array(_, dims) => {
+                        self.diagnostics.push(Diagnostic::error(
+                            "invalid-index",
+                            format!(
+                                "'{}' has {} dimension(s) but is indexed with {}",
+                                name,
+                                dims,
+                                indices.len()
+                            ),
+                            dim.span,
+                        ));
+                        Type::Unknown
+                    }
+                    Type::Unknown => Type::Unknown,
+                    other => {
+                        self.diagnostics.push(Diagnostic::error(
+                            "invalid-index",
+                            format!(
+                                "'{}' of type {} is not an array",
+                                name,
+                                self.display(&other)
+                            ),
+                            dim.span,
+                        ));
+                        Type::Unknown
+                    }
+                }
+            }
+            VariableKind::VariableComponent(inner, component) => {
+                let base = self.variable_type(inner);
+                match base {
+                    Type::Record(record) => match self.component_type(record, component) {
+                        Some(ty) => ty,
+                        None => {
+                            self.diagnostics.push(Diagnostic::error(
+                                "unknown-component",
+                                format!(
+                                    "record '{}' has no component '{}'",
+                                    self.table.symbol(record).name,
+                                    component
+                                ),
+                                Span::new(variable.span.end - component.len(), variable.span.end),
+                            ));
+                            Type::Unknown
+                        }
+                    },
+                    Type::Unknown => Type::Unknown,
+                    other => {
+                        self.diagnostics.push(Diagnostic::error(
+                            "unknown-component",
+                            format!(
+                                "type {} has no component '{}'",
+                                self.display(&other),
+                                component
+                            ),
+                            Span::new(variable.span.end - component.len(), variable.span.end),
+                        ));
+                        Type::Unknown
+                    }
+                }
+            }
+        }
+    }
+
+    fn name_type(&mut self, name: &str, span: Span) -> Type {
+        let scope = self.scope_at(span.start);
+        let Some(id) = self.table.lookup(scope, name) else {
+            return Type::Unknown;
+        };
+        match self.symbol_type(id) {
+            Some(ty) => ty,
+            None => {
+                let symbol = self.table.symbol(id);
+                self.diagnostics.push(Diagnostic::error(
+                    "not-a-value",
+                    format!(
+                        "{} '{}' cannot be used as a value",
+                        symbol.kind.description(),
+                        name
+                    ),
+                    Span::new(span.start, span.start + name.len()),
+                ));
+                Type::Unknown
+            }
+        }
+    }
+
+    /// Infers the type of an expression, reporting operators applied to incompatible operands.
+    pub fn infer(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Term(Term::Num(Number::Integer(value))) if *value > DNUM_MAX_INTEGER => {
+                self.diagnostics.push(Diagnostic::error(
+                    "literal-out-of-range",
+                    format!(
+                        "integer {} is outside the exact range of dnum (up to {}) and would be rounded",
+                        value, DNUM_MAX_INTEGER
+                    ),
+                    expr.span,
+                ));
+                Type::NUM
+            }
+            ExprKind::Term(Term::Num(_)) => Type::NUM,
+            ExprKind::Term(Term::String(_)) => Type::STRING,
+            ExprKind::Term(Term::Bool(_)) => Type::BOOL,
+            ExprKind::Term(Term::Var(variable)) => self.variable_type(variable),
+            ExprKind::Term(Term::Array(elements)) => {
+                for element in elements {
+                    self.infer(element);
+                }
+                Type::Unknown
+            }
+            ExprKind::FuncCall(name, arguments) => {
+                let name_span = Span::new(expr.span.start, expr.span.start + name.len());
+                self.check_call(name, arguments, name_span, expr.span, true)
+            }
+            ExprKind::UnaryOp(op, operand) => {
+                let ty = self.infer(operand);
+                let valid = match op {
+                    OpCode::Not => Type::BOOL.accepts(&ty),
+                    _ => {
+                        ty.is_unknown()
+                            || ty.is_numeric()
+                            || self.builtin_type("pos").as_ref() == Some(&ty)
+                    }
+                };
+                if valid {
+                    ty
+                } else {
+                    self.diagnostics.push(Diagnostic::error(
+                        "invalid-operands",
+                        format!(
+                            "operator '{}' cannot be applied to {}",
+                            operator(op),
+                            self.display(&ty)
+                        ),
+                        expr.span,
+                    ));
+                    Type::Unknown
+                }
+            }
+            ExprKind::Op(left, op, right) => {
+                let left = self.infer(left);
+                let right = self.infer(right);
+                let result = binary_result(op, &left, &right)
+                    .or_else(|| self.geometry_result(op, &left, &right));
+                match result {
+                    Some(ty) => ty,
+                    None => {
+                        self.diagnostics.push(Diagnostic::error(
+                            "invalid-operands",
+                            format!(
+                                "operator '{}' cannot be applied to {} and {}",
+                                operator(op),
+                                self.display(&left),
+                                self.display(&right)
+                            ),
+                            expr.span,
+                        ));
+                        Type::Unknown
+                    }
+                }
+            }
+            ExprKind::EXP | ExprKind::Error => Type::Unknown,
+        }
+    }
+}
+
+/// Returns the result type of a binary operator, or `None` if the operands are not allowed.
+fn binary_result(op: &OpCode, left: &Type, right: &Type) -> Option<Type> {
+    let numeric = |ty: &Type| ty.is_unknown() || ty.is_numeric();
+    match op {
+        OpCode::Add if *left == Type::STRING || *right == Type::STRING => {
+            (Type::STRING.accepts(left) && Type::STRING.accepts(right)).then_some(Type::STRING)
+        }
+        OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => {
+            if !(numeric(left) && numeric(right)) {
+                return None;
+            }
+            Some(numeric_result(left, right))
+        }
+        OpCode::DivInt | OpCode::Mod => {
+            (numeric(left) && numeric(right)).then(|| numeric_result(left, right))
+        }
+        OpCode::Lt | OpCode::Lte | OpCode::Gt | OpCode::Gte => {
+            (numeric(left) && numeric(right)).then_some(Type::BOOL)
+        }
+        OpCode::Eq | OpCode::Ne => {
+            (left.accepts(right) || right.accepts(left)).then_some(Type::BOOL)
+        }
+        OpCode::And | OpCode::Or | OpCode::Xor | OpCode::Not => {
+            (Type::BOOL.accepts(left) && Type::BOOL.accepts(right)).then_some(Type::BOOL)
+        }
+    }
+}
+
+/// Mixing `num` and `dnum` yields a `dnum`.
+fn numeric_result(left: &Type, right: &Type) -> Type {
+    match (left, right) {
+        (Type::Unknown, _) | (_, Type::Unknown) => Type::Unknown,
+        (Type::Atomic(AtomicType::Dnum), _) | (_, Type::Atomic(AtomicType::Dnum)) => {
+            Type::Atomic(AtomicType::Dnum)
+        }
+        _ => Type::NUM,
+    }
+}
+
+fn operator(op: &OpCode) -> &'static str {
+    match op {
+        OpCode::Add => "+",
+        OpCode::Sub => "-",
+        OpCode::Mul => "*",
+        OpCode::Div => "/",
+        OpCode::DivInt => "DIV",
+        OpCode::Mod => "MOD",
+        OpCode::Lt => "<",
+        OpCode::Lte => "<=",
+        OpCode::Eq => "=",
+        OpCode::Gt => ">",
+        OpCode::Gte => ">=",
+        OpCode::Ne => "<>",
+        OpCode::And => "AND",
+        OpCode::Or => "OR",
+        OpCode::Xor => "XOR",
+        OpCode::Not => "NOT",
+    }
+}
+
+/// Returns the name of the data object a variable reference starts from.
+pub fn root_name(variable: &Variable) -> &str {
+    match &variable.kind {
+        VariableKind::Variable(name) | VariableKind::VariableElement(name, _) => name,
+        VariableKind::VariableComponent(inner, _) => root_name(inner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::messages;
+
+    #[test]
+    fn check_operands_without_cascading() {
+        let input = r#"
+            MODULE mymodule
+                VAR bool b := 1 + "x";
+                VAR num n := "x";
+                VAR string s := "a" + "b";
+                VAR dnum d := 1 + 2;
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "invalid-operands: operator '+' cannot be applied to num and string",
+                "type-mismatch: cannot assign string to num",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_integer_literal_ranges() {
+        let input = r#"
+            MODULE mymodule
+                CONST num max := 8388608;
+                CONST num min := -8388607;
+                CONST num flags := 0xFFFFFF;
+                VAR num mask{2} := [0b1010, 0o77777777];
+                VAR dnum big := 0xFFFFFFFF;
+                VAR dnum huge := 9007199254740993;
+                VAR num real := 1.5E10;
+                PROC main()
+                    mask{1} := -8388608;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "literal-out-of-range: integer 16777215 is outside the exact range of num (-8388607 to 8388608) and would be rounded, use dnum",
+                "literal-out-of-range: integer 16777215 is outside the exact range of num (-8388607 to 8388608) and would be rounded, use dnum",
+                "literal-out-of-range: integer 9007199254740993 is outside the exact range of dnum (up to 4503599627370496) and would be rounded",
+                "literal-out-of-range: integer -8388608 is outside the exact range of num (-8388607 to 8388608) and would be rounded, use dnum",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_aggregate_shapes() {
+        let input = r#"
+            MODULE mymodule
+                CONST num n := 3;
+                RECORD point
+                    num x;
+                    num y;
+                ENDRECORD
+                VAR num cube{2,n,2} := [[[1,2],[3,4],[5,6]],[[7,8],[9,10],[11,12]]];
+                VAR num flat{2,2,2} := [[[1,2],[3,4]],[[5,6],[7]]];
+                VAR point p := [1,2,3];
+                VAR point ps{2} := [[1,2],[3,"y"]];
+                VAR num x := [1];
+                PROC main()
+                    cube{1,2,3} := cube{2,3,1};
+                    ps := [[1,2]];
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "aggregate-shape: expected 2 elements for num{*}, found 1",
+                "aggregate-shape: expected 2 components for point, found 3",
+                "type-mismatch: cannot assign string to num",
+                "type-mismatch: cannot assign an aggregate to num",
+                "aggregate-shape: expected 2 elements for point{*}, found 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_alias_chains() {
+        let input = r#"
+            MODULE mymodule
+                ALIAS num level;
+                ALIAS level height;
+                ALIAS loop1 loop2;
+                ALIAS loop2 loop1;
+                VAR height h := 1;
+                VAR height s := "x";
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "alias-cycle: alias 'loop2' refers to itself",
+                "alias-cycle: alias 'loop1' refers to itself",
+                "type-mismatch: cannot assign string to num",
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_at_alias_cycles() {
+        // Following the chain of `entry` or of the data types ends where it comes back to an alias
+        // it already followed. Only the aliases on the cycle are reported, and the data of their
+        // types is not checked any further
+        let input = r#"
+            MODULE mymodule
+                ALIAS loop1 loop2;
+                ALIAS loop2 loop3;
+                ALIAS loop3 loop1;
+                ALIAS loop2 entry;
+                VAR entry e;
+                VAR loop3 l;
+                PROC main()
+                    e := 1;
+                    l := e;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "alias-cycle: alias 'loop2' refers to itself",
+                "alias-cycle: alias 'loop3' refers to itself",
+                "alias-cycle: alias 'loop1' refers to itself",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_record_components() {
+        let input = r#"
+            MODULE mymodule
+                RECORD point
+                    num x;
+                    num y;
+                ENDRECORD
+                RECORD line
+                    point start;
+                    point end;
+                ENDRECORD
+                PROC main()
+                    VAR line l;
+                    VAR point p;
+                    l.start.x := 1;
+                    l.start := p;
+                    p.z := 1;
+                    p.x := "s";
+                    p := l;
+                    p.x.y := 1;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "unknown-component: record 'point' has no component 'z'",
+                "type-mismatch: cannot assign string to num",
+                "type-mismatch: cannot assign line to point",
+                "unknown-component: type num has no component 'y'",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_conditions_and_indices() {
+        let input = r#"
+            MODULE mymodule
+                CONST num limit := 10;
+                VAR num grid{2,2};
+                PROC main()
+                    VAR num i := 0;
+                    IF i THEN
+                        grid{1} := 1;
+                    ENDIF
+                    WHILE i < limit DO
+                        i := i + grid{1,2};
+                        i{1} := 1;
+                    ENDWHILE
+                    FOR j FROM 1 TO "10" DO
+                    ENDFOR
+                    limit := 1;
+                    i := main;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "type-mismatch: condition must be bool, found num",
+                "invalid-index: 'grid' has 2 dimension(s) but is indexed with 1",
+                "invalid-index: 'i' of type num is not an array",
+                "type-mismatch: loop end must be num, found string",
+                "const-assignment: cannot assign to constant 'limit'",
+                "not-a-value: procedure 'main' cannot be used as a value",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_too_many_dimensions() {
+        let input = r#"
+            MODULE mymodule
+                VAR num cube{2,2,2};
+                VAR num hypercube{2,2,2,2};
+                PROC first()
+                    cube{1,2,3,4} := 1;
+                ENDPROC
+                PROC second()
+                    cube{1,1,1} := "s";
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "invalid-index: 'hypercube' uses 4 dimensions but arrays have at most 3",
+                "invalid-index: 'cube' uses 4 dimensions but arrays have at most 3",
+                "type-mismatch: cannot assign string to num",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_routine_returns() {
+        let input = r#"
+            MODULE mymodule
+                FUNC num sign(num x)
+                    IF x < 0 RETURN -1;
+                    IF x > 0 RETURN "1";
+                    RETURN;
+                ENDFUNC
+                FUNC pos origin(bool valid)
+                    IF valid THEN
+                        RETURN [0, 0];
+                    ENDIF
+                ERROR
+                    TRYNEXT;
+                ENDFUNC
+                FUNC bool wait()
+                    WHILE TRUE DO
+                        WaitTime 1;
+                    ENDWHILE
+                ENDFUNC
+                FUNC num checked(num x)
+                    RETURN 1 / x;
+                ERROR
+                    RETURN 0;
+                UNDO
+                    RETURN;
+                ENDFUNC
+                PROC run()
+                    RETURN sign(1);
+                ENDPROC
+                TRAP tick
+                    RETURN TRUE;
+                ENDTRAP
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "type-mismatch: return value must be num, found string",
+                "invalid-return: function 'sign' must return a value of type num",
+                "aggregate-shape: expected 3 components for pos, found 2",
+                "missing-return: function 'origin' can reach its end without returning a value",
+                "invalid-return: procedure 'run' cannot return a value",
+                "invalid-return: trap 'tick' cannot return a value",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_error_handlers() {
+        let input = r#"
+            MODULE mymodule
+                VAR errnum myErr := -1;
+                VAR string text := "";
+                VAR num plain := 42;
+                PROC run()
+                    RETRY;
+                    TRYNEXT;
+                    RAISE;
+                    RAISE myErr;
+                    RAISE "x";
+                ERROR (ERR_DIVZERO, myErr, text, plain, run, missing, 41)
+                    IF ERRNO = myErr RAISE;
+                    IF ERRNO = ERR_DIVZERO RETRY;
+                    TRYNEXT;
+                UNDO
+                    RETRY;
+                ENDPROC
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "undeclared-identifier: undeclared identifier 'missing'",
+                "misplaced-statement: RETRY is only allowed in an error handler",
+                "misplaced-statement: TRYNEXT is only allowed in an error handler",
+                "misplaced-statement: RAISE without an error number is only allowed in an error handler",
+                "type-mismatch: error number must be num, found string",
+                "type-mismatch: error number must be errnum, found string",
+                "type-mismatch: error number must be errnum, found num",
+                "invalid-error-number: 'run' is not an error number",
+                "misplaced-statement: RETRY is only allowed in an error handler",
+            ]
+        );
+    }
+
+    #[test]
+    fn check_connect_targets() {
+        let input = r#"
+            MODULE mymodule
+                ALIAS intnum sensornum;
+                ALIAS ring2 ring1;
+                ALIAS ring1 ring2;
+                VAR intnum timerInt;
+                VAR sensornum sensorInt;
+                VAR intnum ints{2};
+                PERS intnum stored := 0;
+                VAR num count;
+                VAR ring1 looped;
+                PROC run(VAR intnum passed, intnum copied)
+                    CONNECT timerInt WITH onTimer;
+                    CONNECT sensorInt WITH onTimer;
+                    CONNECT passed WITH onTimer;
+                    CONNECT copied WITH onTimer;
+                    CONNECT ints WITH onTimer;
+                    CONNECT stored WITH onTimer;
+                    CONNECT count WITH onTimer;
+                    CONNECT looped WITH onTimer;
+                    CONNECT timerInt WITH run;
+                ENDPROC
+                TRAP onTimer
+                ENDTRAP
+            ENDMODULE"#;
+        assert_eq!(
+            messages(input),
+            vec![
+                "alias-cycle: alias 'ring1' refers to itself",
+                "alias-cycle: alias 'ring2' refers to itself",
+                "invalid-interrupt: interrupt number must be an intnum variable, found parameter 'copied'",
+                "invalid-interrupt: interrupt number must be an intnum variable, 'ints' is an array",
+                "invalid-interrupt: interrupt number must be an intnum variable, found persistent 'stored'",
+                "invalid-interrupt: interrupt number must be an intnum variable, 'count' is num",
+                "invalid-interrupt: interrupt number must be an intnum variable, 'looped' is ring1",
+                "not-callable: procedure 'run' is not a trap",
+            ]
+        );
+    }
+}
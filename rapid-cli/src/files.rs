@@ -0,0 +1,63 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+/// File extensions of RAPID modules and programs, compared case-insensitively since controllers
+/// often write them in upper case.
+pub const EXTENSIONS: &[&str] = &["mod", "modx", "sys", "prg"];
+
+pub fn is_rapid_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            EXTENSIONS
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+/// Collects the RAPID files below each path in a stable order. Paths naming a file are taken as
+/// they are, whatever their extension.
+pub fn collect(paths: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_file() {
+            files.push(path.clone());
+            continue;
+        }
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = entry.map_err(io::Error::from)?;
+            if entry.file_type().is_file() && is_rapid_file(entry.path()) {
+                files.push(entry.into_path());
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Returns `true` for `.pgf` program files and legacy `.prg` files, whose modules are checked
+/// together as one task.
+pub fn is_program_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("pgf") || extension.eq_ignore_ascii_case("prg")
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognise_rapid_files() {
+        assert!(is_rapid_file(Path::new("TASK1/PROGMOD/MainModule.mod")));
+        assert!(is_rapid_file(Path::new("SYSMOD/user.SYS")));
+        assert!(is_rapid_file(Path::new("program.prg")));
+        assert!(!is_rapid_file(Path::new("SYSPAR/EIO.cfg")));
+        assert!(!is_rapid_file(Path::new("README")));
+        assert!(is_program_file(Path::new("T_ROB1/Program.PGF")));
+        assert!(!is_program_file(Path::new("T_ROB1/MainModule.mod")));
+    }
+}
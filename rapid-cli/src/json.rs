@@ -0,0 +1,254 @@
+use std::collections::BTreeSet;
+
+use rapid_analyzer::{lint, Diagnostic, Severity};
+use rapid_parser::Span;
+use serde_json::{json, Value};
+
+use crate::report::{severity_name, FileReport};
+
+/// The SARIF schema the output conforms to.
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Returns the one-based range of a span. Columns count characters.
+fn range(report: &FileReport, span: Span) -> Value {
+    let (start_line, start_column) = report.line_column(span.start);
+    let (end_line, end_column) = report.line_column(span.end);
+    json!({
+        "start": { "line": start_line, "column": start_column },
+        "end": { "line": end_line, "column": end_column },
+    })
+}
+
+/// Returns the diagnostics of all files as a flat JSON array.
+pub fn json(reports: &[FileReport]) -> Value {
+    let diagnostics = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(move |diagnostic| {
+                json!({
+                    "file": report.path.display().to_string(),
+                    "severity": severity_name(diagnostic.severity),
+                    "code": diagnostic.code,
+                    "message": diagnostic.message,
+                    "range": range(report, diagnostic.span),
+                    "related": diagnostic.related.iter().map(|related| json!({
+                        "message": related.message,
+                        "range": range(report, related.span),
+                    })).collect::<Vec<_>>(),
+                    "fixes": diagnostic.fixes.iter().map(|fix| json!({
+                        "message": fix.message,
+                        "edits": fix.edits.iter().map(|edit| json!({
+                            "range": range(report, edit.span),
+                            "replacement": edit.replacement,
+                        })).collect::<Vec<_>>(),
+                    })).collect::<Vec<_>>(),
+                })
+            })
+        })
+        .collect();
+    Value::Array(diagnostics)
+}
+
+fn region(report: &FileReport, span: Span) -> Value {
+    let (start_line, start_column) = report.line_column(span.start);
+    let (end_line, end_column) = report.line_column(span.end);
+    json!({
+        "startLine": start_line,
+        "startColumn": start_column,
+        "endLine": end_line,
+        "endColumn": end_column,
+    })
+}
+
+fn location(report: &FileReport, span: Span) -> Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": artifact_uri(report) },
+            "region": region(report, span),
+        }
+    })
+}
+
+fn artifact_uri(report: &FileReport) -> String {
+    report.path.display().to_string().replace('\\', "/")
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info | Severity::Hint => "note",
+    }
+}
+
+/// Describes a diagnostic code. Lint rules also carry their description and default level.
+fn rule(id: &str) -> Value {
+    match lint::rule(id) {
+        Some(rule) => json!({
+            "id": id,
+            "shortDescription": { "text": rule.description },
+            "defaultConfiguration": { "level": level(rule.severity) },
+        }),
+        None => json!({ "id": id }),
+    }
+}
+
+fn result(report: &FileReport, diagnostic: &Diagnostic) -> Value {
+    let mut result = json!({
+        "ruleId": diagnostic.code,
+        "level": level(diagnostic.severity),
+        "message": { "text": diagnostic.message },
+        "locations": [location(report, diagnostic.span)],
+    });
+    if !diagnostic.related.is_empty() {
+        result["relatedLocations"] = diagnostic
+            .related
+            .iter()
+            .enumerate()
+            .map(|(id, related)| {
+                let mut location = location(report, related.span);
+                location["id"] = json!(id);
+                location["message"] = json!({ "text": related.message });
+                location
+            })
+            .collect();
+    }
+    if !diagnostic.fixes.is_empty() {
+        result["fixes"] = diagnostic
+            .fixes
+            .iter()
+            .map(|fix| {
+                json!({
+                    "description": { "text": fix.message },
+                    "artifactChanges": [{
+                        "artifactLocation": { "uri": artifact_uri(report) },
+                        "replacements": fix.edits.iter().map(|edit| json!({
+                            "deletedRegion": region(report, edit.span),
+                            "insertedContent": { "text": edit.replacement },
+                        })).collect::<Vec<_>>(),
+                    }],
+                })
+            })
+            .collect();
+    }
+    result
+}
+
+/// Returns the diagnostics of all files as a SARIF 2.1.0 log, as understood by code scanning
+/// services.
+pub fn sarif(reports: &[FileReport]) -> Value {
+    let rules: BTreeSet<&str> = reports
+        .iter()
+        .flat_map(|report| report.diagnostics.iter().map(|d| d.code))
+        .collect();
+    let results: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .diagnostics
+                .iter()
+                .map(move |diagnostic| result(report, diagnostic))
+        })
+        .collect();
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rapid",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.iter().map(|id| rule(id)).collect::<Vec<_>>(),
+                }
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use rapid_analyzer::diagnose;
+
+    fn report() -> FileReport {
+        let source = r#"MODULE demo
+    PROC main()
+        LOCAL VAR num temp;
+        temp := total;
+    ENDPROC
+ENDMODULE"#;
+        FileReport::new(
+            PathBuf::from("src/demo.mod"),
+            source.to_owned(),
+            diagnose(source),
+            0,
+        )
+    }
+
+    #[test]
+    fn write_json() {
+        let value = json(&[report()]);
+        assert_eq!(value.as_array().unwrap().len(), 3);
+        assert_eq!(
+            value[0],
+            json!({
+                "file": "src/demo.mod",
+                "severity": "error",
+                "code": "invalid-scope",
+                "message": "routine data cannot be declared LOCAL or TASK",
+                "range": {
+                    "start": { "line": 3, "column": 9 },
+                    "end": { "line": 3, "column": 28 },
+                },
+                "related": [],
+                "fixes": [{
+                    "message": "remove the scope, routine data is always local",
+                    "edits": [{
+                        "range": {
+                            "start": { "line": 3, "column": 9 },
+                            "end": { "line": 3, "column": 15 },
+                        },
+                        "replacement": "",
+                    }],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn write_sarif() {
+        let value = sarif(&[report()]);
+        let run = &value["runs"][0];
+        assert_eq!(value["version"], "2.1.0");
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([
+                { "id": "invalid-scope" },
+                { "id": "undeclared-identifier" },
+                {
+                    "id": "unused-var",
+                    "shortDescription": {
+                        "text": "A VAR in a routine or a LOCAL VAR in a module is never read."
+                    },
+                    "defaultConfiguration": { "level": "warning" },
+                },
+            ])
+        );
+        let result = &run["results"][1];
+        assert_eq!(result["ruleId"], "undeclared-identifier");
+        assert_eq!(result["level"], "error");
+        assert_eq!(
+            result["locations"][0]["physicalLocation"],
+            json!({
+                "artifactLocation": { "uri": "src/demo.mod" },
+                "region": { "startLine": 4, "startColumn": 17, "endLine": 4, "endColumn": 22 },
+            })
+        );
+        let replacement = &run["results"][0]["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(replacement["insertedContent"]["text"], "");
+    }
+}
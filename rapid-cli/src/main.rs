@@ -0,0 +1,482 @@
+mod files;
+mod json;
+mod report;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use clap::{Parser, ValueEnum};
+use rapid_analyzer::program::mask_header;
+use rapid_analyzer::{
+    apply_fixes, diagnose_with, Backup, ControlFlowGraph, LintConfig, Severity, System, Task,
+};
+use rapid_parser::ast::{Module, RoutineDeclaration, StatementKind};
+
+use crate::report::FileReport;
+
+/// How often `--fix` re-analyses a file to apply fixes that overlapped earlier ones.
+const MAX_FIX_PASSES: usize = 8;
+
+/// Parses and analyses ABB RAPID modules and reports their problems.
+#[derive(Parser, Debug)]
+#[command(name = "rapid", version)]
+struct Args {
+    /// Files or directories to check. Directories are searched for .mod, .modx, .sys and .prg files.
+    /// The modules listed by a .pgf program file or held by a .prg file are checked together.
+    #[arg(default_value = ".")]
+    paths: Vec<PathBuf>,
+
+    /// Treat each path as a controller backup directory and check all its tasks together.
+    #[arg(long)]
+    backup: bool,
+
+    /// How to print the diagnostics.
+    #[arg(long, value_enum, default_value_t = Format::Human)]
+    format: Format,
+
+    /// Apply the safe fixes of the diagnostics to the files in place.
+    #[arg(long)]
+    fix: bool,
+
+    /// The lint configuration to use. By default each file uses the nearest rapid.toml in its
+    /// directory or one of its ancestors.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Print the control-flow graph of each routine named ROUTINE in Graphviz DOT format instead of
+    /// checking the files.
+    #[arg(long, value_name = "ROUTINE")]
+    cfg: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    /// Source snippets with the problems underlined, like rustc.
+    Human,
+    /// A JSON array of diagnostics.
+    Json,
+    /// A SARIF 2.1.0 log for code scanning.
+    Sarif,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args, &mut io::stdout().lock()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Checks every file and prints the report. Returns `false` if any file has errors.
+fn run(args: &Args, out: &mut impl Write) -> io::Result<bool> {
+    if let Some(routine) = &args.cfg {
+        return print_graphs(&args.paths, routine, out);
+    }
+    let mut configs = Configs::new(args.config.as_deref())?;
+    let mut reports = Vec::new();
+    // The tasks and shared persistents of each backup, printed ahead of the human report
+    let mut overviews = Vec::new();
+    if args.backup {
+        for path in &args.paths {
+            let mut backup = Backup::load(path)?;
+            let config = configs.for_directory(path)?;
+            reports.extend(check_system(&mut backup.system, &config, args.fix)?);
+            let shared = backup.system.analyze().shared;
+            report::overview(&backup, &shared, &mut overviews)?;
+        }
+    } else {
+        for path in files::collect(&args.paths)? {
+            let config = configs.for_file(&path)?;
+            if files::is_program_file(&path) {
+                let mut system = System::new();
+                system.add_task(Task::load(&path)?);
+                reports.extend(check_system(&mut system, &config, args.fix)?);
+            } else {
+                reports.push(check(&path, &config, args.fix)?);
+            }
+        }
+    }
+    for report in reports.iter().filter(|report| report.fixed > 0) {
+        eprintln!(
+            "fixed {} problem{} in {}",
+            report.fixed,
+            if report.fixed == 1 { "" } else { "s" },
+            report.path.display()
+        );
+    }
+    match args.format {
+        Format::Human => {
+            out.write_all(&overviews)?;
+            report::human(&reports, out)?
+        }
+        Format::Json => writeln!(out, "{:#}", json::json(&reports))?,
+        Format::Sarif => writeln!(out, "{:#}", json::sarif(&reports))?,
+    }
+    Ok(reports
+        .iter()
+        .all(|report| report.count(Severity::Error) == 0))
+}
+
+/// The lint configurations of the checked files, loaded once per directory.
+struct Configs {
+    /// The configuration given with `--config`, which applies to every file.
+    fixed: Option<Rc<LintConfig>>,
+    by_directory: HashMap<PathBuf, Rc<LintConfig>>,
+}
+
+impl Configs {
+    fn new(path: Option<&Path>) -> io::Result<Self> {
+        let fixed = match path {
+            Some(path) => Some(Rc::new(LintConfig::load(path).map_err(io::Error::other)?)),
+            None => None,
+        };
+        Ok(Configs {
+            fixed,
+            by_directory: HashMap::new(),
+        })
+    }
+
+    fn for_file(&mut self, path: &Path) -> io::Result<Rc<LintConfig>> {
+        let path = fs::canonicalize(path)?;
+        self.for_directory(path.parent().unwrap_or(&path))
+    }
+
+    fn for_directory(&mut self, dir: &Path) -> io::Result<Rc<LintConfig>> {
+        if let Some(config) = &self.fixed {
+            return Ok(config.clone());
+        }
+        let dir = fs::canonicalize(dir)?;
+        if let Some(config) = self.by_directory.get(&dir) {
+            return Ok(config.clone());
+        }
+        let config = match LintConfig::discover(&dir).map_err(io::Error::other)? {
+            Some((_, config)) => config,
+            None => LintConfig::default(),
+        };
+        let config = Rc::new(config);
+        self.by_directory.insert(dir, config.clone());
+        Ok(config)
+    }
+}
+
+fn check(path: &Path, config: &LintConfig, fix: bool) -> io::Result<FileReport> {
+    let (mut source, latin1) = read(path)?;
+    let diagnose = |source: &str| diagnose_with(&mask_header(source), config);
+    let mut diagnostics = diagnose(&source);
+    let mut fixed = 0;
+    if fix {
+        for _ in 0..MAX_FIX_PASSES {
+            // The header is masked without moving any offsets, so fixes apply to the file as is
+            let (fixed_source, applied) = apply_fixes(&source, &diagnostics);
+            if applied == 0 {
+                break;
+            }
+            fixed += applied;
+            source = fixed_source;
+            diagnostics = diagnose(&source);
+        }
+        if fixed > 0 {
+            write(path, &source, latin1)?;
+        }
+    }
+    Ok(FileReport::new(path.to_owned(), source, diagnostics, fixed))
+}
+
+/// Checks the tasks of a system together, so that each module sees the global declarations of the
+/// other modules of its task and the persistents shared between tasks are cross-checked. Returns a
+/// report for each module file.
+fn check_system(
+    system: &mut System,
+    config: &LintConfig,
+    fix: bool,
+) -> io::Result<Vec<FileReport>> {
+    let mut diagnostics = system.diagnose(config);
+    let mut fixed: Vec<Vec<usize>> = system
+        .tasks
+        .iter()
+        .map(|task| vec![0; task.files.len()])
+        .collect();
+    if fix {
+        for _ in 0..MAX_FIX_PASSES {
+            let mut applied_any = false;
+            for (task_index, task) in system.tasks.iter_mut().enumerate() {
+                for (index, diagnostics) in diagnostics[task_index].iter().enumerate() {
+                    let (fixed_source, applied) =
+                        apply_fixes(&task.files[index].source, diagnostics);
+                    if applied > 0 {
+                        task.set_source(index, fixed_source);
+                        fixed[task_index][index] += applied;
+                        applied_any = true;
+                    }
+                }
+            }
+            if !applied_any {
+                break;
+            }
+            diagnostics = system.diagnose(config);
+        }
+        for (task, fixed) in system.tasks.iter().zip(&fixed) {
+            for (file, fixed) in task.files.iter().zip(fixed) {
+                if *fixed > 0 {
+                    let (_, latin1) = read(&file.path)?;
+                    write(&file.path, &file.source, latin1)?;
+                }
+            }
+        }
+    }
+    let mut reports = Vec::new();
+    for ((task, diagnostics), fixed) in system.tasks.iter().zip(diagnostics).zip(fixed) {
+        for ((file, diagnostics), fixed) in task.files.iter().zip(diagnostics).zip(fixed) {
+            reports.push(FileReport::new(
+                file.path.clone(),
+                file.source.clone(),
+                diagnostics,
+                fixed,
+            ));
+        }
+    }
+    Ok(reports)
+}
+
+/// Prints the control-flow graph of every routine called `name` in the files. Returns `false` if
+/// there is none.
+fn print_graphs(paths: &[PathBuf], name: &str, out: &mut impl Write) -> io::Result<bool> {
+    let mut found = false;
+    for path in files::collect(paths)? {
+        let task = Task::load(&path)?;
+        for file in &task.files {
+            for module in &file.modules {
+                let Module::Module(module) = module else {
+                    continue;
+                };
+                for statement in &module.statements {
+                    let StatementKind::RoutineDeclaration(routine) = &statement.kind else {
+                        continue;
+                    };
+                    let routine_name = match routine {
+                        RoutineDeclaration::ProcDeclaration(proc) => &proc.name,
+                        RoutineDeclaration::FuncDeclaration(func) => &func.name,
+                        RoutineDeclaration::TrapDeclaration(trap) => &trap.name,
+                        RoutineDeclaration::RDN | RoutineDeclaration::Error => continue,
+                    };
+                    if !routine_name.eq_ignore_ascii_case(name) {
+                        continue;
+                    }
+                    if let Some(graph) = ControlFlowGraph::new(routine) {
+                        let title = format!("{}.{}", module.name, routine_name);
+                        write!(out, "{}", graph.to_dot(&title, &file.source))?;
+                        found = true;
+                    }
+                }
+            }
+        }
+    }
+    if !found {
+        eprintln!("no routine named '{}' found", name);
+    }
+    Ok(found)
+}
+
+/// Reads a file as UTF-8, falling back to Latin-1 which older controllers use. Returns whether the
+/// fallback was used.
+fn read(path: &Path) -> io::Result<(String, bool)> {
+    let bytes = fs::read(path)?;
+    match String::from_utf8(bytes) {
+        Ok(source) => Ok((source, false)),
+        Err(error) => Ok((error.as_bytes().iter().map(|b| *b as char).collect(), true)),
+    }
+}
+
+fn write(path: &Path, source: &str, latin1: bool) -> io::Result<()> {
+    if !latin1 {
+        return fs::write(path, source);
+    }
+    let bytes = source
+        .chars()
+        .map(|c| u8::try_from(c).map_err(|_| io::Error::other("fix is not valid Latin-1")))
+        .collect::<io::Result<Vec<u8>>>()?;
+    fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_and_fix_directory() {
+        let dir = std::env::temp_dir().join(format!("rapid-cli-{}", std::process::id()));
+        fs::create_dir_all(dir.join("SYSMOD")).unwrap();
+        fs::write(
+            dir.join("main.mod"),
+            "%%%\n  VERSION:1\n%%%\nMODULE main\n    PROC run()\n        LOCAL VAR num i;\n        i := i + 1;\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+        fs::write(dir.join("SYSMOD/user.SYS"), b"MODULE user\n    ! Gr\xf6\xdfe\n    PROC go()\n        TASK VAR num j;\n        j := j + 1;\n    ENDPROC\nENDMODULE\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not RAPID").unwrap();
+
+        let mut args = Args::parse_from(["rapid", dir.to_str().unwrap(), "--format", "json"]);
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let files: Vec<_> = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["file"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("SYSMOD/user.SYS"));
+        assert!(files[1].ends_with("main.mod"));
+
+        args.fix = true;
+        assert!(run(&args, &mut Vec::new()).unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("main.mod")).unwrap(),
+            "%%%\n  VERSION:1\n%%%\nMODULE main\n    PROC run()\n        VAR num i;\n        i := i + 1;\n    ENDPROC\nENDMODULE\n"
+        );
+        assert_eq!(
+            fs::read(dir.join("SYSMOD/user.SYS")).unwrap(),
+            b"MODULE user\n    ! Gr\xf6\xdfe\n    PROC go()\n        VAR num j;\n        j := j + 1;\n    ENDPROC\nENDMODULE\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_program_together() {
+        let dir = std::env::temp_dir().join(format!("rapid-cli-program-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Robot.pgf"),
+            "<Program>\n    <Module>Main.mod</Module>\n    <Module>Tools.mod</Module>\n</Program>\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Main.mod"),
+            "MODULE Main\n    PROC main()\n        LOCAL VAR num n;\n        grip n;\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("Tools.mod"),
+            "MODULE Tools\n    PROC grip(num force)\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+
+        let mut args = Args::parse_from([
+            "rapid",
+            dir.join("Robot.pgf").to_str().unwrap(),
+            "--format",
+            "json",
+        ]);
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        let codes: Vec<_> = value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| d["code"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(codes, vec!["invalid-scope"]);
+
+        args.fix = true;
+        assert!(run(&args, &mut Vec::new()).unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("Main.mod")).unwrap(),
+            "MODULE Main\n    PROC main()\n        VAR num n;\n        grip n;\n    ENDPROC\nENDMODULE\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_backup_tasks() {
+        let mut args = Args::parse_from(["rapid", "--backup", "../rapid-parser/data/BACKUP"]);
+        let mut out = Vec::new();
+        assert!(run(&args, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "backup SyntheticCell (../rapid-parser/data/BACKUP)
+  task T_ROB1: 3 modules
+  task T_LOGGER: 2 modules
+  shared currentTool (tooldata): T_ROB1 reads, T_LOGGER reads
+  shared cycleCount (num): T_ROB1 reads and writes, T_LOGGER does not use it
+  shared loggerRunning (bool): T_ROB1 reads, T_LOGGER writes
+
+checked 5 files, no problems found
+"
+        );
+
+        args.format = Format::Json;
+        let mut out = Vec::new();
+        run(&args, &mut out).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value, serde_json::json!([]));
+
+        args.paths = vec![PathBuf::from("../rapid-parser/data")];
+        let error = run(&args, &mut Vec::new()).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("not a controller backup, expected a RAPID directory"));
+    }
+
+    #[test]
+    fn print_control_flow_graph() {
+        let mut args = Args::parse_from([
+            "rapid",
+            "--cfg",
+            "MAIN",
+            "../rapid-parser/data/BACKUP/RAPID/TASK2",
+        ]);
+        let mut out = Vec::new();
+        assert!(run(&args, &mut out).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("digraph \"Logger.main\" {\n"));
+        assert!(out.contains(r#"b4 [label="B4\lWHILE TRUE\l", shape=box];"#));
+
+        args.cfg = Some("missing".to_owned());
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn use_nearest_config() {
+        let dir = std::env::temp_dir().join(format!("rapid-cli-config-{}", std::process::id()));
+        fs::create_dir_all(dir.join("TASK1")).unwrap();
+        fs::write(dir.join("rapid.toml"), "[lint]\nunused-var = \"error\"\n").unwrap();
+        fs::write(dir.join("quiet.toml"), "[lint]\nunused-var = \"allow\"\n").unwrap();
+        fs::write(
+            dir.join("TASK1/main.mod"),
+            "MODULE main\n    PROC run()\n        VAR num unused;\n    ENDPROC\nENDMODULE\n",
+        )
+        .unwrap();
+
+        let mut args = Args::parse_from(["rapid", dir.to_str().unwrap()]);
+        let mut out = Vec::new();
+        assert!(!run(&args, &mut out).unwrap());
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("error[unused-var]: variable 'unused' is never used"));
+
+        args.config = Some(dir.join("quiet.toml"));
+        let mut out = Vec::new();
+        assert!(run(&args, &mut out).unwrap());
+        assert_eq!(out, b"checked 1 file, no problems found\n");
+
+        fs::write(dir.join("rapid.toml"), "[lint]\nunused-var = 1\n").unwrap();
+        args.config = None;
+        let error = run(&args, &mut Vec::new()).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("rapid.toml: invalid level for 'unused-var', expected one of: allow, hint, info, warning, error"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use rapid_analyzer::{Backup, Diagnostic, Severity, SharedPersistent};
+use rapid_parser::{LineIndex, Span};
+
+/// The width a tab is expanded to in source snippets.
+const TAB_WIDTH: usize = 4;
+
+/// The diagnostics of one checked file.
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The number of fixes applied to the file with `--fix`.
+    pub fixed: usize,
+    index: LineIndex,
+}
+
+impl FileReport {
+    pub fn new(path: PathBuf, source: String, diagnostics: Vec<Diagnostic>, fixed: usize) -> Self {
+        let index = LineIndex::new(&source);
+        FileReport {
+            path,
+            source,
+            diagnostics,
+            fixed,
+            index,
+        }
+    }
+
+    /// Returns the one-based line and column of `offset`. Columns count characters.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let position = self.index.line_col(offset);
+        let line_start = offset.min(self.source.len()) - position.col;
+        let column = self.source[line_start..line_start + position.col]
+            .chars()
+            .count();
+        (position.line + 1, column + 1)
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.source.lines().nth(line - 1).unwrap_or_default()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+}
+
+pub fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+        Severity::Hint => "hint",
+    }
+}
+
+/// Prints the diagnostics the way rustc does, with the offending source lines underlined, followed
+/// by a summary.
+pub fn human(reports: &[FileReport], out: &mut impl Write) -> io::Result<()> {
+    for report in reports {
+        for diagnostic in &report.diagnostics {
+            diagnostic_snippet(report, diagnostic, out)?;
+        }
+    }
+    let errors: usize = reports.iter().map(|r| r.count(Severity::Error)).sum();
+    let warnings: usize = reports.iter().map(|r| r.count(Severity::Warning)).sum();
+    let files = plural(reports.len(), "file");
+    match (errors, warnings) {
+        (0, 0) => writeln!(out, "checked {}, no problems found", files),
+        (0, warnings) => writeln!(
+            out,
+            "warning: found {} in {}",
+            plural(warnings, "warning"),
+            files
+        ),
+        (errors, 0) => writeln!(out, "error: found {} in {}", plural(errors, "error"), files),
+        (errors, warnings) => writeln!(
+            out,
+            "error: found {} and {} in {}",
+            plural(errors, "error"),
+            plural(warnings, "warning"),
+            files
+        ),
+    }
+}
+
+/// Prints the tasks of a backup with the number of modules loaded into each, and how each task
+/// uses the persistents they share.
+pub fn overview(
+    backup: &Backup,
+    shared: &[SharedPersistent],
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match &backup.system_id {
+        Some(id) => writeln!(out, "backup {} ({})", id, backup.path.display())?,
+        None => writeln!(out, "backup {}", backup.path.display())?,
+    }
+    let tasks = &backup.system.tasks;
+    for task in tasks {
+        writeln!(
+            out,
+            "  task {}: {}",
+            task.name,
+            plural(task.modules().count(), "module")
+        )?;
+    }
+    for persistent in shared {
+        let uses: Vec<String> = persistent
+            .tasks
+            .iter()
+            .map(|access| {
+                let access_name = match (access.reads, access.writes) {
+                    (true, true) => "reads and writes",
+                    (true, false) => "reads",
+                    (false, true) => "writes",
+                    (false, false) => "does not use it",
+                };
+                format!("{} {}", tasks[access.task].name, access_name)
+            })
+            .collect();
+        writeln!(
+            out,
+            "  shared {} ({}): {}",
+            persistent.name,
+            persistent.data_type,
+            uses.join(", ")
+        )?;
+    }
+    writeln!(out)
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+/// An underlined part of a source line.
+struct Mark<'a> {
+    start: usize,
+    end: usize,
+    marker: char,
+    label: &'a str,
+}
+
+fn diagnostic_snippet(
+    report: &FileReport,
+    diagnostic: &Diagnostic,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let (line, column) = report.line_column(diagnostic.span.start);
+    writeln!(
+        out,
+        "{}[{}]: {}",
+        severity_name(diagnostic.severity),
+        diagnostic.code,
+        diagnostic.message
+    )?;
+
+    let mut marks: BTreeMap<usize, Vec<Mark>> = BTreeMap::new();
+    let mut add = |span: Span, marker: char, label| {
+        let (line, start, end) = line_range(report, span);
+        marks.entry(line).or_default().push(Mark {
+            start,
+            end,
+            marker,
+            label,
+        });
+    };
+    add(diagnostic.span, '^', "");
+    for related in &diagnostic.related {
+        add(related.span, '-', related.message.as_str());
+    }
+
+    let gutter = " ".repeat(
+        marks
+            .keys()
+            .last()
+            .copied()
+            .unwrap_or(line)
+            .to_string()
+            .len(),
+    );
+    writeln!(
+        out,
+        "{}--> {}:{}:{}",
+        gutter,
+        report.path.display(),
+        line,
+        column
+    )?;
+    writeln!(out, "{} |", gutter)?;
+    let mut previous = None;
+    for (line, marks) in &marks {
+        if previous.is_some_and(|previous| previous + 1 < *line) {
+            writeln!(out, "...")?;
+        }
+        previous = Some(*line);
+        writeln!(
+            out,
+            "{:>width$} | {}",
+            line,
+            expand_tabs(report.line(*line)),
+            width = gutter.len()
+        )?;
+        for mark in marks {
+            let underline = mark.marker.to_string().repeat(mark.end - mark.start);
+            let text = format!("{}{} {}", " ".repeat(mark.start), underline, mark.label);
+            writeln!(out, "{} | {}", gutter, text.trim_end())?;
+        }
+    }
+    writeln!(out, "{} |", gutter)?;
+    for fix in &diagnostic.fixes {
+        writeln!(out, "{} = help: {}", gutter, fix.message)?;
+    }
+    writeln!(out)
+}
+
+/// Returns the line of a span and the display columns it covers on that line. Spans running over
+/// several lines are cut at the end of the first one; empty spans cover one column.
+fn line_range(report: &FileReport, span: Span) -> (usize, usize, usize) {
+    let (line, column) = report.line_column(span.start);
+    let text = report.line(line);
+    let prefix: String = text.chars().take(column - 1).collect();
+    let start = display_width(&prefix);
+    let (end_line, end_column) = report.line_column(span.end);
+    let end = if end_line == line {
+        let covered: String = text.chars().take(end_column - 1).collect();
+        display_width(&covered)
+    } else {
+        display_width(text)
+    };
+    (line, start, end.max(start + 1))
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapid_analyzer::diagnose;
+
+    fn render(source: &str) -> String {
+        let report = FileReport::new(
+            PathBuf::from("demo.mod"),
+            source.to_owned(),
+            diagnose(source),
+            0,
+        );
+        let mut out = Vec::new();
+        human(&[report], &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn render_rustc_style_snippets() {
+        let source = r#"MODULE demo
+    VAR num count;
+    PROC main()
+	count := total;
+    ENDPROC
+    VAR num count;
+ENDMODULE"#;
+        assert_eq!(
+            render(source),
+            r#"error[duplicate-declaration]: 'count' is already declared in this scope
+ --> demo.mod:6:13
+  |
+2 |     VAR num count;
+  |             ----- previous declaration of 'count'
+...
+6 |     VAR num count;
+  |             ^^^^^
+  |
+
+error[undeclared-identifier]: undeclared identifier 'total'
+ --> demo.mod:4:11
+  |
+4 |     count := total;
+  |              ^^^^^
+  |
+
+warning[declaration-order]: data declarations must come before routine declarations
+ --> demo.mod:6:5
+  |
+3 |     PROC main()
+  |     ----------- first routine declared here
+...
+6 |     VAR num count;
+  |     ^^^^^^^^^^^^^^
+  |
+
+error: found 2 errors and 1 warning in 1 file
+"#
+        );
+    }
+
+    #[test]
+    fn render_summary_without_problems() {
+        assert_eq!(
+            render("MODULE demo\nENDMODULE"),
+            "checked 1 file, no problems found\n"
+        );
+    }
+}
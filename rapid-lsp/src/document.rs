@@ -0,0 +1,236 @@
+use lsp_types::{Position, Range};
+use rapid_analyzer::incremental::AnalysisCache;
+use rapid_analyzer::{analyze_module, Analysis, Diagnostic, LintConfig};
+use rapid_parser::ast::Module;
+use rapid_parser::incremental::{ParseCache, TextEdit};
+use rapid_parser::{LineCol, LineIndex, Span};
+use self_cell::self_cell;
+
+type Symbols<'a> = Option<Analysis<'a>>;
+
+self_cell!(
+    /// The parse of a document with the analysis of its module, which borrows from the parse.
+    struct Parsed {
+        owner: ParseCache,
+
+        #[covariant]
+        dependent: Symbols,
+    }
+);
+
+impl Parsed {
+    fn analyse(parse: ParseCache) -> Self {
+        Parsed::new(parse, |parse| match parse.module() {
+            Module::Module(module) => Some(analyze_module(module)),
+            Module::Error => None,
+        })
+    }
+}
+
+/// An open text document with the parse, analysis and diagnostics of its current version, which
+/// the requests share. LSP positions count columns in UTF-16 code units while spans are byte
+/// offsets, so all conversions go through here.
+pub struct Document {
+    index: LineIndex,
+    parsed: Parsed,
+    diagnostics: AnalysisCache,
+}
+
+impl Document {
+    /// Parses and analyses a document, with the lint rules configured by `config`.
+    pub fn new(text: String, version: i32, config: LintConfig) -> Self {
+        let parse = ParseCache::new(version, text);
+        let diagnostics = AnalysisCache::new(&parse, config);
+        Document::with(parse, diagnostics)
+    }
+
+    fn with(parse: ParseCache, diagnostics: AnalysisCache) -> Self {
+        Document {
+            index: LineIndex::new(parse.source()),
+            parsed: Parsed::analyse(parse),
+            diagnostics,
+        }
+    }
+
+    /// Replaces the text with `version` of the document. Only the part of the module that changed
+    /// is parsed again, and only the routines it touches are linted again. Changes to a version
+    /// that is not newer are ignored.
+    pub fn update(self, text: String, version: i32) -> Self {
+        let Document {
+            parsed,
+            mut diagnostics,
+            ..
+        } = self;
+        let mut parse = parsed.into_owner();
+        let edits: Vec<_> = edit_between(parse.source(), &text).into_iter().collect();
+        match parse.update(version, &edits) {
+            Ok(reparse) => {
+                diagnostics.update(&parse, reparse);
+            }
+            Err(error) => eprintln!("ignoring change: {}", error),
+        }
+        Document::with(parse, diagnostics)
+    }
+
+    pub fn text(&self) -> &str {
+        self.parsed.borrow_owner().source()
+    }
+
+    pub fn version(&self) -> i32 {
+        self.parsed.borrow_owner().version()
+    }
+
+    pub fn module(&self) -> &Module {
+        self.parsed.borrow_owner().module()
+    }
+
+    /// The analysis of the module, or `None` if the module could not be parsed at all. Modules
+    /// with syntax errors are analysed as far as they were parsed.
+    pub fn analysis(&self) -> Option<&Analysis<'_>> {
+        self.parsed.borrow_dependent().as_ref()
+    }
+
+    /// The syntax errors, semantic diagnostics and lint findings of the document.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.diagnostics.diagnostics()
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let LineCol { line, col } = self.index.line_col(offset);
+        let line_start = offset.min(self.text().len()) - col;
+        let character = self.text()[line_start..line_start + col]
+            .encode_utf16()
+            .count();
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Returns the byte offset of a position. Columns past the end of a line are clamped to it.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let line_start = self.index.offset(LineCol {
+            line: position.line as usize,
+            col: 0,
+        })?;
+        let mut units = 0;
+        for (offset, c) in self.text()[line_start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return Some(line_start + offset);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.text().len())
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    /// Returns the range covering the whole document.
+    pub fn full_range(&self) -> Range {
+        Range::new(Position::new(0, 0), self.position(self.text().len()))
+    }
+}
+
+/// Returns the edit that turns `old` into `new`, which replaces what lies between their common
+/// start and end, or `None` if they are the same. Documents are synchronised in full, so the edit
+/// is what lets the caches reuse the rest.
+fn edit_between(old: &str, new: &str) -> Option<TextEdit> {
+    if old == new {
+        return None;
+    }
+    let boundary = |old_end: usize, new_end: usize| {
+        old.is_char_boundary(old_end) && new.is_char_boundary(new_end)
+    };
+    let mut prefix = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !boundary(prefix, prefix) {
+        prefix -= 1;
+    }
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(old.len().min(new.len()) - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !boundary(old.len() - suffix, new.len() - suffix) {
+        suffix -= 1;
+    }
+    Some(TextEdit::new(
+        Span::new(prefix, old.len() - suffix),
+        &new[prefix..new.len() - suffix],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_utf16_positions() {
+        let document = Document::new(
+            "MODULE m\n  ! Größe 𝄞\n  x\nENDMODULE".to_owned(),
+            1,
+            LintConfig::default(),
+        );
+        let x = document.text().find('x').unwrap();
+        assert_eq!(document.position(x), Position::new(2, 2));
+        assert_eq!(document.offset(Position::new(2, 2)), Some(x));
+
+        let clef = document.text().find('𝄞').unwrap();
+        assert_eq!(document.position(clef), Position::new(1, 10));
+        assert_eq!(document.position(clef + 4), Position::new(1, 12));
+        assert_eq!(document.offset(Position::new(1, 12)), Some(clef + 4));
+        assert_eq!(document.offset(Position::new(1, 99)), Some(clef + 4));
+        assert_eq!(document.offset(Position::new(9, 0)), None);
+    }
+
+    #[test]
+    fn edit_only_what_changed() {
+        let edit = |old, new| edit_between(old, new).map(|edit| (edit.span, edit.replacement));
+        assert_eq!(edit("a := 1;", "a := 1;"), None);
+        assert_eq!(
+            edit("a := 1;", "a := 12;"),
+            Some((Span::new(6, 6), "2".to_owned()))
+        );
+        assert_eq!(
+            edit("x := 1; x := 1;", "x := 1;"),
+            Some((Span::new(7, 15), String::new()))
+        );
+        // The common start and end never split a character
+        assert_eq!(
+            edit("Größe", "Grüße"),
+            Some((Span::new(2, 4), "ü".to_owned()))
+        );
+    }
+
+    #[test]
+    fn update_to_newer_versions() {
+        let text = "MODULE m\n    PROC p()\n        x := 1;\n    ENDPROC\nENDMODULE";
+        let document = Document::new(text.to_owned(), 1, LintConfig::default());
+        let codes = |document: &Document| {
+            document
+                .diagnostics()
+                .iter()
+                .map(|diagnostic| diagnostic.code)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(codes(&document), vec!["undeclared-identifier"]);
+
+        let fixed = text.replace("PROC p()", "PROC p()\n        VAR num x;");
+        let document = document.update(fixed.clone(), 2);
+        assert_eq!(document.text(), fixed);
+        assert_eq!(document.version(), 2);
+        assert_eq!(codes(&document), vec!["unused-var"]);
+        assert_eq!(
+            document.diagnostics(),
+            Document::new(fixed.clone(), 2, LintConfig::default()).diagnostics()
+        );
+        assert!(document.analysis().is_some());
+
+        let document = document.update(text.to_owned(), 2);
+        assert_eq!(document.text(), fixed);
+    }
+}
@@ -0,0 +1,241 @@
+use lsp_types::{
+    CompletionItem, CompletionItemKind, DiagnosticRelatedInformation, DiagnosticSeverity,
+    DocumentSymbol, FormattingOptions, Hover, HoverContents, Location, MarkupContent, MarkupKind,
+    NumberOrString, SymbolKind as LspSymbolKind, TextEdit, Url,
+};
+use rapid_analyzer::{
+    builtins, outline, Analysis, LintConfig, OutlineItem, OutlineKind, Severity, SymbolKind,
+};
+use rapid_parser::ast::{Module, VarDeclarationType};
+use rapid_parser::format::{format_source, FormatOptions, Indent};
+use rapid_parser::lexer::KEYWORDS;
+
+use crate::document::Document;
+
+/// Passes the analysis of the current version of a document to `f`. Modules with syntax errors
+/// are analysed as far as they were parsed.
+fn with_analysis<T>(document: &Document, f: impl FnOnce(&Analysis) -> T) -> Option<T> {
+    document.analysis().map(f)
+}
+
+/// Returns the lint configuration for a document: the nearest `rapid.toml` next to or above the
+/// file, read when the document is opened. Documents that are not files, or whose configuration is
+/// invalid, use the defaults.
+pub fn lint_config(uri: &Url) -> LintConfig {
+    let Some(dir) = uri
+        .to_file_path()
+        .ok()
+        .and_then(|path| path.parent().map(|dir| dir.to_owned()))
+    else {
+        return LintConfig::default();
+    };
+    match LintConfig::discover(&dir) {
+        Ok(config) => config.map(|(_, config)| config).unwrap_or_default(),
+        Err(error) => {
+            eprintln!("ignoring lint configuration: {}", error);
+            LintConfig::default()
+        }
+    }
+}
+
+/// Returns the syntax errors, semantic diagnostics and lint findings of a document.
+pub fn diagnostics(document: &Document, uri: &Url) -> Vec<lsp_types::Diagnostic> {
+    document
+        .diagnostics()
+        .iter()
+        .cloned()
+        .map(|diagnostic| lsp_types::Diagnostic {
+            range: document.range(diagnostic.span),
+            severity: Some(match diagnostic.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Info => DiagnosticSeverity::INFORMATION,
+                Severity::Hint => DiagnosticSeverity::HINT,
+            }),
+            code: Some(NumberOrString::String(diagnostic.code.to_owned())),
+            source: Some("rapid".to_owned()),
+            message: diagnostic.message,
+            related_information: (!diagnostic.related.is_empty()).then(|| {
+                diagnostic
+                    .related
+                    .into_iter()
+                    .map(|related| DiagnosticRelatedInformation {
+                        location: Location::new(uri.clone(), document.range(related.span)),
+                        message: related.message,
+                    })
+                    .collect()
+            }),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Returns the outline of a document: the module with its records, aliases, data and routines.
+/// Routines contain their local data declarations.
+pub fn document_symbols(document: &Document) -> Vec<DocumentSymbol> {
+    let Module::Module(module) = document.module() else {
+        return Vec::new();
+    };
+    vec![document_symbol(document, outline(module))]
+}
+
+fn document_symbol(document: &Document, item: OutlineItem) -> DocumentSymbol {
+    let kind = match item.kind {
+        OutlineKind::Module => LspSymbolKind::MODULE,
+        OutlineKind::Record => LspSymbolKind::STRUCT,
+        OutlineKind::Component => LspSymbolKind::FIELD,
+        OutlineKind::Alias => LspSymbolKind::TYPE_PARAMETER,
+        OutlineKind::Constant => LspSymbolKind::CONSTANT,
+        OutlineKind::Variable | OutlineKind::Persistent => LspSymbolKind::VARIABLE,
+        OutlineKind::Procedure | OutlineKind::Function | OutlineKind::Trap => {
+            LspSymbolKind::FUNCTION
+        }
+    };
+    #[allow(deprecated)]
+    DocumentSymbol {
+        name: item.name,
+        detail: item.detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: document.range(item.span),
+        selection_range: document.range(item.name_span),
+        children: Some(
+            item.children
+                .into_iter()
+                .map(|child| document_symbol(document, child))
+                .collect(),
+        ),
+    }
+}
+
+/// Shows the declaration of the symbol at `offset`.
+pub fn hover(document: &Document, offset: usize) -> Option<Hover> {
+    with_analysis(document, |analysis| {
+        let table = &analysis.symbols;
+        let id = table.symbol_at(offset)?;
+        let symbol = table.symbol(id);
+        let (source, origin) = if table.is_builtin(id) {
+            (builtins::SOURCE, "built-in ")
+        } else {
+            (document.text(), "")
+        };
+        let declaration = source[symbol.span.start..symbol.span.end]
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim();
+        let declaration = match symbol.kind {
+            SymbolKind::Switch => format!("\\switch {}", symbol.name),
+            _ => declaration.to_owned(),
+        };
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: format!(
+                    "```rapid\n{}\n```\n{}{}",
+                    declaration,
+                    origin,
+                    symbol.kind.description()
+                ),
+            }),
+            range: None,
+        })
+    })
+    .flatten()
+}
+
+/// Returns the declaration of the symbol at `offset`. Built-in symbols have no location.
+pub fn definition(document: &Document, uri: &Url, offset: usize) -> Option<Location> {
+    with_analysis(document, |analysis| {
+        let table = &analysis.symbols;
+        let id = table
+            .symbol_at(offset)
+            .filter(|id| !table.is_builtin(*id))?;
+        Some(Location::new(
+            uri.clone(),
+            document.range(table.symbol(id).name_span),
+        ))
+    })
+    .flatten()
+}
+
+/// Returns every use of the symbol at `offset`, optionally including its declaration.
+pub fn references(
+    document: &Document,
+    uri: &Url,
+    offset: usize,
+    include_declaration: bool,
+) -> Vec<Location> {
+    with_analysis(document, |analysis| {
+        let table = &analysis.symbols;
+        let Some(id) = table.symbol_at(offset) else {
+            return Vec::new();
+        };
+        let declaration =
+            (include_declaration && !table.is_builtin(id)).then(|| table.symbol(id).name_span);
+        declaration
+            .into_iter()
+            .chain(table.references_to(id).map(|reference| reference.span))
+            .map(|span| Location::new(uri.clone(), document.range(span)))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Offers the symbols visible at `offset` and the RAPID keywords.
+pub fn completion(document: &Document, offset: usize) -> Vec<CompletionItem> {
+    let mut items = with_analysis(document, |analysis| {
+        let table = &analysis.symbols;
+        let scope = table.scope_at(analysis.module_scope, offset);
+        table
+            .visible(scope)
+            .into_iter()
+            .map(|id| {
+                let symbol = table.symbol(id);
+                CompletionItem {
+                    label: symbol.name.to_owned(),
+                    kind: Some(match symbol.kind {
+                        SymbolKind::Record(_) => CompletionItemKind::STRUCT,
+                        SymbolKind::Alias(_) => CompletionItemKind::TYPE_PARAMETER,
+                        SymbolKind::Data(decl)
+                            if decl.declaration_type == VarDeclarationType::ConstDeclaration =>
+                        {
+                            CompletionItemKind::CONSTANT
+                        }
+                        SymbolKind::Procedure(_)
+                        | SymbolKind::Function(_)
+                        | SymbolKind::Trap(_) => CompletionItemKind::FUNCTION,
+                        _ => CompletionItemKind::VARIABLE,
+                    }),
+                    detail: Some(symbol.kind.description().to_owned()),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .unwrap_or_default();
+    items.extend(KEYWORDS.iter().map(|(keyword, _)| CompletionItem {
+        label: keyword.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..Default::default()
+    }));
+    items
+}
+
+/// Formats the whole document. Documents with syntax errors are left alone.
+pub fn formatting(document: &Document, options: &FormattingOptions) -> Option<Vec<TextEdit>> {
+    let options = FormatOptions {
+        indent: if options.insert_spaces {
+            Indent::Spaces(options.tab_size as usize)
+        } else {
+            Indent::Tabs
+        },
+        ..FormatOptions::default()
+    };
+    let formatted = format_source(document.text(), &options).ok()?;
+    if formatted == document.text() {
+        return Some(Vec::new());
+    }
+    Some(vec![TextEdit::new(document.full_range(), formatted)])
+}
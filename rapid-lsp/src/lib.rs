@@ -0,0 +1,499 @@
+pub mod document;
+pub mod handlers;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+    Notification as LspNotification, PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, References,
+    Request as LspRequest,
+};
+use lsp_types::{
+    CompletionResponse, DocumentSymbolResponse, GotoDefinitionResponse, HoverProviderCapability,
+    OneOf, PublishDiagnosticsParams, ServerCapabilities, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+use crate::document::Document;
+
+/// Errors that stop the server, such as a broken connection or a malformed message.
+pub type ServerError = Box<dyn Error + Send + Sync>;
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(Default::default()),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+/// Runs the language server on `connection` until the client shuts it down.
+///
+/// # Arguments
+///
+/// * `connection` - The connection to the client, e.g. `Connection::stdio()` or one end of
+///   `Connection::memory()` in tests.
+pub fn run(connection: &Connection) -> Result<(), ServerError> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    let mut server = Server {
+        connection,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                server.handle_request(request)?;
+            }
+            Message::Notification(notification) => server.handle_notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'c> {
+    connection: &'c Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server<'_> {
+    fn handle_request(&self, request: Request) -> Result<(), ServerError> {
+        let id = request.id.clone();
+        let result = match request.method.as_str() {
+            HoverRequest::METHOD => self.position_request::<HoverRequest>(
+                request,
+                |p| &p.text_document_position_params,
+                |d, _, o, _| handlers::hover(d, o),
+            ),
+            GotoDefinition::METHOD => self.position_request::<GotoDefinition>(
+                request,
+                |p| &p.text_document_position_params,
+                |d, uri, o, _| handlers::definition(d, uri, o).map(GotoDefinitionResponse::Scalar),
+            ),
+            References::METHOD => self.position_request::<References>(
+                request,
+                |p| &p.text_document_position,
+                |d, uri, o, p| {
+                    let include_declaration = p.context.include_declaration;
+                    Some(handlers::references(d, uri, o, include_declaration))
+                },
+            ),
+            Completion::METHOD => self.position_request::<Completion>(
+                request,
+                |p| &p.text_document_position,
+                |d, _, o, _| Some(CompletionResponse::Array(handlers::completion(d, o))),
+            ),
+            DocumentSymbolRequest::METHOD => {
+                self.request::<DocumentSymbolRequest>(request, |server, params| {
+                    let document = server.documents.get(&params.text_document.uri)?;
+                    Some(DocumentSymbolResponse::Nested(handlers::document_symbols(
+                        document,
+                    )))
+                })
+            }
+            Formatting::METHOD => self.request::<Formatting>(request, |server, params| {
+                let document = server.documents.get(&params.text_document.uri)?;
+                handlers::formatting(document, &params.options)
+            }),
+            _ => {
+                let message = format!("unsupported request '{}'", request.method);
+                let response = Response::new_err(id, ErrorCode::MethodNotFound as i32, message);
+                self.connection.sender.send(response.into())?;
+                return Ok(());
+            }
+        };
+        let response = match result {
+            Ok(result) => Response::new_ok(id, result),
+            Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+        };
+        self.connection.sender.send(response.into())?;
+        Ok(())
+    }
+
+    fn request<R: LspRequest>(
+        &self,
+        request: Request,
+        handler: impl FnOnce(&Self, R::Params) -> R::Result,
+    ) -> Result<serde_json::Value, serde_json::Error> {
+        let params = serde_json::from_value(request.params)?;
+        serde_json::to_value(handler(self, params))
+    }
+
+    /// Handles a request for a position in an open document. `position` picks the position out of
+    /// the request parameters. The handler is called with the document, its URI, the byte offset of
+    /// the position and the request parameters.
+    fn position_request<R>(
+        &self,
+        request: Request,
+        position: fn(&R::Params) -> &TextDocumentPositionParams,
+        handler: impl FnOnce(&Document, &Url, usize, &R::Params) -> R::Result,
+    ) -> Result<serde_json::Value, serde_json::Error>
+    where
+        R: LspRequest,
+        R::Result: Default,
+    {
+        self.request::<R>(request, |server, params| {
+            let position = position(&params);
+            let uri = &position.text_document.uri;
+            match server.documents.get(uri).and_then(|document| {
+                let offset = document.offset(position.position)?;
+                Some((document, offset))
+            }) {
+                Some((document, offset)) => handler(document, uri, offset, &params),
+                None => R::Result::default(),
+            }
+        })
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<(), ServerError> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = notification
+                    .extract::<lsp_types::DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)?;
+                let document = params.text_document;
+                self.documents.insert(
+                    document.uri.clone(),
+                    Document::new(
+                        document.text,
+                        document.version,
+                        handlers::lint_config(&document.uri),
+                    ),
+                );
+                self.publish_diagnostics(&document.uri)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params = notification.extract::<lsp_types::DidChangeTextDocumentParams>(
+                    DidChangeTextDocument::METHOD,
+                )?;
+                // Documents are synchronised in full, so the last change holds the whole text
+                let uri = params.text_document.uri;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let Some(document) = self.documents.remove(&uri) else {
+                        return Ok(());
+                    };
+                    let document = document.update(change.text, params.text_document.version);
+                    self.documents.insert(uri.clone(), document);
+                    self.publish_diagnostics(&uri)?;
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                let params = notification.extract::<lsp_types::DidCloseTextDocumentParams>(
+                    DidCloseTextDocument::METHOD,
+                )?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                self.send_notification::<PublishDiagnostics>(PublishDiagnosticsParams::new(
+                    uri,
+                    Vec::new(),
+                    None,
+                ))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&self, uri: &Url) -> Result<(), ServerError> {
+        let Some(document) = self.documents.get(uri) else {
+            return Ok(());
+        };
+        let diagnostics = handlers::diagnostics(document, uri);
+        self.send_notification::<PublishDiagnostics>(PublishDiagnosticsParams::new(
+            uri.clone(),
+            diagnostics,
+            Some(document.version()),
+        ))
+    }
+
+    fn send_notification<N: LspNotification>(&self, params: N::Params) -> Result<(), ServerError> {
+        let notification = Notification::new(N::METHOD.to_owned(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::thread::JoinHandle;
+
+    use lsp_server::RequestId;
+    use lsp_types::notification::{Exit, Initialized};
+    use lsp_types::request::{Initialize, Shutdown};
+    use lsp_types::{
+        CompletionParams, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+        DocumentFormattingParams, DocumentSymbolParams, FormattingOptions, GotoDefinitionParams,
+        HoverContents, HoverParams, InitializeParams, Location, Position, ReferenceContext,
+        ReferenceParams, TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+        VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+
+    const SOURCE: &str = r#"MODULE demo
+    VAR num counter := 0;
+    PROC main()
+        counter := counter + 1;
+        MoveL pHome, v100, fine, tool0;
+    ENDPROC
+ENDMODULE
+"#;
+
+    /// A scripted client talking to a server running on another thread.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<()>,
+        notifications: VecDeque<Notification>,
+        next_id: i32,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || run(&server).unwrap());
+            let mut client = Client {
+                connection,
+                server,
+                notifications: VecDeque::new(),
+                next_id: 0,
+            };
+            client.request::<Initialize>(InitializeParams::default());
+            client.notify::<Initialized>(lsp_types::InitializedParams {});
+            client
+        }
+
+        fn uri() -> Url {
+            Url::parse("file:///demo.mod").unwrap()
+        }
+
+        fn open(&mut self, text: &str) -> PublishDiagnosticsParams {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    Self::uri(),
+                    "rapid".to_owned(),
+                    1,
+                    text.into(),
+                ),
+            });
+            self.diagnostics()
+        }
+
+        fn request<R: LspRequest>(&mut self, params: R::Params) -> R::Result {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_owned(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            loop {
+                match self.connection.receiver.recv().unwrap() {
+                    Message::Response(response) if response.id == id => {
+                        assert!(response.error.is_none(), "{:?}", response.error);
+                        return serde_json::from_value(response.result.unwrap()).unwrap();
+                    }
+                    Message::Notification(notification) => {
+                        self.notifications.push_back(notification)
+                    }
+                    message => panic!("unexpected message {:?}", message),
+                }
+            }
+        }
+
+        fn notify<N: LspNotification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_owned(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn diagnostics(&mut self) -> PublishDiagnosticsParams {
+            let notification = match self.notifications.pop_front() {
+                Some(notification) => notification,
+                None => match self.connection.receiver.recv().unwrap() {
+                    Message::Notification(notification) => notification,
+                    message => panic!("unexpected message {:?}", message),
+                },
+            };
+            notification.extract(PublishDiagnostics::METHOD).unwrap()
+        }
+
+        fn position(line: u32, character: u32) -> TextDocumentPositionParams {
+            TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(Self::uri()),
+                Position::new(line, character),
+            )
+        }
+
+        fn hover(&mut self, line: u32, character: u32) -> String {
+            let hover = self.request::<HoverRequest>(HoverParams {
+                text_document_position_params: Self::position(line, character),
+                work_done_progress_params: Default::default(),
+            });
+            match hover.map(|hover| hover.contents) {
+                Some(HoverContents::Markup(markup)) => markup.value,
+                contents => panic!("unexpected hover {:?}", contents),
+            }
+        }
+
+        fn shutdown(self) {
+            self.request_shutdown();
+            self.notify::<Exit>(());
+            self.server.join().unwrap();
+        }
+
+        fn request_shutdown(&self) {
+            let request = Request::new(RequestId::from(0), Shutdown::METHOD.to_owned(), ());
+            self.connection.sender.send(request.into()).unwrap();
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) => assert_eq!(response.id, RequestId::from(0)),
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+    }
+
+    #[test]
+    fn publish_diagnostics_on_open_and_change() {
+        let mut client = Client::start();
+        let published = client.open(SOURCE);
+        assert_eq!(published.version, Some(1));
+        assert_eq!(published.diagnostics.len(), 1);
+        let diagnostic = &published.diagnostics[0];
+        assert_eq!(
+            diagnostic.code,
+            Some(lsp_types::NumberOrString::String(
+                "undeclared-identifier".to_owned()
+            ))
+        );
+        assert_eq!(
+            diagnostic.range,
+            lsp_types::Range::new(Position::new(4, 14), Position::new(4, 19))
+        );
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(Client::uri(), 2),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "MODULE demo\n    PROC main()\n        x := ;\n    ENDPROC\nENDMODULE".into(),
+            }],
+        });
+        let published = client.diagnostics();
+        assert_eq!(published.version, Some(2));
+        assert_eq!(published.diagnostics.len(), 1);
+        assert_eq!(
+            published.diagnostics[0].code,
+            Some(lsp_types::NumberOrString::String("syntax-error".to_owned()))
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn navigate_symbols() {
+        let mut client = Client::start();
+        client.open(SOURCE);
+
+        assert_eq!(
+            client.hover(3, 20),
+            "```rapid\nVAR num counter := 0;\n```\nvariable"
+        );
+        assert!(client
+            .hover(4, 10)
+            .starts_with("```rapid\nPROC MoveL(\\switch Conc, robtarget ToPoint"));
+        assert!(client.hover(4, 10).ends_with("built-in procedure"));
+
+        let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+            text_document_position_params: Client::position(3, 20),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let declaration = lsp_types::Range::new(Position::new(1, 12), Position::new(1, 19));
+        assert_eq!(
+            definition,
+            Some(GotoDefinitionResponse::Scalar(Location::new(
+                Client::uri(),
+                declaration
+            )))
+        );
+
+        let references = client.request::<References>(ReferenceParams {
+            text_document_position: Client::position(1, 14),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: true,
+            },
+        });
+        let lines: Vec<_> = references
+            .unwrap()
+            .iter()
+            .map(|location| (location.range.start.line, location.range.start.character))
+            .collect();
+        assert_eq!(lines, vec![(1, 12), (3, 8), (3, 19)]);
+
+        let completion = client.request::<Completion>(CompletionParams {
+            text_document_position: Client::position(3, 8),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+        let Some(CompletionResponse::Array(items)) = completion else {
+            panic!("expected completion items");
+        };
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        for label in ["counter", "main", "MoveL", "tool0", "IF"] {
+            assert!(labels.contains(&label), "missing {}", label);
+        }
+        client.shutdown();
+    }
+
+    #[test]
+    fn outline_and_format_document() {
+        let mut client = Client::start();
+        client.open("module demo\nrecord point\nnum x;\nendrecord\nproc main()\nvar point p;\nendproc\nendmodule");
+
+        let symbols = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(Client::uri()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let Some(DocumentSymbolResponse::Nested(symbols)) = symbols else {
+            panic!("expected document symbols");
+        };
+        fn names(symbols: &[lsp_types::DocumentSymbol]) -> Vec<String> {
+            symbols
+                .iter()
+                .map(|symbol| {
+                    let children = names(symbol.children.as_deref().unwrap_or_default());
+                    format!("{}[{}]", symbol.name, children.join(","))
+                })
+                .collect()
+        }
+        assert_eq!(names(&symbols), vec!["demo[point[x[]],main[p[]]]"]);
+
+        let edits = client.request::<Formatting>(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(Client::uri()),
+            options: FormattingOptions {
+                tab_size: 2,
+                insert_spaces: true,
+                ..Default::default()
+            },
+            work_done_progress_params: Default::default(),
+        });
+        let edits = edits.unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].new_text,
+            "MODULE demo\n  RECORD point\n    num x;\n  ENDRECORD\n  PROC main()\n    VAR point p;\n  ENDPROC\nENDMODULE\n"
+        );
+        client.shutdown();
+    }
+}
@@ -0,0 +1,10 @@
+use lsp_server::Connection;
+use rapid_lsp::ServerError;
+
+fn main() -> Result<(), ServerError> {
+    let (connection, io_threads) = Connection::stdio();
+    rapid_lsp::run(&connection)?;
+    drop(connection);
+    io_threads.join()?;
+    Ok(())
+}
@@ -0,0 +1,7 @@
+MODULE Gripper
+    VAR num openTime := 0.5;
+
+    PROC GripperOpen()
+        WaitTime openTime;
+    ENDPROC
+ENDMODULE
@@ -0,0 +1,16 @@
+%%%
+  VERSION:1
+  LANGUAGE:ENGLISH
+%%%
+
+MODULE MainModule
+    CONST robtarget pHome := [[500, 0, 600], [0, 0, 1, 0], [0, 0, 0, 0], [9E9, 9E9, 9E9, 9E9, 9E9, 9E9]];
+
+    PROC main()
+        WaitUntil loggerRunning;
+        MoveJ pHome, v1000, z50, currentTool;
+        GripperOpen;
+        SetDO doGripper, 1;
+        cycleCount := cycleCount + 1;
+    ENDPROC
+ENDMODULE
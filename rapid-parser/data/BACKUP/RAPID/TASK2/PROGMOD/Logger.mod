@@ -0,0 +1,9 @@
+MODULE Logger
+    PROC main()
+        loggerRunning := TRUE;
+        WHILE TRUE DO
+            TPWrite "Tool mass: " \Num:=currentTool.tload.mass;
+            WaitTime 1;
+        ENDWHILE
+    ENDPROC
+ENDMODULE
@@ -1,19 +1,31 @@
+// Every node records the byte range it was parsed from as a `Span`. Enum nodes that are always the
+// sole payload of a spanned parent (such as `Term` inside `Expr`) share the span of that parent.
+pub use crate::span::Span;
+use crate::UserError;
+
 /// From: https://fs.gongkong.com/files/technicalData/201309/2013090913353800001.pdf
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Module {
     Module(ModuleInfo),
     Error,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ModuleInfo {
     pub name: String,
+    pub name_span: Span,
     pub attributes: Vec<ModuleAttribute>,
+    /// The parenthesised attribute list, or an empty span after the name if there is none.
+    pub attributes_span: Span,
     pub statements: Vec<Statement>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+#[derive(PartialEq, Eq, Hash, Debug, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Scope {
     LOCAL,
     GLOBAL,
@@ -21,6 +33,7 @@ pub enum Scope {
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Declaration {
     TypeDefinition(TypeDefinition),
     DataDeclaration(DataDeclaration),
@@ -28,10 +41,16 @@ pub enum Declaration {
 }
 
 pub fn tokenize_string(
-    _l: usize,
+    l: usize,
     input: &str,
     r: usize,
-) -> Result<String, lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'_>, &'static str>> {
+) -> Result<String, lalrpop_util::ParseError<usize, crate::lexer::Lexeme<'_>, UserError>> {
+    let invalid = |message| lalrpop_util::ParseError::User {
+        error: UserError {
+            message,
+            span: Span::new(l, r),
+        },
+    };
     let mut result = String::new();
     let mut chars = input.chars().peekable();
 
@@ -57,10 +76,12 @@ pub fn tokenize_string(
                     result.push('\\');
                 } else {
                     // Hex sequence
-                    let hex1 = chars.next().ok_or("Unexpected end of string after '\'")?;
+                    let hex1 = chars
+                        .next()
+                        .ok_or_else(|| invalid("Unexpected end of string after '\\'"))?;
                     let hex2 = chars
                         .next()
-                        .ok_or("Unexpected end of string in hex sequence")?;
+                        .ok_or_else(|| invalid("Unexpected end of string in hex sequence"))?;
                     let hex_str = format!("{}{}", hex1, hex2);
 
                     if let Ok(byte) = u8::from_str_radix(&hex_str, 16) {
@@ -71,9 +92,7 @@ pub fn tokenize_string(
                             chars.next();
                         }
                     } else {
-                        return Err(lalrpop_util::ParseError::User {
-                            error: "Invalid hex sequence",
-                        });
+                        return Err(invalid("Invalid hex sequence"));
                     }
                 }
             }
@@ -92,90 +111,51 @@ pub fn tokenize_string(
     }
 }
 
-// TODO: Move validate_module_attributes and validate_module_declarations to the sematic analysis
-
-fn is_sorted<T>(data: &[T]) -> bool
-where
-    T: Ord,
-{
-    data.windows(2).all(|w| w[0] <= w[1])
-}
-
-pub fn validate_module_attributes<'input>(
-    attrs: &Vec<ModuleAttribute>,
-) -> Result<(), lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'static str>> {
-    if !is_sorted(attrs) {
-        return Err(lalrpop_util::ParseError::User {
-            error: "Module attributes are not in the correct order",
-        });
-    }
-    for attr in attrs {
-        match attr {
-            ModuleAttribute::NOVIEW => {
-                if attrs.contains(&ModuleAttribute::NOSTEPIN)
-                    || attrs.contains(&ModuleAttribute::VIEWONLY)
-                    || attrs.contains(&ModuleAttribute::READONLY)
-                {
-                    return Err(lalrpop_util::ParseError::User {
-                        error: "NOVIEW attribute is mutually exclusive with NOSTEPIN, VIEWONLY, and READONLY",
-                    });
-                }
-            }
-            ModuleAttribute::VIEWONLY => {
-                if attrs.contains(&ModuleAttribute::READONLY) {
-                    return Err(lalrpop_util::ParseError::User {
-                        error: "VIEWONLY attribute is mutually exclusive with READONLY",
-                    });
-                }
-            }
-            _ => {}
-        }
-    }
-    Ok(())
-}
-
-pub fn validate_module_declarations<'input>(
-    items: &Vec<Statement>,
-) -> Result<(), lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'static str>> {
-    let mut seen_data_declaration = false;
-    let mut seen_routine_declaration = false;
-    for item in items {
-        match item {
-            Statement::TypeDefinition(_) => {
-                if seen_data_declaration || seen_routine_declaration {
-                    return Err(lalrpop_util::ParseError::User{ error: "Type definitions must come before data declarations and routine declarations" });
-                }
-            }
-            Statement::DataDeclaration(_) => {
-                if seen_routine_declaration {
-                    return Err(lalrpop_util::ParseError::User {
-                        error: "Data declarations must come before routine declarations",
-                    });
-                }
-                seen_data_declaration = true;
-            }
-            Statement::RoutineDeclaration(_) => {
-                seen_routine_declaration = true;
-            }
-            _ => {}
+/// Converts the text of a number token to its value.
+pub fn parse_number(
+    l: usize,
+    input: &str,
+    r: usize,
+) -> Result<Number, lalrpop_util::ParseError<usize, crate::lexer::Lexeme<'_>, UserError>> {
+    let radix = match input.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0o" | "0O") => 8,
+        Some("0b" | "0B") => 2,
+        _ if input.contains(['.', 'e', 'E']) => {
+            return Ok(Number::Real(input.parse().unwrap()));
         }
-    }
-    Ok(())
+        _ => 10,
+    };
+    let digits = if radix == 10 { input } else { &input[2..] };
+    u64::from_str_radix(digits, radix)
+        .map(Number::Integer)
+        .map_err(|_| lalrpop_util::ParseError::User {
+            error: UserError {
+                message: "Integer literal is too large",
+                span: Span::new(l, r),
+            },
+        })
 }
 
 pub fn validate_routine_declarations<'input>(
     items: &Vec<Statement>,
-) -> Result<(), lalrpop_util::ParseError<usize, lalrpop_util::lexer::Token<'input>, &'static str>> {
+) -> Result<(), lalrpop_util::ParseError<usize, crate::lexer::Lexeme<'input>, UserError>> {
     for item in items {
-        match item {
-            Statement::TypeDefinition(_) => {
+        match item.kind {
+            StatementKind::TypeDefinition(_) => {
                 return Err(lalrpop_util::ParseError::User {
-                    error: "Type definitions are not allowed inside routines",
+                    error: UserError {
+                        message: "Type definitions are not allowed inside routines",
+                        span: item.span,
+                    },
                 });
             }
-            Statement::RoutineDeclaration(_) => {
+            StatementKind::RoutineDeclaration(_) => {
                 return Err(lalrpop_util::ParseError::User {
-                    error: "Routine declarations are not allowed inside other routines",
+                    error: UserError {
+                        message: "Routine declarations are not allowed inside other routines",
+                        span: item.span,
+                    },
                 });
             }
             _ => {}
@@ -184,25 +164,65 @@ pub fn validate_routine_declarations<'input>(
     Ok(())
 }
 
+/// An identifier together with the span it was parsed from.
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
 #[derive(PartialEq, Debug)]
-pub enum Expr {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExprKind {
     Term(Term),
     Op(Box<Expr>, OpCode, Box<Expr>),
     FuncCall(String, Vec<Argument>),
     EXP,
     UnaryOp(OpCode, Box<Expr>),
+    /// An expression that could not be parsed. The syntax error is reported separately.
+    Error,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Term {
     String(String),
     Bool(bool),
-    Num(f64),
+    Num(Number),
     Array(Vec<Expr>),
     Var(Variable),
 }
 
+/// A numeric literal. Integers keep their exact value, whether they fit the type they are used as
+/// is up to the analyzer.
+#[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Number {
+    /// A decimal, hexadecimal (`0xFF`), octal (`0o17`) or binary (`0b1010`) integer.
+    Integer(u64),
+    /// A literal with a fraction or an exponent.
+    Real(f64),
+}
+
+impl Number {
+    pub fn value(&self) -> f64 {
+        match self {
+            Number::Integer(value) => *value as f64,
+            Number::Real(value) => *value,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpCode {
     Add,
     Sub,
@@ -223,6 +243,7 @@ pub enum OpCode {
 }
 
 #[derive(PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ModuleAttribute {
     SYSMODULE,
     NOSTEPIN,
@@ -232,6 +253,7 @@ pub enum ModuleAttribute {
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TypeDefinition {
     RecordDefinition(Scope, RecordDefinition),
     AliasDefinition(Scope, AliasDefinition),
@@ -239,24 +261,36 @@ pub enum TypeDefinition {
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordDefinition {
     pub name: String,
+    pub name_span: Span,
     pub components: Vec<RecordComponent>,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RecordComponent {
     pub data_type: String,
+    pub data_type_span: Span,
     pub name: String,
+    pub name_span: Span,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AliasDefinition {
     pub name: String,
+    pub name_span: Span,
     pub data_type: String,
+    pub data_type_span: Span,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AccessMode {
     IN,
     VAR,
@@ -266,12 +300,15 @@ pub enum AccessMode {
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[allow(clippy::large_enum_variant)]
 pub enum DataDeclaration {
     VarDeclaration(Scope, VarDeclaration),
     DDN,
 }
 
 #[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VarDeclarationType {
     VarDeclaration,
     PersDeclaration,
@@ -279,79 +316,131 @@ pub enum VarDeclarationType {
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct VarDeclaration {
     pub declaration_type: VarDeclarationType,
     pub data_type: String,
+    pub data_type_span: Span,
     pub definition: Definition,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Definition {
     pub identifier: String,
+    pub identifier_span: Span,
     pub expression: Option<Expr>,
     pub dim: Option<Dimension>,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RoutineDeclaration {
     ProcDeclaration(ProcDeclaration),
     FuncDeclaration(FuncDeclaration),
     TrapDeclaration(TrapDeclaration),
     RDN,
+    /// A routine whose head could not be parsed. The syntax error is reported separately.
+    Error,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ProcDeclaration {
+    pub scope: Scope,
     pub name: String,
+    pub name_span: Span,
     pub parameters: Vec<ParameterDeclarationType>,
     pub statements: Vec<Statement>,
     pub backward_handler: Option<Vec<Statement>>,
     pub error_handler: Option<ErrorHandler>,
     pub undo_handler: Option<Vec<Statement>>,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FuncDeclaration {
+    pub scope: Scope,
     pub data_type: String,
+    pub data_type_span: Span,
     pub name: String,
+    pub name_span: Span,
     pub parameters: Vec<ParameterDeclarationType>,
     pub statements: Vec<Statement>,
     pub error_handler: Option<ErrorHandler>,
     pub undo_handler: Option<Vec<Statement>>,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TrapDeclaration {
+    pub scope: Scope,
     pub name: String,
+    pub name_span: Span,
     pub statements: Vec<Statement>,
     pub error_handler: Option<ErrorHandler>,
     pub undo_handler: Option<Vec<Statement>>,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterDeclarationType {
+    pub kind: ParameterDeclarationTypeKind,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
-pub enum ParameterDeclarationType {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParameterDeclarationTypeKind {
     ParameterDeclaration(ParameterDeclaration),
     OptionalParameterDeclaration(Vec<OptionalParameterDeclarationType>),
     PAR,
+    /// A parameter that could not be parsed. The syntax error is reported separately.
+    Error,
 }
 
 #[derive(PartialEq, Debug)]
-pub enum OptionalParameterDeclarationType {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OptionalParameterDeclarationType {
+    pub kind: OptionalParameterDeclarationTypeKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OptionalParameterDeclarationTypeKind {
     OptionalParameterDeclaration(ParameterDeclaration),
     Switch(String),
     ALT,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ParameterDeclaration {
     pub access_mode: AccessMode,
     pub data_type: String,
+    pub data_type_span: Span,
     pub name: String,
+    pub name_span: Span,
     pub dim: Option<Dimension>,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
-pub enum Statement {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StatementKind {
     TypeDefinition(TypeDefinition),
     DataDeclaration(DataDeclaration),
     RoutineDeclaration(RoutineDeclaration),
@@ -359,13 +448,16 @@ pub enum Statement {
     Assignment(AssignmentTarget, Expr),
     // TODO: Make sure that dynamic binding is parsed and works!
     ProcCall(Expr, Vec<Argument>),
-    Goto(String),
+    Goto(Identifier),
     Return(Option<Expr>),
     Raise(Option<Expr>),
     Exit,
     Retry,
     TryNext,
-    Connect(String, String),
+    Connect(
+        Identifier, // Interrupt
+        Identifier, // Trap Routine
+    ),
     If(
         Expr,
         Vec<Statement>,              // Statements
@@ -373,7 +465,7 @@ pub enum Statement {
         Vec<Statement>,              // Else Statements
     ),
     For(
-        String,         // Loop Variable
+        Identifier,     // Loop Variable
         Expr,           // From
         Expr,           // To
         Option<Expr>,   // Step
@@ -390,16 +482,27 @@ pub enum Statement {
     ),
     Comment(String),
     SMT,
+    /// A statement that could not be parsed. The syntax error is reported separately.
+    Error,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AssignmentTarget {
     Variable(Variable),
     VAR, // TODO: Also remove because we shouldn't parse it
 }
 
 #[derive(PartialEq, Debug)]
-pub enum TestCase {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TestCase {
+    pub kind: TestCaseKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TestCaseKind {
     Case(
         Vec<Expr>,      // Conditional Expression
         Vec<Statement>, // Statements
@@ -408,34 +511,69 @@ pub enum TestCase {
 }
 
 #[derive(PartialEq, Debug)]
-pub enum Variable {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Variable {
+    pub kind: VariableKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VariableKind {
     Variable(String),
     VariableElement(String, Dimension),
     VariableComponent(Box<Variable>, String),
 }
 
 #[derive(PartialEq, Debug)]
-pub enum Parameter {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Parameter {
+    pub kind: ParameterKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParameterKind {
     Parameter(String),
     ParameterElement(String, Dimension),
     ParameterComponent(Box<Parameter>, String),
 }
 
 #[derive(PartialEq, Debug)]
-pub enum Argument {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Argument {
+    pub kind: ArgumentKind,
+    pub span: Span,
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArgumentKind {
     Required(Option<String>, Expr),
     Optional(String, Option<Expr>),
-    Conditional(String, Parameter, Parameter),
+    /// `\name ? parameter`: passes the optional `parameter` of the calling routine on, if present.
+    Conditional(String, Parameter),
+}
+
+#[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dimension {
+    pub kind: DimensionKind,
+    pub span: Span,
 }
 
 #[derive(PartialEq, Debug)]
-pub enum Dimension {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DimensionKind {
     Dimension(Vec<Expr>),
     DIM,
 }
 
 #[derive(PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ErrorHandler {
     pub numbers: Vec<Expr>,
     pub statements: Vec<Statement>,
+    pub span: Span,
 }
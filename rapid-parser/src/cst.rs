@@ -0,0 +1,1396 @@
+// A lossless concrete syntax tree in the style of rowan's red/green trees. Unlike the AST, it keeps
+// every token of the source, including whitespace and comments wherever they appear, so that the
+// text of the tree is always exactly the source. Tools that rewrite code edit the tree and print it
+// back without disturbing the author's layout and comments.
+//
+// Nodes start and end at significant tokens: whitespace and comments between two statements are
+// children of the enclosing block, not of either statement.
+
+use rowan::{GreenNode, GreenNodeBuilder, TextRange};
+
+use crate::lexer::{tokenize, Token};
+use crate::Span;
+
+/// The kinds of tokens and nodes of the concrete syntax tree.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum SyntaxKind {
+    // Trivia
+    Whitespace,
+    /// A comment from `!` up to (not including) the end of the line.
+    Comment,
+
+    // Literals and names
+    Ident,
+    Number,
+    String,
+    /// A placeholder for a part of the program still to be written, such as `<EXP>`.
+    Placeholder,
+
+    // Punctuation
+    LParen,
+    RParen,
+    LBrack,
+    RBrack,
+    LCurly,
+    RCurly,
+    Comma,
+    Semicolon,
+    Colon,
+    Assign,
+    Dot,
+    Backslash,
+    Question,
+    Pipe,
+    Percent,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Lt,
+    LtEq,
+    Eq,
+    Gt,
+    GtEq,
+    NotEq,
+
+    // Keywords
+    AliasKw,
+    AndKw,
+    BackwardKw,
+    CaseKw,
+    CatchKw,
+    ConnectKw,
+    ConstKw,
+    DefaultKw,
+    DivKw,
+    DoKw,
+    ElseKw,
+    ElseIfKw,
+    EndForKw,
+    EndFuncKw,
+    EndIfKw,
+    EndModuleKw,
+    EndProcKw,
+    EndRecordKw,
+    EndTaskKw,
+    EndTestKw,
+    EndTrapKw,
+    EndWhileKw,
+    ErrorKw,
+    ExitKw,
+    FalseKw,
+    ForKw,
+    FromKw,
+    FuncKw,
+    GotoKw,
+    IfKw,
+    InoutKw,
+    LocalKw,
+    ModKw,
+    ModuleKw,
+    NostepinKw,
+    NotKw,
+    NoviewKw,
+    OrKw,
+    PersKw,
+    ProcKw,
+    RaiseKw,
+    ReadonlyKw,
+    RecordKw,
+    RetryKw,
+    ReturnKw,
+    StepKw,
+    SwitchKw,
+    SysmoduleKw,
+    TaskKw,
+    TestKw,
+    ThenKw,
+    ToKw,
+    TrapKw,
+    TrueKw,
+    TrynextKw,
+    UndoKw,
+    VarKw,
+    ViewonlyKw,
+    WhileKw,
+    WithKw,
+    XorKw,
+
+    /// A character that cannot start a token, or an unterminated string.
+    Error,
+
+    // Nodes
+    SourceFile,
+    Module,
+    AttributeList,
+    RecordDef,
+    RecordComponent,
+    AliasDef,
+    DataDecl,
+    Dim,
+    ProcDef,
+    FuncDef,
+    TrapDef,
+    ParamList,
+    Param,
+    OptionalParam,
+    BackwardHandler,
+    ErrorHandler,
+    ErrorNumbers,
+    UndoHandler,
+    LabelStmt,
+    AssignStmt,
+    ProcCallStmt,
+    GotoStmt,
+    ReturnStmt,
+    RaiseStmt,
+    ExitStmt,
+    RetryStmt,
+    TrynextStmt,
+    ConnectStmt,
+    IfStmt,
+    ElseIfBranch,
+    ElseBranch,
+    ForStmt,
+    WhileStmt,
+    TestStmt,
+    CaseBranch,
+    DefaultBranch,
+    PlaceholderStmt,
+    ArgList,
+    Arg,
+    Literal,
+    NameRef,
+    IndexExpr,
+    FieldExpr,
+    CallExpr,
+    ArrayExpr,
+    ParenExpr,
+    PrefixExpr,
+    BinExpr,
+    /// Tokens the parser could not make sense of.
+    ErrorNode,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::Comment)
+    }
+
+    pub fn is_keyword(self) -> bool {
+        (SyntaxKind::AliasKw..=SyntaxKind::XorKw).contains(&self)
+    }
+
+    /// Keywords that end a list of statements.
+    fn ends_block(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::EndModuleKw
+                | SyntaxKind::EndProcKw
+                | SyntaxKind::EndFuncKw
+                | SyntaxKind::EndTrapKw
+                | SyntaxKind::EndRecordKw
+                | SyntaxKind::EndIfKw
+                | SyntaxKind::ElseKw
+                | SyntaxKind::ElseIfKw
+                | SyntaxKind::EndForKw
+                | SyntaxKind::EndWhileKw
+                | SyntaxKind::EndTestKw
+                | SyntaxKind::CaseKw
+                | SyntaxKind::DefaultKw
+                | SyntaxKind::BackwardKw
+                | SyntaxKind::ErrorKw
+                | SyntaxKind::UndoKw
+        )
+    }
+
+    /// Keywords that start a statement or declaration, where the parser resynchronises after an
+    /// error.
+    fn starts_statement(self) -> bool {
+        matches!(
+            self,
+            SyntaxKind::LocalKw
+                | SyntaxKind::TaskKw
+                | SyntaxKind::VarKw
+                | SyntaxKind::PersKw
+                | SyntaxKind::ConstKw
+                | SyntaxKind::RecordKw
+                | SyntaxKind::AliasKw
+                | SyntaxKind::ProcKw
+                | SyntaxKind::FuncKw
+                | SyntaxKind::TrapKw
+                | SyntaxKind::GotoKw
+                | SyntaxKind::ReturnKw
+                | SyntaxKind::RaiseKw
+                | SyntaxKind::ExitKw
+                | SyntaxKind::RetryKw
+                | SyntaxKind::TrynextKw
+                | SyntaxKind::ConnectKw
+                | SyntaxKind::IfKw
+                | SyntaxKind::ForKw
+                | SyntaxKind::WhileKw
+                | SyntaxKind::TestKw
+        )
+    }
+
+    /// How the kind is referred to in syntax errors.
+    fn describe(self) -> &'static str {
+        match self {
+            SyntaxKind::Ident => "an identifier",
+            SyntaxKind::LParen => "'('",
+            SyntaxKind::RParen => "')'",
+            SyntaxKind::RBrack => "']'",
+            SyntaxKind::RCurly => "'}'",
+            SyntaxKind::Semicolon => "';'",
+            SyntaxKind::Colon => "':'",
+            SyntaxKind::Assign => "':='",
+            SyntaxKind::Percent => "'%'",
+            SyntaxKind::EndModuleKw => "ENDMODULE",
+            SyntaxKind::EndProcKw => "ENDPROC",
+            SyntaxKind::EndFuncKw => "ENDFUNC",
+            SyntaxKind::EndTrapKw => "ENDTRAP",
+            SyntaxKind::EndRecordKw => "ENDRECORD",
+            SyntaxKind::EndIfKw => "ENDIF",
+            SyntaxKind::EndForKw => "ENDFOR",
+            SyntaxKind::EndWhileKw => "ENDWHILE",
+            SyntaxKind::EndTestKw => "ENDTEST",
+            SyntaxKind::ThenKw => "THEN",
+            SyntaxKind::DoKw => "DO",
+            SyntaxKind::FromKw => "FROM",
+            SyntaxKind::ToKw => "TO",
+            SyntaxKind::WithKw => "WITH",
+            _ => "a token",
+        }
+    }
+}
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> Self {
+        rowan::SyntaxKind(kind as u16)
+    }
+}
+
+/// The RAPID language for rowan.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, PartialOrd, Ord)]
+pub enum RapidLanguage {}
+
+impl rowan::Language for RapidLanguage {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        assert!(raw.0 <= SyntaxKind::ErrorNode as u16);
+        // SAFETY: `SyntaxKind` is `repr(u16)` and `raw` is in the range of its variants
+        unsafe { std::mem::transmute::<u16, SyntaxKind>(raw.0) }
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub type SyntaxNode = rowan::SyntaxNode<RapidLanguage>;
+pub type SyntaxToken = rowan::SyntaxToken<RapidLanguage>;
+pub type SyntaxElement = rowan::SyntaxElement<RapidLanguage>;
+
+impl From<TextRange> for Span {
+    fn from(range: TextRange) -> Self {
+        Span::new(range.start().into(), range.end().into())
+    }
+}
+
+/// A problem found while building the concrete syntax tree. The tree is complete regardless: the
+/// offending tokens are kept in `ErrorNode` nodes.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CstError {
+    pub message: String,
+    pub span: Span,
+}
+
+/// The concrete syntax tree of a source file.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Parse {
+    green: GreenNode,
+    errors: Vec<CstError>,
+}
+
+impl Parse {
+    /// Returns the root `SourceFile` node.
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+
+    pub fn green(&self) -> &GreenNode {
+        &self.green
+    }
+
+    pub fn errors(&self) -> &[CstError] {
+        &self.errors
+    }
+}
+
+/// Parses a source file into a lossless concrete syntax tree. The text of the tree is always
+/// exactly `source`, whatever errors it contains.
+pub fn parse(source: &str) -> Parse {
+    let mut parser = Parser {
+        source,
+        tokens: tokenize(source),
+        position: 0,
+        builder: GreenNodeBuilder::new(),
+        errors: Vec::new(),
+    };
+    parser.source_file();
+    Parse {
+        green: parser.builder.finish(),
+        errors: parser.errors,
+    }
+}
+
+/// A recursive descent parser following the grammar of `rapid.lalrpop`.
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<Token>,
+    /// The index of the next token, which may be trivia.
+    position: usize,
+    builder: GreenNodeBuilder<'static>,
+    errors: Vec<CstError>,
+}
+
+impl Parser<'_> {
+    /// Returns the index of the `n`th significant token from the current position.
+    fn nth_index(&self, n: usize) -> Option<usize> {
+        (self.position..self.tokens.len())
+            .filter(|i| !self.tokens[*i].kind.is_trivia())
+            .nth(n)
+    }
+
+    fn nth(&self, n: usize) -> Option<SyntaxKind> {
+        self.nth_index(n).map(|i| self.tokens[i].kind)
+    }
+
+    fn current(&self) -> Option<SyntaxKind> {
+        self.nth(0)
+    }
+
+    fn at(&self, kind: SyntaxKind) -> bool {
+        self.current() == Some(kind)
+    }
+
+    fn at_end(&self) -> bool {
+        self.current().is_none()
+    }
+
+    /// Adds the trivia before the next significant token to the current node.
+    fn trivia(&mut self) {
+        while let Some(token) = self.tokens.get(self.position) {
+            if !token.kind.is_trivia() {
+                break;
+            }
+            self.token(*token);
+            self.position += 1;
+        }
+    }
+
+    fn token(&mut self, token: Token) {
+        self.builder.token(
+            token.kind.into(),
+            &self.source[token.span.start..token.span.end],
+        );
+    }
+
+    /// Adds the next significant token to the current node.
+    fn bump(&mut self) {
+        self.trivia();
+        if let Some(token) = self.tokens.get(self.position) {
+            self.token(*token);
+            self.position += 1;
+        }
+    }
+
+    fn eat(&mut self, kind: SyntaxKind) -> bool {
+        if self.at(kind) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn start(&mut self, kind: SyntaxKind) {
+        self.trivia();
+        self.builder.start_node(kind.into());
+    }
+
+    fn finish(&mut self) {
+        self.builder.finish_node();
+    }
+
+    fn checkpoint(&mut self) -> rowan::Checkpoint {
+        self.trivia();
+        self.builder.checkpoint()
+    }
+
+    fn start_at(&mut self, checkpoint: rowan::Checkpoint, kind: SyntaxKind) {
+        self.builder.start_node_at(checkpoint, kind.into());
+    }
+
+    /// Reports an error at the next significant token, or at the end of the source.
+    fn error(&mut self, message: impl Into<String>) {
+        let span = match self.nth_index(0) {
+            Some(i) => self.tokens[i].span,
+            None => Span::new(self.source.len(), self.source.len()),
+        };
+        self.errors.push(CstError {
+            message: message.into(),
+            span,
+        });
+    }
+
+    fn expect(&mut self, kind: SyntaxKind) -> bool {
+        if self.eat(kind) {
+            return true;
+        }
+        self.error(format!("expected {}", kind.describe()));
+        false
+    }
+
+    /// Reports an error and wraps the next token in an error node.
+    fn error_token(&mut self, message: &str) {
+        self.error(message);
+        if !self.at_end() {
+            self.start(SyntaxKind::ErrorNode);
+            self.bump();
+            self.finish();
+        }
+    }
+
+    fn source_file(&mut self) {
+        self.builder.start_node(SyntaxKind::SourceFile.into());
+        while !self.at_end() {
+            if self.at(SyntaxKind::ModuleKw) {
+                self.module();
+            } else {
+                self.start(SyntaxKind::ErrorNode);
+                self.error("expected MODULE");
+                while !self.at_end() && !self.at(SyntaxKind::ModuleKw) {
+                    self.bump();
+                }
+                self.finish();
+            }
+        }
+        self.trivia();
+        self.finish();
+    }
+
+    fn module(&mut self) {
+        self.start(SyntaxKind::Module);
+        self.bump();
+        self.expect(SyntaxKind::Ident);
+        if self.at(SyntaxKind::LParen) {
+            self.start(SyntaxKind::AttributeList);
+            self.bump();
+            loop {
+                match self.current() {
+                    Some(
+                        SyntaxKind::SysmoduleKw
+                        | SyntaxKind::NostepinKw
+                        | SyntaxKind::ViewonlyKw
+                        | SyntaxKind::ReadonlyKw
+                        | SyntaxKind::NoviewKw,
+                    ) => self.bump(),
+                    _ => self.error_token("expected a module attribute"),
+                }
+                if !self.eat(SyntaxKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(SyntaxKind::RParen);
+            self.finish();
+        }
+        self.statements(&[SyntaxKind::EndModuleKw]);
+        self.expect(SyntaxKind::EndModuleKw);
+        self.finish();
+    }
+
+    /// Parses statements up to one of the `terminators`. Blocks inside routines also end at the end
+    /// of the routine or module, so that a missing `ENDIF` does not swallow the rest of the file.
+    /// Other keywords ending blocks are reported and skipped.
+    fn statements(&mut self, terminators: &[SyntaxKind]) {
+        let nested = terminators != [SyntaxKind::EndModuleKw];
+        loop {
+            match self.current() {
+                None => return,
+                Some(kind) if terminators.contains(&kind) || kind == SyntaxKind::EndModuleKw => {
+                    return
+                }
+                Some(SyntaxKind::EndProcKw | SyntaxKind::EndFuncKw | SyntaxKind::EndTrapKw)
+                    if nested =>
+                {
+                    return
+                }
+                Some(kind) if kind.ends_block() => {
+                    let span = self.tokens[self.nth_index(0).unwrap()].span;
+                    let message = format!("unexpected {}", &self.source[span.start..span.end]);
+                    self.error_token(&message);
+                }
+                Some(_) => self.statement(),
+            }
+        }
+    }
+
+    fn statement(&mut self) {
+        let Some(kind) = self.current() else {
+            return;
+        };
+        match kind {
+            SyntaxKind::LocalKw | SyntaxKind::TaskKw => match self.nth(1) {
+                Some(SyntaxKind::RecordKw) => self.record(),
+                Some(SyntaxKind::AliasKw) => self.alias(),
+                Some(SyntaxKind::ProcKw | SyntaxKind::FuncKw | SyntaxKind::TrapKw) => {
+                    self.routine()
+                }
+                _ => self.data_declaration(),
+            },
+            SyntaxKind::VarKw | SyntaxKind::PersKw | SyntaxKind::ConstKw => self.data_declaration(),
+            SyntaxKind::RecordKw => self.record(),
+            SyntaxKind::AliasKw => self.alias(),
+            SyntaxKind::ProcKw | SyntaxKind::FuncKw | SyntaxKind::TrapKw => self.routine(),
+            SyntaxKind::Ident if self.nth(1) == Some(SyntaxKind::Colon) => {
+                self.start(SyntaxKind::LabelStmt);
+                self.bump();
+                self.bump();
+                self.finish();
+            }
+            SyntaxKind::Ident => self.assignment_or_call(),
+            SyntaxKind::Percent => {
+                self.start(SyntaxKind::ProcCallStmt);
+                self.bump();
+                self.expr();
+                self.expect(SyntaxKind::Percent);
+                if !self.at(SyntaxKind::Semicolon) {
+                    self.arguments();
+                }
+                self.expect(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::Placeholder => {
+                let checkpoint = self.checkpoint();
+                self.bump();
+                if self.at(SyntaxKind::Assign) {
+                    self.start_at(checkpoint, SyntaxKind::AssignStmt);
+                    self.bump();
+                    self.expr();
+                } else {
+                    self.start_at(checkpoint, SyntaxKind::PlaceholderStmt);
+                }
+                self.eat(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::GotoKw => {
+                self.start(SyntaxKind::GotoStmt);
+                self.bump();
+                self.expect(SyntaxKind::Ident);
+                self.expect(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::ReturnKw | SyntaxKind::RaiseKw => {
+                let node = if kind == SyntaxKind::ReturnKw {
+                    SyntaxKind::ReturnStmt
+                } else {
+                    SyntaxKind::RaiseStmt
+                };
+                self.start(node);
+                self.bump();
+                if !self.at(SyntaxKind::Semicolon) {
+                    self.expr();
+                }
+                self.expect(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::ExitKw | SyntaxKind::RetryKw | SyntaxKind::TrynextKw => {
+                let node = match kind {
+                    SyntaxKind::ExitKw => SyntaxKind::ExitStmt,
+                    SyntaxKind::RetryKw => SyntaxKind::RetryStmt,
+                    _ => SyntaxKind::TrynextStmt,
+                };
+                self.start(node);
+                self.bump();
+                self.expect(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::ConnectKw => {
+                self.start(SyntaxKind::ConnectStmt);
+                self.bump();
+                self.variable();
+                self.expect(SyntaxKind::WithKw);
+                self.expect(SyntaxKind::Ident);
+                self.expect(SyntaxKind::Semicolon);
+                self.finish();
+            }
+            SyntaxKind::IfKw => self.if_statement(),
+            SyntaxKind::ForKw => {
+                self.start(SyntaxKind::ForStmt);
+                self.bump();
+                self.expect(SyntaxKind::Ident);
+                self.expect(SyntaxKind::FromKw);
+                self.expr();
+                self.expect(SyntaxKind::ToKw);
+                self.expr();
+                if self.eat(SyntaxKind::StepKw) {
+                    self.expr();
+                }
+                self.expect(SyntaxKind::DoKw);
+                self.statements(&[SyntaxKind::EndForKw]);
+                self.expect(SyntaxKind::EndForKw);
+                self.finish();
+            }
+            SyntaxKind::WhileKw => {
+                self.start(SyntaxKind::WhileStmt);
+                self.bump();
+                self.expr();
+                self.expect(SyntaxKind::DoKw);
+                self.statements(&[SyntaxKind::EndWhileKw]);
+                self.expect(SyntaxKind::EndWhileKw);
+                self.finish();
+            }
+            SyntaxKind::TestKw => self.test_statement(),
+            _ => self.recover("expected a statement"),
+        }
+    }
+
+    /// Reports an error and wraps the rest of the statement in an error node.
+    fn recover(&mut self, message: &str) {
+        self.error(message);
+        self.start(SyntaxKind::ErrorNode);
+        self.skip_statement();
+        self.finish();
+    }
+
+    /// Skips past the next `;`, or up to a keyword that starts a statement or ends a block. Always
+    /// skips at least one token unless it ends a block.
+    fn skip_statement(&mut self) {
+        let mut skipped = false;
+        loop {
+            match self.current() {
+                None => break,
+                Some(SyntaxKind::Semicolon) => {
+                    self.bump();
+                    break;
+                }
+                Some(kind) if kind.ends_block() || (skipped && kind.starts_statement()) => break,
+                Some(_) => self.bump(),
+            }
+            skipped = true;
+        }
+    }
+
+    /// Adds `LOCAL` or `TASK` in front of a declaration to the current node.
+    fn scope(&mut self) {
+        if !self.eat(SyntaxKind::LocalKw) {
+            self.eat(SyntaxKind::TaskKw);
+        }
+    }
+
+    fn data_declaration(&mut self) {
+        self.start(SyntaxKind::DataDecl);
+        self.scope();
+        if !(self.eat(SyntaxKind::VarKw)
+            || self.eat(SyntaxKind::PersKw)
+            || self.eat(SyntaxKind::ConstKw))
+        {
+            self.error("expected VAR, PERS or CONST");
+        }
+        self.expect(SyntaxKind::Ident);
+        self.expect(SyntaxKind::Ident);
+        if self.at(SyntaxKind::LCurly) {
+            self.dim();
+        }
+        if self.eat(SyntaxKind::Assign) {
+            self.expr();
+        }
+        self.expect(SyntaxKind::Semicolon);
+        self.finish();
+    }
+
+    fn record(&mut self) {
+        self.start(SyntaxKind::RecordDef);
+        self.scope();
+        self.bump();
+        self.expect(SyntaxKind::Ident);
+        while self.at(SyntaxKind::Ident) {
+            self.start(SyntaxKind::RecordComponent);
+            self.bump();
+            self.expect(SyntaxKind::Ident);
+            self.expect(SyntaxKind::Semicolon);
+            self.finish();
+        }
+        self.expect(SyntaxKind::EndRecordKw);
+        self.finish();
+    }
+
+    fn alias(&mut self) {
+        self.start(SyntaxKind::AliasDef);
+        self.scope();
+        self.bump();
+        self.expect(SyntaxKind::Ident);
+        self.expect(SyntaxKind::Ident);
+        self.expect(SyntaxKind::Semicolon);
+        self.finish();
+    }
+
+    fn routine(&mut self) {
+        let keyword = self.nth(usize::from(matches!(
+            self.current(),
+            Some(SyntaxKind::LocalKw | SyntaxKind::TaskKw)
+        )));
+        let (node, end) = match keyword {
+            Some(SyntaxKind::FuncKw) => (SyntaxKind::FuncDef, SyntaxKind::EndFuncKw),
+            Some(SyntaxKind::TrapKw) => (SyntaxKind::TrapDef, SyntaxKind::EndTrapKw),
+            _ => (SyntaxKind::ProcDef, SyntaxKind::EndProcKw),
+        };
+        let terminators = [
+            end,
+            SyntaxKind::BackwardKw,
+            SyntaxKind::ErrorKw,
+            SyntaxKind::UndoKw,
+        ];
+        self.start(node);
+        self.scope();
+        self.bump();
+        if node == SyntaxKind::FuncDef {
+            self.expect(SyntaxKind::Ident);
+        }
+        self.expect(SyntaxKind::Ident);
+        if node != SyntaxKind::TrapDef {
+            self.parameters();
+        }
+        self.statements(&terminators);
+        if node == SyntaxKind::ProcDef && self.at(SyntaxKind::BackwardKw) {
+            self.start(SyntaxKind::BackwardHandler);
+            self.bump();
+            self.statements(&terminators);
+            self.finish();
+        }
+        if self.at(SyntaxKind::ErrorKw) {
+            self.start(SyntaxKind::ErrorHandler);
+            self.bump();
+            if self.at(SyntaxKind::LParen) {
+                self.start(SyntaxKind::ErrorNumbers);
+                self.bump();
+                loop {
+                    if !(self.eat(SyntaxKind::Number) || self.eat(SyntaxKind::Ident)) {
+                        self.error_token("expected an error number");
+                    }
+                    if !self.eat(SyntaxKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(SyntaxKind::RParen);
+                self.finish();
+            }
+            self.statements(&terminators);
+            self.finish();
+        }
+        if self.at(SyntaxKind::UndoKw) {
+            self.start(SyntaxKind::UndoHandler);
+            self.bump();
+            self.statements(&terminators);
+            self.finish();
+        }
+        self.expect(end);
+        self.finish();
+    }
+
+    fn parameters(&mut self) {
+        self.start(SyntaxKind::ParamList);
+        if !self.expect(SyntaxKind::LParen) {
+            self.finish();
+            return;
+        }
+        if !self.at(SyntaxKind::RParen) {
+            loop {
+                if self.at(SyntaxKind::Backslash) {
+                    self.start(SyntaxKind::OptionalParam);
+                    self.bump();
+                    loop {
+                        self.parameter();
+                        if !self.eat(SyntaxKind::Pipe) {
+                            break;
+                        }
+                    }
+                    self.finish();
+                } else {
+                    self.parameter();
+                }
+                if !self.eat(SyntaxKind::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect(SyntaxKind::RParen);
+        self.finish();
+    }
+
+    fn parameter(&mut self) {
+        match self.current() {
+            Some(SyntaxKind::Placeholder) => self.bump(),
+            Some(SyntaxKind::SwitchKw) => {
+                self.start(SyntaxKind::Param);
+                self.bump();
+                self.expect(SyntaxKind::Ident);
+                self.finish();
+            }
+            Some(
+                SyntaxKind::VarKw | SyntaxKind::PersKw | SyntaxKind::InoutKw | SyntaxKind::Ident,
+            ) => {
+                self.start(SyntaxKind::Param);
+                if !self.at(SyntaxKind::Ident) {
+                    self.bump();
+                }
+                self.expect(SyntaxKind::Ident);
+                self.expect(SyntaxKind::Ident);
+                if self.at(SyntaxKind::LCurly) {
+                    self.dim();
+                }
+                self.finish();
+            }
+            _ => self.error_token("expected a parameter"),
+        }
+    }
+
+    /// Parses array dimensions or indices: `{2, 3}`, `{*}` for parameters or `{<DIM>}`.
+    fn dim(&mut self) {
+        self.start(SyntaxKind::Dim);
+        self.bump();
+        loop {
+            if !(self.eat(SyntaxKind::Star) || self.eat(SyntaxKind::Placeholder)) {
+                self.expr();
+            }
+            if !self.eat(SyntaxKind::Comma) {
+                break;
+            }
+        }
+        self.expect(SyntaxKind::RCurly);
+        self.finish();
+    }
+
+    fn assignment_or_call(&mut self) {
+        if matches!(
+            self.nth(1),
+            Some(SyntaxKind::Assign | SyntaxKind::LCurly | SyntaxKind::Dot)
+        ) {
+            let checkpoint = self.checkpoint();
+            self.variable();
+            if self.at(SyntaxKind::Assign) {
+                self.start_at(checkpoint, SyntaxKind::AssignStmt);
+                self.bump();
+                self.expr();
+                self.expect(SyntaxKind::Semicolon);
+            } else {
+                self.start_at(checkpoint, SyntaxKind::ErrorNode);
+                self.error("expected ':='");
+                self.skip_statement();
+            }
+            self.finish();
+            return;
+        }
+        self.start(SyntaxKind::ProcCallStmt);
+        self.start(SyntaxKind::NameRef);
+        self.bump();
+        self.finish();
+        if !self.at(SyntaxKind::Semicolon) {
+            self.arguments();
+        }
+        self.expect(SyntaxKind::Semicolon);
+        self.finish();
+    }
+
+    /// Parses a name with indices and components, such as `a{i}.b`.
+    fn variable(&mut self) {
+        let checkpoint = self.checkpoint();
+        self.start(SyntaxKind::NameRef);
+        self.expect(SyntaxKind::Ident);
+        self.finish();
+        loop {
+            match self.current() {
+                Some(SyntaxKind::LCurly) => {
+                    self.start_at(checkpoint, SyntaxKind::IndexExpr);
+                    self.dim();
+                    self.finish();
+                }
+                Some(SyntaxKind::Dot) => {
+                    self.start_at(checkpoint, SyntaxKind::FieldExpr);
+                    self.bump();
+                    self.expect(SyntaxKind::Ident);
+                    self.finish();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn if_statement(&mut self) {
+        self.start(SyntaxKind::IfStmt);
+        self.bump();
+        self.expr();
+        if !self.eat(SyntaxKind::ThenKw) {
+            // The compact form `IF condition statement`
+            self.statement();
+            self.finish();
+            return;
+        }
+        self.statements(&[
+            SyntaxKind::ElseIfKw,
+            SyntaxKind::ElseKw,
+            SyntaxKind::EndIfKw,
+        ]);
+        while self.at(SyntaxKind::ElseIfKw) {
+            self.start(SyntaxKind::ElseIfBranch);
+            self.bump();
+            self.expr();
+            self.expect(SyntaxKind::ThenKw);
+            self.statements(&[
+                SyntaxKind::ElseIfKw,
+                SyntaxKind::ElseKw,
+                SyntaxKind::EndIfKw,
+            ]);
+            self.finish();
+        }
+        if self.at(SyntaxKind::ElseKw) {
+            self.start(SyntaxKind::ElseBranch);
+            self.bump();
+            self.statements(&[
+                SyntaxKind::ElseIfKw,
+                SyntaxKind::ElseKw,
+                SyntaxKind::EndIfKw,
+            ]);
+            self.finish();
+        }
+        self.expect(SyntaxKind::EndIfKw);
+        self.finish();
+    }
+
+    fn test_statement(&mut self) {
+        self.start(SyntaxKind::TestStmt);
+        self.bump();
+        self.expr();
+        loop {
+            match self.current() {
+                Some(SyntaxKind::CaseKw) => {
+                    self.start(SyntaxKind::CaseBranch);
+                    self.bump();
+                    loop {
+                        self.expr();
+                        if !self.eat(SyntaxKind::Comma) {
+                            break;
+                        }
+                    }
+                    self.expect(SyntaxKind::Colon);
+                    self.statements(&[
+                        SyntaxKind::CaseKw,
+                        SyntaxKind::DefaultKw,
+                        SyntaxKind::EndTestKw,
+                    ]);
+                    self.finish();
+                }
+                Some(SyntaxKind::DefaultKw) => {
+                    self.start(SyntaxKind::DefaultBranch);
+                    self.bump();
+                    self.expect(SyntaxKind::Colon);
+                    self.statements(&[
+                        SyntaxKind::CaseKw,
+                        SyntaxKind::DefaultKw,
+                        SyntaxKind::EndTestKw,
+                    ]);
+                    self.finish();
+                }
+                Some(SyntaxKind::Placeholder) => self.bump(),
+                _ => break,
+            }
+        }
+        self.expect(SyntaxKind::EndTestKw);
+        self.finish();
+    }
+
+    /// Parses the arguments of a call: separated by commas, except before optional arguments.
+    fn arguments(&mut self) {
+        self.start(SyntaxKind::ArgList);
+        loop {
+            self.argument();
+            if !self.eat(SyntaxKind::Comma) && !self.at(SyntaxKind::Backslash) {
+                break;
+            }
+        }
+        self.finish();
+    }
+
+    fn argument(&mut self) {
+        if self.at(SyntaxKind::Placeholder) && self.nth(1) != Some(SyntaxKind::Assign) {
+            self.bump();
+            return;
+        }
+        self.start(SyntaxKind::Arg);
+        if self.eat(SyntaxKind::Backslash) {
+            self.expect(SyntaxKind::Ident);
+            if self.eat(SyntaxKind::Assign) {
+                self.expr();
+            } else if self.eat(SyntaxKind::Question) {
+                self.variable();
+            }
+        } else {
+            if self.at(SyntaxKind::Ident) && self.nth(1) == Some(SyntaxKind::Assign) {
+                self.bump();
+                self.bump();
+            }
+            self.expr();
+        }
+        self.finish();
+    }
+
+    /// Parses an expression. As in the AST grammar, `NOT` and a sign apply to a whole `AND`
+    /// expression.
+    fn expr(&mut self) {
+        let checkpoint = self.checkpoint();
+        if matches!(
+            self.current(),
+            Some(SyntaxKind::NotKw | SyntaxKind::Plus | SyntaxKind::Minus)
+        ) {
+            self.start(SyntaxKind::PrefixExpr);
+            self.bump();
+            self.and_expr();
+            self.finish();
+        } else {
+            self.and_expr();
+        }
+        while matches!(self.current(), Some(SyntaxKind::OrKw | SyntaxKind::XorKw)) {
+            self.start_at(checkpoint, SyntaxKind::BinExpr);
+            self.bump();
+            self.and_expr();
+            self.finish();
+        }
+    }
+
+    fn and_expr(&mut self) {
+        self.binary(&[SyntaxKind::AndKw], Self::comparison);
+    }
+
+    fn comparison(&mut self) {
+        self.binary(
+            &[
+                SyntaxKind::Lt,
+                SyntaxKind::LtEq,
+                SyntaxKind::Eq,
+                SyntaxKind::Gt,
+                SyntaxKind::GtEq,
+                SyntaxKind::NotEq,
+            ],
+            Self::sum,
+        );
+    }
+
+    fn sum(&mut self) {
+        self.binary(&[SyntaxKind::Plus, SyntaxKind::Minus], Self::product);
+    }
+
+    fn product(&mut self) {
+        self.binary(
+            &[
+                SyntaxKind::Star,
+                SyntaxKind::Slash,
+                SyntaxKind::DivKw,
+                SyntaxKind::ModKw,
+            ],
+            Self::term,
+        );
+    }
+
+    /// Parses a left-associative chain of `operand`s joined by `operators`.
+    fn binary(&mut self, operators: &[SyntaxKind], operand: fn(&mut Self)) {
+        let checkpoint = self.checkpoint();
+        operand(self);
+        while self.current().is_some_and(|kind| operators.contains(&kind)) {
+            self.start_at(checkpoint, SyntaxKind::BinExpr);
+            self.bump();
+            operand(self);
+            self.finish();
+        }
+    }
+
+    fn term(&mut self) {
+        match self.current() {
+            Some(
+                SyntaxKind::Number
+                | SyntaxKind::String
+                | SyntaxKind::TrueKw
+                | SyntaxKind::FalseKw
+                | SyntaxKind::Placeholder,
+            ) => {
+                self.start(SyntaxKind::Literal);
+                self.bump();
+                self.finish();
+            }
+            Some(SyntaxKind::LBrack) => {
+                self.start(SyntaxKind::ArrayExpr);
+                self.bump();
+                if !self.at(SyntaxKind::RBrack) {
+                    loop {
+                        self.expr();
+                        if !self.eat(SyntaxKind::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.expect(SyntaxKind::RBrack);
+                self.finish();
+            }
+            Some(SyntaxKind::LParen) => {
+                self.start(SyntaxKind::ParenExpr);
+                self.bump();
+                self.expr();
+                self.expect(SyntaxKind::RParen);
+                self.finish();
+            }
+            Some(SyntaxKind::Ident) if self.nth(1) == Some(SyntaxKind::LParen) => {
+                self.start(SyntaxKind::CallExpr);
+                self.start(SyntaxKind::NameRef);
+                self.bump();
+                self.finish();
+                self.bump();
+                if !self.at(SyntaxKind::RParen) {
+                    self.arguments();
+                }
+                self.expect(SyntaxKind::RParen);
+                self.finish();
+            }
+            Some(SyntaxKind::Ident) => self.variable(),
+            _ => self.error("expected an expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Prints the tree with one line per node and token, leaving out whitespace.
+    fn debug_tree(node: &SyntaxNode) -> String {
+        let mut out = String::new();
+        let mut depth = 0;
+        for event in node.preorder_with_tokens() {
+            match event {
+                rowan::WalkEvent::Enter(element) => {
+                    match &element {
+                        SyntaxElement::Node(node) => {
+                            out += &format!("{}{:?}\n", "  ".repeat(depth), node.kind())
+                        }
+                        SyntaxElement::Token(token) if token.kind() != SyntaxKind::Whitespace => {
+                            out += &format!(
+                                "{}{:?} {:?}\n",
+                                "  ".repeat(depth),
+                                token.kind(),
+                                token.text()
+                            )
+                        }
+                        SyntaxElement::Token(_) => {}
+                    }
+                    depth += 1;
+                }
+                rowan::WalkEvent::Leave(_) => depth -= 1,
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn keep_every_token_of_the_data_files() {
+        for entry in std::fs::read_dir("data").unwrap() {
+            let path = entry.unwrap().path();
+            let Ok(source) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let parse = parse(&source);
+            assert_eq!(parse.syntax().to_string(), source);
+            assert_eq!(parse.errors(), &[], "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn keep_comments_anywhere() {
+        let source = "MODULE demo\n    PERS string ip := \"127.0.0.1\"; !local IP\n    PROC main(num a, ! first\n              num b)\n        x := a + ! plus\n            b;\n    ENDPROC\nENDMODULE\n";
+        let parse = parse(source);
+        assert_eq!(parse.errors(), &[]);
+        assert_eq!(parse.syntax().to_string(), source);
+        assert_eq!(
+            debug_tree(&parse.syntax()),
+            r#"SourceFile
+  Module
+    ModuleKw "MODULE"
+    Ident "demo"
+    DataDecl
+      PersKw "PERS"
+      Ident "string"
+      Ident "ip"
+      Assign ":="
+      Literal
+        String "\"127.0.0.1\""
+      Semicolon ";"
+    Comment "!local IP"
+    ProcDef
+      ProcKw "PROC"
+      Ident "main"
+      ParamList
+        LParen "("
+        Param
+          Ident "num"
+          Ident "a"
+        Comma ","
+        Comment "! first"
+        Param
+          Ident "num"
+          Ident "b"
+        RParen ")"
+      AssignStmt
+        NameRef
+          Ident "x"
+        Assign ":="
+        BinExpr
+          NameRef
+            Ident "a"
+          Plus "+"
+          Comment "! plus"
+          NameRef
+            Ident "b"
+        Semicolon ";"
+      EndProcKw "ENDPROC"
+    EndModuleKw "ENDMODULE"
+"#
+        );
+    }
+
+    #[test]
+    fn parse_statements_and_expressions() {
+        let source = r#"MODULE demo(SYSMODULE, NOSTEPIN)
+    LOCAL RECORD point
+        num x;
+    ENDRECORD
+    ALIAS num length;
+    CONST num grid{2, 2} := [[1, 2], [3, 4]];
+    LOCAL FUNC num norm(\switch fast | num scale, VAR num values{*})
+        RETURN Sqrt(values{1} * values{1}) DIV 2;
+    ENDFUNC
+    TRAP tick
+        count := count + 1;
+    ENDTRAP
+    PROC main()
+        IF NOT a AND b OR c THEN
+            MoveL p10, v100, fine, tool0 \WObj:=wobj0;
+        ELSEIF a <> 2 THEN
+            GOTO done;
+        ELSE
+            %"proc" + "name"% 1;
+        ENDIF
+        FOR i FROM 1 TO 10 STEP 2 DO
+            IF i = 5 EXIT;
+        ENDFOR
+        WHILE TRUE DO
+            CONNECT intno WITH tick;
+        ENDWHILE
+        TEST state
+        CASE 1, 2:
+            RETRY;
+        DEFAULT:
+            TRYNEXT;
+        ENDTEST
+        done:
+        point.x := -norm(\fast, values);
+    ERROR (ERR_DIVZERO, 41)
+        RAISE;
+    UNDO
+        RETURN;
+    ENDPROC
+ENDMODULE"#;
+        let parse = parse(source);
+        assert_eq!(parse.errors(), &[]);
+        assert_eq!(parse.syntax().to_string(), source);
+        let kinds: Vec<SyntaxKind> = parse
+            .syntax()
+            .descendants()
+            .map(|node| node.kind())
+            .collect();
+        for kind in [
+            SyntaxKind::AttributeList,
+            SyntaxKind::RecordDef,
+            SyntaxKind::AliasDef,
+            SyntaxKind::ArrayExpr,
+            SyntaxKind::OptionalParam,
+            SyntaxKind::CallExpr,
+            SyntaxKind::IndexExpr,
+            SyntaxKind::TrapDef,
+            SyntaxKind::PrefixExpr,
+            SyntaxKind::ElseIfBranch,
+            SyntaxKind::ElseBranch,
+            SyntaxKind::ForStmt,
+            SyntaxKind::WhileStmt,
+            SyntaxKind::ConnectStmt,
+            SyntaxKind::CaseBranch,
+            SyntaxKind::DefaultBranch,
+            SyntaxKind::LabelStmt,
+            SyntaxKind::FieldExpr,
+            SyntaxKind::ErrorNumbers,
+            SyntaxKind::UndoHandler,
+        ] {
+            assert!(kinds.contains(&kind), "no {:?} in the tree", kind);
+        }
+    }
+
+    #[test]
+    fn recover_from_errors_without_losing_text() {
+        let source = "MODULE demo\n    PROC main()\n        x := ;\n        ENDIF\n        y := 1 +;\n        Stop;\n    ENDPROC\n    VAR num\nENDMODULE\n$";
+        let parse = parse(source);
+        assert_eq!(parse.syntax().to_string(), source);
+        let messages: Vec<_> = parse
+            .errors()
+            .iter()
+            .map(|error| {
+                (
+                    error.message.as_str(),
+                    &source[error.span.start..error.span.end],
+                )
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                ("expected an expression", ";"),
+                ("unexpected ENDIF", "ENDIF"),
+                ("expected an expression", ";"),
+                ("expected an identifier", "ENDMODULE"),
+                ("expected ';'", "ENDMODULE"),
+                ("expected MODULE", "$"),
+            ]
+        );
+        // The routine after the errors is still recognised
+        assert!(parse
+            .syntax()
+            .descendants()
+            .any(|node| node.kind() == SyntaxKind::ProcCallStmt));
+    }
+
+    #[test]
+    fn rewrite_a_name_and_keep_the_layout() {
+        let source = "MODULE demo\n    VAR num count; ! items\n    PROC main()\n        count := count+1; ! next\n    ENDPROC\nENDMODULE";
+        let mut root = parse(source).syntax();
+        while let Some(token) = root
+            .descendants_with_tokens()
+            .filter_map(|element| element.into_token())
+            .find(|token| token.kind() == SyntaxKind::Ident && token.text() == "count")
+        {
+            let name = rowan::GreenToken::new(SyntaxKind::Ident.into(), "total");
+            root = SyntaxNode::new_root(token.replace_with(name));
+        }
+        assert_eq!(
+            root.to_string(),
+            "MODULE demo\n    VAR num total; ! items\n    PROC main()\n        total := total+1; ! next\n    ENDPROC\nENDMODULE"
+        );
+    }
+}
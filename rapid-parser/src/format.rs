@@ -0,0 +1,1123 @@
+use crate::ast::{
+    AccessMode, Argument, ArgumentKind, AssignmentTarget, DataDeclaration, Dimension,
+    DimensionKind, ErrorHandler, Expr, ExprKind, Module, ModuleAttribute, ModuleInfo, Number,
+    OpCode, OptionalParameterDeclarationTypeKind, Parameter, ParameterDeclaration,
+    ParameterDeclarationType, ParameterDeclarationTypeKind, RoutineDeclaration, Scope, Statement,
+    StatementKind, Term, TestCaseKind, TypeDefinition, VarDeclaration, VarDeclarationType,
+    Variable, VariableKind,
+};
+use crate::{lexer, parse_module, RapidParseError, Span};
+
+/// Marks a position where a line may be broken if it does not fit the line width. It is printed
+/// as a space otherwise.
+const BREAK: char = '\u{1}';
+
+/// The width assumed for a tab when measuring lines.
+const TAB_WIDTH: usize = 4;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum KeywordCase {
+    Upper,
+    Lower,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FormatOptions {
+    pub indent: Indent,
+    pub keyword_case: KeywordCase,
+    /// Lines longer than this are broken after the commas of argument lists, parameter lists and
+    /// aggregates. Continuation lines are indented one level deeper.
+    pub line_width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: Indent::Spaces(4),
+            keyword_case: KeywordCase::Upper,
+            line_width: 100,
+        }
+    }
+}
+
+/// Prints a module as canonically formatted RAPID code.
+///
+/// Comments are kept since they are statements of the AST. Without the source text the original
+/// spelling of literals, blank lines and comments trailing a statement on the same line are not
+/// known; use [`format_source`] to keep them. Nodes that failed to parse are printed as the
+/// corresponding placeholder (e.g. `<STM>`). The AST does not keep the `<EIT>` placeholder of an
+/// `ELSEIF` nor the `<ARG>` placeholders of an argument list, so they are left out: the `ELSEIF`
+/// is printed with an empty branch.
+pub fn format_module(module: &ModuleInfo, options: &FormatOptions) -> String {
+    let mut printer = Printer::new(None, options);
+    printer.module(module);
+    printer.finish()
+}
+
+/// Parses and formats RAPID source code.
+///
+/// # Arguments
+///
+/// * `source` - The RAPID module to format.
+/// * `options` - Indentation, keyword case and line width.
+///
+/// # Returns
+///
+/// The formatted module, or the syntax errors if the source does not parse. Formatting never
+/// changes the meaning of a module and formatting the result again does not change it.
+/// Placeholders are kept except `<EIT>` and `<ARG>`, as with [`format_module`]. Comments inside a
+/// statement, such as in a parameter list or an expression, are moved to a line of their own after
+/// it.
+pub fn format_source<'input>(
+    source: &'input str,
+    options: &FormatOptions,
+) -> Result<String, Vec<RapidParseError<'input>>> {
+    match parse_module(source).into_result()? {
+        Module::Module(module) => {
+            let mut printer = Printer::new(Some(source), options);
+            printer.skipped = lexer::skipped_comments(source);
+            printer.module(&module);
+            Ok(printer.finish())
+        }
+        Module::Error => unreachable!("a module that failed to parse always has errors"),
+    }
+}
+
+struct Printer<'s> {
+    source: Option<&'s str>,
+    options: &'s FormatOptions,
+    lines: Vec<String>,
+    level: usize,
+    /// The source offset just past the last element printed. Only tracked with source text.
+    pos: Option<usize>,
+    /// The comments in the source that are not statements, which are still to be printed.
+    skipped: Vec<Span>,
+}
+
+impl<'s> Printer<'s> {
+    fn new(source: Option<&'s str>, options: &'s FormatOptions) -> Self {
+        Printer {
+            source,
+            options,
+            lines: Vec::new(),
+            level: 0,
+            pos: source.map(|_| 0),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Prints the comments that are not statements and start before `offset`, each on a line of
+    /// its own.
+    fn skipped_comments(&mut self, offset: usize) {
+        let Some(source) = self.source else {
+            return;
+        };
+        let count = self.skipped.partition_point(|span| span.start < offset);
+        for span in self.skipped.drain(..count).collect::<Vec<_>>() {
+            self.line(source[span.start..span.end].trim_end());
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        text
+    }
+
+    fn kw(&self, keyword: &str) -> String {
+        match self.options.keyword_case {
+            KeywordCase::Upper => keyword.to_owned(),
+            KeywordCase::Lower => keyword.to_ascii_lowercase(),
+        }
+    }
+
+    fn indentation(&self, level: usize) -> String {
+        match self.options.indent {
+            Indent::Spaces(width) => " ".repeat(width * level),
+            Indent::Tabs => "\t".repeat(level),
+        }
+    }
+
+    /// Emits a line at the current indentation, breaking it at [`BREAK`] markers if it is too long.
+    ///
+    /// Breaks in the outermost list are preferred, e.g. between the arguments of a call rather than
+    /// inside an aggregate passed to it. Deeper breaks are only used if the line still does not fit.
+    fn line(&mut self, text: &str) {
+        let indentation = self.indentation(self.level);
+        let flat = text.replace(BREAK, " ");
+        if width(&indentation) + width(&flat) <= self.options.line_width || !text.contains(BREAK) {
+            self.lines.push(indentation + &flat);
+            return;
+        }
+        let continuation = self.indentation(self.level + 1);
+        let segments = break_depths(text);
+        let mut depths: Vec<usize> = segments.iter().skip(1).map(|(depth, _)| *depth).collect();
+        depths.sort_unstable();
+        depths.dedup();
+        let mut wrapped = Vec::new();
+        for max_depth in depths {
+            let mut chunks: Vec<String> = Vec::new();
+            for (i, (depth, segment)) in segments.iter().enumerate() {
+                match chunks.last_mut() {
+                    Some(chunk) if i > 0 && *depth > max_depth => {
+                        chunk.push(' ');
+                        chunk.push_str(segment);
+                    }
+                    _ => chunks.push(segment.to_string()),
+                }
+            }
+            wrapped.clear();
+            let mut current = indentation.clone();
+            for (i, chunk) in chunks.iter().enumerate() {
+                if i > 0 {
+                    if width(&current) + 1 + width(chunk) > self.options.line_width {
+                        wrapped.push(std::mem::take(&mut current));
+                        current = continuation.clone();
+                    } else {
+                        current.push(' ');
+                    }
+                }
+                current.push_str(chunk);
+            }
+            wrapped.push(current);
+            if wrapped
+                .iter()
+                .all(|line| width(line) <= self.options.line_width)
+            {
+                break;
+            }
+        }
+        self.lines.append(&mut wrapped);
+    }
+
+    /// Moves the source position to `offset`.
+    fn seek(&mut self, offset: usize) {
+        if self.source.is_some() {
+            self.pos = Some(offset);
+        }
+    }
+
+    /// Moves the source position past `token` if it is the next token in the source. Returns the
+    /// offset the token starts at.
+    fn advance(&mut self, token: &str) -> Option<usize> {
+        let source = self.source?;
+        let pos = self.pos?;
+        let rest = source.get(pos..)?;
+        let start = pos + rest.len() - rest.trim_start().len();
+        let end = start + token.len();
+        if !source.get(start..end)?.eq_ignore_ascii_case(token) {
+            return None;
+        }
+        let ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        if token.ends_with(ident) && source[end..].starts_with(ident) {
+            return None;
+        }
+        self.pos = Some(end);
+        Some(start)
+    }
+
+    /// Moves the source position past the next occurrence of `c` after `from`.
+    fn seek_past(&mut self, from: usize, c: char) {
+        if let Some(offset) = self.source.and_then(|source| source.get(from..)?.find(c)) {
+            self.pos = Some(from + offset + c.len_utf8());
+        }
+    }
+
+    /// Keeps a single blank line where the source has blank lines between the last element printed
+    /// and `offset`.
+    fn blank_lines(&mut self, offset: usize) {
+        let (Some(source), Some(pos)) = (self.source, self.pos) else {
+            return;
+        };
+        let Some(gap) = source.get(pos..offset) else {
+            return;
+        };
+        if gap.matches('\n').count() >= 2 && self.lines.last().is_some_and(|l| !l.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    fn on_same_line(&self, offset: usize) -> bool {
+        match (self.source, self.pos) {
+            (Some(source), Some(pos)) => source
+                .get(pos..offset)
+                .is_some_and(|gap| !gap.contains('\n')),
+            _ => false,
+        }
+    }
+
+    /// Prints a line starting with `keyword`, keeping a blank line before it if the source has one.
+    fn keyword_line(&mut self, keyword: &str, text: &str) {
+        let pos = self.pos;
+        if let Some(start) = self.advance(keyword) {
+            let end = self.pos;
+            self.pos = pos;
+            self.skipped_comments(start);
+            self.blank_lines(start);
+            self.pos = end;
+        }
+        self.line(text);
+    }
+
+    fn scope(&self, scope: Scope) -> String {
+        match scope {
+            Scope::GLOBAL => String::new(),
+            Scope::LOCAL => self.kw("LOCAL "),
+            Scope::TASK => self.kw("TASK "),
+        }
+    }
+
+    fn module(&mut self, module: &ModuleInfo) {
+        let mut header = format!("{} {}", self.kw("MODULE"), module.name);
+        if !module.attributes.is_empty() {
+            let attributes: Vec<String> = module
+                .attributes
+                .iter()
+                .map(|attribute| self.kw(attribute_name(attribute)))
+                .collect();
+            header += &format!("({})", attributes.join(", "));
+        }
+        self.line(&header);
+        self.seek(module.name_span.end);
+        if !module.attributes.is_empty() {
+            self.seek_past(module.name_span.end, ')');
+        }
+        self.block(&module.statements);
+        self.keyword_line("ENDMODULE", &self.kw("ENDMODULE"));
+        self.skipped_comments(usize::MAX);
+    }
+
+    fn block(&mut self, statements: &[Statement]) {
+        self.level += 1;
+        for statement in statements {
+            self.statement(statement);
+        }
+        if let Some(last) = statements.last() {
+            self.skipped_comments(last.span.end);
+        }
+        self.level -= 1;
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        if let StatementKind::Comment(comment) = &statement.kind {
+            let comment = comment.trim_end();
+            let trailing = self.on_same_line(statement.span.start);
+            match self.lines.last_mut() {
+                Some(last) if trailing && !last.is_empty() => {
+                    last.push(' ');
+                    last.push_str(comment);
+                }
+                _ => {
+                    self.skipped_comments(statement.span.start);
+                    self.blank_lines(statement.span.start);
+                    self.line(comment);
+                }
+            }
+            self.seek(statement.span.start + comment.len());
+            return;
+        }
+
+        self.skipped_comments(statement.span.start);
+        self.blank_lines(statement.span.start);
+        match &statement.kind {
+            StatementKind::TypeDefinition(TypeDefinition::RecordDefinition(scope, record)) => {
+                self.line(&format!(
+                    "{}{} {}",
+                    self.scope(*scope),
+                    self.kw("RECORD"),
+                    record.name
+                ));
+                self.level += 1;
+                for component in &record.components {
+                    self.line(&format!("{} {};", component.data_type, component.name));
+                }
+                self.level -= 1;
+                self.seek(
+                    record
+                        .components
+                        .last()
+                        .map_or(record.name_span.end, |c| c.span.end),
+                );
+                self.keyword_line("ENDRECORD", &self.kw("ENDRECORD"));
+            }
+            StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(scope, alias)) => {
+                self.line(&format!(
+                    "{}{} {} {};",
+                    self.scope(*scope),
+                    self.kw("ALIAS"),
+                    alias.data_type,
+                    alias.name
+                ));
+            }
+            StatementKind::TypeDefinition(TypeDefinition::TDN) => self.line("<TDN>"),
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(scope, decl)) => {
+                let text = self.scope(*scope) + &self.declaration(decl);
+                self.line(&text);
+            }
+            StatementKind::DataDeclaration(DataDeclaration::DDN) => self.line("<DDN>"),
+            StatementKind::RoutineDeclaration(routine) => self.routine(routine),
+            StatementKind::Label(name) => self.line(&format!("{}:", name)),
+            StatementKind::Assignment(target, expr) => {
+                let target = match target {
+                    AssignmentTarget::Variable(variable) => self.variable(variable),
+                    AssignmentTarget::VAR => "<VAR>".to_owned(),
+                };
+                self.line(&format!("{} := {};", target, self.expr(expr, 1)));
+            }
+            StatementKind::ProcCall(callee, arguments) => {
+                let callee = match &callee.kind {
+                    // A procedure name parsed as an identifier spans exactly the name
+                    ExprKind::Term(Term::String(name)) if callee.span.len() == name.len() => {
+                        name.clone()
+                    }
+                    _ => format!("%{}%", self.expr(callee, 1)),
+                };
+                let text = if arguments.is_empty() {
+                    format!("{};", callee)
+                } else {
+                    format!("{} {};", callee, self.arguments(arguments))
+                };
+                self.line(&text);
+            }
+            StatementKind::Goto(label) => {
+                self.line(&format!("{} {};", self.kw("GOTO"), label.name));
+            }
+            StatementKind::Return(expr) => {
+                let text = self.with_optional_expr("RETURN", expr.as_ref());
+                self.line(&text);
+            }
+            StatementKind::Raise(expr) => {
+                let text = self.with_optional_expr("RAISE", expr.as_ref());
+                self.line(&text);
+            }
+            StatementKind::Exit => self.line(&format!("{};", self.kw("EXIT"))),
+            StatementKind::Retry => self.line(&format!("{};", self.kw("RETRY"))),
+            StatementKind::TryNext => self.line(&format!("{};", self.kw("TRYNEXT"))),
+            StatementKind::Connect(interrupt, trap) => {
+                self.line(&format!(
+                    "{} {} {} {};",
+                    self.kw("CONNECT"),
+                    interrupt.name,
+                    self.kw("WITH"),
+                    trap.name
+                ));
+            }
+            StatementKind::If(condition, statements, else_ifs, else_statements) => {
+                self.line(&format!(
+                    "{} {} {}",
+                    self.kw("IF"),
+                    self.expr(condition, 1),
+                    self.kw("THEN")
+                ));
+                self.seek(condition.span.end);
+                self.advance("THEN");
+                self.block(statements);
+                for (condition, statements) in else_ifs {
+                    let text = format!(
+                        "{} {} {}",
+                        self.kw("ELSEIF"),
+                        self.expr(condition, 1),
+                        self.kw("THEN")
+                    );
+                    self.keyword_line("ELSEIF", &text);
+                    self.seek(condition.span.end);
+                    self.advance("THEN");
+                    self.block(statements);
+                }
+                if !else_statements.is_empty() {
+                    self.keyword_line("ELSE", &self.kw("ELSE"));
+                    self.block(else_statements);
+                }
+                self.keyword_line("ENDIF", &self.kw("ENDIF"));
+            }
+            StatementKind::For(variable, from, to, step, statements) => {
+                let mut text = format!(
+                    "{} {} {} {} {} {}",
+                    self.kw("FOR"),
+                    variable.name,
+                    self.kw("FROM"),
+                    self.expr(from, 1),
+                    self.kw("TO"),
+                    self.expr(to, 1)
+                );
+                if let Some(step) = step {
+                    text += &format!(" {} {}", self.kw("STEP"), self.expr(step, 1));
+                }
+                text += &format!(" {}", self.kw("DO"));
+                self.line(&text);
+                self.seek(step.as_ref().unwrap_or(to).span.end);
+                self.advance("DO");
+                self.block(statements);
+                self.keyword_line("ENDFOR", &self.kw("ENDFOR"));
+            }
+            StatementKind::While(condition, statements) => {
+                self.line(&format!(
+                    "{} {} {}",
+                    self.kw("WHILE"),
+                    self.expr(condition, 1),
+                    self.kw("DO")
+                ));
+                self.seek(condition.span.end);
+                self.advance("DO");
+                self.block(statements);
+                self.keyword_line("ENDWHILE", &self.kw("ENDWHILE"));
+            }
+            StatementKind::Test(expr, cases, default) => {
+                self.line(&format!("{} {}", self.kw("TEST"), self.expr(expr, 1)));
+                self.seek(expr.span.end);
+                self.level += 1;
+                for case in cases {
+                    self.blank_lines(case.span.start);
+                    match &case.kind {
+                        TestCaseKind::Case(values, statements) => {
+                            let values: Vec<String> =
+                                values.iter().map(|value| self.expr(value, 1)).collect();
+                            self.line(&format!("{} {}:", self.kw("CASE"), values.join(", ")));
+                            if let TestCaseKind::Case(values, _) = &case.kind {
+                                self.seek(values.last().map_or(case.span.start, |v| v.span.end));
+                            }
+                            self.advance(":");
+                            self.block(statements);
+                        }
+                        TestCaseKind::CSE => self.line("<CSE>"),
+                    }
+                    self.seek(case.span.end);
+                }
+                if let Some(default) = default {
+                    self.keyword_line("DEFAULT", &format!("{}:", self.kw("DEFAULT")));
+                    self.advance(":");
+                    self.block(default);
+                }
+                self.level -= 1;
+                self.keyword_line("ENDTEST", &self.kw("ENDTEST"));
+            }
+            StatementKind::Comment(_) => unreachable!("comments are printed above"),
+            StatementKind::SMT | StatementKind::Error => self.line("<STM>;"),
+        }
+        self.seek(statement.span.end);
+    }
+
+    fn with_optional_expr(&self, keyword: &str, expr: Option<&Expr>) -> String {
+        match expr {
+            Some(expr) => format!("{} {};", self.kw(keyword), self.expr(expr, 1)),
+            None => format!("{};", self.kw(keyword)),
+        }
+    }
+
+    fn declaration(&self, decl: &VarDeclaration) -> String {
+        let keyword = match decl.declaration_type {
+            VarDeclarationType::VarDeclaration => "VAR",
+            VarDeclarationType::PersDeclaration => "PERS",
+            VarDeclarationType::ConstDeclaration => "CONST",
+        };
+        let definition = &decl.definition;
+        let mut text = format!(
+            "{} {} {}",
+            self.kw(keyword),
+            decl.data_type,
+            definition.identifier
+        );
+        if let Some(dim) = &definition.dim {
+            text += &self.dimension(dim);
+        }
+        if let Some(expr) = &definition.expression {
+            text += &format!(" := {}", self.expr(expr, 1));
+        }
+        text.push(';');
+        text
+    }
+
+    fn routine(&mut self, routine: &RoutineDeclaration) {
+        match routine {
+            RoutineDeclaration::ProcDeclaration(proc) => {
+                self.line(&format!(
+                    "{}{} {}({})",
+                    self.scope(proc.scope),
+                    self.kw("PROC"),
+                    proc.name,
+                    self.parameters(&proc.parameters)
+                ));
+                self.seek_past(proc.name_span.end, ')');
+                self.block(&proc.statements);
+                if let Some(handler) = &proc.backward_handler {
+                    self.keyword_line("BACKWARD", &self.kw("BACKWARD"));
+                    self.block(handler);
+                }
+                self.handlers(proc.error_handler.as_ref(), proc.undo_handler.as_deref());
+                self.keyword_line("ENDPROC", &self.kw("ENDPROC"));
+            }
+            RoutineDeclaration::FuncDeclaration(func) => {
+                self.line(&format!(
+                    "{}{} {} {}({})",
+                    self.scope(func.scope),
+                    self.kw("FUNC"),
+                    func.data_type,
+                    func.name,
+                    self.parameters(&func.parameters)
+                ));
+                self.seek_past(func.name_span.end, ')');
+                self.block(&func.statements);
+                self.handlers(func.error_handler.as_ref(), func.undo_handler.as_deref());
+                self.keyword_line("ENDFUNC", &self.kw("ENDFUNC"));
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => {
+                self.line(&format!(
+                    "{}{} {}",
+                    self.scope(trap.scope),
+                    self.kw("TRAP"),
+                    trap.name
+                ));
+                self.seek(trap.name_span.end);
+                self.block(&trap.statements);
+                self.handlers(trap.error_handler.as_ref(), trap.undo_handler.as_deref());
+                self.keyword_line("ENDTRAP", &self.kw("ENDTRAP"));
+            }
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => self.line("<RDN>"),
+        }
+    }
+
+    fn handlers(&mut self, error: Option<&ErrorHandler>, undo: Option<&[Statement]>) {
+        if let Some(handler) = error {
+            let mut text = self.kw("ERROR");
+            if !handler.numbers.is_empty() {
+                let numbers: Vec<String> = handler
+                    .numbers
+                    .iter()
+                    .map(|number| match &number.kind {
+                        ExprKind::Term(Term::String(name)) => name.clone(),
+                        _ => self.expr(number, 1),
+                    })
+                    .collect();
+                text += &format!(" ({})", numbers.join(", "));
+            }
+            self.keyword_line("ERROR", &text);
+            if let Some(last) = handler.numbers.last() {
+                self.seek(last.span.end);
+                self.advance(")");
+            }
+            self.block(&handler.statements);
+        }
+        if let Some(handler) = undo {
+            self.keyword_line("UNDO", &self.kw("UNDO"));
+            self.block(handler);
+        }
+    }
+
+    fn parameters(&self, parameters: &[ParameterDeclarationType]) -> String {
+        let parameters: Vec<String> = parameters
+            .iter()
+            .map(|parameter| match &parameter.kind {
+                ParameterDeclarationTypeKind::ParameterDeclaration(p) => self.parameter(p),
+                ParameterDeclarationTypeKind::OptionalParameterDeclaration(alternatives) => {
+                    let alternatives: Vec<String> = alternatives
+                        .iter()
+                        .map(|alternative| match &alternative.kind {
+                            OptionalParameterDeclarationTypeKind::OptionalParameterDeclaration(
+                                p,
+                            ) => self.parameter(p),
+                            OptionalParameterDeclarationTypeKind::Switch(name) => {
+                                format!("switch {}", name)
+                            }
+                            OptionalParameterDeclarationTypeKind::ALT => "<ALT>".to_owned(),
+                        })
+                        .collect();
+                    format!("\\{}", alternatives.join(" | "))
+                }
+                ParameterDeclarationTypeKind::PAR | ParameterDeclarationTypeKind::Error => {
+                    "<PAR>".to_owned()
+                }
+            })
+            .collect();
+        parameters.join(&format!(",{}", BREAK))
+    }
+
+    fn parameter(&self, parameter: &ParameterDeclaration) -> String {
+        let mode = match parameter.access_mode {
+            AccessMode::IN => String::new(),
+            AccessMode::VAR => self.kw("VAR "),
+            AccessMode::PERS => self.kw("PERS "),
+            AccessMode::INOUT => self.kw("INOUT "),
+            AccessMode::REF => self.kw("REF "),
+        };
+        let mut text = format!("{}{} {}", mode, parameter.data_type, parameter.name);
+        if let Some(dim) = &parameter.dim {
+            text += &self.dimension(dim);
+        }
+        text
+    }
+
+    /// Joins arguments the way RAPID separates them: required arguments with commas, optional and
+    /// conditional arguments with spaces.
+    fn arguments(&self, arguments: &[Argument]) -> String {
+        let mut text = String::new();
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                if matches!(argument.kind, ArgumentKind::Required(..)) {
+                    text.push(',');
+                }
+                text.push(BREAK);
+            }
+            text += &match &argument.kind {
+                ArgumentKind::Required(None, expr) => self.expr(expr, 1),
+                ArgumentKind::Required(Some(name), expr) => {
+                    format!("{}:={}", name, self.expr(expr, 1))
+                }
+                ArgumentKind::Optional(name, None) => format!("\\{}", name),
+                ArgumentKind::Optional(name, Some(expr)) => {
+                    format!("\\{}:={}", name, self.expr(expr, 1))
+                }
+                ArgumentKind::Conditional(name, parameter) => {
+                    format!("\\{}?{}", name, self.parameter_reference(parameter))
+                }
+            };
+        }
+        text
+    }
+
+    fn parameter_reference(&self, parameter: &Parameter) -> String {
+        match &parameter.kind {
+            crate::ast::ParameterKind::Parameter(name) => name.clone(),
+            crate::ast::ParameterKind::ParameterElement(name, dim) => {
+                format!("{}{}", name, self.dimension(dim))
+            }
+            crate::ast::ParameterKind::ParameterComponent(inner, component) => {
+                format!("{}.{}", self.parameter_reference(inner), component)
+            }
+        }
+    }
+
+    fn variable(&self, variable: &Variable) -> String {
+        match &variable.kind {
+            VariableKind::Variable(name) => name.clone(),
+            VariableKind::VariableElement(name, dim) => format!("{}{}", name, self.dimension(dim)),
+            VariableKind::VariableComponent(inner, component) => {
+                format!("{}.{}", self.variable(inner), component)
+            }
+        }
+    }
+
+    fn dimension(&self, dim: &Dimension) -> String {
+        match &dim.kind {
+            DimensionKind::Dimension(exprs) => {
+                let exprs: Vec<String> = exprs.iter().map(|e| self.expr(e, 1)).collect();
+                format!("{{{}}}", exprs.join(","))
+            }
+            DimensionKind::DIM => "{<DIM>}".to_owned(),
+        }
+    }
+
+    /// Prints an expression, parenthesizing it if it binds less tightly than `min_precedence`.
+    fn expr(&self, expr: &Expr, min_precedence: u8) -> String {
+        let text = match &expr.kind {
+            ExprKind::Term(Term::String(value)) => {
+                self.original(expr).unwrap_or_else(|| quote_string(value))
+            }
+            ExprKind::Term(Term::Num(value)) => {
+                self.original(expr).unwrap_or_else(|| format_number(*value))
+            }
+            ExprKind::Term(Term::Bool(value)) => self.kw(if *value { "TRUE" } else { "FALSE" }),
+            ExprKind::Term(Term::Array(elements)) => {
+                let elements: Vec<String> = elements.iter().map(|e| self.expr(e, 1)).collect();
+                format!("[{}]", elements.join(&format!(",{}", BREAK)))
+            }
+            ExprKind::Term(Term::Var(variable)) => self.variable(variable),
+            ExprKind::FuncCall(name, arguments) => {
+                format!("{}({})", name, self.arguments(arguments))
+            }
+            ExprKind::Op(left, op, right) => {
+                let precedence = precedence(op);
+                format!(
+                    "{} {} {}",
+                    self.expr(left, precedence),
+                    self.operator(op),
+                    self.expr(right, precedence + 1)
+                )
+            }
+            ExprKind::UnaryOp(op, operand) => match op {
+                OpCode::Not => format!("{} {}", self.kw("NOT"), self.expr(operand, 2)),
+                // `-a + b` parses as `-(a + b)`, so the parentheses are kept for readers
+                _ => format!("{}{}", self.operator(op), self.expr(operand, 6)),
+            },
+            ExprKind::EXP | ExprKind::Error => "<EXP>".to_owned(),
+        };
+        if expr_precedence(expr) < min_precedence {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    /// Returns the source text of a literal, which keeps its original spelling.
+    fn original(&self, expr: &Expr) -> Option<String> {
+        self.source
+            .and_then(|source| source.get(expr.span.start..expr.span.end))
+            .map(str::to_owned)
+    }
+
+    fn operator(&self, op: &OpCode) -> String {
+        match op {
+            OpCode::Add => "+".to_owned(),
+            OpCode::Sub => "-".to_owned(),
+            OpCode::Mul => "*".to_owned(),
+            OpCode::Div => "/".to_owned(),
+            OpCode::DivInt => self.kw("DIV"),
+            OpCode::Mod => self.kw("MOD"),
+            OpCode::Lt => "<".to_owned(),
+            OpCode::Lte => "<=".to_owned(),
+            OpCode::Eq => "=".to_owned(),
+            OpCode::Gt => ">".to_owned(),
+            OpCode::Gte => ">=".to_owned(),
+            OpCode::Ne => "<>".to_owned(),
+            OpCode::And => self.kw("AND"),
+            OpCode::Or => self.kw("OR"),
+            OpCode::Xor => self.kw("XOR"),
+            OpCode::Not => self.kw("NOT"),
+        }
+    }
+}
+
+fn width(text: &str) -> usize {
+    text.chars()
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Splits a line at its [`BREAK`] markers. Each segment is paired with the bracket depth of the
+/// marker before it.
+fn break_depths(text: &str) -> Vec<(usize, &str)> {
+    let mut segments = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut segment_start = (0, 0);
+    for (offset, c) in text.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            BREAK if !in_string => {
+                segments.push((segment_start.0, &text[segment_start.1..offset]));
+                segment_start = (depth, offset + BREAK.len_utf8());
+            }
+            _ => {}
+        }
+    }
+    segments.push((segment_start.0, &text[segment_start.1..]));
+    segments
+}
+
+fn attribute_name(attribute: &ModuleAttribute) -> &'static str {
+    match attribute {
+        ModuleAttribute::SYSMODULE => "SYSMODULE",
+        ModuleAttribute::NOSTEPIN => "NOSTEPIN",
+        ModuleAttribute::VIEWONLY => "VIEWONLY",
+        ModuleAttribute::READONLY => "READONLY",
+        ModuleAttribute::NOVIEW => "NOVIEW",
+    }
+}
+
+/// The grammar level an operator is parsed at, from the loosest (`OR`) to the tightest (`*`).
+fn precedence(op: &OpCode) -> u8 {
+    match op {
+        OpCode::Or | OpCode::Xor | OpCode::Not => 1,
+        OpCode::And => 2,
+        OpCode::Lt | OpCode::Lte | OpCode::Eq | OpCode::Gt | OpCode::Gte | OpCode::Ne => 3,
+        OpCode::Add | OpCode::Sub => 4,
+        OpCode::Mul | OpCode::Div | OpCode::DivInt | OpCode::Mod => 5,
+    }
+}
+
+fn expr_precedence(expr: &Expr) -> u8 {
+    match &expr.kind {
+        ExprKind::Op(_, op, _) => precedence(op),
+        // Unary operators apply to a whole AND expression, e.g. `-a * b` is `-(a * b)`
+        ExprKind::UnaryOp(..) => 1,
+        _ => 6,
+    }
+}
+
+/// Quotes a string value, escaping quotes, backslashes and non-printable characters.
+fn quote_string(value: &str) -> String {
+    let mut text = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => text.push_str("\"\""),
+            '\\' => text.push_str("\\\\"),
+            c if (c as u32) < 0x20 || (0x7f..=0xff).contains(&(c as u32)) => {
+                text += &format!("\\{:02X}", c as u32)
+            }
+            c => text.push(c),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn format_number(number: Number) -> String {
+    let value = match number {
+        Number::Integer(value) => return value.to_string(),
+        Number::Real(value) => value,
+    };
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> ModuleInfo {
+        match parse_module(input).into_result().unwrap() {
+            Module::Module(module) => module,
+            Module::Error => panic!("expected a module"),
+        }
+    }
+
+    /// Formats `input` and checks that the result means the same, i.e. prints to the same
+    /// canonical form, and that formatting it again does not change it.
+    fn assert_round_trip(input: &str, options: &FormatOptions) -> String {
+        let formatted = format_source(input, options).unwrap();
+        let canonical = FormatOptions::default();
+        assert_eq!(
+            format_module(&parse(&formatted), &canonical),
+            format_module(&parse(input), &canonical)
+        );
+        assert_eq!(format_source(&formatted, options).unwrap(), formatted);
+        formatted
+    }
+
+    #[test]
+    fn round_trip_data_modules() {
+        for input in [
+            include_str!("../data/LOGGER.mod"),
+            include_str!("../data/SERVER.mod"),
+        ] {
+            assert_round_trip(input, &FormatOptions::default());
+            let options = FormatOptions {
+                indent: Indent::Tabs,
+                keyword_case: KeywordCase::Lower,
+                line_width: 40,
+            };
+            assert_round_trip(input, &options);
+        }
+    }
+
+    #[test]
+    fn format_layout_and_comments() {
+        let input = r#"module demo(SYSMODULE,noview)
+  ! Settings
+  local   var num  count:=0; ! counter
+  CONST string greeting:="Say ""hi""";
+
+
+  PROC main(num a,\switch fast|num slow)
+    if a>0 and (count=0 or count>10) then
+      count:=count+a*(2-a);
+    elseif NOT a=0 then
+      TPWrite "neg"\Num:=-(a+1);
+    else
+      MoveL Offs(p,0,0,1e3),v100,fine,tool0;
+    endif
+  ENDPROC
+  local func num twice(num x)
+    return 2*x;
+  endfunc
+  local trap stop
+  endtrap
+ENDMODULE"#;
+        let formatted = assert_round_trip(input, &FormatOptions::default());
+        assert_eq!(
+            formatted,
+            r#"MODULE demo(SYSMODULE, NOVIEW)
+    ! Settings
+    LOCAL VAR num count := 0; ! counter
+    CONST string greeting := "Say ""hi""";
+
+    PROC main(num a, \switch fast | num slow)
+        IF a > 0 AND (count = 0 OR count > 10) THEN
+            count := count + a * (2 - a);
+        ELSEIF NOT a = 0 THEN
+            TPWrite "neg" \Num:=-(a + 1);
+        ELSE
+            MoveL Offs(p, 0, 0, 1e3), v100, fine, tool0;
+        ENDIF
+    ENDPROC
+    LOCAL FUNC num twice(num x)
+        RETURN 2 * x;
+    ENDFUNC
+    LOCAL TRAP stop
+    ENDTRAP
+ENDMODULE
+"#
+        );
+    }
+
+    #[test]
+    fn format_keyword_case_and_indent() {
+        let input = r#"MODULE demo
+PROC main()
+FOR i FROM 1 TO 10 STEP 2 DO
+TEST i
+CASE 1, 3:
+WaitTime 1;
+DEFAULT:
+RETURN;
+ENDTEST
+ENDFOR
+ENDPROC
+ENDMODULE"#;
+        let options = FormatOptions {
+            indent: Indent::Spaces(2),
+            keyword_case: KeywordCase::Lower,
+            line_width: 100,
+        };
+        assert_eq!(
+            assert_round_trip(input, &options),
+            r#"module demo
+  proc main()
+    for i from 1 to 10 step 2 do
+      test i
+        case 1, 3:
+          WaitTime 1;
+        default:
+          return;
+      endtest
+    endfor
+  endproc
+endmodule
+"#
+        );
+    }
+
+    #[test]
+    fn format_breaks_long_lines() {
+        let input = r#"MODULE demo
+    PROC main()
+        MoveL Offs(pHome, 100, 200, 300), v1000, z50, tool0 \WObj:=wobj0;
+    ENDPROC
+ENDMODULE"#;
+        let options = FormatOptions {
+            line_width: 40,
+            ..FormatOptions::default()
+        };
+        assert_eq!(
+            assert_round_trip(input, &options),
+            r#"MODULE demo
+    PROC main()
+        MoveL Offs(pHome, 100, 200,
+            300), v1000, z50, tool0
+            \WObj:=wobj0;
+    ENDPROC
+ENDMODULE
+"#
+        );
+    }
+
+    #[test]
+    fn format_module_without_source() {
+        let module = parse(
+            r#"MODULE demo
+    VAR string s := "a\5Cb";
+    VAR num n := 0.50;
+    PROC main()
+        n := -(n - 1) * 2;
+    ENDPROC
+ENDMODULE"#,
+        );
+        assert_eq!(
+            format_module(&module, &FormatOptions::default()),
+            r#"MODULE demo
+    VAR string s := "a\\b";
+    VAR num n := 0.5;
+    PROC main()
+        n := -((n - 1) * 2);
+    ENDPROC
+ENDMODULE
+"#
+        );
+    }
+
+    #[test]
+    fn round_trip_placeholders() {
+        let input = r#"MODULE demo
+<TDN>
+<DDN>
+VAR num a{<DIM>} := <EXP>;
+PROC main(<PAR>)
+<STM>;
+<VAR> := <EXP>;
+MoveL <ARG>, <EXP>;
+TEST <EXP>
+<CSE>
+ENDTEST
+IF <EXP> THEN
+<STM>;
+ELSEIF a = 1 THEN <EIT>
+ENDIF
+ENDPROC
+<RDN>
+ENDMODULE"#;
+        assert_eq!(
+            assert_round_trip(input, &FormatOptions::default()),
+            r#"MODULE demo
+    <TDN>
+    <DDN>
+    VAR num a{<DIM>} := <EXP>;
+    PROC main(<PAR>)
+        <STM>;
+        <VAR> := <EXP>;
+        MoveL <EXP>;
+        TEST <EXP>
+            <CSE>
+        ENDTEST
+        IF <EXP> THEN
+            <STM>;
+        ELSEIF a = 1 THEN
+        ENDIF
+    ENDPROC
+    <RDN>
+ENDMODULE
+"#
+        );
+    }
+
+    #[test]
+    fn move_comments_out_of_statements() {
+        let input = "MODULE m\n    PROC p(num a, ! first\n        num b) ! head\n        x := a + ! second\n            b; ! end\n        y := ! last\n            b;\n    ENDPROC\nENDMODULE";
+        let formatted = format_source(input, &FormatOptions::default()).unwrap();
+        assert_eq!(
+            formatted,
+            r#"MODULE m
+    PROC p(num a, num b) ! head
+        ! first
+        x := a + b; ! end
+        ! second
+        y := b;
+        ! last
+    ENDPROC
+ENDMODULE
+"#
+        );
+        assert_eq!(
+            format_source(&formatted, &FormatOptions::default()).unwrap(),
+            formatted
+        );
+    }
+
+    #[test]
+    fn refuse_to_format_invalid_source() {
+        assert!(format_source(
+            "MODULE demo\n    x := ;\nENDMODULE",
+            &FormatOptions::default()
+        )
+        .is_err());
+    }
+}
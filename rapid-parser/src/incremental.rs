@@ -0,0 +1,661 @@
+// Incremental reparsing for editors. A `ParseCache` holds the source and parse of one document
+// version. Edits are merged into a single changed range, and only the module items (declarations
+// and routines) touching that range are parsed again. The items before it are reused as they are
+// and the items after it are moved by the change in length. Whenever the reparsed items do not
+// parse cleanly on their own, the whole module is parsed instead, so the result is always the
+// same as that of `parse_module`.
+
+use crate::ast::{
+    Argument, ArgumentKind, AssignmentTarget, DataDeclaration, Definition, Dimension,
+    DimensionKind, ErrorHandler, Expr, ExprKind, Identifier, Module,
+    OptionalParameterDeclarationType, OptionalParameterDeclarationTypeKind, Parameter,
+    ParameterDeclaration, ParameterDeclarationType, ParameterDeclarationTypeKind, ParameterKind,
+    RoutineDeclaration, Statement, StatementKind, Term, TestCase, TestCaseKind, TypeDefinition,
+    Variable, VariableKind,
+};
+use crate::lexer::Lexer;
+use crate::{parse_module, rapid, ParseError, Span, SyntaxError};
+
+/// Replaces `span` of the text with `replacement`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TextEdit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    pub fn new(span: Span, replacement: impl Into<String>) -> Self {
+        TextEdit {
+            span,
+            replacement: replacement.into(),
+        }
+    }
+}
+
+/// How a [`ParseCache::update`] brought the parse up to date.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Reparse {
+    /// The whole module was parsed again.
+    Full,
+    /// Only the module items in `span` (in the new text) were parsed again, and `reused` items
+    /// were kept from the previous parse.
+    Incremental { span: Span, reused: usize },
+}
+
+/// The edits were made to an older version of the document than the cached one.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct OutdatedVersion {
+    pub cached: i32,
+    pub given: i32,
+}
+
+impl std::fmt::Display for OutdatedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "version {} is not newer than the cached version {}",
+            self.given, self.cached
+        )
+    }
+}
+
+impl std::error::Error for OutdatedVersion {}
+
+/// The parse of one version of a document, updated incrementally as the document is edited.
+#[derive(Debug)]
+pub struct ParseCache {
+    version: i32,
+    source: String,
+    module: Module,
+    errors: Vec<SyntaxError>,
+}
+
+impl ParseCache {
+    /// Parses `source` as the given version of a document.
+    pub fn new(version: i32, source: impl Into<String>) -> Self {
+        let source = source.into();
+        let (module, errors) = full_parse(&source);
+        ParseCache {
+            version,
+            source,
+            module,
+            errors,
+        }
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+
+    pub fn errors(&self) -> &[SyntaxError] {
+        &self.errors
+    }
+
+    /// Applies `edits` to the cached source to get `version` of the document and updates the parse.
+    ///
+    /// Like the content changes of the language server protocol, each edit applies to the text as
+    /// left by the edits before it. Offsets out of range or inside a character are clamped.
+    pub fn update(&mut self, version: i32, edits: &[TextEdit]) -> Result<Reparse, OutdatedVersion> {
+        if version <= self.version {
+            return Err(OutdatedVersion {
+                cached: self.version,
+                given: version,
+            });
+        }
+        self.version = version;
+        let Some(change) = self.apply(edits) else {
+            return Ok(Reparse::Incremental {
+                span: Span::default(),
+                reused: self.statements().len(),
+            });
+        };
+        if let Some(reparse) = self.reparse(&change) {
+            return Ok(reparse);
+        }
+        (self.module, self.errors) = full_parse(&self.source);
+        Ok(Reparse::Full)
+    }
+
+    fn statements(&self) -> &[Statement] {
+        match &self.module {
+            Module::Module(module) => &module.statements,
+            Module::Error => &[],
+        }
+    }
+
+    /// Applies the edits to the source and returns the range they changed, or `None` if there are
+    /// no edits.
+    fn apply(&mut self, edits: &[TextEdit]) -> Option<Change> {
+        let mut change: Option<Change> = None;
+        for edit in edits {
+            let start = self.floor(edit.span.start);
+            let end = self.floor(edit.span.end).max(start);
+            self.source.replace_range(start..end, &edit.replacement);
+            let inserted_end = start + edit.replacement.len();
+            change = Some(match change {
+                None => Change {
+                    old: Span::new(start, end),
+                    new: Span::new(start, inserted_end),
+                },
+                Some(change) => {
+                    // Map the end of the edit back into the text before the first edit
+                    let old_end = if end > change.new.end {
+                        change.old.end + (end - change.new.end)
+                    } else {
+                        change.old.end
+                    };
+                    let new_end = change.new.end.max(end) + edit.replacement.len() - (end - start);
+                    Change {
+                        old: Span::new(change.old.start.min(start), old_end),
+                        new: Span::new(change.new.start.min(start), new_end),
+                    }
+                }
+            });
+        }
+        change
+    }
+
+    /// Returns the largest character boundary in the source not after `offset`.
+    fn floor(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+
+    /// Reparses the module items touching `change`. Returns `None` if the change reaches into the
+    /// module header or `ENDMODULE`, or if the items do not parse without errors on their own.
+    fn reparse(&mut self, change: &Change) -> Option<Reparse> {
+        let Module::Module(module) = &mut self.module else {
+            return None;
+        };
+        let delta = change.new.end as isize - change.old.end as isize;
+        let body_start = module.attributes_span.end;
+        let body_end = module.span.end.checked_sub("ENDMODULE".len())?;
+        if change.old.start <= body_start || change.old.end >= body_end {
+            return None;
+        }
+
+        // A comment started by the change runs to the end of its line
+        let line_end = match self.source[change.new.end..].find('\n') {
+            Some(newline) => change.new.end + newline + 1,
+            None => self.source.len(),
+        };
+        let mut old = Span::new(change.old.start, (line_end as isize - delta) as usize);
+        if old.end >= body_end {
+            return None;
+        }
+        // Items touching the change are reparsed, as the change may extend them
+        let first = module
+            .statements
+            .iter()
+            .position(|statement| statement.span.end >= old.start)
+            .unwrap_or(module.statements.len());
+        let last = module.statements[first..]
+            .iter()
+            .position(|statement| statement.span.start > old.end)
+            .map_or(module.statements.len(), |count| first + count);
+        if last > first {
+            old.start = old.start.min(module.statements[first].span.start);
+            old.end = old.end.max(module.statements[last - 1].span.end);
+        }
+        let new = Span::new(old.start, (old.end as isize + delta) as usize);
+
+        let mut errors = Vec::new();
+        let mut statements = rapid::StatementsParser::new()
+            .parse(
+                &mut errors,
+                Lexer::new(&self.source[new.start..new.end]).spanned(),
+            )
+            .ok()?;
+        if !errors.is_empty() {
+            return None;
+        }
+        for statement in &mut statements {
+            statement.shift(new.start as isize);
+        }
+        for statement in &mut module.statements[last..] {
+            statement.shift(delta);
+        }
+        let reused = module.statements.len() - (last - first);
+        module.statements.splice(first..last, statements);
+        module.span.end = (module.span.end as isize + delta) as usize;
+
+        // Errors of the reparsed items are gone, the ones after them move with the text
+        self.errors.retain(|error| {
+            let span = error_span(error);
+            span.end <= old.start || span.start >= old.end
+        });
+        for error in &mut self.errors {
+            if error_span(error).start >= old.end {
+                shift_error(error, delta);
+            }
+        }
+        Some(Reparse::Incremental { span: new, reused })
+    }
+}
+
+/// The text changed by a series of edits, before and after them.
+struct Change {
+    old: Span,
+    new: Span,
+}
+
+fn full_parse(source: &str) -> (Module, Vec<SyntaxError>) {
+    let parsed = parse_module(source);
+    let errors = parsed
+        .errors
+        .into_iter()
+        .map(|error| error.map_token(|token| token.1.to_owned()))
+        .collect();
+    (parsed.module, errors)
+}
+
+fn error_span(error: &SyntaxError) -> Span {
+    match error {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => {
+            Span::new(*location, *location)
+        }
+        ParseError::UnrecognizedToken { token, .. } | ParseError::ExtraToken { token } => {
+            Span::new(token.0, token.2)
+        }
+        ParseError::User { error } => error.span,
+    }
+}
+
+fn shift_error(error: &mut SyntaxError, delta: isize) {
+    match error {
+        ParseError::InvalidToken { location } | ParseError::UnrecognizedEof { location, .. } => {
+            *location = (*location as isize + delta) as usize
+        }
+        ParseError::UnrecognizedToken { token, .. } | ParseError::ExtraToken { token } => {
+            token.0 = (token.0 as isize + delta) as usize;
+            token.2 = (token.2 as isize + delta) as usize;
+        }
+        ParseError::User { error } => error.span.shift(delta),
+    }
+}
+
+/// Moves every span in a syntax tree by `delta` bytes.
+trait Shift {
+    fn shift(&mut self, delta: isize);
+}
+
+impl Shift for Span {
+    fn shift(&mut self, delta: isize) {
+        self.start = (self.start as isize + delta) as usize;
+        self.end = (self.end as isize + delta) as usize;
+    }
+}
+
+impl<T: Shift> Shift for Vec<T> {
+    fn shift(&mut self, delta: isize) {
+        for item in self {
+            item.shift(delta);
+        }
+    }
+}
+
+impl<T: Shift> Shift for Option<T> {
+    fn shift(&mut self, delta: isize) {
+        if let Some(item) = self {
+            item.shift(delta);
+        }
+    }
+}
+
+impl<T: Shift> Shift for Box<T> {
+    fn shift(&mut self, delta: isize) {
+        (**self).shift(delta);
+    }
+}
+
+impl Shift for Statement {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            StatementKind::TypeDefinition(TypeDefinition::RecordDefinition(_, record)) => {
+                record.name_span.shift(delta);
+                record.span.shift(delta);
+                for component in &mut record.components {
+                    component.data_type_span.shift(delta);
+                    component.name_span.shift(delta);
+                    component.span.shift(delta);
+                }
+            }
+            StatementKind::TypeDefinition(TypeDefinition::AliasDefinition(_, alias)) => {
+                alias.name_span.shift(delta);
+                alias.data_type_span.shift(delta);
+                alias.span.shift(delta);
+            }
+            StatementKind::DataDeclaration(DataDeclaration::VarDeclaration(_, decl)) => {
+                decl.data_type_span.shift(delta);
+                decl.definition.shift(delta);
+                decl.span.shift(delta);
+            }
+            StatementKind::RoutineDeclaration(routine) => routine.shift(delta),
+            StatementKind::Assignment(target, expr) => {
+                if let AssignmentTarget::Variable(variable) = target {
+                    variable.shift(delta);
+                }
+                expr.shift(delta);
+            }
+            StatementKind::ProcCall(expr, arguments) => {
+                expr.shift(delta);
+                arguments.shift(delta);
+            }
+            StatementKind::Goto(label) => label.shift(delta),
+            StatementKind::Return(expr) | StatementKind::Raise(expr) => expr.shift(delta),
+            StatementKind::Connect(interrupt, trap) => {
+                interrupt.shift(delta);
+                trap.shift(delta);
+            }
+            StatementKind::If(condition, statements, else_ifs, else_statements) => {
+                condition.shift(delta);
+                statements.shift(delta);
+                for (condition, statements) in else_ifs {
+                    condition.shift(delta);
+                    statements.shift(delta);
+                }
+                else_statements.shift(delta);
+            }
+            StatementKind::For(variable, from, to, step, statements) => {
+                variable.shift(delta);
+                from.shift(delta);
+                to.shift(delta);
+                step.shift(delta);
+                statements.shift(delta);
+            }
+            StatementKind::While(condition, statements) => {
+                condition.shift(delta);
+                statements.shift(delta);
+            }
+            StatementKind::Test(expr, cases, default) => {
+                expr.shift(delta);
+                cases.shift(delta);
+                default.shift(delta);
+            }
+            StatementKind::TypeDefinition(TypeDefinition::TDN)
+            | StatementKind::DataDeclaration(DataDeclaration::DDN)
+            | StatementKind::Label(_)
+            | StatementKind::Exit
+            | StatementKind::Retry
+            | StatementKind::TryNext
+            | StatementKind::Comment(_)
+            | StatementKind::SMT
+            | StatementKind::Error => {}
+        }
+    }
+}
+
+impl Shift for RoutineDeclaration {
+    fn shift(&mut self, delta: isize) {
+        match self {
+            RoutineDeclaration::ProcDeclaration(proc) => {
+                proc.name_span.shift(delta);
+                proc.parameters.shift(delta);
+                proc.statements.shift(delta);
+                proc.backward_handler.shift(delta);
+                proc.error_handler.shift(delta);
+                proc.undo_handler.shift(delta);
+                proc.span.shift(delta);
+            }
+            RoutineDeclaration::FuncDeclaration(func) => {
+                func.data_type_span.shift(delta);
+                func.name_span.shift(delta);
+                func.parameters.shift(delta);
+                func.statements.shift(delta);
+                func.error_handler.shift(delta);
+                func.undo_handler.shift(delta);
+                func.span.shift(delta);
+            }
+            RoutineDeclaration::TrapDeclaration(trap) => {
+                trap.name_span.shift(delta);
+                trap.statements.shift(delta);
+                trap.error_handler.shift(delta);
+                trap.undo_handler.shift(delta);
+                trap.span.shift(delta);
+            }
+            RoutineDeclaration::RDN | RoutineDeclaration::Error => {}
+        }
+    }
+}
+
+impl Shift for ParameterDeclarationType {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            ParameterDeclarationTypeKind::ParameterDeclaration(parameter) => parameter.shift(delta),
+            ParameterDeclarationTypeKind::OptionalParameterDeclaration(alternatives) => {
+                alternatives.shift(delta)
+            }
+            ParameterDeclarationTypeKind::PAR | ParameterDeclarationTypeKind::Error => {}
+        }
+    }
+}
+
+impl Shift for OptionalParameterDeclarationType {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        if let OptionalParameterDeclarationTypeKind::OptionalParameterDeclaration(parameter) =
+            &mut self.kind
+        {
+            parameter.shift(delta);
+        }
+    }
+}
+
+impl Shift for ParameterDeclaration {
+    fn shift(&mut self, delta: isize) {
+        self.data_type_span.shift(delta);
+        self.name_span.shift(delta);
+        self.dim.shift(delta);
+        self.span.shift(delta);
+    }
+}
+
+impl Shift for ErrorHandler {
+    fn shift(&mut self, delta: isize) {
+        self.numbers.shift(delta);
+        self.statements.shift(delta);
+        self.span.shift(delta);
+    }
+}
+
+impl Shift for Definition {
+    fn shift(&mut self, delta: isize) {
+        self.identifier_span.shift(delta);
+        self.expression.shift(delta);
+        self.dim.shift(delta);
+        self.span.shift(delta);
+    }
+}
+
+impl Shift for Identifier {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+    }
+}
+
+impl Shift for TestCase {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        if let TestCaseKind::Case(values, statements) = &mut self.kind {
+            values.shift(delta);
+            statements.shift(delta);
+        }
+    }
+}
+
+impl Shift for Expr {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            ExprKind::Term(Term::Array(elements)) => elements.shift(delta),
+            ExprKind::Term(Term::Var(variable)) => variable.shift(delta),
+            ExprKind::Op(left, _, right) => {
+                left.shift(delta);
+                right.shift(delta);
+            }
+            ExprKind::FuncCall(_, arguments) => arguments.shift(delta),
+            ExprKind::UnaryOp(_, operand) => operand.shift(delta),
+            ExprKind::Term(_) | ExprKind::EXP | ExprKind::Error => {}
+        }
+    }
+}
+
+impl Shift for Variable {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            VariableKind::Variable(_) => {}
+            VariableKind::VariableElement(_, dimension) => dimension.shift(delta),
+            VariableKind::VariableComponent(variable, _) => variable.shift(delta),
+        }
+    }
+}
+
+impl Shift for Parameter {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            ParameterKind::Parameter(_) => {}
+            ParameterKind::ParameterElement(_, dimension) => dimension.shift(delta),
+            ParameterKind::ParameterComponent(parameter, _) => parameter.shift(delta),
+        }
+    }
+}
+
+impl Shift for Argument {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        match &mut self.kind {
+            ArgumentKind::Required(_, expr) => expr.shift(delta),
+            ArgumentKind::Optional(_, expr) => expr.shift(delta),
+            ArgumentKind::Conditional(_, parameter) => parameter.shift(delta),
+        }
+    }
+}
+
+impl Shift for Dimension {
+    fn shift(&mut self, delta: isize) {
+        self.span.shift(delta);
+        if let DimensionKind::Dimension(sizes) = &mut self.kind {
+            sizes.shift(delta);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "MODULE demo\n    VAR num count := 0;\n\n    PROC first()\n        count := count + 1;\n    ENDPROC\n\n    ! Says hello\n    PROC second()\n        TPWrite \"hello\";\n    ENDPROC\nENDMODULE\n";
+
+    fn edit(source: &str, find: &str, replacement: &str) -> TextEdit {
+        let start = source.find(find).unwrap();
+        TextEdit::new(Span::new(start, start + find.len()), replacement)
+    }
+
+    /// Checks that the cache holds exactly what a full parse of its source gives.
+    fn assert_matches_full_parse(cache: &ParseCache) {
+        let (module, errors) = full_parse(cache.source());
+        assert_eq!(cache.module(), &module);
+        assert_eq!(cache.errors(), errors.as_slice());
+    }
+
+    #[test]
+    fn reparse_only_the_edited_routine() {
+        let mut cache = ParseCache::new(1, SOURCE);
+        let reparse = cache
+            .update(2, &[edit(SOURCE, "count + 1", "count + 10")])
+            .unwrap();
+        let Reparse::Incremental { span, reused } = reparse else {
+            panic!("expected an incremental reparse, got {:?}", reparse);
+        };
+        assert_eq!(reused, 3);
+        assert!(cache.source()[span.start..span.end].starts_with("PROC first()"));
+        assert!(cache.source()[span.start..span.end].ends_with("ENDPROC"));
+        assert_matches_full_parse(&cache);
+
+        // Edits apply one after the other, and shift everything after them
+        let source = cache.source().to_owned();
+        let hello = edit(&source, "\"hello\"", "\"hi\"");
+        let count = edit(&source, "VAR num count", "VAR num counter");
+        assert!(matches!(
+            cache.update(3, &[hello, count]),
+            Ok(Reparse::Incremental { .. })
+        ));
+        assert!(cache.source().contains("TPWrite \"hi\";"));
+        assert_matches_full_parse(&cache);
+    }
+
+    #[test]
+    fn fall_back_to_a_full_parse() {
+        let mut cache = ParseCache::new(1, SOURCE);
+        // Without its ENDPROC the first routine runs into the next one
+        assert_eq!(
+            cache.update(2, &[edit(SOURCE, "ENDPROC", "")]),
+            Ok(Reparse::Full)
+        );
+        assert!(!cache.errors().is_empty());
+        assert_matches_full_parse(&cache);
+
+        // Renaming the module touches the header
+        let source = cache.source().to_owned();
+        assert_eq!(
+            cache.update(3, &[edit(&source, "demo", "main")]),
+            Ok(Reparse::Full)
+        );
+        assert_matches_full_parse(&cache);
+
+        assert_eq!(
+            cache.update(3, &[]),
+            Err(OutdatedVersion {
+                cached: 3,
+                given: 3
+            })
+        );
+    }
+
+    #[test]
+    fn comment_out_the_rest_of_a_line() {
+        let source = "MODULE demo\n    VAR num a; VAR num b;\nENDMODULE";
+        let mut cache = ParseCache::new(1, source);
+        let start = source.find(" VAR num b").unwrap();
+        cache
+            .update(2, &[TextEdit::new(Span::new(start, start), " !")])
+            .unwrap();
+        assert_matches_full_parse(&cache);
+    }
+
+    #[test]
+    fn keep_errors_outside_the_reparsed_items() {
+        let source = "MODULE demo\n    PROC first()\n        x := ;\n    ENDPROC\n    PROC second()\n        y := 1;\n    ENDPROC\n    PROC third()\n        z := ;\n    ENDPROC\nENDMODULE";
+        let mut cache = ParseCache::new(1, source);
+        assert_eq!(cache.errors().len(), 2);
+        let reparse = cache
+            .update(2, &[edit(source, "y := 1", "y := 12")])
+            .unwrap();
+        assert!(matches!(reparse, Reparse::Incremental { reused: 2, .. }));
+        assert_matches_full_parse(&cache);
+
+        // Fixing an error drops it
+        let source = cache.source().to_owned();
+        let reparse = cache
+            .update(3, &[edit(&source, "z := ;", "z := 3;")])
+            .unwrap();
+        assert!(matches!(reparse, Reparse::Incremental { .. }));
+        assert_eq!(cache.errors().len(), 1);
+        assert_matches_full_parse(&cache);
+    }
+}
@@ -0,0 +1,530 @@
+// The tokenizer shared by the parsers and by editors. `tokenize` splits the source into tokens of
+// every kind, trivia included, for the concrete syntax tree and for highlighting; `Lexer` hands the
+// significant tokens to the LALRPOP parser.
+
+use std::fmt;
+
+pub use crate::cst::SyntaxKind;
+use crate::{Span, UserError};
+
+/// A token of the source text. Every byte of the source belongs to exactly one token, including
+/// whitespace and comments.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Token {
+    pub kind: SyntaxKind,
+    pub span: Span,
+}
+
+/// A significant token with its text, as handed to the parser.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Lexeme<'input>(pub SyntaxKind, pub &'input str);
+
+impl fmt::Display for Lexeme<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.1)
+    }
+}
+
+/// RAPID keywords in upper case with their kinds, for editors to offer and highlight. Keywords are
+/// matched regardless of case.
+pub const KEYWORDS: &[(&str, SyntaxKind)] = &[
+    ("ALIAS", SyntaxKind::AliasKw),
+    ("AND", SyntaxKind::AndKw),
+    ("BACKWARD", SyntaxKind::BackwardKw),
+    ("CASE", SyntaxKind::CaseKw),
+    ("CATCH", SyntaxKind::CatchKw),
+    ("CONNECT", SyntaxKind::ConnectKw),
+    ("CONST", SyntaxKind::ConstKw),
+    ("DEFAULT", SyntaxKind::DefaultKw),
+    ("DIV", SyntaxKind::DivKw),
+    ("DO", SyntaxKind::DoKw),
+    ("ELSE", SyntaxKind::ElseKw),
+    ("ELSEIF", SyntaxKind::ElseIfKw),
+    ("ENDFOR", SyntaxKind::EndForKw),
+    ("ENDFUNC", SyntaxKind::EndFuncKw),
+    ("ENDIF", SyntaxKind::EndIfKw),
+    ("ENDMODULE", SyntaxKind::EndModuleKw),
+    ("ENDPROC", SyntaxKind::EndProcKw),
+    ("ENDRECORD", SyntaxKind::EndRecordKw),
+    ("ENDTASK", SyntaxKind::EndTaskKw),
+    ("ENDTEST", SyntaxKind::EndTestKw),
+    ("ENDTRAP", SyntaxKind::EndTrapKw),
+    ("ENDWHILE", SyntaxKind::EndWhileKw),
+    ("ERROR", SyntaxKind::ErrorKw),
+    ("EXIT", SyntaxKind::ExitKw),
+    ("FALSE", SyntaxKind::FalseKw),
+    ("FOR", SyntaxKind::ForKw),
+    ("FROM", SyntaxKind::FromKw),
+    ("FUNC", SyntaxKind::FuncKw),
+    ("GOTO", SyntaxKind::GotoKw),
+    ("IF", SyntaxKind::IfKw),
+    ("INOUT", SyntaxKind::InoutKw),
+    ("LOCAL", SyntaxKind::LocalKw),
+    ("MOD", SyntaxKind::ModKw),
+    ("MODULE", SyntaxKind::ModuleKw),
+    ("NOSTEPIN", SyntaxKind::NostepinKw),
+    ("NOT", SyntaxKind::NotKw),
+    ("NOVIEW", SyntaxKind::NoviewKw),
+    ("OR", SyntaxKind::OrKw),
+    ("PERS", SyntaxKind::PersKw),
+    ("PROC", SyntaxKind::ProcKw),
+    ("RAISE", SyntaxKind::RaiseKw),
+    ("READONLY", SyntaxKind::ReadonlyKw),
+    ("RECORD", SyntaxKind::RecordKw),
+    ("RETRY", SyntaxKind::RetryKw),
+    ("RETURN", SyntaxKind::ReturnKw),
+    ("STEP", SyntaxKind::StepKw),
+    ("SWITCH", SyntaxKind::SwitchKw),
+    ("SYSMODULE", SyntaxKind::SysmoduleKw),
+    ("TASK", SyntaxKind::TaskKw),
+    ("TEST", SyntaxKind::TestKw),
+    ("THEN", SyntaxKind::ThenKw),
+    ("TO", SyntaxKind::ToKw),
+    ("TRAP", SyntaxKind::TrapKw),
+    ("TRUE", SyntaxKind::TrueKw),
+    ("TRYNEXT", SyntaxKind::TrynextKw),
+    ("UNDO", SyntaxKind::UndoKw),
+    ("VAR", SyntaxKind::VarKw),
+    ("VIEWONLY", SyntaxKind::ViewonlyKw),
+    ("WHILE", SyntaxKind::WhileKw),
+    ("WITH", SyntaxKind::WithKw),
+    ("XOR", SyntaxKind::XorKw),
+];
+
+/// Returns the keyword kind of an identifier, or `None` if it is not a keyword.
+pub fn keyword(text: &str) -> Option<SyntaxKind> {
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| keyword.eq_ignore_ascii_case(text))
+        .map(|(_, kind)| *kind)
+}
+
+/// Splits the source into tokens. The tokens cover the whole source without gaps; characters that
+/// cannot start a token become `Error` tokens.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    Lexer::new(source).collect()
+}
+
+/// An iterator over the tokens of a source, see `tokenize`.
+#[derive(Debug, Clone)]
+pub struct Lexer<'input> {
+    source: &'input str,
+    offset: usize,
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(source: &'input str) -> Self {
+        Lexer { source, offset: 0 }
+    }
+
+    /// Returns the significant tokens in the form the LALRPOP parser takes them, starting at a
+    /// statement. Whitespace is skipped. Comments are statements of the AST, so a comment where a
+    /// statement may start is passed on with the line break ending it, if any; other comments,
+    /// such as those in parameter lists, expressions and records, are skipped like whitespace.
+    /// `Error` tokens are passed on for the parser to report and recover from.
+    pub fn spanned(
+        self,
+    ) -> impl Iterator<Item = Result<(usize, Lexeme<'input>, usize), UserError>> {
+        let source = self.source;
+        let mut tokens = self.peekable();
+        let mut statements = Statements::default();
+        std::iter::from_fn(move || loop {
+            let token = tokens.next()?;
+            let Span { start, mut end } = token.span;
+            let kind = token.kind;
+            match kind {
+                SyntaxKind::Whitespace => continue,
+                SyntaxKind::Comment if !statements.at_start => continue,
+                SyntaxKind::Comment => {
+                    if let Some(next) = tokens.peek_mut() {
+                        // A comment is followed by whitespace starting with its line break
+                        end += 1;
+                        next.span.start += 1;
+                        if next.span.start == next.span.end {
+                            tokens.next();
+                        }
+                    }
+                }
+                _ => {}
+            }
+            statements.advance(kind, &source[start..end]);
+            return Some(Ok((start, Lexeme(kind, &source[start..end]), end)));
+        })
+    }
+}
+
+/// Returns the comments that `Lexer::spanned` skips since no statement may start there, such as
+/// comments in parameter lists and expressions.
+pub(crate) fn skipped_comments(source: &str) -> Vec<Span> {
+    let mut statements = Statements::default();
+    let mut skipped = Vec::new();
+    for token in Lexer::new(source) {
+        match token.kind {
+            SyntaxKind::Whitespace => {}
+            SyntaxKind::Comment if !statements.at_start => skipped.push(token.span),
+            kind => statements.advance(kind, &source[token.span.start..token.span.end]),
+        }
+    }
+    skipped
+}
+
+/// Follows the significant tokens closely enough to tell where a statement may start, which is
+/// where the grammar takes a comment: after a `;`, a label or `CASE` colon, a keyword opening a
+/// block such as `THEN`, the end of a routine, and the head of a module or routine.
+#[derive(Debug)]
+struct Statements {
+    at_start: bool,
+    /// Statements in a `RECORD` are components, which cannot be comments.
+    in_record: bool,
+    /// After `MODULE` and `TRAP`: the next name ends the head.
+    before_name: bool,
+    /// After `PROC`, `FUNC`, the name of a module and `ERROR`: the next `(` opens a list ending
+    /// the head.
+    before_list: bool,
+    /// The nesting of parentheses, and the depth of the list ending a head.
+    depth: usize,
+    list: Option<usize>,
+}
+
+impl Default for Statements {
+    fn default() -> Self {
+        Statements {
+            at_start: true,
+            in_record: false,
+            before_name: false,
+            before_list: false,
+            depth: 0,
+            list: None,
+        }
+    }
+}
+
+impl Statements {
+    fn advance(&mut self, kind: SyntaxKind, text: &str) {
+        let before_list = std::mem::take(&mut self.before_list);
+        self.at_start = false;
+        match kind {
+            SyntaxKind::Comment => self.at_start = true,
+            SyntaxKind::Semicolon => self.at_start = !self.in_record,
+            SyntaxKind::Colon
+            | SyntaxKind::ThenKw
+            | SyntaxKind::ElseKw
+            | SyntaxKind::DoKw
+            | SyntaxKind::BackwardKw
+            | SyntaxKind::UndoKw
+            | SyntaxKind::EndProcKw
+            | SyntaxKind::EndFuncKw
+            | SyntaxKind::EndTrapKw => self.at_start = true,
+            SyntaxKind::RecordKw => self.in_record = true,
+            SyntaxKind::EndRecordKw => {
+                self.in_record = false;
+                self.at_start = true;
+            }
+            SyntaxKind::ModuleKw | SyntaxKind::TrapKw => self.before_name = true,
+            SyntaxKind::ProcKw | SyntaxKind::FuncKw => self.before_list = true,
+            SyntaxKind::ErrorKw => {
+                self.at_start = true;
+                self.before_list = true;
+            }
+            SyntaxKind::Ident if std::mem::take(&mut self.before_name) => {
+                self.at_start = true;
+                self.before_list = true;
+            }
+            SyntaxKind::Ident => self.before_list = before_list,
+            SyntaxKind::Placeholder => {
+                self.at_start = matches!(text, "<RDN>" | "<TDN>" | "<DDN>");
+            }
+            SyntaxKind::LParen => {
+                if before_list {
+                    self.list = Some(self.depth);
+                }
+                self.depth += 1;
+            }
+            SyntaxKind::RParen => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.list == Some(self.depth) {
+                    self.list = None;
+                    self.at_start = true;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.offset >= self.source.len() {
+            return None;
+        }
+        let (kind, len) = next_token(&self.source[self.offset..]);
+        let span = Span::new(self.offset, self.offset + len);
+        self.offset += len;
+        Some(Token { kind, span })
+    }
+}
+
+fn next_token(rest: &str) -> (SyntaxKind, usize) {
+    let bytes = rest.as_bytes();
+    let first = rest.chars().next().unwrap();
+    let take_while = |start: usize, predicate: fn(u8) -> bool| {
+        start
+            + bytes[start..]
+                .iter()
+                .take_while(|byte| predicate(**byte))
+                .count()
+    };
+    match first {
+        c if c.is_whitespace() => {
+            let len = rest
+                .char_indices()
+                .find(|(_, c)| !c.is_whitespace())
+                .map_or(rest.len(), |(i, _)| i);
+            (SyntaxKind::Whitespace, len)
+        }
+        '!' => (SyntaxKind::Comment, rest.find('\n').unwrap_or(rest.len())),
+        '"' => {
+            // Doubled quotes stand for a quote, strings cannot run over a line. An unterminated
+            // string ends at the last doubled quote, if any, like the longest valid string.
+            let mut i = 1;
+            let mut last_pair = None;
+            loop {
+                match bytes.get(i) {
+                    Some(b'"') if bytes.get(i + 1) == Some(&b'"') => {
+                        last_pair = Some(i);
+                        i += 2;
+                    }
+                    Some(b'"') => return (SyntaxKind::String, i + 1),
+                    Some(b'\n') | None => {
+                        return match last_pair {
+                            Some(pair) => (SyntaxKind::String, pair + 1),
+                            None => (SyntaxKind::Error, i),
+                        }
+                    }
+                    Some(_) => i += 1,
+                }
+            }
+        }
+        c if c.is_ascii_alphabetic() || c == '_' => {
+            let len = take_while(1, |b| b.is_ascii_alphanumeric() || b == b'_');
+            (keyword(&rest[..len]).unwrap_or(SyntaxKind::Ident), len)
+        }
+        c if c.is_ascii_digit() || (c == '.' && bytes.get(1).is_some_and(u8::is_ascii_digit)) => {
+            if let Some(is_digit) = radix_digits(bytes) {
+                return (SyntaxKind::Number, take_while(2, is_digit));
+            }
+            let mut len = take_while(0, |b| b.is_ascii_digit());
+            if bytes.get(len) == Some(&b'.') {
+                len = take_while(len + 1, |b| b.is_ascii_digit());
+            }
+            if matches!(bytes.get(len), Some(b'e' | b'E')) {
+                let sign = usize::from(matches!(bytes.get(len + 1), Some(b'+' | b'-')));
+                if bytes.get(len + 1 + sign).is_some_and(u8::is_ascii_digit) {
+                    len = take_while(len + 1 + sign, |b| b.is_ascii_digit());
+                }
+            }
+            (SyntaxKind::Number, len)
+        }
+        '<' if is_placeholder(bytes) => (SyntaxKind::Placeholder, 5),
+        _ => {
+            let two = rest.get(..2).unwrap_or("");
+            let kind = match two {
+                ":=" => Some(SyntaxKind::Assign),
+                "<=" => Some(SyntaxKind::LtEq),
+                ">=" => Some(SyntaxKind::GtEq),
+                "<>" => Some(SyntaxKind::NotEq),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                return (kind, 2);
+            }
+            let kind = match first {
+                '(' => SyntaxKind::LParen,
+                ')' => SyntaxKind::RParen,
+                '[' => SyntaxKind::LBrack,
+                ']' => SyntaxKind::RBrack,
+                '{' => SyntaxKind::LCurly,
+                '}' => SyntaxKind::RCurly,
+                ',' => SyntaxKind::Comma,
+                ';' => SyntaxKind::Semicolon,
+                ':' => SyntaxKind::Colon,
+                '.' => SyntaxKind::Dot,
+                '\\' => SyntaxKind::Backslash,
+                '?' => SyntaxKind::Question,
+                '|' => SyntaxKind::Pipe,
+                '%' => SyntaxKind::Percent,
+                '+' => SyntaxKind::Plus,
+                '-' => SyntaxKind::Minus,
+                '*' => SyntaxKind::Star,
+                '/' => SyntaxKind::Slash,
+                '<' => SyntaxKind::Lt,
+                '>' => SyntaxKind::Gt,
+                '=' => SyntaxKind::Eq,
+                _ => SyntaxKind::Error,
+            };
+            (kind, first.len_utf8())
+        }
+    }
+}
+
+/// Returns the digits allowed after a `0x`, `0o` or `0b` prefix, if the bytes start with one
+/// followed by a digit.
+fn radix_digits(bytes: &[u8]) -> Option<fn(u8) -> bool> {
+    if bytes.first() != Some(&b'0') {
+        return None;
+    }
+    let is_digit: fn(u8) -> bool = match bytes.get(1)? {
+        b'x' | b'X' => |b| b.is_ascii_hexdigit(),
+        b'o' | b'O' => |b| matches!(b, b'0'..=b'7'),
+        b'b' | b'B' => |b| matches!(b, b'0' | b'1'),
+        _ => return None,
+    };
+    bytes
+        .get(2)
+        .is_some_and(|b| is_digit(*b))
+        .then_some(is_digit)
+}
+
+/// Placeholders such as `<EXP>` stand for parts of a program that are still to be written.
+const PLACEHOLDERS: &[&[u8]] = &[
+    b"<ALT>", b"<ARG>", b"<CSE>", b"<DDN>", b"<DIM>", b"<EIT>", b"<EXP>", b"<PAR>", b"<RDN>",
+    b"<STM>", b"<TDN>", b"<VAR>",
+];
+
+fn is_placeholder(bytes: &[u8]) -> bool {
+    PLACEHOLDERS
+        .iter()
+        .any(|placeholder| bytes.starts_with(placeholder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(SyntaxKind, &str)> {
+        tokenize(source)
+            .into_iter()
+            .map(|token| (token.kind, &source[token.span.start..token.span.end]))
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_losslessly() {
+        assert_eq!(
+            kinds("Var num x{2}:=[1.5E-3, \"a\"\"b\"]; ! done\n<EXP> <> ä"),
+            vec![
+                (SyntaxKind::VarKw, "Var"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Ident, "num"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Ident, "x"),
+                (SyntaxKind::LCurly, "{"),
+                (SyntaxKind::Number, "2"),
+                (SyntaxKind::RCurly, "}"),
+                (SyntaxKind::Assign, ":="),
+                (SyntaxKind::LBrack, "["),
+                (SyntaxKind::Number, "1.5E-3"),
+                (SyntaxKind::Comma, ","),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::String, "\"a\"\"b\""),
+                (SyntaxKind::RBrack, "]"),
+                (SyntaxKind::Semicolon, ";"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Comment, "! done"),
+                (SyntaxKind::Whitespace, "\n"),
+                (SyntaxKind::Placeholder, "<EXP>"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::NotEq, "<>"),
+                (SyntaxKind::Whitespace, " "),
+                (SyntaxKind::Error, "ä"),
+            ]
+        );
+        assert_eq!(
+            kinds("\"open\nx"),
+            vec![
+                (SyntaxKind::Error, "\"open"),
+                (SyntaxKind::Whitespace, "\n"),
+                (SyntaxKind::Ident, "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn match_keywords_regardless_of_case() {
+        for text in [
+            "FROM", "Step", "goto", "nOt", "xor", "Div", "mod", "Pers", "inout", "SWITCH",
+        ] {
+            let kind = keyword(text).unwrap();
+            assert!(kind.is_keyword());
+            assert_eq!(keyword(&text.to_lowercase()), Some(kind));
+        }
+        assert_eq!(keyword("fromage"), None);
+    }
+
+    #[test]
+    fn hand_significant_tokens_to_the_parser() {
+        let source = "a := b; ! note\r\n  c;\n! last";
+        let tokens: Vec<_> = Lexer::new(source)
+            .spanned()
+            .map(|token| token.unwrap())
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (0, Lexeme(SyntaxKind::Ident, "a"), 1),
+                (2, Lexeme(SyntaxKind::Assign, ":="), 4),
+                (5, Lexeme(SyntaxKind::Ident, "b"), 6),
+                (6, Lexeme(SyntaxKind::Semicolon, ";"), 7),
+                (8, Lexeme(SyntaxKind::Comment, "! note\r\n"), 16),
+                (18, Lexeme(SyntaxKind::Ident, "c"), 19),
+                (19, Lexeme(SyntaxKind::Semicolon, ";"), 20),
+                (21, Lexeme(SyntaxKind::Comment, "! last"), 27),
+            ]
+        );
+    }
+
+    #[test]
+    fn pass_on_only_the_comments_of_statements() {
+        let comments = |source| -> Vec<&str> {
+            Lexer::new(source)
+                .spanned()
+                .map(|token| token.unwrap().1)
+                .filter(|lexeme| lexeme.0 == SyntaxKind::Comment)
+                .map(|lexeme| lexeme.1.trim_end())
+                .collect()
+        };
+        let source = "MODULE m(SYSMODULE) ! module
+            RECORD r ! record
+num x; ! component
+ENDRECORD ! after record
+            PROC p(num a, ! parameter
+num b) ! head
+            x := a + ! expression
+b; ! statement
+            IF x THEN ! then
+label: ! label
+ENDIF
+            ERROR (ERR_A, ! error list
+ERR_B) ! handler
+            ENDPROC ! after routine
+            TRAP t ! trap
+ENDTRAP
+ENDMODULE ! end";
+        assert_eq!(
+            comments(source),
+            vec![
+                "! module",
+                "! after record",
+                "! head",
+                "! statement",
+                "! then",
+                "! label",
+                "! handler",
+                "! after routine",
+                "! trap",
+            ]
+        );
+    }
+}
@@ -1,40 +1,157 @@
 pub mod ast;
+pub mod cst;
+pub mod format;
+pub mod incremental;
+pub mod lexer;
+pub mod span;
 use lalrpop_util::lalrpop_mod;
-lalrpop_mod!(pub rapid);
+// The generated parser only uses `SyntaxKind` in its inner modules, for matching tokens
+lalrpop_mod!(#[allow(clippy::all, unused_imports)] pub rapid);
 
-pub use lalrpop_util::ParseError;
+pub use span::{LineCol, LineIndex, Span};
+
+pub use lalrpop_util::{ErrorRecovery, ParseError};
+
+/// A syntax error produced by the RAPID parser.
+pub type RapidParseError<'input> = ParseError<usize, lexer::Lexeme<'input>, UserError>;
+
+/// A syntax error that does not borrow from the source, for parses kept across edits.
+pub type SyntaxError = ParseError<usize, String, UserError>;
+
+/// An error raised by a grammar action rather than by the parser itself, such as an invalid escape
+/// sequence in a string literal.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct UserError {
+    pub message: &'static str,
+    pub span: Span,
+}
+
+impl std::fmt::Display for UserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message)
+    }
+}
+
+/// The outcome of parsing a module: a (possibly partial) AST together with every syntax error found.
+///
+/// When the parser recovers from an error, the offending statement, routine or expression is kept in
+/// the AST as an `Error` node. If the module header itself cannot be parsed, `module` is
+/// `ast::Module::Error`.
+#[derive(Debug)]
+pub struct ParsedModule<'input> {
+    pub module: ast::Module,
+    pub errors: Vec<RapidParseError<'input>>,
+}
+
+impl<'input> ParsedModule<'input> {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn is_err(&self) -> bool {
+        !self.is_ok()
+    }
+
+    /// Returns the module if it was parsed without any syntax errors.
+    pub fn into_result(self) -> Result<ast::Module, Vec<RapidParseError<'input>>> {
+        if self.errors.is_empty() {
+            Ok(self.module)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
 
 /// Parses a RAPID module from the given input string.
 ///
+/// The parser recovers from syntax errors at statement and routine boundaries, so a single call
+/// reports every syntax error in the module instead of stopping at the first one.
+///
 /// # Arguments
 ///
 /// * `input` - A string slice containing the RAPID module code to parse.
 ///
 /// # Returns
 ///
-/// A `Result` containing either the parsed `ast::Module` or a `ParseError`.
-pub fn parse_module(
-    input: &str,
-) -> Result<ast::Module, ParseError<usize, lalrpop_util::lexer::Token<'_>, &'static str>> {
-    rapid::ModuleParser::new().parse(input)
+/// A `ParsedModule` containing the (possibly partial) `ast::Module` and all syntax errors.
+pub fn parse_module(input: &str) -> ParsedModule<'_> {
+    let mut errors = Vec::new();
+    let module =
+        match rapid::ModuleParser::new().parse(&mut errors, lexer::Lexer::new(input).spanned()) {
+            Ok(module) => module,
+            Err(error) => {
+                errors.push(ErrorRecovery {
+                    error,
+                    dropped_tokens: Vec::new(),
+                });
+                ast::Module::Error
+            }
+        };
+    ParsedModule {
+        module,
+        errors: errors.into_iter().map(|e| e.error).collect(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn parse_statement(input: &str) -> Result<ast::Statement, RapidParseError<'_>> {
+        let mut errors = Vec::new();
+        let statement =
+            rapid::StatementParser::new().parse(&mut errors, lexer::Lexer::new(input).spanned())?;
+        match errors.into_iter().next() {
+            Some(recovered) => Err(recovered.error),
+            None => Ok(statement),
+        }
+    }
+
+    fn parse_expr(input: &str) -> Result<ast::Expr, RapidParseError<'_>> {
+        let mut errors = Vec::new();
+        let expr =
+            rapid::ExprParser::new().parse(&mut errors, lexer::Lexer::new(input).spanned())?;
+        match errors.into_iter().next() {
+            Some(recovered) => Err(recovered.error),
+            None => Ok(expr),
+        }
+    }
+
+    fn parse_routine(input: &str) -> Result<ast::RoutineDeclaration, RapidParseError<'_>> {
+        let mut errors = Vec::new();
+        let routine = rapid::RoutineDeclarationParser::new()
+            .parse(&mut errors, lexer::Lexer::new(input).spanned())?;
+        match errors.into_iter().next() {
+            Some(recovered) => Err(recovered.error),
+            None => Ok(routine),
+        }
+    }
+
     #[test]
     fn parse_module_attributes() {
         let input = r#"MODULE mymodule (SYSMODULE, VIEWONLY)
 ENDMODULE"#;
-        parse_module(&input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
     fn parse_module_without_attributes() {
         let input = r#"MODULE mymodule
 ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
+    }
+
+    #[test]
+    fn parse_comments_in_parameter_lists() {
+        let input = "MODULE mymodule\n    PROC p(num a, ! c\n    num b)\n    ENDPROC\nENDMODULE";
+        parse_module(input).into_result().unwrap();
+    }
+
+    #[test]
+    fn parse_comments_in_expressions() {
+        let input =
+            "MODULE mymodule\n    PROC p()\n        x := a + ! c\n        b;\n    ENDPROC\nENDMODULE";
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -44,7 +161,7 @@ ENDMODULE"#;
                 ALIAS num level;
                 CONST level low := 2.5;
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -55,7 +172,7 @@ ENDMODULE"#;
                 PERS num somePers;
                 CONST num someConst;
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -68,20 +185,57 @@ ENDMODULE"#;
                 TASK VAR num someTaskVar;
                 TASK PERS num somePers;
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
     fn parse_multi_dim_array() {
         let input = "VAR num someArray{2,2} := [[1,2],[1,2]];";
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn parse_three_dim_array() {
+        let statement = parse_statement("VAR num cube{2,3,4};").unwrap();
+        let ast::StatementKind::DataDeclaration(ast::DataDeclaration::VarDeclaration(_, decl)) =
+            statement.kind
+        else {
+            panic!("expected a declaration");
+        };
+        let Some(ast::DimensionKind::Dimension(dims)) = decl.definition.dim.map(|dim| dim.kind)
+        else {
+            panic!("expected dimensions");
+        };
+        assert_eq!(dims.len(), 3);
+        assert!(parse_statement("cube{1,2,3} := cube{2,3,4};").is_ok());
+        // Too many dimensions are reported by the analyzer, not the parser
+        assert!(parse_statement("VAR num hypercube{2,2,2,2};").is_ok());
+    }
+
+    #[test]
+    fn parse_four_indices_without_recovery() {
+        let input = r#"
+            MODULE mymodule
+                PROC first()
+                    a := b{1,2,3,4};
+                ENDPROC
+                PROC second()
+                    c := 1;
+                ENDPROC
+            ENDMODULE"#;
+        let parsed = parse_module(input);
+        assert!(parsed.errors.is_empty());
+        let ast::Module::Module(info) = parsed.module else {
+            panic!("expected a module");
+        };
+        assert_eq!(info.statements.len(), 2);
+    }
+
     #[test]
     fn parse_multi_dim_persistent_array() {
         let input = "PERS num someArray{2,2} := [[2,2],[3,3]];";
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -92,7 +246,7 @@ ENDMODULE"#;
                 ! Comment
                 ! Another comment
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -101,7 +255,7 @@ ENDMODULE"#;
             MODULE mymodule
                 <TDN>
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -113,7 +267,7 @@ ENDMODULE"#;
                     bool anotherRecord;
                 ENDRECORD
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -126,7 +280,7 @@ ENDMODULE"#;
                 ENDRECORD
                 VAR testRecord someRecord;
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -145,7 +299,7 @@ ENDMODULE"#;
                 PERS num assignedMultiDim2{2,2} := [[3,3],[4,5]];
                 CONST num someConst := 3.14;
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -172,6 +326,130 @@ ENDMODULE"#;
         assert!(parse_module(input).is_err());
     }
 
+    #[test]
+    fn recover_from_multiple_statement_errors() {
+        let input = r#"
+            MODULE mymdule
+                PROC myproc()
+                    VAR num someVariable
+                    someVariable := 5;
+                    someVariable := ;
+                    someVariable := 6;
+                ENDPROC
+            ENDMODULE"#;
+        let parsed = parse_module(input);
+        assert_eq!(parsed.errors.len(), 2);
+        let ast::Module::Module(info) = parsed.module else {
+            panic!("expected a partial module");
+        };
+        let ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(proc)) =
+            &info.statements[0].kind
+        else {
+            panic!("expected a procedure");
+        };
+        let kinds: Vec<_> = proc.statements.iter().map(|s| &s.kind).collect();
+        assert!(matches!(kinds[0], ast::StatementKind::Error));
+        assert!(matches!(kinds[1], ast::StatementKind::Error));
+        assert!(matches!(kinds[2], ast::StatementKind::Assignment(..)));
+    }
+
+    #[test]
+    fn recover_from_broken_index() {
+        let input = r#"
+            MODULE mymodule
+                PROC first()
+                    a := b{1,,2};
+                    c := 1;
+                ENDPROC
+                PROC second()
+                    c := 2;
+                ENDPROC
+            ENDMODULE"#;
+        let parsed = parse_module(input);
+        assert_eq!(parsed.errors.len(), 1);
+        let ast::Module::Module(info) = parsed.module else {
+            panic!("expected a partial module");
+        };
+        assert_eq!(info.statements.len(), 2);
+        let ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(proc)) =
+            &info.statements[0].kind
+        else {
+            panic!("expected a procedure");
+        };
+        assert_eq!(proc.statements.len(), 2);
+        assert!(matches!(
+            info.statements[1].kind,
+            ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(_))
+        ));
+    }
+
+    #[test]
+    fn recover_from_routine_head_error() {
+        let input = r#"
+            MODULE mymdule
+                PROC (num a)
+                    a := 1;
+                ENDPROC
+                PROC broken(num)
+                    b := ;
+                ENDPROC
+                PROC fine()
+                    c := 1;
+                ENDPROC
+            ENDMODULE"#;
+        let parsed = parse_module(input);
+        assert_eq!(parsed.errors.len(), 3);
+        let ast::Module::Module(info) = parsed.module else {
+            panic!("expected a partial module");
+        };
+        assert!(matches!(
+            info.statements[0].kind,
+            ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::Error)
+        ));
+        let ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(
+            broken,
+        )) = &info.statements[1].kind
+        else {
+            panic!("expected a procedure");
+        };
+        assert_eq!(
+            broken.parameters[0].kind,
+            ast::ParameterDeclarationTypeKind::Error
+        );
+        assert!(matches!(
+            info.statements[2].kind,
+            ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(_))
+        ));
+    }
+
+    #[test]
+    fn recover_from_broken_func_call_arguments() {
+        let input = "a := foo(1,,2) + 1;";
+        let mut errors = Vec::new();
+        let statement = rapid::StatementParser::new()
+            .parse(&mut errors, lexer::Lexer::new(input).spanned())
+            .unwrap();
+        assert_eq!(errors.len(), 1);
+        let ast::StatementKind::Assignment(_, value) = statement.kind else {
+            panic!("expected an assignment");
+        };
+        let ast::ExprKind::Op(left, ast::OpCode::Add, _) = value.kind else {
+            panic!("expected an addition");
+        };
+        assert_eq!(left.kind, ast::ExprKind::Error);
+    }
+
+    #[test]
+    fn broken_module_header_is_module_error() {
+        let input = r#"
+            MODULE
+                VAR num a;
+            ENDMODULE"#;
+        let parsed = parse_module(input);
+        assert!(parsed.is_err());
+        assert_eq!(parsed.module, ast::Module::Error);
+    }
+
     #[test]
     fn parse_par_parameter_declaration() {
         let input = r#"
@@ -181,7 +459,7 @@ ENDMODULE"#;
                     someVariable := 5;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -193,7 +471,7 @@ ENDMODULE"#;
                     someVariable := 5;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -205,7 +483,7 @@ ENDMODULE"#;
                     someVariable := 5;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -216,7 +494,7 @@ ENDMODULE"#;
                     <STM>;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -227,7 +505,7 @@ ENDMODULE"#;
                     <STM>;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -238,7 +516,7 @@ ENDMODULE"#;
                     <STM>;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -249,7 +527,7 @@ ENDMODULE"#;
                     <STM>;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -262,7 +540,7 @@ ENDMODULE"#;
             ENDMODULE"#;
         // NOTE: This should actually fail, but the RAPID parser in RobotStudio is also not complaining
         // parse_module(input).unwrap_err();
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -280,7 +558,7 @@ ENDMODULE"#;
                     someVariable := 2;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -298,7 +576,7 @@ ENDMODULE"#;
                     someVariable := 2;
                 ENDPROC
             ENDMODULE"#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -315,7 +593,7 @@ ENDMODULE"#;
                 ENDTRAP
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -330,7 +608,7 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -346,7 +624,7 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -362,7 +640,7 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -379,7 +657,24 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
+    }
+
+    #[test]
+    fn parse_conditional_argument_takes_one_parameter() {
+        let statement = parse_statement(r"myproc \speed ? speed, other;").unwrap();
+        let ast::StatementKind::ProcCall(_, arguments) = statement.kind else {
+            panic!("expected a procedure call");
+        };
+        assert_eq!(arguments.len(), 2);
+        assert!(matches!(
+            &arguments[0].kind,
+            ast::ArgumentKind::Conditional(name, _) if name == "speed"
+        ));
+        assert!(matches!(
+            arguments[1].kind,
+            ast::ArgumentKind::Required(None, _)
+        ));
     }
 
     #[test]
@@ -392,7 +687,7 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -404,7 +699,7 @@ ENDMODULE"#;
                 ENDPROC
             ENDMODULE
         "#;
-        parse_module(input).unwrap();
+        parse_module(input).into_result().unwrap();
     }
 
     #[test]
@@ -416,7 +711,7 @@ ENDMODULE"#;
             counter := counter + 1;
             ENDIF
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -429,7 +724,7 @@ ENDMODULE"#;
             counter := counter + 1;
             ENDIF
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -438,7 +733,7 @@ ENDMODULE"#;
         let input = r#"
             IF ERRNO = escape1 GOTO next;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -447,7 +742,7 @@ ENDMODULE"#;
         let input = r#"
             a{i} := b{i};
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -458,7 +753,7 @@ ENDMODULE"#;
                 a{i} := b{i};
             ENDFOR
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         println!("{:?}", result);
         assert!(result.is_ok());
     }
@@ -470,7 +765,7 @@ ENDMODULE"#;
                 a := a + 1;
             ENDWHILE
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -486,7 +781,7 @@ ENDMODULE"#;
                 b := 10;
             ENDTEST
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -503,7 +798,7 @@ ENDMODULE"#;
             RAISE;
             ENDFUNC
         "#;
-        let result = rapid::RoutineDeclarationParser::new().parse(input);
+        let result = parse_routine(input);
         assert!(result.is_ok());
     }
 
@@ -512,7 +807,7 @@ ENDMODULE"#;
         let input = r#"
             num result := veclen(vector);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -521,7 +816,7 @@ ENDMODULE"#;
         let input = r#"
             num result := sqrt(quad(vector.x) + quad(vector.y));
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -530,7 +825,7 @@ ENDMODULE"#;
         let input = r#"
             num result := polar(3.12, 0.785398);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -539,7 +834,7 @@ ENDMODULE"#;
         let input = r#"
             num result := polar(radius := 3.12, angle := 0.785398);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -548,7 +843,7 @@ ENDMODULE"#;
         let input = r#"
             num result := polar(radius := 3.12, angle := 0.785398, \angle2 := 0.785398);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -557,10 +852,16 @@ ENDMODULE"#;
         let input = r#"
             someString := "This is a string with a ""quote"" in it";
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
         if let Ok(statement) = result {
-            if let ast::Statement::Assignment(_, ast::Expr::Term(ast::Term::String(v))) = statement
+            if let ast::StatementKind::Assignment(
+                _,
+                ast::Expr {
+                    kind: ast::ExprKind::Term(ast::Term::String(v)),
+                    ..
+                },
+            ) = statement.kind
             {
                 assert_eq!(v, r#"This is a string with a "quote" in it"#);
             }
@@ -572,10 +873,16 @@ ENDMODULE"#;
         let input = r#"
             someString := "This is a string with a BEL control character\07";
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
         if let Ok(statement) = result {
-            if let ast::Statement::Assignment(_, ast::Expr::Term(ast::Term::String(v))) = statement
+            if let ast::StatementKind::Assignment(
+                _,
+                ast::Expr {
+                    kind: ast::ExprKind::Term(ast::Term::String(v)),
+                    ..
+                },
+            ) = statement.kind
             {
                 assert_eq!(v, "This is a string with a BEL control character\u{7}");
             }
@@ -587,21 +894,86 @@ ENDMODULE"#;
         let input = r#"
             VAR string someVar := "This is a string with a "";
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_err());
-        assert_eq!(
+        assert!(matches!(
             result.unwrap_err(),
-            lalrpop_util::ParseError::UnrecognizedEof {
-                location: 62,
-                expected: vec!["\"".to_string()]
+            ParseError::UnrecognizedToken {
+                token: (61, lexer::Lexeme(lexer::SyntaxKind::Error, "\";"), 63),
+                ..
             }
+        ));
+    }
+
+    #[test]
+    fn recover_from_invalid_characters() {
+        let input =
+            "MODULE m\n    PROC main()\n        x := 1 $;\n        y := 2;\n    ENDPROC\nENDMODULE";
+        let parsed = parse_module(input);
+        let ast::Module::Module(module) = parsed.module else {
+            panic!("expected a partial module");
+        };
+        let ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(proc)) =
+            &module.statements[0].kind
+        else {
+            panic!("expected a procedure");
+        };
+        assert_eq!(proc.statements[0].kind, ast::StatementKind::Error);
+        assert!(matches!(
+            proc.statements[1].kind,
+            ast::StatementKind::Assignment(..)
+        ));
+        let start = input.find('$').unwrap();
+        assert!(matches!(
+            parsed.errors[..],
+            [ParseError::UnrecognizedToken {
+                token: (l, lexer::Lexeme(lexer::SyntaxKind::Error, "$"), _),
+                ..
+            }] if l == start
+        ));
+    }
+
+    #[test]
+    fn recover_from_invalid_string_escape() {
+        let input = "MODULE m\n    PROC main()\n        TPWrite \"a\\zz\";\n        TPWrite \"b\";\n    ENDPROC\nENDMODULE";
+        let parsed = parse_module(input);
+        assert!(matches!(parsed.module, ast::Module::Module(_)));
+        let start = input.find("\"a").unwrap();
+        assert_eq!(
+            parsed.errors,
+            vec![ParseError::User {
+                error: UserError {
+                    message: "Invalid hex sequence",
+                    span: ast::Span::new(start, start + 6),
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_several_strings_in_one_expression() {
+        let input = r#"data := date + " " + time + "";"#;
+        let statement = parse_statement(input).unwrap();
+        let ast::StatementKind::Assignment(_, expr) = statement.kind else {
+            panic!("expected an assignment");
+        };
+        let ast::ExprKind::Op(left, ast::OpCode::Add, right) = expr.kind else {
+            panic!("expected an addition");
+        };
+        assert_eq!(
+            right.kind,
+            ast::ExprKind::Term(ast::Term::String(String::new()))
+        );
+        assert_eq!(
+            &input[left.span.start..left.span.end],
+            r#"date + " " + time"#
         );
     }
 
     #[test]
     fn parse_leading_dot_float_number() {
         let input = "VAR num someVar := .14;";
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -610,7 +982,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := NOT true;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -619,7 +991,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := true AND false;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -628,7 +1000,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := true OR false;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -637,7 +1009,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := true XOR false;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -646,7 +1018,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := (true AND false) OR (false AND true);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -655,7 +1027,7 @@ ENDMODULE"#;
         let input = r#"
             VAR bool someVar := (true);
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -664,7 +1036,7 @@ ENDMODULE"#;
         let input = r#"
             % "proc" + NumToStr(product_id, 0) % x, y, z;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -673,7 +1045,7 @@ ENDMODULE"#;
         let input = r#"
             % procname{product_id} % x, y, z;
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
@@ -690,7 +1062,7 @@ ENDMODULE"#;
         ];
 
         for input in inputs {
-            let result = rapid::ExprParser::new().parse(input);
+            let result = parse_expr(input);
             assert!(result.is_ok());
         }
     }
@@ -707,10 +1079,140 @@ ENDMODULE"#;
                 b := 10;
             ENDTEST
         "#;
-        let result = rapid::StatementParser::new().parse(input);
+        let result = parse_statement(input);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn parse_number_literals() {
+        let number = |input| match parse_expr(input).unwrap().kind {
+            ast::ExprKind::Term(ast::Term::Num(number)) => number,
+            kind => panic!("expected a number, got {:?}", kind),
+        };
+        assert_eq!(number("42"), ast::Number::Integer(42));
+        assert_eq!(number("0xFF"), ast::Number::Integer(255));
+        assert_eq!(number("0o17"), ast::Number::Integer(15));
+        assert_eq!(number("0b1010"), ast::Number::Integer(10));
+        assert_eq!(number("0XfF"), ast::Number::Integer(255));
+        assert_eq!(number("4.0"), ast::Number::Real(4.0));
+        assert_eq!(number(".5"), ast::Number::Real(0.5));
+        assert_eq!(number("1E3"), ast::Number::Real(1000.0));
+        assert_eq!(
+            parse_expr("0x1FFFFFFFFFFFFFFFF").unwrap_err(),
+            ParseError::User {
+                error: UserError {
+                    message: "Integer literal is too large",
+                    span: Span::new(0, 19),
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn parse_lowercase_keywords() {
+        let input = r#"
+            module mymodule
+                local record point
+                    num x;
+                endrecord
+                alias num length;
+                pers num count := 0;
+                const num limit := 10 div 2 mod 3;
+                proc main(inout num a, var num b, \Switch fast)
+                    for i from 1 to limit step 2 do
+                        if not a > 0 and b < 1 or fast xor TRUE goto done;
+                    endfor
+                    done:
+                endproc
+            endmodule"#;
+        parse_module(input).into_result().unwrap();
+    }
+
+    #[test]
+    fn parse_routine_scopes() {
+        let input = r#"
+            MODULE mymodule
+                LOCAL PROC helper()
+                ENDPROC
+                local FUNC num twice(num x)
+                    RETURN 2 * x;
+                ENDFUNC
+                LOCAL TRAP handler
+                ENDTRAP
+                PROC main()
+                ENDPROC
+            ENDMODULE"#;
+        let ast::Module::Module(info) = parse_module(input).into_result().unwrap() else {
+            panic!("expected a module");
+        };
+        let scopes: Vec<_> = info
+            .statements
+            .iter()
+            .map(|statement| match &statement.kind {
+                ast::StatementKind::RoutineDeclaration(
+                    ast::RoutineDeclaration::ProcDeclaration(proc),
+                ) => (proc.scope, &input[proc.span.start..proc.span.start + 4]),
+                ast::StatementKind::RoutineDeclaration(
+                    ast::RoutineDeclaration::FuncDeclaration(func),
+                ) => (func.scope, &input[func.span.start..func.span.start + 4]),
+                ast::StatementKind::RoutineDeclaration(
+                    ast::RoutineDeclaration::TrapDeclaration(trap),
+                ) => (trap.scope, &input[trap.span.start..trap.span.start + 4]),
+                _ => panic!("expected a routine"),
+            })
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                (ast::Scope::LOCAL, "PROC"),
+                (ast::Scope::LOCAL, "FUNC"),
+                (ast::Scope::LOCAL, "TRAP"),
+                (ast::Scope::GLOBAL, "PROC"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_records_spans() {
+        let input = "MODULE mymodule\n    PROC main()\n        a := b.c;\n    ENDPROC\nENDMODULE";
+        let module = parse_module(input).into_result().unwrap();
+        let ast::Module::Module(info) = module else {
+            panic!("expected a module");
+        };
+        assert_eq!(info.span, Span::new(0, input.len()));
+        assert_eq!(&input[info.name_span.start..info.name_span.end], "mymodule");
+
+        let ast::StatementKind::RoutineDeclaration(ast::RoutineDeclaration::ProcDeclaration(proc)) =
+            &info.statements[0].kind
+        else {
+            panic!("expected a procedure");
+        };
+        assert_eq!(
+            &input[proc.span.start..proc.span.end],
+            "PROC main()\n        a := b.c;\n    ENDPROC"
+        );
+        assert_eq!(&input[proc.name_span.start..proc.name_span.end], "main");
+
+        let statement = &proc.statements[0];
+        assert_eq!(
+            &input[statement.span.start..statement.span.end],
+            "a := b.c;"
+        );
+        let ast::StatementKind::Assignment(ast::AssignmentTarget::Variable(target), value) =
+            &statement.kind
+        else {
+            panic!("expected an assignment");
+        };
+        assert_eq!(&input[target.span.start..target.span.end], "a");
+        assert_eq!(&input[value.span.start..value.span.end], "b.c");
+
+        let index = LineIndex::new(input);
+        assert_eq!(
+            index.line_col(statement.span.start),
+            LineCol { line: 2, col: 8 }
+        );
+    }
+
     #[test]
     fn parse_complex_file_logger() {
         // Source from: https://raw.githubusercontent.com/robotics/open_abb/fuerte-devel/RAPID/LOGGER.mod
@@ -0,0 +1,113 @@
+/// A half-open byte range `[start, end)` into the parsed source text.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns `true` if `offset` lies within the span. The end offset is included so that a
+    /// cursor placed directly behind an identifier still hits it.
+    pub fn contains(&self, offset: usize) -> bool {
+        self.start <= offset && offset <= self.end
+    }
+
+    /// Returns the smallest span covering both `self` and `other`.
+    pub fn cover(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A zero-based line and column position. The column is counted in bytes from the start of the line.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Converts byte offsets into line/column positions and back.
+#[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex {
+            line_starts,
+            len: text.len(),
+        }
+    }
+
+    /// Returns the line and column of `offset`. Offsets past the end of the text are clamped.
+    pub fn line_col(&self, offset: usize) -> LineCol {
+        let offset = offset.min(self.len);
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        LineCol {
+            line,
+            col: offset - self.line_starts[line],
+        }
+    }
+
+    /// Returns the byte offset of a line/column position, or `None` if the position is outside the text.
+    pub fn offset(&self, pos: LineCol) -> Option<usize> {
+        let start = *self.line_starts.get(pos.line)?;
+        let end = self
+            .line_starts
+            .get(pos.line + 1)
+            .copied()
+            .unwrap_or(self.len);
+        let offset = start + pos.col;
+        if offset <= end {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_round_trip() {
+        let text = "MODULE m\n  VAR num x;\nENDMODULE";
+        let index = LineIndex::new(text);
+        assert_eq!(index.line_count(), 3);
+        assert_eq!(index.line_col(0), LineCol { line: 0, col: 0 });
+        assert_eq!(index.line_col(11), LineCol { line: 1, col: 2 });
+        assert_eq!(index.line_col(text.len()), LineCol { line: 2, col: 9 });
+        assert_eq!(index.offset(LineCol { line: 1, col: 2 }), Some(11));
+        assert_eq!(index.offset(LineCol { line: 5, col: 0 }), None);
+    }
+}
@@ -14,52 +14,203 @@ export async function activate(context: vscode.ExtensionContext) {
     context.subscriptions.push(diagnosticCollection);
 
     const wasmModule = await import(path.join(__dirname, '..', 'pkg', 'rapid_wasm.js'));
-    let disposable = vscode.workspace.onDidChangeTextDocument(event => {
-        if (event.document.languageId === 'rapid') {
-            updateDiagnostics(event.document, wasmModule);
+    // Documents are parsed once when opened, and after that only the changed routines
+    const openDocument = (document: vscode.TextDocument) => {
+        if (document.languageId === 'rapid') {
+            updateDiagnostics(document, () =>
+                wasmModule.open_document(document.uri.toString(), document.version, document.getText()));
         }
-    });
+    };
+    context.subscriptions.push(vscode.workspace.onDidOpenTextDocument(openDocument));
+    context.subscriptions.push(vscode.workspace.onDidChangeTextDocument(event => {
+        const document = event.document;
+        if (document.languageId === 'rapid') {
+            const changes = event.contentChanges.map(change => ({
+                range: {
+                    start: { line: change.range.start.line, character: change.range.start.character },
+                    end: { line: change.range.end.line, character: change.range.end.character }
+                },
+                text: change.text
+            }));
+            updateDiagnostics(document, () =>
+                wasmModule.change_document(document.uri.toString(), document.version, JSON.stringify(changes)));
+        }
+    }));
+    context.subscriptions.push(vscode.workspace.onDidCloseTextDocument(document => {
+        if (document.languageId === 'rapid') {
+            wasmModule.close_document(document.uri.toString());
+            documentFixes.delete(document.uri.toString());
+            diagnosticCollection.delete(document.uri);
+        }
+    }));
+
+    context.subscriptions.push(
+        vscode.languages.registerCodeActionsProvider('rapid', new RapidQuickFixes(), {
+            providedCodeActionKinds: [vscode.CodeActionKind.QuickFix]
+        })
+    );
+    context.subscriptions.push(
+        vscode.languages.registerDocumentSymbolProvider('rapid', new RapidOutline(wasmModule))
+    );
+
+    // Open the documents that were already open before activation
+    vscode.workspace.textDocuments.forEach(openDocument);
+}
 
-    context.subscriptions.push(disposable);
+interface RapidPosition {
+    line: number;
+    character: number;
+}
 
-    // Run once for the current file
-    if (vscode.window.activeTextEditor) {
-        updateDiagnostics(vscode.window.activeTextEditor.document, wasmModule);
+interface RapidRange {
+    start: RapidPosition;
+    end: RapidPosition;
+}
+
+interface RapidFix {
+    message: string;
+    edits: { range: RapidRange; newText: string }[];
+}
+
+interface RapidDiagnostic {
+    severity: 'error' | 'warning' | 'info' | 'hint';
+    code: string;
+    message: string;
+    range: RapidRange;
+    related: { message: string; range: RapidRange }[];
+    fixes: RapidFix[];
+}
+
+interface RapidOutlineItem {
+    name: string;
+    detail: string | null;
+    kind: 'module' | 'record' | 'component' | 'alias' | 'constant' | 'variable' | 'persistent'
+        | 'procedure' | 'function' | 'trap';
+    range: RapidRange;
+    selectionRange: RapidRange;
+    children: RapidOutlineItem[];
+}
+
+// The fixes of the diagnostics currently shown, per document and diagnostic
+const documentFixes = new Map<string, Map<string, RapidFix[]>>();
+
+function toRange(range: RapidRange): vscode.Range {
+    return new vscode.Range(
+        range.start.line, range.start.character,
+        range.end.line, range.end.character
+    );
+}
+
+function toSeverity(severity: RapidDiagnostic['severity']): vscode.DiagnosticSeverity {
+    switch (severity) {
+        case 'error': return vscode.DiagnosticSeverity.Error;
+        case 'warning': return vscode.DiagnosticSeverity.Warning;
+        case 'info': return vscode.DiagnosticSeverity.Information;
+        case 'hint': return vscode.DiagnosticSeverity.Hint;
     }
 }
 
-function updateDiagnostics(document: vscode.TextDocument, wasmModule: any) {
-    const text = document.getText();
+function fixKey(diagnostic: vscode.Diagnostic): string {
+    const { start, end } = diagnostic.range;
+    return `${start.line}:${start.character}:${end.line}:${end.character}:${diagnostic.code}:${diagnostic.message}`;
+}
+
+// Shows the diagnostics returned as JSON by `analyze`
+function updateDiagnostics(document: vscode.TextDocument, analyze: () => string) {
     try {
-        const result = JSON.parse(wasmModule.parse_rapid(text));
-        if (result.success) {
-            diagnosticCollection.delete(document.uri);
-        } else {
-            const diagnostics: vscode.Diagnostic[] = [];
-            for (const error of result.errors) {
-                if (error.error_position) {
-                    const [startPos, endPos] = error.error_position;
-                    const range = new vscode.Range(
-                        document.positionAt(startPos),
-                        document.positionAt(endPos)
-                    );
-                    const diagnostic = new vscode.Diagnostic(range, error.message, vscode.DiagnosticSeverity.Error);
-                    diagnostics.push(diagnostic);
-                } else {
-                    // If no specific position is available, mark the entire document
-                    const range = new vscode.Range(
-                        document.positionAt(0),
-                        document.positionAt(document.getText().length)
-                    );
-                    const diagnostic = new vscode.Diagnostic(range, error.message, vscode.DiagnosticSeverity.Error);
-                    diagnostics.push(diagnostic);
-                }
+        const result = JSON.parse(analyze());
+        const diagnostics: vscode.Diagnostic[] = [];
+        const fixes = new Map<string, RapidFix[]>();
+        for (const rapidDiagnostic of result.diagnostics as RapidDiagnostic[]) {
+            const diagnostic = new vscode.Diagnostic(
+                toRange(rapidDiagnostic.range),
+                rapidDiagnostic.message,
+                toSeverity(rapidDiagnostic.severity)
+            );
+            diagnostic.code = rapidDiagnostic.code;
+            diagnostic.source = 'rapid';
+            diagnostic.relatedInformation = rapidDiagnostic.related.map(related =>
+                new vscode.DiagnosticRelatedInformation(
+                    new vscode.Location(document.uri, toRange(related.range)),
+                    related.message
+                )
+            );
+            if (rapidDiagnostic.fixes.length > 0) {
+                fixes.set(fixKey(diagnostic), rapidDiagnostic.fixes);
             }
-            diagnosticCollection.set(document.uri, diagnostics);
+            diagnostics.push(diagnostic);
         }
+        documentFixes.set(document.uri.toString(), fixes);
+        diagnosticCollection.set(document.uri, diagnostics);
     } catch (error) {
         outputChannel.appendLine(`Error parsing RAPID code: ${error}`);
     }
 }
 
+class RapidQuickFixes implements vscode.CodeActionProvider {
+    provideCodeActions(
+        document: vscode.TextDocument,
+        _range: vscode.Range,
+        context: vscode.CodeActionContext
+    ): vscode.CodeAction[] {
+        const fixes = documentFixes.get(document.uri.toString());
+        const actions: vscode.CodeAction[] = [];
+        for (const diagnostic of context.diagnostics) {
+            for (const fix of fixes?.get(fixKey(diagnostic)) ?? []) {
+                const action = new vscode.CodeAction(fix.message, vscode.CodeActionKind.QuickFix);
+                action.diagnostics = [diagnostic];
+                action.edit = new vscode.WorkspaceEdit();
+                for (const edit of fix.edits) {
+                    action.edit.replace(document.uri, toRange(edit.range), edit.newText);
+                }
+                actions.push(action);
+            }
+        }
+        return actions;
+    }
+}
+
+function toSymbolKind(kind: RapidOutlineItem['kind']): vscode.SymbolKind {
+    switch (kind) {
+        case 'module': return vscode.SymbolKind.Module;
+        case 'record': return vscode.SymbolKind.Struct;
+        case 'component': return vscode.SymbolKind.Field;
+        case 'alias': return vscode.SymbolKind.TypeParameter;
+        case 'constant': return vscode.SymbolKind.Constant;
+        case 'variable': return vscode.SymbolKind.Variable;
+        case 'persistent': return vscode.SymbolKind.Variable;
+        case 'procedure': return vscode.SymbolKind.Function;
+        case 'function': return vscode.SymbolKind.Function;
+        case 'trap': return vscode.SymbolKind.Event;
+    }
+}
+
+function toDocumentSymbol(item: RapidOutlineItem): vscode.DocumentSymbol {
+    const symbol = new vscode.DocumentSymbol(
+        item.name,
+        item.detail ?? '',
+        toSymbolKind(item.kind),
+        toRange(item.range),
+        toRange(item.selectionRange)
+    );
+    symbol.children = item.children.map(toDocumentSymbol);
+    return symbol;
+}
+
+// Provides the outline view and breadcrumbs
+class RapidOutline implements vscode.DocumentSymbolProvider {
+    constructor(private wasmModule: any) {}
+
+    provideDocumentSymbols(document: vscode.TextDocument): vscode.DocumentSymbol[] {
+        try {
+            const outline: RapidOutlineItem | null =
+                JSON.parse(this.wasmModule.get_document_outline(document.uri.toString()));
+            return outline ? [toDocumentSymbol(outline)] : [];
+        } catch (error) {
+            outputChannel.appendLine(`Error building the RAPID outline: ${error}`);
+            return [];
+        }
+    }
+}
+
 export function deactivate() {}
\ No newline at end of file
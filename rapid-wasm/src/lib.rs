@@ -1,63 +1,652 @@
-use rapid_parser::{parse_module, ParseError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use rapid_analyzer::incremental::{AnalysisCache, Reanalysis};
+use rapid_analyzer::{
+    analyze_module, diagnose, outline, LintConfig, ReferenceKind, ScopeId, ScopeKind, Severity,
+    SymbolKind,
+};
+use rapid_parser::ast::{Module, Scope};
+use rapid_parser::incremental::{OutdatedVersion, ParseCache, TextEdit as SourceEdit};
+use rapid_parser::{parse_module, LineCol, LineIndex, Span};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use web_sys::console;
 
-#[derive(Serialize, Deserialize)]
+/// The result of `parse_rapid`. `success` is `false` if any diagnostic is an error.
+#[derive(Serialize, PartialEq, Debug, Clone)]
 struct ParseResult {
     success: bool,
-    errors: Vec<ParseErrorInfo>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A zero-based position. Columns count UTF-16 code units, like JavaScript strings and editors do.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+struct Position {
+    line: usize,
+    character: usize,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+struct Range {
+    start: Position,
+    end: Position,
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct RelatedInformation {
+    message: String,
+    range: Range,
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct TextEdit {
+    range: Range,
+    new_text: String,
+}
+
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct Fix {
+    message: String,
+    edits: Vec<TextEdit>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ParseErrorInfo {
+#[derive(Serialize, PartialEq, Debug, Clone)]
+struct Diagnostic {
+    /// One of `error`, `warning`, `info` or `hint`.
+    severity: &'static str,
+    /// A stable identifier such as `undeclared-identifier`.
+    code: &'static str,
     message: String,
-    error_position: Option<(usize, usize)>,
+    range: Range,
+    related: Vec<RelatedInformation>,
+    fixes: Vec<Fix>,
+}
+
+/// An entry of the document outline, such as a routine with its local data as children.
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OutlineItem {
+    name: String,
+    /// The data type of data and components, or the routine kind and return type.
+    detail: Option<String>,
+    /// One of `module`, `record`, `component`, `alias`, `constant`, `variable`, `persistent`,
+    /// `procedure`, `function` or `trap`.
+    kind: &'static str,
+    range: Range,
+    selection_range: Range,
+    children: Vec<OutlineItem>,
+}
+
+/// The symbol table of a module. Scopes, symbols and references point at each other by their
+/// index in these lists. The built-in catalogue is left out.
+#[derive(Serialize, PartialEq, Debug, Default)]
+struct Symbols {
+    scopes: Vec<ScopeInfo>,
+    symbols: Vec<SymbolInfo>,
+    references: Vec<ReferenceInfo>,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+struct ScopeInfo {
+    /// One of `module`, `routine` or `loop`.
+    kind: &'static str,
+    parent: Option<usize>,
+    range: Range,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SymbolInfo {
+    name: String,
+    /// A description such as `variable`, `procedure` or `label`.
+    kind: &'static str,
+    /// One of `LOCAL`, `GLOBAL` or `TASK`.
+    visibility: &'static str,
+    scope: usize,
+    /// The type of data and parameters, or the return type of a function.
+    data_type: Option<String>,
+    range: Range,
+    selection_range: Range,
+}
+
+#[derive(Serialize, PartialEq, Debug)]
+struct ReferenceInfo {
+    name: String,
+    /// One of `read`, `write`, `call`, `type` or `label`.
+    kind: &'static str,
+    scope: usize,
+    range: Range,
+    /// The symbol the name resolved to, unless it is unresolved or built in.
+    symbol: Option<usize>,
+    builtin: bool,
+}
+
+/// A change of an open document, like a content change event of VS Code. Without a range the text
+/// replaces the whole document.
+#[derive(Deserialize, PartialEq, Debug)]
+struct ContentChange {
+    range: Option<Range>,
+    text: String,
+}
+
+/// An open document with the diagnostics of its current version.
+struct Document {
+    parse: ParseCache,
+    analysis: AnalysisCache,
+    result: ParseResult,
+}
+
+impl Document {
+    fn new(parse: ParseCache) -> Self {
+        let analysis = AnalysisCache::new(&parse, LintConfig::default());
+        let result = parse_result(parse.source(), analysis.diagnostics().to_vec());
+        Document {
+            parse,
+            analysis,
+            result,
+        }
+    }
+
+    /// Applies `edits` to get `version` of the document and brings its diagnostics up to date.
+    fn update(
+        &mut self,
+        version: i32,
+        edits: &[SourceEdit],
+    ) -> Result<Reanalysis, OutdatedVersion> {
+        let reparse = self.parse.update(version, edits)?;
+        let reanalysis = self.analysis.update(&self.parse, reparse);
+        self.result = parse_result(self.parse.source(), self.analysis.diagnostics().to_vec());
+        Ok(reanalysis)
+    }
+}
+
+thread_local! {
+    /// The open documents by URI.
+    static DOCUMENTS: RefCell<HashMap<String, Document>> = RefCell::new(HashMap::new());
+}
+
+/// Converts byte offsets in the source into editor positions.
+struct Source<'a> {
+    text: &'a str,
+    index: LineIndex,
+}
+
+impl<'a> Source<'a> {
+    fn new(text: &'a str) -> Self {
+        Source {
+            text,
+            index: LineIndex::new(text),
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let LineCol { line, col } = self.index.line_col(offset);
+        let line_start = offset.min(self.text.len()) - col;
+        let character = self.text[line_start..line_start + col]
+            .encode_utf16()
+            .count();
+        Position { line, character }
+    }
+
+    fn range(&self, span: Span) -> Range {
+        Range {
+            start: self.position(span.start),
+            end: self.position(span.end),
+        }
+    }
+
+    /// Returns the byte offset of a position. Positions past the end of a line are clamped to it.
+    fn offset(&self, position: Position) -> usize {
+        let Some(line_start) = self.index.offset(LineCol {
+            line: position.line,
+            col: 0,
+        }) else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (offset, c) in self.text[line_start..].char_indices() {
+            if units >= position.character || c == '\n' {
+                return line_start + offset;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+}
+
+/// Converts content changes into edits of byte ranges, each applying to the text left by the
+/// changes before it.
+fn source_edits(text: &str, changes: Vec<ContentChange>) -> Vec<SourceEdit> {
+    let mut text = text.to_owned();
+    let mut edits = Vec::new();
+    for change in changes {
+        let span = match change.range {
+            Some(range) => {
+                let source = Source::new(&text);
+                Span::new(source.offset(range.start), source.offset(range.end))
+            }
+            None => Span::new(0, text.len()),
+        };
+        text.replace_range(span.start..span.end.max(span.start), &change.text);
+        edits.push(SourceEdit::new(span, change.text));
+    }
+    edits
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+        Severity::Hint => "hint",
+    }
+}
+
+/// Parses and analyses a module and converts its diagnostics for JavaScript.
+fn analyze(input: &str) -> ParseResult {
+    parse_result(input, diagnose(input))
 }
 
+fn parse_result(input: &str, diagnostics: Vec<rapid_analyzer::Diagnostic>) -> ParseResult {
+    let source = Source::new(input);
+    let diagnostics: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .map(|diagnostic| Diagnostic {
+            severity: severity_name(diagnostic.severity),
+            code: diagnostic.code,
+            range: source.range(diagnostic.span),
+            message: diagnostic.message,
+            related: diagnostic
+                .related
+                .into_iter()
+                .map(|related| RelatedInformation {
+                    range: source.range(related.span),
+                    message: related.message,
+                })
+                .collect(),
+            fixes: diagnostic
+                .fixes
+                .into_iter()
+                .map(|fix| Fix {
+                    message: fix.message,
+                    edits: fix
+                        .edits
+                        .into_iter()
+                        .map(|edit| TextEdit {
+                            range: source.range(edit.span),
+                            new_text: edit.replacement,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+        .collect();
+    ParseResult {
+        success: diagnostics.iter().all(|d| d.severity != "error"),
+        diagnostics,
+    }
+}
+
+fn outline_item(source: &Source, item: rapid_analyzer::OutlineItem) -> OutlineItem {
+    OutlineItem {
+        name: item.name,
+        detail: item.detail,
+        kind: item.kind.name(),
+        range: source.range(item.span),
+        selection_range: source.range(item.name_span),
+        children: item
+            .children
+            .into_iter()
+            .map(|child| outline_item(source, child))
+            .collect(),
+    }
+}
+
+/// Returns the outline of a module, or `None` if it does not parse far enough to have one.
+fn module_outline(input: &str, module: &Module) -> Option<OutlineItem> {
+    let Module::Module(module) = module else {
+        return None;
+    };
+    Some(outline_item(&Source::new(input), outline(module)))
+}
+
+/// Collects the symbol table of a module for JavaScript, numbering the scopes in the order they
+/// are nested and the symbols in the order they are declared.
+fn module_symbols(input: &str) -> Symbols {
+    let Module::Module(module) = parse_module(input).module else {
+        return Symbols::default();
+    };
+    let source = Source::new(input);
+    let analysis = analyze_module(&module);
+    let table = &analysis.symbols;
+
+    let mut scopes = Vec::new();
+    let mut scope_index: HashMap<ScopeId, usize> = HashMap::new();
+    let mut pending = vec![analysis.module_scope];
+    while let Some(id) = pending.pop() {
+        let scope = table.scope(id);
+        scope_index.insert(id, scopes.len());
+        scopes.push(ScopeInfo {
+            kind: match scope.kind {
+                ScopeKind::Builtin => "builtin",
+                ScopeKind::Task => "task",
+                ScopeKind::Module => "module",
+                ScopeKind::Routine => "routine",
+                ScopeKind::Loop => "loop",
+            },
+            parent: scope
+                .parent
+                .and_then(|parent| scope_index.get(&parent).copied()),
+            range: source.range(scope.span),
+        });
+        pending.extend(scope.children.iter().rev());
+    }
+
+    let mut symbols = Vec::new();
+    let mut symbol_index = HashMap::new();
+    for (id, symbol) in table.symbols() {
+        let Some(&scope) = scope_index.get(&symbol.scope) else {
+            continue;
+        };
+        let data_type = match symbol.kind {
+            SymbolKind::Data(decl) => Some(decl.data_type.clone()),
+            SymbolKind::Parameter(parameter) => Some(parameter.data_type.clone()),
+            SymbolKind::Function(func) => Some(func.data_type.clone()),
+            SymbolKind::Alias(alias) => Some(alias.data_type.clone()),
+            _ => None,
+        };
+        symbol_index.insert(id, symbols.len());
+        symbols.push(SymbolInfo {
+            name: symbol.name.to_owned(),
+            kind: symbol.kind.description(),
+            visibility: match symbol.visibility {
+                Scope::LOCAL => "LOCAL",
+                Scope::GLOBAL => "GLOBAL",
+                Scope::TASK => "TASK",
+            },
+            scope,
+            data_type,
+            range: source.range(symbol.span),
+            selection_range: source.range(symbol.name_span),
+        });
+    }
+
+    let references = table
+        .references()
+        .iter()
+        .map(|reference| ReferenceInfo {
+            name: reference.name.clone(),
+            kind: match reference.kind {
+                ReferenceKind::Read => "read",
+                ReferenceKind::Write => "write",
+                ReferenceKind::Call => "call",
+                ReferenceKind::Type => "type",
+                ReferenceKind::Label => "label",
+            },
+            scope: scope_index.get(&reference.scope).copied().unwrap_or(0),
+            range: source.range(reference.span),
+            symbol: reference
+                .symbol
+                .and_then(|symbol| symbol_index.get(&symbol).copied()),
+            builtin: reference
+                .symbol
+                .is_some_and(|symbol| table.is_builtin(symbol)),
+        })
+        .collect();
+
+    Symbols {
+        scopes,
+        symbols,
+        references,
+    }
+}
+
+fn to_json(value: &impl Serialize) -> Result<JsValue, JsValue> {
+    serde_json::to_string(value)
+        .map(JsValue::from)
+        .map_err(|error| JsValue::from(error.to_string()))
+}
+
+/// Parses and analyses a RAPID module and returns a JSON `ParseResult` with every syntax error,
+/// semantic problem and lint finding in it.
 #[wasm_bindgen]
 pub fn parse_rapid(input: &str) -> Result<JsValue, JsValue> {
     console::log_1(&"Parsing RAPID code...".into());
+    to_json(&analyze(input))
+}
 
-    let result = match parse_module(input) {
-        Ok(_) => ParseResult {
-            success: true,
-            errors: vec![],
-        },
-        Err(errors) => {
-            let parse_error = match errors {
-                ParseError::UnrecognizedEof { location, expected } => ParseErrorInfo {
-                    message: format!("Unexpected EOF. Expected one of: {}", expected.join(", ")),
-                    error_position: Some((location, input.len())),
-                },
-                ParseError::UnrecognizedToken { token, expected } => ParseErrorInfo {
-                    message: format!(
-                        "Unexpected token '{}'. Expected one of: {}",
-                        token.1,
-                        expected.join(", ")
-                    ),
-                    error_position: Some((token.0, token.2)),
-                },
-                ParseError::InvalidToken { location } => ParseErrorInfo {
-                    message: format!("Invalid token at location: {:?}", location),
-                    error_position: Some((location, location)),
-                },
-                ParseError::ExtraToken { token } => ParseErrorInfo {
-                    message: format!("Extra token at location: {:?}", token),
-                    error_position: Some((token.0, token.2)),
-                },
-                ParseError::User { error } => ParseErrorInfo {
-                    message: format!("User error: {}", error),
-                    error_position: None,
+/// Parses a RAPID module and returns its syntax tree as JSON. Spans in the tree are byte offsets
+/// into the UTF-8 encoded source. A module that cannot be parsed at all is `"Error"`.
+#[wasm_bindgen]
+pub fn get_ast(input: &str) -> Result<JsValue, JsValue> {
+    to_json(&parse_module(input).module)
+}
+
+/// Analyses a RAPID module and returns its scopes, declared symbols and name references as JSON.
+#[wasm_bindgen]
+pub fn get_symbols(input: &str) -> Result<JsValue, JsValue> {
+    to_json(&module_symbols(input))
+}
+
+/// Returns the outline of a RAPID module as JSON: the module with its records, aliases, data and
+/// routines, or `null` if it cannot be parsed.
+#[wasm_bindgen]
+pub fn get_outline(input: &str) -> Result<JsValue, JsValue> {
+    to_json(&module_outline(input, &parse_module(input).module))
+}
+
+/// Opens a document to be kept up to date with `change_document`, and returns its diagnostics as
+/// JSON like `parse_rapid`.
+#[wasm_bindgen]
+pub fn open_document(uri: &str, version: i32, text: &str) -> Result<JsValue, JsValue> {
+    let document = Document::new(ParseCache::new(version, text));
+    let result = to_json(&document.result);
+    DOCUMENTS.with_borrow_mut(|documents| documents.insert(uri.to_owned(), document));
+    result
+}
+
+/// Applies the content changes of `version` of an open document and returns its diagnostics as
+/// JSON like `parse_rapid`. `changes` is a JSON array of `{ "range", "text" }` objects.
+///
+/// Only the declarations and routines touched by the changes are parsed again. If those are
+/// routines whose names and parameters stay as they are, only they are analysed again: the other
+/// routines keep their diagnostics, and the checks across the module (declarations, unused data
+/// and routines, interrupts and traps, lint suppressions) are redone from what each routine uses.
+/// Any other change analyses the whole module again. The diagnostics of a version are computed
+/// once, so passing the current version again returns them without any work.
+#[wasm_bindgen]
+pub fn change_document(uri: &str, version: i32, changes: &str) -> Result<JsValue, JsValue> {
+    let changes: Vec<ContentChange> =
+        serde_json::from_str(changes).map_err(|error| JsValue::from(error.to_string()))?;
+    DOCUMENTS.with_borrow_mut(|documents| {
+        let document = documents
+            .get_mut(uri)
+            .ok_or_else(|| JsValue::from(format!("document {} is not open", uri)))?;
+        if version != document.parse.version() {
+            let edits = source_edits(document.parse.source(), changes);
+            document
+                .update(version, &edits)
+                .map_err(|error| JsValue::from(error.to_string()))?;
+        }
+        to_json(&document.result)
+    })
+}
+
+/// Returns the outline of the current version of an open document as JSON like `get_outline`.
+#[wasm_bindgen]
+pub fn get_document_outline(uri: &str) -> Result<JsValue, JsValue> {
+    DOCUMENTS.with_borrow(|documents| {
+        let document = documents
+            .get(uri)
+            .ok_or_else(|| JsValue::from(format!("document {} is not open", uri)))?;
+        to_json(&module_outline(
+            document.parse.source(),
+            document.parse.module(),
+        ))
+    })
+}
+
+/// Forgets an open document.
+#[wasm_bindgen]
+pub fn close_document(uri: &str) {
+    DOCUMENTS.with_borrow_mut(|documents| documents.remove(uri));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn report_all_diagnostics_with_positions() {
+        let input = "MODULE demo\n    PROC main()\n        LOCAL VAR string x;\n        x := \"é\\4\";\n        TPWrite x + y;\n    ENDPROC\nENDMODULE";
+        let result = analyze(input);
+        assert!(!result.success);
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            value["diagnostics"][0],
+            json!({
+                "severity": "error",
+                "code": "syntax-error",
+                "message": "Invalid hex sequence",
+                "range": {
+                    "start": { "line": 3, "character": 13 },
+                    "end": { "line": 3, "character": 18 },
                 },
-            };
+                "related": [],
+                "fixes": [],
+            })
+        );
+        assert_eq!(
+            value["diagnostics"][1]["fixes"][0],
+            json!({
+                "message": "remove the scope, routine data is always local",
+                "edits": [{
+                    "range": {
+                        "start": { "line": 2, "character": 8 },
+                        "end": { "line": 2, "character": 14 },
+                    },
+                    "newText": "",
+                }],
+            })
+        );
+        let codes: Vec<_> = result.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(
+            codes,
+            vec!["syntax-error", "invalid-scope", "undeclared-identifier"]
+        );
+    }
 
-            ParseResult {
-                success: false,
-                errors: vec![parse_error],
+    #[test]
+    fn succeed_with_warnings_only() {
+        let input = "MODULE demo\n    PROC main()\n        VAR num unused;\n    ENDPROC\nENDMODULE";
+        let result = analyze(input);
+        assert!(result.success);
+        assert_eq!(result.diagnostics[0].severity, "warning");
+        assert_eq!(result.diagnostics[0].code, "unused-var");
+        assert_eq!(
+            result.diagnostics[0].range.start,
+            Position {
+                line: 2,
+                character: 16
             }
-        }
-    };
+        );
+    }
+
+    #[test]
+    fn serialize_ast() {
+        let input = "MODULE demo\n    VAR num x := 1;\nENDMODULE";
+        let value = serde_json::to_value(parse_module(input).module).unwrap();
+        let module = &value["Module"];
+        assert_eq!(module["name"], "demo");
+        assert_eq!(module["span"], json!({ "start": 0, "end": input.len() }));
+        assert!(module["statements"][0]["kind"]["DataDeclaration"].is_object());
+        assert_eq!(serde_json::to_value(Module::Error).unwrap(), "Error");
+    }
+
+    #[test]
+    fn outline_with_ranges() {
+        let input = "MODULE demo\n    PROC main()\n        VAR num i;\n    ENDPROC\nENDMODULE";
+        let value =
+            serde_json::to_value(module_outline(input, &parse_module(input).module)).unwrap();
+        assert_eq!(value["kind"], "module");
+        let main = &value["children"][0];
+        assert_eq!(main["name"], "main");
+        assert_eq!(main["kind"], "procedure");
+        assert_eq!(
+            main["selectionRange"],
+            json!({ "start": { "line": 1, "character": 9 }, "end": { "line": 1, "character": 13 } })
+        );
+        assert_eq!(main["children"][0]["detail"], "num");
+        assert_eq!(module_outline("MODULE", &Module::Error), None);
+    }
+
+    #[test]
+    fn symbols_link_scopes_and_references() {
+        let input = "MODULE demo\n    PROC main()\n        VAR num i;\n        i := Abs(i);\n    ENDPROC\nENDMODULE";
+        let symbols = module_symbols(input);
+        let scopes: Vec<_> = symbols.scopes.iter().map(|s| (s.kind, s.parent)).collect();
+        assert_eq!(scopes, vec![("module", None), ("routine", Some(0))]);
+        let declared: Vec<_> = symbols
+            .symbols
+            .iter()
+            .map(|s| (s.name.as_str(), s.kind, s.scope, s.data_type.as_deref()))
+            .collect();
+        assert_eq!(
+            declared,
+            vec![
+                ("main", "procedure", 0, None),
+                ("i", "variable", 1, Some("num"))
+            ]
+        );
+        let references: Vec<_> = symbols
+            .references
+            .iter()
+            .map(|r| (r.name.as_str(), r.kind, r.symbol, r.builtin))
+            .collect();
+        assert!(references.contains(&("i", "write", Some(1), false)));
+        assert!(references.contains(&("Abs", "call", None, true)));
+    }
+
+    #[test]
+    fn update_open_documents_incrementally() {
+        let text = "MODULE demo\n    PROC main()\n        VAR num i;\n        i := i + 1;\n    ENDPROC\nENDMODULE";
+        let mut document = Document::new(ParseCache::new(1, text));
+        assert!(document.result.diagnostics.is_empty());
+
+        // Rename the assigned variable to one that does not exist
+        let changes: Vec<ContentChange> = serde_json::from_value(json!([
+            { "range": { "start": { "line": 3, "character": 8 }, "end": { "line": 3, "character": 9 } }, "text": "k" },
+            { "range": { "start": { "line": 3, "character": 9 }, "end": { "line": 3, "character": 9 } }, "text": "x" },
+        ]))
+        .unwrap();
+        let edits = source_edits(document.parse.source(), changes);
+        let offset = text.find("i := i").unwrap() + 1;
+        assert_eq!(edits[1].span, Span::new(offset, offset));
+        assert_eq!(
+            document.update(2, &edits),
+            Ok(Reanalysis::Incremental {
+                analysed: 1,
+                reused: 0
+            })
+        );
+        assert!(document.parse.source().contains("kx := i + 1;"));
+        assert_eq!(document.result, analyze(document.parse.source()));
+        let codes: Vec<_> = document.result.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec!["undeclared-identifier"]);
 
-    Ok(serde_json::to_string(&result).unwrap().into())
+        // A change without a range replaces the whole text
+        let edits = source_edits(
+            document.parse.source(),
+            vec![ContentChange {
+                range: None,
+                text: "MODULE other\nENDMODULE".to_owned(),
+            }],
+        );
+        assert_eq!(document.update(3, &edits), Ok(Reanalysis::Full));
+        assert_eq!(document.parse.source(), "MODULE other\nENDMODULE");
+        assert_eq!(document.result, analyze(document.parse.source()));
+    }
 }